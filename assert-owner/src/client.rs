@@ -0,0 +1,204 @@
+use crate::{GuardInstruction, BPF_LOADER_UPGRADEABLE_ID};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
+
+/// Returns an instruction checking the given guard against the given
+/// accounts, referred to by their index in `accounts`.
+pub fn guard_ix(
+    program_id: &Pubkey,
+    instruction: &GuardInstruction,
+    accounts: &[Pubkey],
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: accounts
+            .iter()
+            .map(|key| AccountMeta::new_readonly(*key, false))
+            .collect(),
+        data: instruction.pack(),
+    }
+}
+
+/// Returns an instruction asserting the given account is owned by `owner`,
+/// in the legacy 32 bytes format understood by every deployment.
+pub fn assert_owner_ix(program_id: &Pubkey, account: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new_readonly(*account, false)],
+        data: owner.to_bytes().to_vec(),
+    }
+}
+
+/// Returns an instruction asserting each of the given accounts is owned by
+/// the program it's paired with.
+pub fn assert_owners_ix(program_id: &Pubkey, accounts: &[(Pubkey, Pubkey)]) -> Instruction {
+    let (keys, owners): (Vec<_>, Vec<_>) = accounts.iter().copied().unzip();
+    guard_ix(program_id, &GuardInstruction::AssertOwner { owners }, &keys)
+}
+
+/// Returns an instruction asserting the given account is owned by one of
+/// the given programs.
+pub fn assert_owner_in_ix(program_id: &Pubkey, account: &Pubkey, owners: &[Pubkey]) -> Instruction {
+    let instruction = GuardInstruction::AssertOwnerIn {
+        account: 0,
+        owners: owners.to_vec(),
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given account's data is `len`
+/// bytes long.
+pub fn assert_data_len_ix(program_id: &Pubkey, account: &Pubkey, len: u64) -> Instruction {
+    let instruction = GuardInstruction::AssertDataLen { account: 0, len };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given account's data starts with
+/// the given discriminator.
+pub fn assert_discriminator_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    discriminator: [u8; 8],
+) -> Instruction {
+    let instruction = GuardInstruction::AssertDiscriminator {
+        account: 0,
+        discriminator,
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given account is, or isn't,
+/// executable.
+pub fn assert_executable_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    executable: bool,
+) -> Instruction {
+    let instruction = GuardInstruction::AssertExecutable {
+        account: 0,
+        executable,
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given account is a token account of
+/// the given mint and owner, with the given delegate and close authority,
+/// if any, `Some(None)` meaning none.
+pub fn assert_token_account_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    delegate: Option<Option<Pubkey>>,
+    close_authority: Option<Option<Pubkey>>,
+) -> Instruction {
+    let instruction = GuardInstruction::AssertTokenAccount {
+        account: 0,
+        mint: *mint,
+        owner: *owner,
+        delegate,
+        close_authority,
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given account is rent exempt.
+pub fn assert_rent_exempt_ix(program_id: &Pubkey, account: &Pubkey) -> Instruction {
+    let instruction = GuardInstruction::AssertRentExempt { account: 0 };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given upgradeable program's upgrade
+/// authority is the given one, `None` meaning the program is immutable.
+pub fn assert_upgrade_authority_ix(
+    program_id: &Pubkey,
+    program: &Pubkey,
+    authority: Option<Pubkey>,
+) -> Instruction {
+    let (program_data, _) =
+        Pubkey::find_program_address(&[program.as_ref()], &BPF_LOADER_UPGRADEABLE_ID);
+    let instruction = GuardInstruction::AssertUpgradeAuthority {
+        account: 0,
+        program: *program,
+        authority,
+    };
+    guard_ix(program_id, &instruction, &[program_data])
+}
+
+/// Returns an instruction asserting the given account is a mint of the
+/// given decimals and authorities, whose supply is at most `max_supply`, if
+/// given.
+pub fn assert_mint_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    decimals: u8,
+    mint_authority: Option<Pubkey>,
+    freeze_authority: Option<Pubkey>,
+    max_supply: Option<u64>,
+) -> Instruction {
+    let instruction = GuardInstruction::AssertMint {
+        account: 0,
+        decimals,
+        mint_authority,
+        freeze_authority,
+        max_supply,
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given account's data at `offset` is
+/// the given bytes.
+pub fn assert_data_slice_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    offset: u64,
+    expected: &[u8],
+) -> Instruction {
+    let instruction = GuardInstruction::AssertDataSlice {
+        account: 0,
+        offset,
+        expected: expected.to_vec(),
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the current slot is at most
+/// `max_slot_age` slots after `slot`.
+pub fn assert_slot_age_ix(program_id: &Pubkey, slot: u64, max_slot_age: u64) -> Instruction {
+    let instruction = GuardInstruction::AssertSlotAge { slot, max_slot_age };
+    guard_ix(program_id, &instruction, &[])
+}
+
+/// Returns an instruction asserting the current unix timestamp is within
+/// `[min, max]`.
+pub fn assert_unix_timestamp_ix(
+    program_id: &Pubkey,
+    min: Option<i64>,
+    max: Option<i64>,
+) -> Instruction {
+    let instruction = GuardInstruction::AssertUnixTimestamp { min, max };
+    guard_ix(program_id, &instruction, &[])
+}
+
+/// Returns an instruction asserting the given account holds within
+/// `[min, max]` lamports.
+pub fn assert_lamports_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    min: u64,
+    max: u64,
+) -> Instruction {
+    let instruction = GuardInstruction::AssertLamports {
+        account: 0,
+        min,
+        max,
+    };
+    guard_ix(program_id, &instruction, &[*account])
+}
+
+/// Returns an instruction asserting the given token account is, or isn't,
+/// frozen.
+pub fn assert_frozen_ix(program_id: &Pubkey, account: &Pubkey, frozen: bool) -> Instruction {
+    let instruction = GuardInstruction::AssertFrozen { account: 0, frozen };
+    guard_ix(program_id, &instruction, &[*account])
+}
@@ -0,0 +1,96 @@
+use std::convert::TryFrom;
+
+use solana_program::program_error::ProgramError;
+
+/// Errors of the failed assertions, returned as `ProgramError::Custom` with
+/// their code, so that failing guards can be told apart from the logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum GuardError {
+    OwnerMismatch = 0x100,
+    DataLenMismatch,
+    DiscriminatorMismatch,
+    ExecutableMismatch,
+    TokenAccountMismatch,
+    NotRentExempt,
+    UpgradeAuthorityMismatch,
+    MintMismatch,
+    DataSliceMismatch,
+    SlotTooOld,
+    TimestampOutOfRange,
+    ProgramDataAddressMismatch,
+    InvalidTokenAccount,
+    InvalidMint,
+    InvalidProgramData,
+    LamportsOutOfRange,
+    FrozenMismatch,
+}
+
+impl GuardError {
+    const ALL: [GuardError; 17] = [
+        GuardError::OwnerMismatch,
+        GuardError::DataLenMismatch,
+        GuardError::DiscriminatorMismatch,
+        GuardError::ExecutableMismatch,
+        GuardError::TokenAccountMismatch,
+        GuardError::NotRentExempt,
+        GuardError::UpgradeAuthorityMismatch,
+        GuardError::MintMismatch,
+        GuardError::DataSliceMismatch,
+        GuardError::SlotTooOld,
+        GuardError::TimestampOutOfRange,
+        GuardError::ProgramDataAddressMismatch,
+        GuardError::InvalidTokenAccount,
+        GuardError::InvalidMint,
+        GuardError::InvalidProgramData,
+        GuardError::LamportsOutOfRange,
+        GuardError::FrozenMismatch,
+    ];
+}
+
+impl From<GuardError> for u32 {
+    fn from(err: GuardError) -> u32 {
+        err as u32
+    }
+}
+
+impl From<GuardError> for ProgramError {
+    fn from(err: GuardError) -> ProgramError {
+        ProgramError::Custom(err.into())
+    }
+}
+
+impl TryFrom<u32> for GuardError {
+    type Error = u32;
+
+    fn try_from(code: u32) -> Result<Self, u32> {
+        GuardError::ALL
+            .iter()
+            .copied()
+            .find(|err| u32::from(*err) == code)
+            .ok_or(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        // Codes are stable, so that the client can decode them.
+        assert_eq!(u32::from(GuardError::OwnerMismatch), 0x100);
+        assert_eq!(u32::from(GuardError::UpgradeAuthorityMismatch), 0x106);
+        assert_eq!(u32::from(GuardError::FrozenMismatch), 0x110);
+        for (idx, err) in GuardError::ALL.iter().enumerate() {
+            assert_eq!(u32::from(*err), 0x100 + idx as u32);
+            assert_eq!(GuardError::try_from(u32::from(*err)), Ok(*err));
+        }
+        assert_eq!(
+            ProgramError::from(GuardError::InvalidMint),
+            ProgramError::Custom(0x10d)
+        );
+        assert_eq!(GuardError::try_from(0xff), Err(0xff));
+        assert_eq!(GuardError::try_from(0x111), Err(0x111));
+    }
+}
@@ -0,0 +1,158 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+/// Assertions checked by the program, each failing the transaction unless it
+/// holds, Borsh encoded as the instruction data. Accounts are referred to by
+/// their index in the instruction's accounts.
+///
+/// For compatibility, instruction data of exactly 32 bytes is an owner,
+/// asserted for the first account, so instructions that would encode to 32
+/// bytes are packed with a trailing zero byte.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum GuardInstruction {
+    /// The first accounts are owned by the given programs, in order.
+    AssertOwner { owners: Vec<Pubkey> },
+    /// The account's data is `len` bytes long.
+    AssertDataLen { account: u8, len: u64 },
+    /// The account's data starts with the given (e.g. Anchor) discriminator.
+    AssertDiscriminator { account: u8, discriminator: [u8; 8] },
+    /// The account is, or isn't, executable.
+    AssertExecutable { account: u8, executable: bool },
+    /// The account is an SPL Token or Token-2022 account of the given mint
+    /// and owner, guarding against token account substitution. The delegate
+    /// and close authority, if given, are checked too, `Some(None)` meaning
+    /// the account has none.
+    AssertTokenAccount {
+        account: u8,
+        mint: Pubkey,
+        owner: Pubkey,
+        delegate: Option<Option<Pubkey>>,
+        close_authority: Option<Option<Pubkey>>,
+    },
+    /// The account holds enough lamports to be rent exempt for its data
+    /// length.
+    AssertRentExempt { account: u8 },
+    /// The account is the ProgramData account of the given upgradeable
+    /// program, whose upgrade authority is the given one, `None` meaning the
+    /// program is immutable.
+    AssertUpgradeAuthority {
+        account: u8,
+        program: Pubkey,
+        authority: Option<Pubkey>,
+    },
+    /// The account is owned by one of the given programs, e.g. either token
+    /// program.
+    AssertOwnerIn { account: u8, owners: Vec<Pubkey> },
+    /// The account is an SPL Token or Token-2022 mint of the given decimals
+    /// and authorities, `None` meaning the mint has none, whose supply is at
+    /// most `max_supply`, if given.
+    AssertMint {
+        account: u8,
+        decimals: u8,
+        mint_authority: Option<Pubkey>,
+        freeze_authority: Option<Pubkey>,
+        max_supply: Option<u64>,
+    },
+    /// The account's data at `offset` is the given bytes, e.g. a field of an
+    /// account whose layout has no dedicated assertion.
+    AssertDataSlice {
+        account: u8,
+        offset: u64,
+        expected: Vec<u8>,
+    },
+    /// The current slot is at most `max_slot_age` slots after `slot`, e.g.
+    /// the slot a quote was made at.
+    AssertSlotAge { slot: u64, max_slot_age: u64 },
+    /// The current unix timestamp is within `[min, max]`, bounds included,
+    /// e.g. a quote's validity window.
+    AssertUnixTimestamp { min: Option<i64>, max: Option<i64> },
+    /// The account holds within `[min, max]` lamports, bounds included, e.g.
+    /// a fee payer that can afford the rest of the transaction.
+    AssertLamports { account: u8, min: u64, max: u64 },
+    /// The account is an SPL Token or Token-2022 account that is, or isn't,
+    /// frozen.
+    AssertFrozen { account: u8, frozen: bool },
+}
+
+impl GuardInstruction {
+    /// Packs the instruction data.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = borsh::to_vec(self).unwrap();
+        if data.len() == 32 {
+            data.push(0);
+        }
+        data
+    }
+
+    /// Unpacks the instruction data.
+    pub fn unpack(mut data: &[u8]) -> Option<Self> {
+        let len = data.len();
+        let instruction = Self::deserialize(&mut data).ok()?;
+        match data {
+            [] => Some(instruction),
+            [0] if len == 33 => Some(instruction),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let ixs = [
+            GuardInstruction::AssertOwner {
+                owners: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            },
+            GuardInstruction::AssertTokenAccount {
+                account: 1,
+                mint: Pubkey::new_unique(),
+                owner: Pubkey::new_unique(),
+                delegate: Some(None),
+                close_authority: None,
+            },
+            GuardInstruction::AssertUpgradeAuthority {
+                account: 2,
+                program: Pubkey::new_unique(),
+                authority: None,
+            },
+            GuardInstruction::AssertUnixTimestamp {
+                min: Some(-1),
+                max: None,
+            },
+        ];
+        for ix in ixs {
+            let data = ix.pack();
+            assert_eq!(data, borsh::to_vec(&ix).unwrap());
+            assert_eq!(GuardInstruction::unpack(&data), Some(ix));
+        }
+
+        // Trailing data is rejected.
+        let mut data = GuardInstruction::AssertRentExempt { account: 0 }.pack();
+        data.push(0);
+        assert_eq!(GuardInstruction::unpack(&data), None);
+        assert_eq!(GuardInstruction::unpack(&[0xff]), None);
+        assert_eq!(GuardInstruction::unpack(&[]), None);
+    }
+
+    #[test]
+    fn test_pack_pads_legacy_len() {
+        // 1 + 1 + 8 + 4 + 18 bytes, which would be read as a legacy owner.
+        let ix = GuardInstruction::AssertDataSlice {
+            account: 0,
+            offset: 0,
+            expected: vec![7; 18],
+        };
+        let data = ix.pack();
+        assert_eq!(data.len(), 33);
+        assert_eq!(data.last(), Some(&0));
+        assert_eq!(GuardInstruction::unpack(&data), Some(ix.clone()));
+
+        // The padding is a zero byte.
+        let mut data = ix.pack();
+        data[32] = 1;
+        assert_eq!(GuardInstruction::unpack(&data), None);
+    }
+}
@@ -1,21 +1,467 @@
 use std::convert::TryFrom;
 
 use solana_program::{
-    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
-    program_error::ProgramError, pubkey::Pubkey,
+    account_info::AccountInfo, clock::Clock, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::ProgramError, program_option::COption, program_pack::IsInitialized,
+    program_pack::Pack, pubkey, pubkey::Pubkey, rent::Rent, sysvar::Sysvar,
 };
+use spl_token::state::{Account as TokenAccount, Mint};
+
+#[cfg(feature = "client")]
+pub mod client;
+mod error;
+mod instruction;
+
+pub use error::*;
+pub use instruction::*;
+
+/// The Token-2022 program, whose accounts are asserted like SPL Token ones.
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// The upgradeable BPF loader, owning the ProgramData accounts.
+pub const BPF_LOADER_UPGRADEABLE_ID: Pubkey =
+    pubkey!("BPFLoaderUpgradeab1e11111111111111111111111");
+
+// Token-2022 account types.
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
 
 entrypoint!(entry);
 fn entry(_program_id: &Pubkey, accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
-    let account = accounts.get(0).ok_or(ProgramError::NotEnoughAccountKeys)?;
-    if instruction_data.len() != 32 {
-        return Err(ProgramError::InvalidInstructionData);
-    }
-    let expected_owner = Pubkey::try_from(instruction_data)
-    .map_err(|_| ProgramError::InvalidInstructionData)?;
-    if expected_owner != *account.owner {
-        msg!("Account owner mismatch");
-        return Err(ProgramError::Custom(0x100));
+    let instruction = if instruction_data.len() == 32 {
+        let owner =
+            Pubkey::try_from(instruction_data).map_err(|_| ProgramError::InvalidInstructionData)?;
+        GuardInstruction::AssertOwner {
+            owners: vec![owner],
+        }
+    } else {
+        GuardInstruction::unpack(instruction_data).ok_or(ProgramError::InvalidInstructionData)?
+    };
+    let account = |idx: u8| {
+        accounts
+            .get(idx as usize)
+            .ok_or(ProgramError::NotEnoughAccountKeys)
+    };
+
+    match instruction {
+        GuardInstruction::AssertOwner { owners } => {
+            if accounts.len() < owners.len() {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            }
+            for (idx, (account, expected_owner)) in accounts.iter().zip(owners).enumerate() {
+                if expected_owner != *account.owner {
+                    msg!("Account {} owner mismatch", idx);
+                    return Err(GuardError::OwnerMismatch.into());
+                }
+            }
+        }
+        GuardInstruction::AssertDataLen { account: idx, len } => {
+            if account(idx)?.data_len() as u64 != len {
+                msg!("Account {} data length mismatch", idx);
+                return Err(GuardError::DataLenMismatch.into());
+            }
+        }
+        GuardInstruction::AssertDiscriminator {
+            account: idx,
+            discriminator,
+        } => {
+            if account(idx)?.try_borrow_data()?.get(..8) != Some(&discriminator[..]) {
+                msg!("Account {} discriminator mismatch", idx);
+                return Err(GuardError::DiscriminatorMismatch.into());
+            }
+        }
+        GuardInstruction::AssertExecutable {
+            account: idx,
+            executable,
+        } => {
+            if account(idx)?.executable != executable {
+                msg!("Account {} executable mismatch", idx);
+                return Err(GuardError::ExecutableMismatch.into());
+            }
+        }
+        GuardInstruction::AssertTokenAccount {
+            account: idx,
+            mint,
+            owner,
+            delegate,
+            close_authority,
+        } => {
+            let token_account = load_token_account(account(idx)?, idx)?;
+            if token_account.mint != mint
+                || token_account.owner != owner
+                || !matches_option(delegate, token_account.delegate)
+                || !matches_option(close_authority, token_account.close_authority)
+            {
+                msg!("Account {} token account mismatch", idx);
+                return Err(GuardError::TokenAccountMismatch.into());
+            }
+        }
+        GuardInstruction::AssertRentExempt { account: idx } => {
+            let account = account(idx)?;
+            if !Rent::get()?.is_exempt(account.lamports(), account.data_len()) {
+                msg!("Account {} not rent exempt", idx);
+                return Err(GuardError::NotRentExempt.into());
+            }
+        }
+        GuardInstruction::AssertUpgradeAuthority {
+            account: idx,
+            program,
+            authority,
+        } => {
+            let account = account(idx)?;
+            let (program_data, _) =
+                Pubkey::find_program_address(&[program.as_ref()], &BPF_LOADER_UPGRADEABLE_ID);
+            if account.key != &program_data {
+                msg!("Account {} program data address mismatch", idx);
+                return Err(GuardError::ProgramDataAddressMismatch.into());
+            }
+            match upgrade_authority(account) {
+                Some(current) if current == authority => {}
+                Some(_) => {
+                    msg!("Account {} upgrade authority mismatch", idx);
+                    return Err(GuardError::UpgradeAuthorityMismatch.into());
+                }
+                None => {
+                    msg!("Account {} not a program data account", idx);
+                    return Err(GuardError::InvalidProgramData.into());
+                }
+            }
+        }
+        GuardInstruction::AssertOwnerIn {
+            account: idx,
+            owners,
+        } => {
+            if !owners.contains(account(idx)?.owner) {
+                msg!("Account {} owner mismatch", idx);
+                return Err(GuardError::OwnerMismatch.into());
+            }
+        }
+        GuardInstruction::AssertMint {
+            account: idx,
+            decimals,
+            mint_authority,
+            freeze_authority,
+            max_supply,
+        } => {
+            let mint = match unpack_token_state::<Mint>(account(idx)?, ACCOUNT_TYPE_MINT) {
+                Some(mint) => mint,
+                None => {
+                    msg!("Account {} not a mint", idx);
+                    return Err(GuardError::InvalidMint.into());
+                }
+            };
+            if mint.decimals != decimals
+                || Option::from(mint.mint_authority) != mint_authority
+                || Option::from(mint.freeze_authority) != freeze_authority
+                || matches!(max_supply, Some(max_supply) if mint.supply > max_supply)
+            {
+                msg!("Account {} mint mismatch", idx);
+                return Err(GuardError::MintMismatch.into());
+            }
+        }
+        GuardInstruction::AssertDataSlice {
+            account: idx,
+            offset,
+            expected,
+        } => {
+            let data = account(idx)?.try_borrow_data()?;
+            let slice = usize::try_from(offset)
+                .ok()
+                .and_then(|offset| data.get(offset..)?.get(..expected.len()));
+            if slice != Some(&expected[..]) {
+                msg!("Account {} data slice mismatch", idx);
+                return Err(GuardError::DataSliceMismatch.into());
+            }
+        }
+        GuardInstruction::AssertSlotAge { slot, max_slot_age } => {
+            let current_slot = Clock::get()?.slot;
+            if current_slot.saturating_sub(slot) > max_slot_age {
+                msg!("Slot {} too old at slot {}", slot, current_slot);
+                return Err(GuardError::SlotTooOld.into());
+            }
+        }
+        GuardInstruction::AssertUnixTimestamp { min, max } => {
+            let timestamp = Clock::get()?.unix_timestamp;
+            if matches!(min, Some(min) if timestamp < min)
+                || matches!(max, Some(max) if timestamp > max)
+            {
+                msg!("Unix timestamp {} out of range", timestamp);
+                return Err(GuardError::TimestampOutOfRange.into());
+            }
+        }
+        GuardInstruction::AssertLamports {
+            account: idx,
+            min,
+            max,
+        } => {
+            let lamports = account(idx)?.lamports();
+            if lamports < min || lamports > max {
+                msg!("Account {} lamports {} out of range", idx, lamports);
+                return Err(GuardError::LamportsOutOfRange.into());
+            }
+        }
+        GuardInstruction::AssertFrozen {
+            account: idx,
+            frozen,
+        } => {
+            let token_account = load_token_account(account(idx)?, idx)?;
+            if token_account.is_frozen() != frozen {
+                msg!("Account {} frozen mismatch", idx);
+                return Err(GuardError::FrozenMismatch.into());
+            }
+        }
     }
     Ok(())
 }
+
+/// Unpacks the given SPL Token or Token-2022 account or mint, if it's one.
+/// Token-2022 ones with extensions are padded to the length of an account,
+/// followed by the given account type.
+fn unpack_token_state<T: Pack + IsInitialized>(
+    account: &AccountInfo,
+    account_type: u8,
+) -> Option<T> {
+    let data = account.try_borrow_data().ok()?;
+    let is_token_state = match data.get(TokenAccount::LEN) {
+        None => {
+            data.len() == T::LEN
+                && (account.owner == &spl_token::ID || account.owner == &TOKEN_2022_PROGRAM_ID)
+        }
+        Some(&ty) => account.owner == &TOKEN_2022_PROGRAM_ID && ty == account_type,
+    };
+    if !is_token_state {
+        return None;
+    }
+    T::unpack(&data[..T::LEN]).ok()
+}
+
+/// Unpacks the given token account, the `idx`th of the instruction.
+fn load_token_account(account: &AccountInfo, idx: u8) -> Result<TokenAccount, ProgramError> {
+    unpack_token_state(account, ACCOUNT_TYPE_ACCOUNT).ok_or_else(|| {
+        msg!("Account {} not a token account", idx);
+        GuardError::InvalidTokenAccount.into()
+    })
+}
+
+/// Returns the upgrade authority of the given ProgramData account, if it's
+/// one.
+fn upgrade_authority(account: &AccountInfo) -> Option<Option<Pubkey>> {
+    // `UpgradeableLoaderState::ProgramData`: a u32 tag, the slot of the last
+    // deployment, then the optional authority.
+    const PROGRAM_DATA: u32 = 3;
+    if account.owner != &BPF_LOADER_UPGRADEABLE_ID {
+        return None;
+    }
+    let data = account.try_borrow_data().ok()?;
+    if data.get(..4)? != PROGRAM_DATA.to_le_bytes() {
+        return None;
+    }
+    match *data.get(12)? {
+        0 => Some(None),
+        1 => Pubkey::try_from(data.get(13..45)?).ok().map(Some),
+        _ => None,
+    }
+}
+
+/// Returns true if the given value is the expected one, if any.
+fn matches_option(expected: Option<Option<Pubkey>>, value: COption<Pubkey>) -> bool {
+    match expected {
+        Some(expected) => expected == Option::from(value),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+    use spl_token::state::AccountState;
+
+    fn account(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(data.into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    // A token account, extended with Token-2022's account type if
+    // `extensions` is given.
+    fn token_account(
+        program: Pubkey,
+        mint: Pubkey,
+        owner: Pubkey,
+        extensions: Option<u8>,
+    ) -> AccountInfo<'static> {
+        let mut data = vec![0u8; TokenAccount::LEN];
+        TokenAccount {
+            mint,
+            owner,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }
+        .pack_into_slice(&mut data);
+        if let Some(account_type) = extensions {
+            data.push(account_type);
+            data.extend_from_slice(&[0; 8]);
+        }
+        account(Pubkey::new_unique(), program, data)
+    }
+
+    fn program_data(program: &Pubkey, authority: Option<Pubkey>) -> AccountInfo<'static> {
+        let (address, _) =
+            Pubkey::find_program_address(&[program.as_ref()], &BPF_LOADER_UPGRADEABLE_ID);
+        let mut data = vec![0u8; 45];
+        data[..4].copy_from_slice(&3u32.to_le_bytes());
+        data[4..12].copy_from_slice(&42u64.to_le_bytes());
+        if let Some(authority) = authority {
+            data[12] = 1;
+            data[13..45].copy_from_slice(authority.as_ref());
+        }
+        account(address, BPF_LOADER_UPGRADEABLE_ID, data)
+    }
+
+    fn run(accounts: &[AccountInfo], ix: &GuardInstruction) -> ProgramResult {
+        entry(&Pubkey::new_unique(), accounts, &ix.pack())
+    }
+
+    #[test]
+    fn test_legacy_owner() {
+        let owner = Pubkey::new_unique();
+        let accounts = [account(Pubkey::new_unique(), owner, Vec::new())];
+        entry(&Pubkey::new_unique(), &accounts, owner.as_ref()).unwrap();
+        assert_eq!(
+            entry(
+                &Pubkey::new_unique(),
+                &accounts,
+                Pubkey::new_unique().as_ref()
+            ),
+            Err(GuardError::OwnerMismatch.into())
+        );
+        assert_eq!(
+            entry(&Pubkey::new_unique(), &[], owner.as_ref()),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        // As the 32 bytes are an owner, other data of that length is invalid
+        // unless padded.
+        assert_eq!(
+            entry(&Pubkey::new_unique(), &accounts, &owner.as_ref()[..31]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        let ix = GuardInstruction::AssertOwner {
+            owners: vec![owner],
+        };
+        run(&accounts, &ix).unwrap();
+    }
+
+    #[test]
+    fn test_token_2022_account() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let ix = GuardInstruction::AssertTokenAccount {
+            account: 0,
+            mint,
+            owner,
+            delegate: Some(None),
+            close_authority: None,
+        };
+        let accounts = |program, extensions| [token_account(program, mint, owner, extensions)];
+        run(&accounts(spl_token::ID, None), &ix).unwrap();
+        run(&accounts(TOKEN_2022_PROGRAM_ID, None), &ix).unwrap();
+        run(
+            &accounts(TOKEN_2022_PROGRAM_ID, Some(ACCOUNT_TYPE_ACCOUNT)),
+            &ix,
+        )
+        .unwrap();
+
+        // The account type at offset 165 must be an account's, and only
+        // Token-2022 accounts have one.
+        let invalid: ProgramResult = Err(GuardError::InvalidTokenAccount.into());
+        assert_eq!(
+            run(
+                &accounts(TOKEN_2022_PROGRAM_ID, Some(ACCOUNT_TYPE_MINT)),
+                &ix
+            ),
+            invalid
+        );
+        assert_eq!(
+            run(&accounts(spl_token::ID, Some(ACCOUNT_TYPE_ACCOUNT)), &ix),
+            invalid
+        );
+        assert_eq!(run(&accounts(Pubkey::new_unique(), None), &ix), invalid);
+
+        let spoofed = GuardInstruction::AssertTokenAccount {
+            account: 0,
+            mint,
+            owner: Pubkey::new_unique(),
+            delegate: None,
+            close_authority: None,
+        };
+        assert_eq!(
+            run(
+                &accounts(TOKEN_2022_PROGRAM_ID, Some(ACCOUNT_TYPE_ACCOUNT)),
+                &spoofed
+            ),
+            Err(GuardError::TokenAccountMismatch.into())
+        );
+        let frozen = GuardInstruction::AssertFrozen {
+            account: 0,
+            frozen: false,
+        };
+        run(
+            &accounts(TOKEN_2022_PROGRAM_ID, Some(ACCOUNT_TYPE_ACCOUNT)),
+            &frozen,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_authority() {
+        let program = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let ix = |authority| GuardInstruction::AssertUpgradeAuthority {
+            account: 0,
+            program,
+            authority,
+        };
+        let accounts = [program_data(&program, Some(authority))];
+        run(&accounts, &ix(Some(authority))).unwrap();
+        assert_eq!(
+            run(&accounts, &ix(Some(Pubkey::new_unique()))),
+            Err(GuardError::UpgradeAuthorityMismatch.into())
+        );
+        assert_eq!(
+            run(&accounts, &ix(None)),
+            Err(GuardError::UpgradeAuthorityMismatch.into())
+        );
+        run(&[program_data(&program, None)], &ix(None)).unwrap();
+
+        // The ProgramData account of another program, or one it doesn't
+        // own, is rejected.
+        assert_eq!(
+            run(
+                &[program_data(&Pubkey::new_unique(), Some(authority))],
+                &ix(Some(authority))
+            ),
+            Err(GuardError::ProgramDataAddressMismatch.into())
+        );
+        let mut spoofed = accounts[0].clone();
+        spoofed.owner = Box::leak(Box::new(Pubkey::new_unique()));
+        assert_eq!(
+            run(&[spoofed], &ix(Some(authority))),
+            Err(GuardError::InvalidProgramData.into())
+        );
+        let invalid_option = program_data(&program, Some(authority));
+        invalid_option.data.borrow_mut()[12] = 2;
+        assert_eq!(
+            run(&[invalid_option], &ix(Some(authority))),
+            Err(GuardError::InvalidProgramData.into())
+        );
+    }
+}
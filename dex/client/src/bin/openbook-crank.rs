@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use openbook_client::{
+    ConsumePath, Crank, CrankConfig, CrankMarket, MarketInfo, PriorityFeeEstimator, ProxyRoute,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::read_keypair_file;
+use std::time::Duration;
+
+/// Consumes the events of the given markets, through a `MarketProxy` if
+/// given, deepest and longest waiting queues first. Markets are cranked with
+/// `ConsumeEventsPermissioned` when a consume events authority is given, and
+/// with `ConsumeEvents` otherwise.
+#[derive(Parser, Debug)]
+struct Opts {
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    #[clap(long)]
+    payer: String,
+    #[clap(long)]
+    dex_program_id: Pubkey,
+    #[clap(long = "market", required = true)]
+    markets: Vec<Pubkey>,
+    #[clap(long)]
+    proxy_program_id: Option<Pubkey>,
+    /// Keypair of the markets' consume events authority.
+    #[clap(long)]
+    authority: Option<String>,
+    #[clap(long, required_unless_present = "authority")]
+    coin_fee_wallet: Option<Pubkey>,
+    #[clap(long, required_unless_present = "authority")]
+    pc_fee_wallet: Option<Pubkey>,
+    #[clap(long, default_value_t = 10)]
+    max_open_orders: usize,
+    #[clap(long, default_value_t = 4)]
+    max_batches: usize,
+    #[clap(long, default_value_t = 32)]
+    limit: u16,
+    /// Priority fee, in micro-lamports per compute unit, or the fallback of
+    /// the estimated fee.
+    #[clap(long)]
+    compute_unit_price: Option<u64>,
+    /// Estimates the priority fee as this percentile of the recent fees paid
+    /// on each market's event queue.
+    #[clap(long)]
+    priority_fee_percentile: Option<u8>,
+    /// The highest estimated priority fee, in micro-lamports per compute unit.
+    #[clap(long, default_value_t = 100_000)]
+    max_compute_unit_price: u64,
+    /// Compute units of each `ConsumeEvents`, estimated if unset.
+    #[clap(long)]
+    compute_unit_limit: Option<u32>,
+    /// Compute units of the `ConsumeEvents` packed in a transaction.
+    #[clap(long, default_value_t = openbook_client::MAX_COMPUTE_UNITS)]
+    max_transaction_compute_units: u32,
+    /// Queued events a second of waiting is worth when prioritizing markets.
+    #[clap(long, default_value_t = 10)]
+    age_weight: u64,
+    #[clap(long, default_value_t = 1000)]
+    poll_interval_ms: u64,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opts = Opts::parse();
+    let client = RpcClient::new(opts.url.clone());
+    let payer =
+        read_keypair_file(&opts.payer).map_err(|e| anyhow!("invalid payer keypair: {}", e))?;
+    let authority = opts
+        .authority
+        .as_ref()
+        .map(read_keypair_file)
+        .transpose()
+        .map_err(|e| anyhow!("invalid authority keypair: {}", e))?;
+    let path = match (opts.coin_fee_wallet, opts.pc_fee_wallet) {
+        (Some(coin_fee_wallet), Some(pc_fee_wallet)) if authority.is_none() => {
+            ConsumePath::Permissionless {
+                coin_fee_wallet,
+                pc_fee_wallet,
+            }
+        }
+        _ => ConsumePath::Permissioned,
+    };
+    let markets = opts
+        .markets
+        .iter()
+        .map(|market| {
+            Ok(CrankMarket {
+                market: MarketInfo::load(&client, &opts.dex_program_id, market)?,
+                path: path.clone(),
+                proxy: opts.proxy_program_id.map(ProxyRoute::new),
+            })
+        })
+        .collect::<Result<_>>()?;
+    let crank = Crank {
+        client,
+        payer,
+        authority,
+        markets,
+        config: CrankConfig {
+            max_open_orders: opts.max_open_orders,
+            max_batches: opts.max_batches,
+            limit: opts.limit,
+            compute_unit_price: opts.compute_unit_price,
+            priority_fee: opts
+                .priority_fee_percentile
+                .map(|percentile| PriorityFeeEstimator {
+                    percentile,
+                    min_price: 0,
+                    max_price: opts.max_compute_unit_price,
+                }),
+            compute_unit_limit: opts.compute_unit_limit,
+            max_transaction_compute_units: opts.max_transaction_compute_units,
+            age_weight: opts.age_weight,
+            poll_interval: Duration::from_millis(opts.poll_interval_ms),
+        },
+    };
+    crank.run()
+}
@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use openbook_client::{DeployParams, Deployer, ListingParams};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Signer};
+use std::fs;
+
+/// Deploys a permissioned market of the given mints behind a `MarketProxy`,
+/// creating the proxy's config if needed, with the payer, the proxy's upgrade
+/// authority, as its admin, and prints the manifest of the created addresses
+/// as JSON.
+#[derive(Parser, Debug)]
+struct Opts {
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    #[clap(long)]
+    payer: String,
+    #[clap(long)]
+    dex_program_id: Pubkey,
+    #[clap(long)]
+    proxy_program_id: Pubkey,
+    #[clap(long)]
+    coin_mint: Pubkey,
+    #[clap(long)]
+    pc_mint: Pubkey,
+    #[clap(long)]
+    coin_lot_size: u64,
+    #[clap(long)]
+    pc_lot_size: u64,
+    /// Fee charged by the proxy, in basis points, if its config is created.
+    #[clap(long, default_value_t = 0)]
+    fee_bps: u16,
+    /// Defaults to the payer.
+    #[clap(long)]
+    fee_receiver: Option<Pubkey>,
+    /// Defaults to the payer.
+    #[clap(long)]
+    prune_authority: Option<Pubkey>,
+    /// Requires `ConsumeEventsPermissioned` if given.
+    #[clap(long)]
+    consume_events_authority: Option<Pubkey>,
+    #[clap(long = "referral")]
+    referrals: Vec<Pubkey>,
+    /// File the manifest is written to, rather than printed.
+    #[clap(long)]
+    manifest: Option<String>,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opts = Opts::parse();
+    let payer =
+        read_keypair_file(&opts.payer).map_err(|e| anyhow!("invalid payer keypair: {}", e))?;
+    let params = DeployParams {
+        listing: ListingParams::new(opts.coin_lot_size, opts.pc_lot_size),
+        fee_bps: opts.fee_bps,
+        fee_receiver: opts.fee_receiver.unwrap_or_else(|| payer.pubkey()),
+        prune_authority: opts.prune_authority,
+        consume_events_authority: opts.consume_events_authority,
+        referrals: opts.referrals,
+    };
+    let deployer = Deployer {
+        client: RpcClient::new(opts.url.clone()),
+        payer,
+        proxy_program_id: opts.proxy_program_id,
+        dex_program_id: opts.dex_program_id,
+    };
+    let (_, manifest) = deployer.deploy(&opts.coin_mint, &opts.pc_mint, &params)?;
+
+    let json = serde_json::to_string_pretty(&manifest.to_json())?;
+    match &opts.manifest {
+        Some(path) => fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use openbook_client::{ListingParams, MarketAuthorities, MarketListing};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Signer};
+
+/// Lists a market of the given mints, printing the addresses of its
+/// accounts. The market is permissioned when a proxy program is given, with
+/// the proxy's open orders init authority as its open orders authority.
+#[derive(Parser, Debug)]
+struct Opts {
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    #[clap(long)]
+    payer: String,
+    #[clap(long)]
+    dex_program_id: Pubkey,
+    #[clap(long)]
+    coin_mint: Pubkey,
+    #[clap(long)]
+    pc_mint: Pubkey,
+    #[clap(long)]
+    coin_lot_size: u64,
+    #[clap(long)]
+    pc_lot_size: u64,
+    #[clap(long)]
+    pc_dust_threshold: Option<u64>,
+    #[clap(long)]
+    request_queue_capacity: Option<usize>,
+    #[clap(long)]
+    event_queue_capacity: Option<usize>,
+    #[clap(long)]
+    book_capacity: Option<usize>,
+    #[clap(long)]
+    proxy_program_id: Option<Pubkey>,
+    /// Defaults to the payer.
+    #[clap(long, requires = "proxy_program_id")]
+    prune_authority: Option<Pubkey>,
+    /// Requires `ConsumeEventsPermissioned` if given.
+    #[clap(long, requires = "proxy_program_id")]
+    consume_events_authority: Option<Pubkey>,
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+    let client = RpcClient::new(opts.url.clone());
+    let payer =
+        read_keypair_file(&opts.payer).map_err(|e| anyhow!("invalid payer keypair: {}", e))?;
+
+    let mut params = ListingParams::new(opts.coin_lot_size, opts.pc_lot_size);
+    params.pc_dust_threshold = opts.pc_dust_threshold.unwrap_or(params.pc_dust_threshold);
+    params.request_queue_capacity = opts
+        .request_queue_capacity
+        .unwrap_or(params.request_queue_capacity);
+    params.event_queue_capacity = opts
+        .event_queue_capacity
+        .unwrap_or(params.event_queue_capacity);
+    params.book_capacity = opts.book_capacity.unwrap_or(params.book_capacity);
+
+    let listing = MarketListing::generate(&opts.dex_program_id);
+    let authorities = opts.proxy_program_id.map(|proxy_program_id| {
+        MarketAuthorities::proxy(
+            &proxy_program_id,
+            &opts.dex_program_id,
+            &listing.market.pubkey(),
+            opts.prune_authority.unwrap_or_else(|| payer.pubkey()),
+            opts.consume_events_authority,
+        )
+    });
+    let market = listing.list(
+        &client,
+        &opts.dex_program_id,
+        &payer,
+        &opts.coin_mint,
+        &opts.pc_mint,
+        &params,
+        authorities.as_ref(),
+    )?;
+
+    let header = &market.header;
+    println!("market: {}", header.own_address);
+    println!("request queue: {}", header.req_q);
+    println!("event queue: {}", header.event_q);
+    println!("bids: {}", header.bids);
+    println!("asks: {}", header.asks);
+    println!("coin vault: {}", header.coin_vault);
+    println!("pc vault: {}", header.pc_vault);
+    println!("vault signer: {}", market.vault_signer);
+    if let Some(authorities) = &authorities {
+        println!(
+            "open orders authority: {}",
+            authorities.open_orders_authority
+        );
+        println!("prune authority: {}", authorities.prune_authority);
+        if let Some(authority) = &authorities.consume_events_authority {
+            println!("consume events authority: {}", authority);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use openbook_client::serum_dex_permissioned::{
+    admin_config_instruction, middleware_config_hash, pad_symbol, set_market_metadata_instruction,
+    set_referral_instruction, ConfigInstruction, MarketMetadata, MarketMetadataArgs, ProxyConfig,
+    ReferralSet,
+};
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use std::fs;
+
+/// Administers the config of a `MarketProxy`, signed by its admin, or
+/// prints it as JSON.
+#[derive(Parser, Debug)]
+struct Opts {
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    #[clap(long)]
+    proxy_program_id: Pubkey,
+    /// Keypair of the config's admin, paying for the transactions.
+    #[clap(long)]
+    admin: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints the config, its referrals and, if given, the markets' metadata.
+    Show {
+        #[clap(long = "market")]
+        markets: Vec<Pubkey>,
+    },
+    /// Pauses the proxy. Proxies enforcing their config reject relayed
+    /// requests until unpaused, while config instructions, e.g., this CLI's,
+    /// still go through.
+    Pause,
+    /// Resumes relaying requests.
+    Unpause,
+    /// Sets the fee charged by the proxy, in basis points, and its receiver.
+    SetFee {
+        #[clap(long)]
+        fee_bps: Option<u16>,
+        #[clap(long)]
+        fee_receiver: Option<Pubkey>,
+    },
+    /// Hands the config over to a new admin, which can't be undone by the
+    /// current one.
+    TransferAdmin {
+        #[clap(long)]
+        new_admin: Pubkey,
+    },
+    /// Manages the whitelist of referrals, i.e., the proxy's `ReferralSet`.
+    Whitelist {
+        #[clap(subcommand)]
+        command: WhitelistCommand,
+    },
+    /// Sets the metadata of the given market, hashing the given middleware
+    /// config file if any.
+    SetMarketMetadata {
+        #[clap(long)]
+        market: Pubkey,
+        #[clap(long)]
+        symbol: String,
+        #[clap(long)]
+        base_symbol: String,
+        #[clap(long)]
+        quote_symbol: String,
+        #[clap(long)]
+        middleware_config: Option<String>,
+    },
+    RemoveMarketMetadata {
+        #[clap(long)]
+        market: Pubkey,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum WhitelistCommand {
+    /// Prints the whitelisted referrals.
+    List,
+    /// Whitelists the given referral.
+    Add {
+        #[clap(long)]
+        referral: Pubkey,
+    },
+    /// Removes the given referral from the whitelist.
+    Remove {
+        #[clap(long)]
+        referral: Pubkey,
+    },
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opts = Opts::parse();
+    let client = RpcClient::new(opts.url.clone());
+    let proxy = &opts.proxy_program_id;
+    if let Command::Show { markets } = &opts.command {
+        let json = serde_json::to_string_pretty(&show(&client, proxy, markets)?)?;
+        println!("{}", json);
+        return Ok(());
+    }
+    if let Command::Whitelist {
+        command: WhitelistCommand::List,
+    } = &opts.command
+    {
+        let referrals: Vec<_> = referrals(&client, proxy)?
+            .iter()
+            .map(Pubkey::to_string)
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&referrals)?);
+        return Ok(());
+    }
+
+    let admin = opts
+        .admin
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing admin keypair"))?;
+    let admin = read_keypair_file(admin).map_err(|e| anyhow!("invalid admin keypair: {}", e))?;
+    let update = |paused, fee_bps, fee_receiver| {
+        let ix = ConfigInstruction::UpdateConfig {
+            paused,
+            fee_bps,
+            fee_receiver,
+        };
+        admin_config_instruction(proxy, &admin.pubkey(), &ix)
+    };
+    let ix = match opts.command {
+        Command::Show { .. } => unreachable!(),
+        Command::Pause => update(Some(true), None, None),
+        Command::Unpause => update(Some(false), None, None),
+        Command::SetFee {
+            fee_bps,
+            fee_receiver,
+        } => {
+            if fee_bps.is_none() && fee_receiver.is_none() {
+                return Err(anyhow!("nothing to update"));
+            }
+            update(None, fee_bps, fee_receiver)
+        }
+        Command::TransferAdmin { new_admin } => {
+            let ix = ConfigInstruction::TransferAdmin { new_admin };
+            admin_config_instruction(proxy, &admin.pubkey(), &ix)
+        }
+        Command::Whitelist { command } => {
+            let (referral, allowed) = match command {
+                WhitelistCommand::List => unreachable!(),
+                WhitelistCommand::Add { referral } => (referral, true),
+                WhitelistCommand::Remove { referral } => (referral, false),
+            };
+            set_referral_instruction(proxy, &admin.pubkey(), &referral, allowed)
+        }
+        Command::SetMarketMetadata {
+            market,
+            symbol,
+            base_symbol,
+            quote_symbol,
+            middleware_config,
+        } => {
+            let middleware_hash = match &middleware_config {
+                Some(path) => middleware_config_hash(&fs::read(path)?),
+                None => [0; 32],
+            };
+            let metadata = MarketMetadataArgs {
+                symbol: padded_symbol(&symbol)?,
+                base_symbol: padded_symbol(&base_symbol)?,
+                quote_symbol: padded_symbol(&quote_symbol)?,
+                middleware_hash,
+            };
+            set_market_metadata_instruction(proxy, &admin.pubkey(), &market, Some(metadata))
+        }
+        Command::RemoveMarketMetadata { market } => {
+            set_market_metadata_instruction(proxy, &admin.pubkey(), &market, None)
+        }
+    };
+    let signature = send(&client, &admin, ix)?;
+    println!("{}", signature);
+    Ok(())
+}
+
+// Returns the proxy's config, referrals and the given markets' metadata.
+fn show(client: &RpcClient, proxy: &Pubkey, markets: &[Pubkey]) -> Result<Value> {
+    let (address, _) = ProxyConfig::address(proxy);
+    let config = ProxyConfig::unpack(&client.get_account_data(&address)?)
+        .map_err(|e| anyhow!("invalid config {}: {:?}", address, e))?;
+    let referrals = referrals(client, proxy)?;
+    let mut metadata = Vec::new();
+    for market in markets {
+        let (key, _) = MarketMetadata::address(proxy, market);
+        let account = client
+            .get_account_with_commitment(&key, client.commitment())?
+            .value;
+        let value = match account {
+            Some(account) => {
+                let data = MarketMetadata::unpack(&account.data)
+                    .map_err(|e| anyhow!("invalid metadata {}: {:?}", key, e))?;
+                json!({
+                    "market": market.to_string(),
+                    "symbol": data.symbol(),
+                    "baseSymbol": data.base_symbol(),
+                    "quoteSymbol": data.quote_symbol(),
+                    "middlewareHash": hex(&data.middleware_hash),
+                })
+            }
+            None => json!({ "market": market.to_string() }),
+        };
+        metadata.push(value);
+    }
+    Ok(json!({
+        "config": address.to_string(),
+        "admin": config.admin.to_string(),
+        "paused": config.paused,
+        "feeBps": config.fee_bps,
+        "feeReceiver": config.fee_receiver.to_string(),
+        "dexProgramId": config.dex_program_id.to_string(),
+        "referrals": referrals.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+        "markets": metadata,
+    }))
+}
+
+// Returns the referrals of the proxy's `ReferralSet`, if it exists.
+fn referrals(client: &RpcClient, proxy: &Pubkey) -> Result<Vec<Pubkey>> {
+    let (set, _) = ReferralSet::address(proxy);
+    match client
+        .get_account_with_commitment(&set, client.commitment())?
+        .value
+    {
+        Some(account) => Ok(ReferralSet::unpack(&account.data)
+            .map_err(|e| anyhow!("invalid referral set {}: {:?}", set, e))?
+            .referrals),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn padded_symbol<const N: usize>(symbol: &str) -> Result<[u8; N]> {
+    pad_symbol(symbol).ok_or_else(|| anyhow!("symbol {} is too long", symbol))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn send(client: &RpcClient, admin: &Keypair, ix: Instruction) -> Result<String> {
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&admin.pubkey()),
+        &[admin],
+        client.get_latest_blockhash()?,
+    );
+    Ok(client.send_and_confirm_transaction(&tx)?.to_string())
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use openbook_client::{
+    AccountReconciliation, FillStore, MarketInfo, NativeAmounts, Reconciler, ReconciliationReport,
+    SqliteFillStore,
+};
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::fs;
+
+/// Reconciles the open orders accounts of a market, from the fills indexed
+/// in a SQLite `FillStore` and the deposits and settlements of the market's
+/// transactions, with their totals and the vaults on chain, and prints the
+/// report as JSON. The range reconciled starts at the end of the given
+/// baseline, a previous report, or at `--from` with empty accounts, and
+/// ends now. Fails if any account or vault doesn't reconcile.
+#[derive(Parser, Debug)]
+struct Opts {
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    #[clap(long)]
+    dex_program_id: Pubkey,
+    #[clap(long)]
+    market: Pubkey,
+    /// The SQLite database of the fills.
+    #[clap(long)]
+    fills: String,
+    /// A previous report, whose totals on chain are those the accounts start
+    /// with.
+    #[clap(long, conflicts_with = "from")]
+    baseline: Option<String>,
+    /// Unix timestamp the range starts at, the creation of the market by
+    /// default.
+    #[clap(long, default_value_t = 0)]
+    from: i64,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opts = Opts::parse();
+    let client = RpcClient::new(opts.url.clone());
+    let (from, start) = match &opts.baseline {
+        Some(path) => baseline(&serde_json::from_slice(&fs::read(path)?)?)?,
+        None => (opts.from, HashMap::new()),
+    };
+    let market = MarketInfo::load(&client, &opts.dex_program_id, &opts.market)?;
+    let reconciler = Reconciler { client, market };
+    let fills = SqliteFillStore::open(&opts.fills)?.fills(&opts.market, from, i64::MAX)?;
+    let report = reconciler.report(from, &start, &fills)?;
+    println!("{}", serde_json::to_string_pretty(&report_json(&report))?);
+    if !report.is_reconciled() {
+        return Err(anyhow!("market {} doesn't reconcile", opts.market));
+    }
+    Ok(())
+}
+
+// Returns the end of the given report and the accounts' totals on chain then.
+fn baseline(report: &Value) -> Result<(i64, HashMap<Pubkey, NativeAmounts>)> {
+    let invalid = || anyhow!("invalid baseline");
+    let to = report["to"].as_i64().ok_or_else(invalid)?;
+    let accounts = report["accounts"].as_array().ok_or_else(invalid)?;
+    let mut start = HashMap::new();
+    for account in accounts {
+        let open_orders = account["openOrders"]
+            .as_str()
+            .ok_or_else(invalid)?
+            .parse()?;
+        let actual = &account["actual"];
+        let amounts = NativeAmounts {
+            coin: actual["coin"].as_i64().ok_or_else(invalid)? as i128,
+            pc: actual["pc"].as_i64().ok_or_else(invalid)? as i128,
+        };
+        start.insert(open_orders, amounts);
+    }
+    Ok((to, start))
+}
+
+fn report_json(report: &ReconciliationReport) -> Value {
+    let account = |account: &AccountReconciliation| {
+        json!({
+            "openOrders": account.open_orders.to_string(),
+            "start": amounts_json(account.start),
+            "fills": amounts_json(account.fills),
+            "transfers": amounts_json(account.transfers),
+            "expected": amounts_json(account.expected()),
+            "actual": amounts_json(account.actual),
+            "discrepancy": amounts_json(account.discrepancy()),
+        })
+    };
+    let unattributed: Vec<_> = report
+        .unattributed
+        .iter()
+        .map(|transfer| {
+            json!({
+                "signature": transfer.signature.to_string(),
+                "blockTime": transfer.block_time,
+                "amounts": amounts_json(transfer.amounts),
+            })
+        })
+        .collect();
+    let vaults = &report.vaults;
+    json!({
+        "market": report.market.to_string(),
+        "from": report.from,
+        "to": report.to,
+        "reconciled": report.is_reconciled(),
+        "accounts": report.accounts.iter().map(account).collect::<Vec<_>>(),
+        "unattributed": unattributed,
+        "vaults": {
+            "coinVault": vaults.coin_vault,
+            "pcVault": vaults.pc_vault,
+            "coinDepositsTotal": vaults.coin_deposits_total,
+            "pcDepositsTotal": vaults.pc_deposits_total,
+            "pcFeesAccrued": vaults.pc_fees_accrued,
+            "referrerRebatesAccrued": vaults.referrer_rebates_accrued,
+            "openOrdersTotal": amounts_json(vaults.open_orders_total),
+            "unconsumed": amounts_json(vaults.unconsumed()),
+            "discrepancy": amounts_json(vaults.discrepancy()),
+        },
+    })
+}
+
+// The balances of markets fit in an `i64` in practice.
+fn amounts_json(amounts: NativeAmounts) -> Value {
+    json!({ "coin": amounts.coin as i64, "pc": amounts.pc as i64 })
+}
@@ -0,0 +1,233 @@
+use crate::{IndexedFill, MarketInfo};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The trades of a market over an interval, in UI units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candle {
+    /// Unix time the interval starts at, in seconds.
+    pub start: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// The coin traded.
+    pub volume: f64,
+    /// The pc traded, before fees.
+    pub quote_volume: f64,
+    pub trades: u64,
+    // The sequence numbers of the trades opening and closing the candle.
+    first_seq_num: u64,
+    last_seq_num: u64,
+}
+
+/// Aggregates the fills of a market into candles of a fixed interval.
+/// Candles only depend on the fills aggregated, whatever their order: each
+/// trade is counted once, by its taker fill, and candles open and close with
+/// the trades of the lowest and highest sequence numbers.
+#[derive(Clone, Debug)]
+pub struct CandleAggregator {
+    interval: i64,
+    coin_scale: f64,
+    pc_scale: f64,
+    candles: BTreeMap<i64, Candle>,
+}
+
+impl CandleAggregator {
+    /// Aggregates candles of the given interval, of at least a second, in
+    /// the UI units of the given market.
+    pub fn new(market: &MarketInfo, interval: Duration) -> Self {
+        Self {
+            interval: (interval.as_secs() as i64).max(1),
+            coin_scale: 10f64.powi(market.coin_decimals as i32),
+            pc_scale: 10f64.powi(market.pc_decimals as i32),
+            candles: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the given fill to the candle of its interval, unless it's a
+    /// maker fill, or trades nothing.
+    pub fn push(&mut self, fill: &IndexedFill) {
+        if fill.maker || fill.native_coin_qty == 0 {
+            return;
+        }
+        let volume = fill.native_coin_qty as f64 / self.coin_scale;
+        let quote_volume = fill.native_pc_qty as f64 / self.pc_scale;
+        let price = quote_volume / volume;
+        let start = fill.timestamp.div_euclid(self.interval) * self.interval;
+        let candle = self.candles.entry(start).or_insert(Candle {
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0.0,
+            quote_volume: 0.0,
+            trades: 0,
+            first_seq_num: fill.seq_num,
+            last_seq_num: fill.seq_num,
+        });
+        candle.high = candle.high.max(price);
+        candle.low = candle.low.min(price);
+        candle.volume += volume;
+        candle.quote_volume += quote_volume;
+        candle.trades += 1;
+        if fill.seq_num < candle.first_seq_num {
+            candle.first_seq_num = fill.seq_num;
+            candle.open = price;
+        }
+        if fill.seq_num > candle.last_seq_num {
+            candle.last_seq_num = fill.seq_num;
+            candle.close = price;
+        }
+    }
+
+    /// Returns the candles of the intervals with trades, oldest first.
+    pub fn candles(&self) -> impl Iterator<Item = &Candle> {
+        self.candles.values()
+    }
+
+    /// Returns the candles starting in the given range of unix times, with
+    /// the intervals without trades filled with candles of the previous
+    /// close, e.g. for charts.
+    pub fn dense_candles(&self, from: i64, to: i64) -> Vec<Candle> {
+        let mut close = self
+            .candles
+            .range(..from)
+            .next_back()
+            .map(|(_, candle)| candle.close);
+        let mut candles = Vec::new();
+        let mut start = from.div_euclid(self.interval) * self.interval;
+        if start < from {
+            start += self.interval;
+        }
+        while start < to {
+            match self.candles.get(&start) {
+                Some(candle) => {
+                    close = Some(candle.close);
+                    candles.push(*candle);
+                }
+                None => candles.extend(close.map(|close| Candle {
+                    start,
+                    open: close,
+                    high: close,
+                    low: close,
+                    close,
+                    volume: 0.0,
+                    quote_volume: 0.0,
+                    trades: 0,
+                    first_seq_num: 0,
+                    last_seq_num: 0,
+                })),
+            }
+            start += self.interval;
+        }
+        candles
+    }
+}
+
+/// Aggregates the given fills into candles of the given interval.
+pub fn aggregate_candles(
+    market: &MarketInfo,
+    interval: Duration,
+    fills: &[IndexedFill],
+) -> Vec<Candle> {
+    let mut aggregator = CandleAggregator::new(market, interval);
+    for fill in fills {
+        aggregator.push(fill);
+    }
+    aggregator.candles().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex::matching::Side;
+    use serum_dex_permissioned::MarketHeader;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn fill(seq_num: u64, timestamp: i64, maker: bool, coin: u64, pc: u64) -> IndexedFill {
+        IndexedFill {
+            market: Pubkey::default(),
+            seq_num,
+            timestamp,
+            side: Side::Bid,
+            maker,
+            open_orders: Pubkey::default(),
+            wallet: None,
+            order_id: 0,
+            client_order_id: 0,
+            native_coin_qty: coin,
+            native_pc_qty: pc,
+            native_fee_or_rebate: 0,
+        }
+    }
+
+    #[test]
+    fn test_candles() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        let market = MarketInfo::new(&program_id, header, 3, 2).unwrap();
+
+        // Out of order, with the maker side of the second trade.
+        let fills = [
+            fill(2, 65, false, 1_000, 300),
+            fill(1, 61, true, 1_000, 200),
+            fill(1, 61, false, 1_000, 200),
+            fill(0, 59, false, 2_000, 200),
+        ];
+        let candles = aggregate_candles(&market, Duration::from_secs(60), &fills);
+        assert_eq!(candles.len(), 2);
+        assert_eq!(
+            (candles[0].start, candles[0].open, candles[0].volume),
+            (0, 1.0, 2.0)
+        );
+        let candle = candles[1];
+        assert_eq!(candle.start, 60);
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (2.0, 3.0, 2.0, 3.0)
+        );
+        assert_eq!(
+            (candle.volume, candle.quote_volume, candle.trades),
+            (2.0, 5.0, 2)
+        );
+
+        let mut aggregator = CandleAggregator::new(&market, Duration::from_secs(60));
+        for fill in fills.iter().rev() {
+            aggregator.push(fill);
+        }
+        assert_eq!(aggregator.candles().copied().collect::<Vec<_>>(), candles);
+        let dense = aggregator.dense_candles(1, 240);
+        assert_eq!(dense.len(), 3);
+        assert_eq!(
+            (dense[1].start, dense[1].open, dense[1].trades),
+            (120, 3.0, 0)
+        );
+    }
+}
@@ -0,0 +1,656 @@
+use crate::{
+    consume_events_compute_units, consume_events_ix, consume_events_permissioned_ix, MarketInfo,
+    PriorityFeeEstimator, ProxyRoute, MAX_COMPUTE_UNITS,
+};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serum_dex_permissioned::EventQueueReader;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::Message;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use std::convert::TryInto;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Event queues loaded per `get_multiple_accounts` request.
+const EVENT_QUEUES_PER_REQUEST: usize = 100;
+
+/// How a market's events are consumed.
+#[derive(Clone, Debug)]
+pub enum ConsumePath {
+    /// `ConsumeEvents`, crediting the crank's share of the fees to the given
+    /// wallets.
+    Permissionless {
+        coin_fee_wallet: Pubkey,
+        pc_fee_wallet: Pubkey,
+    },
+    /// `ConsumeEventsPermissioned`, signed by the market's consume events
+    /// authority, the `Crank`'s authority.
+    Permissioned,
+}
+
+/// A market watched by the `Crank`.
+#[derive(Clone, Debug)]
+pub struct CrankMarket {
+    pub market: MarketInfo,
+    pub path: ConsumePath,
+    /// The proxy the market's instructions are routed through, if any.
+    pub proxy: Option<ProxyRoute>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CrankConfig {
+    /// Open orders accounts passed to each `ConsumeEvents`.
+    pub max_open_orders: usize,
+    /// `ConsumeEvents` sent for a market at each poll.
+    pub max_batches: usize,
+    /// Events processed by each `ConsumeEvents`.
+    pub limit: u16,
+    /// Priority fee, in micro-lamports per compute unit.
+    pub compute_unit_price: Option<u64>,
+    /// Estimates the priority fee of each market from the recent fees paid
+    /// on its event queue, instead of `compute_unit_price`, which is paid
+    /// when the estimation fails.
+    pub priority_fee: Option<PriorityFeeEstimator>,
+    /// Compute units requested by each `ConsumeEvents`, by default estimated
+    /// from `max_open_orders` and `limit` (see
+    /// `consume_events_compute_units`).
+    pub compute_unit_limit: Option<u32>,
+    /// Compute units of the `ConsumeEvents` of several markets packed in one
+    /// transaction, along with the transaction size limit.
+    pub max_transaction_compute_units: u32,
+    /// How many queued events a second of waiting to be cranked is worth
+    /// when prioritizing markets (see `crank_priority`).
+    pub age_weight: u64,
+    pub poll_interval: Duration,
+}
+
+impl Default for CrankConfig {
+    fn default() -> Self {
+        Self {
+            max_open_orders: 10,
+            max_batches: 4,
+            limit: 32,
+            compute_unit_price: None,
+            priority_fee: None,
+            compute_unit_limit: None,
+            max_transaction_compute_units: MAX_COMPUTE_UNITS,
+            age_weight: 10,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Observes the `Crank`, e.g. to export metrics.
+pub trait CrankMetrics {
+    /// Called at each poll with the number of events queued on a market and
+    /// how long they've waited to be cranked.
+    fn on_queue(&mut self, _market: &Pubkey, _depth: usize, _age: Duration) {}
+
+    /// Called once a transaction consuming the events of the given markets
+    /// landed.
+    fn on_sent(&mut self, _markets: &[Pubkey], _signature: &Signature) {}
+
+    /// Called when a transaction consuming the events of the given markets
+    /// failed.
+    fn on_failed(&mut self, _markets: &[Pubkey], _error: &anyhow::Error) {}
+}
+
+impl CrankMetrics for () {}
+
+// A `ConsumeEvents` scheduled at a poll.
+#[derive(Clone, Debug)]
+struct ScheduledIx {
+    market: usize,
+    ix: Instruction,
+    units: u32,
+    price: Option<u64>,
+}
+
+/// Consumes the events of the configured markets, paid by `payer`.
+///
+/// At each poll, the markets with events are cranked by priority, the
+/// deepest queues and those waiting the longest first, one `ConsumeEvents`
+/// of each market in turn, packing those of several markets in a transaction
+/// within `max_transaction_compute_units`, so that one crank can serve a
+/// whole venue. A failed `ConsumeEvents` fails the others of its
+/// transaction, which are retried at the next poll.
+pub struct Crank {
+    pub client: RpcClient,
+    pub payer: Keypair,
+    /// The consume events authority of the permissioned markets.
+    pub authority: Option<Keypair>,
+    pub markets: Vec<CrankMarket>,
+    pub config: CrankConfig,
+}
+
+impl Crank {
+    /// Cranks the markets until an RPC error other than a failed transaction.
+    pub fn run(&self) -> Result<()> {
+        self.run_with_metrics(&mut ())
+    }
+
+    /// Cranks the markets as `run`, reporting to the given metrics.
+    pub fn run_with_metrics(&self, metrics: &mut impl CrankMetrics) -> Result<()> {
+        let mut waiting_since = vec![None; self.markets.len()];
+        loop {
+            let accounts = self.load_event_queues()?;
+            let now = Instant::now();
+            let mut pending = Vec::new();
+            for (i, (market, data)) in self.markets.iter().zip(&accounts).enumerate() {
+                let event_q = &market.market.header.event_q;
+                let queue = EventQueueReader::unpack(data)
+                    .map_err(|e| anyhow!("invalid event queue {}: {:?}", event_q, e))?;
+                if queue.is_empty() {
+                    waiting_since[i] = None;
+                    metrics.on_queue(market.market.address(), 0, Duration::ZERO);
+                    continue;
+                }
+                let age = now.duration_since(*waiting_since[i].get_or_insert(now));
+                metrics.on_queue(market.market.address(), queue.len(), age);
+                info!(
+                    "{} events on market {}",
+                    queue.len(),
+                    market.market.address()
+                );
+                let priority = crank_priority(queue.len(), age, self.config.age_weight);
+                pending.push((priority, i, queue));
+            }
+            // By priority, then in the configured order.
+            pending.sort_by_key(|(priority, i, _)| (std::cmp::Reverse(*priority), *i));
+
+            let mut scheduled = Vec::new();
+            for (_, i, queue) in &pending {
+                scheduled.push(self.schedule(*i, queue)?);
+            }
+            let txs = pack_transactions(
+                interleave(scheduled),
+                &self.payer.pubkey(),
+                self.config.max_transaction_compute_units,
+            );
+            for tx in txs {
+                let mut markets = Vec::new();
+                for ix in &tx {
+                    if !markets.contains(&ix.market) {
+                        markets.push(ix.market);
+                    }
+                }
+                let addresses: Vec<_> = markets
+                    .iter()
+                    .map(|i| *self.markets[*i].market.address())
+                    .collect();
+                match self.send(&tx) {
+                    Ok(signature) => {
+                        info!("consumed events: {}", signature);
+                        metrics.on_sent(&addresses, &signature);
+                        for i in markets {
+                            waiting_since[i] = None;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("consume events on {:?} failed: {}", addresses, e);
+                        metrics.on_failed(&addresses, &e);
+                    }
+                }
+            }
+            thread::sleep(self.config.poll_interval);
+        }
+    }
+
+    /// Returns the `ConsumeEvents` processing the events of the given queue,
+    /// to be sent in order.
+    pub fn consume_events_ixs(
+        &self,
+        market: &CrankMarket,
+        queue: &EventQueueReader,
+    ) -> Result<Vec<Instruction>> {
+        let owners = queue
+            .events()
+            .filter_map(|event| event.ok())
+            .map(|event| *event.owner());
+        let batches =
+            open_orders_batches(owners, self.config.max_open_orders, self.config.max_batches);
+        let limit = self.config.limit;
+        batches
+            .iter()
+            .map(|open_orders| {
+                let ix = match &market.path {
+                    ConsumePath::Permissionless {
+                        coin_fee_wallet,
+                        pc_fee_wallet,
+                    } => consume_events_ix(
+                        &market.market,
+                        open_orders,
+                        coin_fee_wallet,
+                        pc_fee_wallet,
+                        limit,
+                    )?,
+                    ConsumePath::Permissioned => {
+                        let authority = self
+                            .authority
+                            .as_ref()
+                            .ok_or_else(|| anyhow!("missing consume events authority"))?;
+                        consume_events_permissioned_ix(
+                            &market.market,
+                            open_orders,
+                            &authority.pubkey(),
+                            limit,
+                        )?
+                    }
+                };
+                Ok(match &market.proxy {
+                    Some(proxy) => proxy.route(ix),
+                    None => ix,
+                })
+            })
+            .collect()
+    }
+
+    // Loads the event queues of the markets, in order.
+    fn load_event_queues(&self) -> Result<Vec<Vec<u8>>> {
+        let mut queues = Vec::with_capacity(self.markets.len());
+        for markets in self.markets.chunks(EVENT_QUEUES_PER_REQUEST) {
+            let keys: Vec<_> = markets
+                .iter()
+                .map(|market| market.market.header.event_q)
+                .collect();
+            let accounts = self.client.get_multiple_accounts(&keys)?;
+            for (key, account) in keys.iter().zip(accounts) {
+                let account = account.ok_or_else(|| anyhow!("missing event queue {}", key))?;
+                queues.push(account.data);
+            }
+        }
+        Ok(queues)
+    }
+
+    // Returns the `ConsumeEvents` of the given market, with their compute
+    // units and priority fee.
+    fn schedule(&self, i: usize, queue: &EventQueueReader) -> Result<Vec<ScheduledIx>> {
+        let market = &self.markets[i];
+        let price = self.compute_unit_price(&market.market.header.event_q);
+        let units = self.config.compute_unit_limit.unwrap_or_else(|| {
+            consume_events_compute_units(
+                self.config.max_open_orders,
+                self.config.limit,
+                market.proxy.is_some(),
+            )
+        });
+        Ok(self
+            .consume_events_ixs(market, queue)?
+            .into_iter()
+            .map(|ix| ScheduledIx {
+                market: i,
+                ix,
+                units,
+                price,
+            })
+            .collect())
+    }
+
+    // Returns the priority fee of the `ConsumeEvents` of the given queue.
+    fn compute_unit_price(&self, event_q: &Pubkey) -> Option<u64> {
+        let estimator = match &self.config.priority_fee {
+            Some(estimator) => estimator,
+            None => return self.config.compute_unit_price,
+        };
+        match estimator.estimate(&self.client, &[*event_q]) {
+            Ok(price) => Some(price),
+            Err(e) => {
+                warn!("priority fee estimation failed: {}", e);
+                self.config.compute_unit_price
+            }
+        }
+    }
+
+    fn send(&self, tx: &[ScheduledIx]) -> Result<Signature> {
+        let ixs = transaction_ixs(tx);
+        let mut signers: Vec<&dyn Signer> = vec![&self.payer];
+        if let Some(authority) = &self.authority {
+            // Only `ConsumeEventsPermissioned` needs it.
+            let signs = |ix: &Instruction| {
+                ix.accounts
+                    .iter()
+                    .any(|meta| meta.is_signer && meta.pubkey == authority.pubkey())
+            };
+            if ixs.iter().any(signs) {
+                signers.push(authority);
+            }
+        }
+        let tx = Transaction::new_signed_with_payer(
+            &ixs,
+            Some(&self.payer.pubkey()),
+            &signers,
+            self.client.get_latest_blockhash()?,
+        );
+        Ok(self.client.send_and_confirm_transaction(&tx)?)
+    }
+}
+
+/// The priority of cranking a market of `depth` queued events, which have
+/// waited `age` since its last crank: its depth, plus `age_weight` events per
+/// second of waiting, so that shallow queues aren't starved by deep ones.
+pub fn crank_priority(depth: usize, age: Duration, age_weight: u64) -> u64 {
+    (depth as u64).saturating_add(age.as_secs().saturating_mul(age_weight))
+}
+
+// Takes the first `ConsumeEvents` of each market in turn, then the second,
+// and so on, keeping each market's in order.
+fn interleave(markets: Vec<Vec<ScheduledIx>>) -> Vec<ScheduledIx> {
+    let mut markets: Vec<_> = markets.into_iter().map(Vec::into_iter).collect();
+    let mut ixs = Vec::new();
+    loop {
+        let len = ixs.len();
+        ixs.extend(markets.iter_mut().filter_map(Iterator::next));
+        if ixs.len() == len {
+            return ixs;
+        }
+    }
+}
+
+// Packs the given `ConsumeEvents`, in order, into as few transactions paid
+// by `payer` as fit the compute and size limits.
+fn pack_transactions(
+    ixs: Vec<ScheduledIx>,
+    payer: &Pubkey,
+    max_units: u32,
+) -> Vec<Vec<ScheduledIx>> {
+    let fits = |tx: &[ScheduledIx]| {
+        let units = tx
+            .iter()
+            .fold(0u32, |units, ix| units.saturating_add(ix.units));
+        let message = Message::new(&transaction_ixs(tx), Some(payer));
+        let signatures = message.header.num_required_signatures as usize;
+        units <= max_units && 1 + signatures * 64 + message.serialize().len() <= PACKET_DATA_SIZE
+    };
+    let mut txs = Vec::new();
+    let mut tx = Vec::new();
+    for ix in ixs {
+        tx.push(ix);
+        if tx.len() > 1 && !fits(&tx) {
+            let ix = tx.pop().unwrap();
+            txs.push(std::mem::replace(&mut tx, vec![ix]));
+        }
+    }
+    if !tx.is_empty() {
+        txs.push(tx);
+    }
+    txs
+}
+
+// Returns the instructions of a transaction of the given `ConsumeEvents`,
+// requesting their compute units at the highest of their priority fees.
+fn transaction_ixs(tx: &[ScheduledIx]) -> Vec<Instruction> {
+    let units = tx
+        .iter()
+        .fold(0u32, |units, ix| units.saturating_add(ix.units));
+    let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(units)];
+    if let Some(price) = tx.iter().filter_map(|ix| ix.price).max() {
+        ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+    }
+    ixs.extend(tx.iter().map(|ix| ix.ix.clone()));
+    ixs
+}
+
+/// Splits the owners of the queued events, in queue order, into batches of
+/// up to `max_open_orders` distinct open orders accounts, each covering the
+/// events following the previous one's, sorted as the DEX expects them.
+pub fn open_orders_batches(
+    owners: impl Iterator<Item = Pubkey>,
+    max_open_orders: usize,
+    max_batches: usize,
+) -> Vec<Vec<Pubkey>> {
+    let mut batches: Vec<Vec<Pubkey>> = Vec::new();
+    let mut batch = Vec::new();
+    for owner in owners {
+        if batch.contains(&owner) {
+            continue;
+        }
+        if batch.len() == max_open_orders {
+            batches.push(std::mem::take(&mut batch));
+            if batches.len() == max_batches {
+                break;
+            }
+        }
+        batch.push(owner);
+    }
+    if !batch.is_empty() && batches.len() < max_batches {
+        batches.push(batch);
+    }
+    for batch in &mut batches {
+        // The DEX looks the owners up by their key as little endian words.
+        batch.sort_unstable_by_key(key_words);
+    }
+    batches
+}
+
+fn key_words(key: &Pubkey) -> [u64; 4] {
+    let mut words = [0; 4];
+    for (word, bytes) in words.iter_mut().zip(key.as_ref().chunks(8)) {
+        *word = u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use serum_dex::state::{AccountFlag, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
+    use serum_dex_permissioned::MarketHeader;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::instruction::AccountMeta;
+
+    fn market() -> MarketInfo {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 10,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        MarketInfo::new(&program_id, header, 0, 0).unwrap()
+    }
+
+    // Returns the data of a queue of `count` fills, of distinct owners.
+    fn queue_data(count: u64) -> Vec<u8> {
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::EventQueue as u64;
+        for word in [flags, 0, count, count].iter() {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for owner in 0..4u8 {
+            let mut event = vec![0; 88];
+            event[0] = 0x1;
+            event[48..80].copy_from_slice(&[owner + 1; 32]);
+            data.extend_from_slice(&event);
+        }
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        data
+    }
+
+    #[derive(Default)]
+    struct Metrics {
+        queues: Vec<(Pubkey, usize)>,
+        failed: Vec<Vec<Pubkey>>,
+    }
+
+    impl CrankMetrics for Metrics {
+        fn on_queue(&mut self, market: &Pubkey, depth: usize, _age: Duration) {
+            self.queues.push((*market, depth));
+        }
+
+        fn on_failed(&mut self, markets: &[Pubkey], _error: &anyhow::Error) {
+            self.failed.push(markets.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_open_orders_batches() {
+        let keys: Vec<_> = (0..4u8).map(|i| Pubkey::new_from_array([i; 32])).collect();
+        let owners = [0, 1, 0, 2, 1, 3, 0].iter().map(|i| keys[*i]);
+
+        let batches = open_orders_batches(owners.clone(), 2, usize::MAX);
+        assert_eq!(
+            batches,
+            vec![
+                vec![keys[0], keys[1]],
+                vec![keys[1], keys[2]],
+                vec![keys[0], keys[3]],
+            ]
+        );
+        assert_eq!(open_orders_batches(owners.clone(), 2, 1).len(), 1);
+        assert_eq!(open_orders_batches(owners, 4, 1), vec![keys.clone()]);
+
+        // Keys are compared by their words, rather than their bytes.
+        let mut low = [0; 32];
+        low[0] = 1;
+        let mut high = [0; 32];
+        high[1] = 1;
+        let (low, high) = (Pubkey::new_from_array(low), Pubkey::new_from_array(high));
+        let batches = open_orders_batches(vec![high, low].into_iter(), 2, 1);
+        assert_eq!(batches, vec![vec![low, high]]);
+    }
+
+    #[test]
+    fn test_schedule() {
+        assert_eq!(crank_priority(5, Duration::from_millis(2_500), 10), 25);
+        assert_eq!(
+            crank_priority(5, Duration::from_secs(u64::MAX), 10),
+            u64::MAX
+        );
+
+        let program_id = Pubkey::new_unique();
+        let scheduled = |market, accounts: usize| ScheduledIx {
+            market,
+            ix: Instruction::new_with_bytes(
+                program_id,
+                &[0],
+                (0..accounts)
+                    .map(|_| AccountMeta::new(Pubkey::new_unique(), false))
+                    .collect(),
+            ),
+            units: 300_000,
+            price: Some(market as u64),
+        };
+        let ixs = interleave(vec![
+            vec![scheduled(0, 1), scheduled(0, 1), scheduled(0, 1)],
+            vec![scheduled(1, 1)],
+            vec![scheduled(2, 1), scheduled(2, 1)],
+        ]);
+        let markets: Vec<_> = ixs.iter().map(|ix| ix.market).collect();
+        assert_eq!(markets, vec![0, 1, 2, 0, 2, 0]);
+
+        // Four `ConsumeEvents` fit in the compute units of a transaction.
+        let payer = Pubkey::new_unique();
+        let txs = pack_transactions(ixs, &payer, MAX_COMPUTE_UNITS);
+        let lens: Vec<_> = txs.iter().map(Vec::len).collect();
+        assert_eq!(lens, vec![4, 2]);
+        let tx = transaction_ixs(&txs[0]);
+        assert_eq!(
+            tx[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(1_200_000)
+        );
+        assert_eq!(tx[1], ComputeBudgetInstruction::set_compute_unit_price(2));
+
+        // Only two of 14 accounts each fit in its size.
+        let ixs = (0..3).map(|market| scheduled(market, 14)).collect();
+        let lens: Vec<_> = pack_transactions(ixs, &payer, MAX_COMPUTE_UNITS)
+            .iter()
+            .map(Vec::len)
+            .collect();
+        assert_eq!(lens, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_crank_failures() {
+        let market = market();
+        let crank = |client, path| Crank {
+            client,
+            payer: Keypair::new(),
+            authority: None,
+            markets: vec![CrankMarket {
+                market: market.clone(),
+                path,
+                proxy: None,
+            }],
+            config: CrankConfig {
+                poll_interval: Duration::ZERO,
+                ..CrankConfig::default()
+            },
+        };
+        let permissionless = ConsumePath::Permissionless {
+            coin_fee_wallet: Pubkey::new_unique(),
+            pc_fee_wallet: Pubkey::new_unique(),
+        };
+
+        // RPC errors and missing event queues stop the crank.
+        let client = RpcClient::new_mock("fails".to_string());
+        assert!(crank(client, permissionless.clone()).run().is_err());
+        let client = RpcClient::new_mock("succeeds".to_string());
+        let err = crank(client, permissionless.clone()).run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("missing event queue {}", market.header.event_q)
+        );
+
+        // The failed transaction is reported, then the queue is found empty
+        // at the next poll, and the RPC node fails at the third.
+        let queue = |count| {
+            let data = queue_data(count);
+            let response = mock::multiple_accounts(&[Some((&market.program_id, &data))]);
+            (RpcRequest::GetMultipleAccounts, response)
+        };
+        let mocks = vec![queue(2), queue(0)].into_iter().collect();
+        let client = RpcClient::new_mock_with_mocks_map("fails".to_string(), mocks);
+        let mut metrics = Metrics::default();
+        assert!(crank(client, permissionless)
+            .run_with_metrics(&mut metrics)
+            .is_err());
+        let address = *market.address();
+        assert_eq!(metrics.queues, vec![(address, 2), (address, 0)]);
+        assert_eq!(metrics.failed, vec![vec![address]]);
+
+        // Permissioned markets are cranked with the authority only.
+        let crank = crank(
+            RpcClient::new_mock("fails".to_string()),
+            ConsumePath::Permissioned,
+        );
+        let data = queue_data(2);
+        let queue = EventQueueReader::unpack(&data).unwrap();
+        let err = crank
+            .consume_events_ixs(&crank.markets[0], &queue)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "missing consume events authority");
+        let data = queue_data(0);
+        let queue = EventQueueReader::unpack(&data).unwrap();
+        assert!(crank
+            .consume_events_ixs(&crank.markets[0], &queue)
+            .unwrap()
+            .is_empty());
+    }
+}
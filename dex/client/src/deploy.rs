@@ -0,0 +1,257 @@
+use crate::{
+    associated_token_address, create_associated_token_account_ix, ListingParams, MarketAuthorities,
+    MarketInfo, MarketListing,
+};
+use anyhow::Result;
+use log::info;
+use serde_json::{json, Value};
+use serum_dex_permissioned::{
+    cache_market_bumps_instruction, initialize_config_instruction, referral_treasury_address,
+    set_referral_instruction, MarketBumps, ProxyConfig, ReferralSet,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+
+/// Parameters of a permissioned market to deploy behind a `MarketProxy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeployParams {
+    pub listing: ListingParams,
+    /// Fee charged by the proxy, in basis points, if its config is created.
+    pub fee_bps: u16,
+    /// Receiver of the proxy's fees, if its config is created.
+    pub fee_receiver: Pubkey,
+    /// Defaults to the payer.
+    pub prune_authority: Option<Pubkey>,
+    /// Requires `ConsumeEventsPermissioned` if set.
+    pub consume_events_authority: Option<Pubkey>,
+    /// Referrals added to the proxy's `ReferralSet`.
+    pub referrals: Vec<Pubkey>,
+}
+
+/// The addresses of a deployed market and of the proxy accounts serving it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeployManifest {
+    pub proxy_program_id: Pubkey,
+    pub dex_program_id: Pubkey,
+    pub config: Pubkey,
+    pub referral_set: Pubkey,
+    pub market_bumps: Pubkey,
+    /// The PDA owning the referral treasury.
+    pub referral_treasury: Pubkey,
+    /// The referral treasury's pc token account.
+    pub referral_treasury_pc: Pubkey,
+    pub market: Pubkey,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub req_q: Pubkey,
+    pub event_q: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub vault_signer: Pubkey,
+    pub authorities: MarketAuthorities,
+}
+
+impl DeployManifest {
+    /// Returns the addresses of the given listing behind the given proxy.
+    pub fn new(
+        proxy_program_id: &Pubkey,
+        dex_program_id: &Pubkey,
+        listing: &MarketListing,
+        coin_mint: &Pubkey,
+        pc_mint: &Pubkey,
+        authorities: MarketAuthorities,
+    ) -> Self {
+        let market = listing.market.pubkey();
+        let (referral_treasury, _) = referral_treasury_address(proxy_program_id);
+        Self {
+            proxy_program_id: *proxy_program_id,
+            dex_program_id: *dex_program_id,
+            config: ProxyConfig::address(proxy_program_id).0,
+            referral_set: ReferralSet::address(proxy_program_id).0,
+            market_bumps: MarketBumps::address(proxy_program_id, dex_program_id, &market).0,
+            referral_treasury,
+            referral_treasury_pc: associated_token_address(&referral_treasury, pc_mint),
+            market,
+            coin_mint: *coin_mint,
+            pc_mint: *pc_mint,
+            req_q: listing.req_q.pubkey(),
+            event_q: listing.event_q.pubkey(),
+            bids: listing.bids.pubkey(),
+            asks: listing.asks.pubkey(),
+            coin_vault: listing.coin_vault.pubkey(),
+            pc_vault: listing.pc_vault.pubkey(),
+            vault_signer: listing.vault_signer,
+            authorities,
+        }
+    }
+
+    /// Returns the manifest as JSON, with base58 addresses, for frontends.
+    pub fn to_json(&self) -> Value {
+        let key = |key: &Pubkey| key.to_string();
+        json!({
+            "proxyProgramId": key(&self.proxy_program_id),
+            "dexProgramId": key(&self.dex_program_id),
+            "config": key(&self.config),
+            "referralSet": key(&self.referral_set),
+            "marketBumps": key(&self.market_bumps),
+            "referralTreasury": key(&self.referral_treasury),
+            "referralTreasuryPc": key(&self.referral_treasury_pc),
+            "market": key(&self.market),
+            "coinMint": key(&self.coin_mint),
+            "pcMint": key(&self.pc_mint),
+            "requestQueue": key(&self.req_q),
+            "eventQueue": key(&self.event_q),
+            "bids": key(&self.bids),
+            "asks": key(&self.asks),
+            "coinVault": key(&self.coin_vault),
+            "pcVault": key(&self.pc_vault),
+            "vaultSigner": key(&self.vault_signer),
+            "openOrdersAuthority": key(&self.authorities.open_orders_authority),
+            "pruneAuthority": key(&self.authorities.prune_authority),
+            "consumeEventsAuthority": self.authorities.consume_events_authority.as_ref().map(key),
+        })
+    }
+}
+
+/// Deploys permissioned markets behind a `MarketProxy`, paid for by `payer`,
+/// who administers the proxy's config if it creates it, which only the
+/// proxy's upgrade authority can.
+pub struct Deployer {
+    pub client: RpcClient,
+    pub payer: Keypair,
+    pub proxy_program_id: Pubkey,
+    pub dex_program_id: Pubkey,
+}
+
+impl Deployer {
+    /// Deploys a market of the given mints: creates the proxy's config
+    /// unless it exists, lists the market with the proxy's authorities,
+    /// caches its bumps, adds the referrals and creates the referral
+    /// treasury's pc account.
+    pub fn deploy(
+        &self,
+        coin_mint: &Pubkey,
+        pc_mint: &Pubkey,
+        params: &DeployParams,
+    ) -> Result<(MarketInfo, DeployManifest)> {
+        let payer = self.payer.pubkey();
+        let (config, _) = ProxyConfig::address(&self.proxy_program_id);
+        let commitment = self.client.commitment();
+        if self
+            .client
+            .get_account_with_commitment(&config, commitment)?
+            .value
+            .is_none()
+        {
+            let ix = initialize_config_instruction(
+                &self.proxy_program_id,
+                &payer,
+                params.fee_bps,
+                &params.fee_receiver,
+                &self.dex_program_id,
+            );
+            self.send(&[ix])?;
+            info!("initialized proxy config {}", config);
+        }
+
+        let listing = MarketListing::generate(&self.dex_program_id);
+        let authorities = MarketAuthorities::proxy(
+            &self.proxy_program_id,
+            &self.dex_program_id,
+            &listing.market.pubkey(),
+            params.prune_authority.unwrap_or(payer),
+            params.consume_events_authority,
+        );
+        let market = listing.list(
+            &self.client,
+            &self.dex_program_id,
+            &self.payer,
+            coin_mint,
+            pc_mint,
+            &params.listing,
+            Some(&authorities),
+        )?;
+        info!("listed market {}", market.address());
+
+        let manifest = DeployManifest::new(
+            &self.proxy_program_id,
+            &self.dex_program_id,
+            &listing,
+            coin_mint,
+            pc_mint,
+            authorities,
+        );
+        let mut ixs = vec![cache_market_bumps_instruction(
+            &self.proxy_program_id,
+            &payer,
+            &self.dex_program_id,
+            &manifest.market,
+        )];
+        ixs.extend(params.referrals.iter().map(|referral| {
+            set_referral_instruction(&self.proxy_program_id, &payer, referral, true)
+        }));
+        ixs.push(create_associated_token_account_ix(
+            &payer,
+            &manifest.referral_treasury,
+            pc_mint,
+        ));
+        self.send(&ixs)?;
+        Ok((market, manifest))
+    }
+
+    fn send(&self, ixs: &[Instruction]) -> Result<()> {
+        let tx = Transaction::new_signed_with_payer(
+            ixs,
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            self.client.get_latest_blockhash()?,
+        );
+        self.client.send_and_confirm_transaction(&tx)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let (proxy_program_id, dex_program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (coin_mint, pc_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let listing = MarketListing::generate(&dex_program_id);
+        let authorities = MarketAuthorities::proxy(
+            &proxy_program_id,
+            &dex_program_id,
+            &listing.market.pubkey(),
+            Pubkey::new_unique(),
+            None,
+        );
+        let manifest = DeployManifest::new(
+            &proxy_program_id,
+            &dex_program_id,
+            &listing,
+            &coin_mint,
+            &pc_mint,
+            authorities,
+        );
+        assert_eq!(manifest.config, ProxyConfig::address(&proxy_program_id).0);
+        assert_eq!(
+            manifest.referral_treasury_pc,
+            associated_token_address(&manifest.referral_treasury, &pc_mint)
+        );
+
+        let json = manifest.to_json();
+        assert_eq!(json["market"], listing.market.pubkey().to_string());
+        assert_eq!(
+            json["openOrdersAuthority"],
+            authorities.open_orders_authority.to_string()
+        );
+        assert!(json["consumeEventsAuthority"].is_null());
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{Fill, MarketInfo};
+use anyhow::{anyhow, Result};
+use futures::stream::{Stream, StreamExt};
+use log::{info, warn};
+use serum_dex::matching::Side;
+use serum_dex::state::ACCOUNT_HEAD_PADDING;
+use serum_dex_permissioned::{OpenOrdersRegistry, QueueEvent};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A fill of a market, as persisted by a `FillStore`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedFill {
+    pub market: Pubkey,
+    pub seq_num: u64,
+    /// Unix time the fill was indexed at, in seconds.
+    pub timestamp: i64,
+    pub side: Side,
+    pub maker: bool,
+    pub open_orders: Pubkey,
+    /// The wallet owning the open orders account, if resolved.
+    pub wallet: Option<Pubkey>,
+    pub order_id: u128,
+    pub client_order_id: u64,
+    /// Before the fee or rebate, see `Fill::native_pc_qty`.
+    pub native_coin_qty: u64,
+    pub native_pc_qty: u64,
+    pub native_fee_or_rebate: u64,
+}
+
+impl IndexedFill {
+    /// Returns the given fill of the given market, owned by `wallet`.
+    pub fn new(market: &Pubkey, fill: &Fill, wallet: Option<Pubkey>, timestamp: i64) -> Self {
+        let (maker, native_fee_or_rebate) = match fill.event {
+            QueueEvent::Fill {
+                maker,
+                native_fee_or_rebate,
+                ..
+            } => (maker, native_fee_or_rebate),
+            QueueEvent::Out { .. } => (false, 0),
+        };
+        Self {
+            market: *market,
+            seq_num: fill.seq_num,
+            timestamp,
+            side: fill.event.side(),
+            maker,
+            open_orders: *fill.event.owner(),
+            wallet,
+            order_id: fill.event.order_id(),
+            client_order_id: fill.event.client_order_id(),
+            native_coin_qty: fill.native_coin_qty(),
+            native_pc_qty: fill.native_pc_qty(),
+            native_fee_or_rebate,
+        }
+    }
+}
+
+/// Persists the fills of the `FillIndexer`.
+pub trait FillStore {
+    /// Returns the sequence number following the last fill of the given
+    /// market stored, if any.
+    fn next_seq_num(&mut self, market: &Pubkey) -> Result<Option<u64>>;
+
+    /// Stores the given fills, ignoring those already stored.
+    fn insert(&mut self, fills: &[IndexedFill]) -> Result<()>;
+
+    /// Returns the fills of the given market indexed between the given unix
+    /// timestamps, inclusive, by sequence number.
+    fn fills(&mut self, market: &Pubkey, from: i64, to: i64) -> Result<Vec<IndexedFill>>;
+}
+
+/// Stores fills in memory, e.g. for tests or short-lived services.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFillStore {
+    pub fills: Vec<IndexedFill>,
+}
+
+impl FillStore for MemoryFillStore {
+    fn next_seq_num(&mut self, market: &Pubkey) -> Result<Option<u64>> {
+        Ok(self
+            .fills
+            .iter()
+            .filter(|fill| &fill.market == market)
+            .map(|fill| fill.seq_num + 1)
+            .max())
+    }
+
+    fn insert(&mut self, fills: &[IndexedFill]) -> Result<()> {
+        for fill in fills {
+            let stored = self
+                .fills
+                .iter()
+                .any(|f| f.market == fill.market && f.seq_num == fill.seq_num);
+            if !stored {
+                self.fills.push(fill.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn fills(&mut self, market: &Pubkey, from: i64, to: i64) -> Result<Vec<IndexedFill>> {
+        let mut fills: Vec<_> = self
+            .fills
+            .iter()
+            .filter(|fill| &fill.market == market && (from..=to).contains(&fill.timestamp))
+            .cloned()
+            .collect();
+        fills.sort_by_key(|fill| fill.seq_num);
+        Ok(fills)
+    }
+}
+
+/// Resolves the wallets owning the open orders accounts of a market: those
+/// listed in the `OpenOrdersRegistry`s of a proxy, and otherwise the owner
+/// the account records.
+pub struct WalletResolver {
+    pub proxy_program_id: Option<Pubkey>,
+    // Open orders PDAs missing from the registries resolve to `None`.
+    wallets: HashMap<Pubkey, Option<Pubkey>>,
+}
+
+impl WalletResolver {
+    pub fn new(proxy_program_id: Option<Pubkey>) -> Self {
+        Self {
+            proxy_program_id,
+            wallets: HashMap::new(),
+        }
+    }
+
+    /// Records the open orders accounts of the given registry.
+    pub fn insert_registry(&mut self, registry: &OpenOrdersRegistry) {
+        for open_orders in &registry.open_orders {
+            self.wallets.insert(*open_orders, Some(registry.owner));
+        }
+    }
+
+    /// Returns the wallet owning the given open orders account, if known.
+    pub fn get(&self, open_orders: &Pubkey) -> Option<&Pubkey> {
+        self.wallets.get(open_orders).and_then(Option::as_ref)
+    }
+
+    /// Returns the wallet owning the given open orders account, loading the
+    /// proxy's registries of the market, then the account, if unknown.
+    pub async fn resolve(
+        &mut self,
+        client: &RpcClient,
+        market: &Pubkey,
+        open_orders: &Pubkey,
+    ) -> Result<Option<Pubkey>> {
+        if !self.wallets.contains_key(open_orders) {
+            self.load_registries(client, market).await?;
+        }
+        if !self.wallets.contains_key(open_orders) {
+            let account = client.get_account(open_orders).await?;
+            // The account flags and market precede the owner.
+            let offset = ACCOUNT_HEAD_PADDING.len() + 40;
+            let owner = account
+                .data
+                .get(offset..offset + 32)
+                .map(|owner| Pubkey::new_from_array(owner.try_into().unwrap()))
+                .ok_or_else(|| anyhow!("invalid open orders {}", open_orders))?;
+            // Open orders PDAs own themselves, and aren't owned by a wallet.
+            let wallet = Some(owner).filter(|owner| owner != open_orders);
+            self.wallets.insert(*open_orders, wallet);
+        }
+        Ok(self.get(open_orders).copied())
+    }
+
+    /// Loads the proxy's registries of the given market.
+    pub async fn load_registries(&mut self, client: &RpcClient, market: &Pubkey) -> Result<()> {
+        let proxy_program_id = match &self.proxy_program_id {
+            Some(proxy_program_id) => proxy_program_id,
+            None => return Ok(()),
+        };
+        let filters = vec![
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                0,
+                OpenOrdersRegistry::DISCRIMINATOR.to_vec(),
+            )),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(8, market.to_bytes().to_vec())),
+        ];
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = client
+            .get_program_accounts_with_config(proxy_program_id, config)
+            .await?;
+        let registries = accounts
+            .iter()
+            .filter_map(|(_, account)| OpenOrdersRegistry::unpack(&account.data).ok());
+        for registry in registries {
+            self.insert_registry(&registry);
+        }
+        Ok(())
+    }
+}
+
+/// Indexes the fills of a market into a `FillStore`, along with the wallets
+/// owning the open orders accounts filled.
+pub struct FillIndexer<S> {
+    pub market: MarketInfo,
+    pub store: S,
+    pub resolver: WalletResolver,
+}
+
+impl<S: FillStore> FillIndexer<S> {
+    pub fn new(market: MarketInfo, store: S, proxy_program_id: Option<Pubkey>) -> Self {
+        Self {
+            market,
+            store,
+            resolver: WalletResolver::new(proxy_program_id),
+        }
+    }
+
+    /// The sequence number of the next fill to index, to resume the fill
+    /// stream from (see `FillTracker::new`).
+    pub fn next_seq_num(&mut self) -> Result<Option<u64>> {
+        self.store.next_seq_num(self.market.address())
+    }
+
+    /// Indexes the given fills, resolving their wallets over RPC.
+    pub async fn index(&mut self, client: &RpcClient, fills: &[Fill]) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let market = *self.market.address();
+        let mut indexed = Vec::with_capacity(fills.len());
+        for fill in fills {
+            let wallet = self
+                .resolver
+                .resolve(client, &market, fill.event.owner())
+                .await?;
+            indexed.push(IndexedFill::new(&market, fill, wallet, timestamp));
+        }
+        self.store.insert(&indexed)
+    }
+
+    /// Indexes the fills of the given stream until it ends, or an RPC or
+    /// storage error, skipping those that don't decode.
+    pub async fn run(
+        &mut self,
+        client: &RpcClient,
+        fills: impl Stream<Item = Result<Fill>>,
+    ) -> Result<()> {
+        futures::pin_mut!(fills);
+        while let Some(fill) = fills.next().await {
+            match fill {
+                Ok(fill) => {
+                    self.index(client, &[fill]).await?;
+                    info!("indexed fill {} of {}", fill.seq_num, self.market.address());
+                }
+                Err(e) => warn!("skipping event queue update: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of the given side in the stores' tables.
+#[cfg_attr(not(any(feature = "sqlite", feature = "postgres")), allow(dead_code))]
+pub(crate) fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "bid",
+        Side::Ask => "ask",
+    }
+}
+
+/// Parses the name of a side in the stores' tables.
+#[cfg_attr(not(any(feature = "sqlite", feature = "postgres")), allow(dead_code))]
+pub(crate) fn parse_side(name: &str) -> Result<Side> {
+    match name {
+        "bid" => Ok(Side::Bid),
+        "ask" => Ok(Side::Ask),
+        _ => Err(anyhow!("invalid side {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_resolver() {
+        let (market, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let open_orders = Pubkey::new_unique();
+        let event = QueueEvent::Fill {
+            side: Side::Ask,
+            maker: false,
+            native_qty_paid: 10,
+            native_qty_received: 98,
+            native_fee_or_rebate: 2,
+            order_id: 1,
+            owner: open_orders,
+            owner_slot: 0,
+            fee_tier: 0,
+            client_order_id: 7,
+        };
+        let fill = IndexedFill::new(&market, &Fill { seq_num: 3, event }, None, 0);
+        assert_eq!((fill.native_coin_qty, fill.native_pc_qty), (10, 100));
+
+        let mut store = MemoryFillStore::default();
+        assert_eq!(store.next_seq_num(&market).unwrap(), None);
+        store.insert(&[fill.clone(), fill.clone()]).unwrap();
+        assert_eq!(store.fills.len(), 1);
+        assert_eq!(store.next_seq_num(&market).unwrap(), Some(4));
+        assert_eq!(store.fills(&market, 0, 0).unwrap(), vec![fill]);
+        assert!(store.fills(&market, 1, i64::MAX).unwrap().is_empty());
+
+        let mut resolver = WalletResolver::new(None);
+        resolver.insert_registry(&OpenOrdersRegistry {
+            market,
+            owner,
+            open_orders: vec![open_orders],
+            bump: 0,
+        });
+        assert_eq!(resolver.get(&open_orders), Some(&owner));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::MarketInfo;
+use anyhow::Result;
+use serum_dex::instruction::{self as dex_instruction, NewOrderInstructionV3};
+use serum_dex::matching::Side;
+use serum_dex_permissioned::{proxy_dex_instruction, ProxyHeader};
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::sysvar;
+use solana_system_interface::program as system_program;
+
+/// The associated token account program, holding the wallets funds are
+/// settled into by default.
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// Returns the address of the given wallet's associated token account of the
+/// given mint.
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), spl_token::ID.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Returns a `CreateIdempotent` creating the given wallet's associated token
+/// account of the given mint, paid for by `payer`, unless it exists.
+pub fn create_associated_token_account_ix(
+    payer: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(associated_token_address(wallet, mint), false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+        ],
+        // `AssociatedTokenAccountInstruction::CreateIdempotent`.
+        data: vec![1],
+    }
+}
+
+/// Returns a `NewOrderV3` placing the given order on the market, paid from
+/// `order_payer`, a token account of the pc mint for bids and of the coin
+/// mint for asks. The market's oracle, if it has one, is appended so that
+/// the order can take oracle pegged orders.
+pub fn new_order_ix(
+    market: &MarketInfo,
+    open_orders: &Pubkey,
+    order_payer: &Pubkey,
+    owner: &Pubkey,
+    order: NewOrderInstructionV3,
+) -> Result<Instruction> {
+    let header = &market.header;
+    let mut ix = dex_instruction::new_order(
+        market.address(),
+        open_orders,
+        &header.req_q,
+        &header.event_q,
+        &header.bids,
+        &header.asks,
+        order_payer,
+        owner,
+        &header.coin_vault,
+        &header.pc_vault,
+        &spl_token::ID,
+        &sysvar::rent::ID,
+        None,
+        &market.program_id,
+        order.side,
+        order.limit_price,
+        order.max_coin_qty,
+        order.order_type,
+        order.client_order_id,
+        order.self_trade_behavior,
+        order.limit,
+        order.max_native_pc_qty_including_fees,
+        order.max_ts,
+    )?;
+    if let Some(oracle) = header.oracle {
+        ix.accounts.push(AccountMeta::new_readonly(oracle, false));
+    }
+    Ok(ix)
+}
+
+/// Returns a `CancelOrderV2` cancelling the given order.
+pub fn cancel_order_ix(
+    market: &MarketInfo,
+    open_orders: &Pubkey,
+    owner: &Pubkey,
+    side: Side,
+    order_id: u128,
+) -> Result<Instruction> {
+    let header = &market.header;
+    Ok(dex_instruction::cancel_order(
+        &market.program_id,
+        market.address(),
+        &header.bids,
+        &header.asks,
+        open_orders,
+        owner,
+        &header.event_q,
+        side,
+        order_id,
+    )?)
+}
+
+/// Returns a `CancelOrderByClientIdV2` cancelling the order with the given
+/// client order id.
+pub fn cancel_order_by_client_id_ix(
+    market: &MarketInfo,
+    open_orders: &Pubkey,
+    owner: &Pubkey,
+    client_order_id: u64,
+) -> Result<Instruction> {
+    let header = &market.header;
+    Ok(dex_instruction::cancel_order_by_client_order_id(
+        &market.program_id,
+        market.address(),
+        &header.bids,
+        &header.asks,
+        open_orders,
+        owner,
+        &header.event_q,
+        client_order_id,
+    )?)
+}
+
+/// Returns a `SettleFunds` moving the free balances of the open orders
+/// account to the given wallets, crediting the referrer's pc wallet, if
+/// any, with its share of the fees.
+pub fn settle_funds_ix(
+    market: &MarketInfo,
+    open_orders: &Pubkey,
+    owner: &Pubkey,
+    coin_wallet: &Pubkey,
+    pc_wallet: &Pubkey,
+    referrer_pc_wallet: Option<&Pubkey>,
+) -> Result<Instruction> {
+    let header = &market.header;
+    Ok(dex_instruction::settle_funds(
+        &market.program_id,
+        market.address(),
+        &spl_token::ID,
+        open_orders,
+        owner,
+        &header.coin_vault,
+        coin_wallet,
+        &header.pc_vault,
+        pc_wallet,
+        referrer_pc_wallet,
+        &market.vault_signer,
+    )?)
+}
+
+/// Returns a `ConsumeEvents` processing up to `limit` events of the given
+/// open orders accounts, crediting the crank's share of the fees to the
+/// given wallets.
+pub fn consume_events_ix(
+    market: &MarketInfo,
+    open_orders: &[Pubkey],
+    coin_fee_wallet: &Pubkey,
+    pc_fee_wallet: &Pubkey,
+    limit: u16,
+) -> Result<Instruction> {
+    Ok(dex_instruction::consume_events(
+        &market.program_id,
+        open_orders.iter().collect(),
+        market.address(),
+        &market.header.event_q,
+        coin_fee_wallet,
+        pc_fee_wallet,
+        limit,
+    )?)
+}
+
+/// Returns a `ConsumeEventsPermissioned` processing up to `limit` events of
+/// the given open orders accounts, signed by the market's consume events
+/// authority.
+pub fn consume_events_permissioned_ix(
+    market: &MarketInfo,
+    open_orders: &[Pubkey],
+    authority: &Pubkey,
+    limit: u16,
+) -> Result<Instruction> {
+    Ok(dex_instruction::consume_events_permissioned(
+        &market.program_id,
+        open_orders.iter().collect(),
+        market.address(),
+        &market.header.event_q,
+        authority,
+        limit,
+    )?)
+}
+
+/// Returns a `Prune` cancelling up to `limit` orders of the given open
+/// orders account, signed by the market's prune authority.
+pub fn prune_ix(
+    market: &MarketInfo,
+    prune_authority: &Pubkey,
+    open_orders: &Pubkey,
+    open_orders_owner: &Pubkey,
+    limit: u16,
+) -> Result<Instruction> {
+    let header = &market.header;
+    Ok(dex_instruction::prune(
+        &market.program_id,
+        market.address(),
+        &header.bids,
+        &header.asks,
+        prune_authority,
+        open_orders,
+        open_orders_owner,
+        &header.event_q,
+        limit,
+    )?)
+}
+
+/// Routes the given DEX instruction through a `MarketProxy` program, with
+/// the given middleware accounts (see `proxy_dex_instruction`).
+pub fn proxy_ix(
+    proxy_program_id: &Pubkey,
+    header: &ProxyHeader,
+    dex_ix: Instruction,
+    trailing_accounts: &[AccountMeta],
+) -> Instruction {
+    proxy_dex_instruction(proxy_program_id, header, dex_ix, trailing_accounts.to_vec())
+}
+
+/// A `MarketProxy` program instructions are routed through, along with the
+/// header and middleware accounts of its requests.
+#[derive(Clone, Debug)]
+pub struct ProxyRoute {
+    pub program_id: Pubkey,
+    pub header: ProxyHeader,
+    pub trailing_accounts: Vec<AccountMeta>,
+}
+
+impl ProxyRoute {
+    /// Creates a route through the given proxy, with a default header and no
+    /// middleware accounts.
+    pub fn new(program_id: Pubkey) -> Self {
+        Self {
+            program_id,
+            header: ProxyHeader::default(),
+            trailing_accounts: Vec::new(),
+        }
+    }
+
+    /// Routes the given DEX instruction through the proxy.
+    pub fn route(&self, dex_ix: Instruction) -> Instruction {
+        proxy_ix(
+            &self.program_id,
+            &self.header,
+            dex_ix,
+            &self.trailing_accounts,
+        )
+    }
+}
@@ -0,0 +1,88 @@
+//! Rust client for the OpenBook DEX: loads markets over RPC, converts
+//! between UI amounts and lots, and builds instructions placing, cancelling
+//! and settling orders, either directly against the DEX or routed through a
+//! `MarketProxy` program (see `proxy_ix`). The `Crank` consumes the events
+//! of a set of markets by priority, reporting to `CrankMetrics`, and is run
+//! by the `openbook-crank` binary, and the `Settler` settles the funds of the
+//! users of a proxy on their behalf, both paying priority fees estimated by a
+//! `PriorityFeeEstimator` if configured, and the `PruneBot` prunes the orders
+//! of permissioned markets past their `max_ts` or their owner's heartbeat.
+//! Markets are listed with `MarketListing`, or the `openbook-list-market`
+//! binary, and deployed behind a proxy, along with the proxy's config, with
+//! the `Deployer`, or the `openbook-deploy` binary, and administered with the
+//! `openbook-proxy-admin` binary. `nonblocking` offers `async` variants of
+//! loading markets and sending orders, and `simulate_order` previews the
+//! fills of an order against an L2 snapshot, while the `ReplaySimulator`
+//! backtests a `Strategy` against recorded `BookSnapshot`s.
+//! The `OrderTransactionBuilder` assembles the transaction of an order, from
+//! the creation of the wallets to the settlement, as a v0 message looking up
+//! the market's accounts in a lookup table of `create_lookup_table` if needed.
+//! The `OrderTracker` follows the orders of bots across markets, and the
+//! `TransactionSender` sends their transactions until they land, placing
+//! each order at most once.
+//!
+//! Fills are streamed from websocket or Geyser updates of event queues with
+//! `fill_stream`, and indexed by the `FillIndexer` into a `FillStore`, such
+//! as the `SqliteFillStore` or `PostgresFillStore` of the `sqlite` and
+//! `postgres` features, and aggregated into candles by the
+//! `CandleAggregator`. The `Reconciler` checks the fills and transfers of
+//! a market's open orders accounts against their totals and the vaults on
+//! chain, and is run by the `openbook-reconcile` binary.
+//!
+//! Integration tests run against a `Localnet` validator of the `localnet`
+//! feature, with the DEX and a proxy loaded.
+
+mod candles;
+mod crank;
+mod deploy;
+mod indexer;
+mod instructions;
+mod listing;
+#[cfg(feature = "localnet")]
+mod localnet;
+mod lookup_table;
+mod market;
+#[cfg(test)]
+mod mock;
+pub mod nonblocking;
+#[cfg(feature = "postgres")]
+mod postgres_store;
+mod priority_fee;
+mod prune;
+mod reconcile;
+mod replay;
+mod sender;
+mod settle;
+mod simulate;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
+mod stream;
+mod tracker;
+mod transaction;
+
+pub use candles::*;
+pub use crank::*;
+pub use deploy::*;
+pub use indexer::*;
+pub use instructions::*;
+pub use listing::*;
+#[cfg(feature = "localnet")]
+pub use localnet::*;
+pub use lookup_table::*;
+pub use market::*;
+#[cfg(feature = "postgres")]
+pub use postgres_store::*;
+pub use priority_fee::*;
+pub use prune::*;
+pub use reconcile::*;
+pub use replay::*;
+pub use sender::*;
+pub use serum_dex;
+pub use serum_dex_permissioned;
+pub use settle::*;
+pub use simulate::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::*;
+pub use stream::*;
+pub use tracker::*;
+pub use transaction::*;
@@ -0,0 +1,314 @@
+use crate::MarketInfo;
+use anyhow::{anyhow, Result};
+use serum_dex::critbit::AnyNode;
+use serum_dex::instruction as dex_instruction;
+use serum_dex::state::{
+    gen_vault_signer_key, Event, EventQueueHeader, MarketState, MarketStateV2, Request,
+    RequestQueueHeader, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING,
+};
+use serum_dex_permissioned::{open_orders_init_authority_address, MarketHeader};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use solana_system_interface::instruction as system_instruction;
+use std::mem::size_of;
+
+/// Parameters of a market to list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListingParams {
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub pc_dust_threshold: u64,
+    /// Requests the request queue holds.
+    pub request_queue_capacity: usize,
+    /// Events the event queue holds, at least 128.
+    pub event_queue_capacity: usize,
+    /// Slab nodes of each of the bids and asks accounts, at least 100.
+    pub book_capacity: usize,
+}
+
+impl ListingParams {
+    /// Parameters with accounts about the size of those listed by the crank.
+    pub fn new(coin_lot_size: u64, pc_lot_size: u64) -> Self {
+        Self {
+            coin_lot_size,
+            pc_lot_size,
+            pc_dust_threshold: 100,
+            request_queue_capacity: 7,
+            event_queue_capacity: 11_915,
+            book_capacity: 909,
+        }
+    }
+}
+
+/// The authorities of a permissioned market.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketAuthorities {
+    pub open_orders_authority: Pubkey,
+    pub prune_authority: Pubkey,
+    /// Requires `ConsumeEventsPermissioned` if set.
+    pub consume_events_authority: Option<Pubkey>,
+}
+
+impl MarketAuthorities {
+    /// Authorities gating the open orders accounts of the market to those
+    /// initialized through the given `MarketProxy` program.
+    pub fn proxy(
+        proxy_program_id: &Pubkey,
+        dex_program_id: &Pubkey,
+        market: &Pubkey,
+        prune_authority: Pubkey,
+        consume_events_authority: Option<Pubkey>,
+    ) -> Self {
+        let (open_orders_authority, _) =
+            open_orders_init_authority_address(proxy_program_id, dex_program_id, market);
+        Self {
+            open_orders_authority,
+            prune_authority,
+            consume_events_authority,
+        }
+    }
+}
+
+/// Returns the size of a market account, within its padding.
+pub fn market_account_len(permissioned: bool) -> usize {
+    if permissioned {
+        padded(size_of::<MarketStateV2>())
+    } else {
+        padded(size_of::<MarketState>())
+    }
+}
+
+/// Returns the size of a request queue account of the given capacity.
+pub fn request_queue_len(capacity: usize) -> usize {
+    padded(size_of::<RequestQueueHeader>() + capacity * size_of::<Request>())
+}
+
+/// Returns the size of an event queue account of the given capacity.
+pub fn event_queue_len(capacity: usize) -> usize {
+    padded(size_of::<EventQueueHeader>() + capacity * size_of::<Event>())
+}
+
+/// Returns the size of a bids or asks account of the given capacity.
+pub fn book_side_len(capacity: usize) -> usize {
+    // Account flags, followed by the slab header.
+    padded(8 + 32 + capacity * size_of::<AnyNode>())
+}
+
+fn padded(len: usize) -> usize {
+    ACCOUNT_HEAD_PADDING.len() + len + ACCOUNT_TAIL_PADDING.len()
+}
+
+/// The keys of the accounts of a market to list.
+pub struct MarketListing {
+    pub market: Keypair,
+    pub req_q: Keypair,
+    pub event_q: Keypair,
+    pub bids: Keypair,
+    pub asks: Keypair,
+    pub coin_vault: Keypair,
+    pub pc_vault: Keypair,
+    pub vault_signer: Pubkey,
+    pub vault_signer_nonce: u64,
+}
+
+impl MarketListing {
+    /// Generates the keys of a new market of the given DEX.
+    pub fn generate(program_id: &Pubkey) -> Self {
+        let market = Keypair::new();
+        let (vault_signer_nonce, vault_signer) = (0..)
+            .find_map(|nonce| {
+                gen_vault_signer_key(nonce, &market.pubkey(), program_id)
+                    .ok()
+                    .map(|key| (nonce, key))
+            })
+            .unwrap();
+        Self {
+            market,
+            req_q: Keypair::new(),
+            event_q: Keypair::new(),
+            bids: Keypair::new(),
+            asks: Keypair::new(),
+            coin_vault: Keypair::new(),
+            pc_vault: Keypair::new(),
+            vault_signer,
+            vault_signer_nonce,
+        }
+    }
+
+    /// Returns the instructions creating the market's vaults, owned by its
+    /// vault signer, to be signed by the vaults.
+    pub fn vault_ixs(
+        &self,
+        client: &RpcClient,
+        payer: &Pubkey,
+        coin_mint: &Pubkey,
+        pc_mint: &Pubkey,
+    ) -> Result<Vec<Instruction>> {
+        let len = spl_token::state::Account::LEN;
+        let lamports = client.get_minimum_balance_for_rent_exemption(len)?;
+        let mut ixs = Vec::new();
+        for (vault, mint) in [(&self.coin_vault, coin_mint), (&self.pc_vault, pc_mint)].iter() {
+            ixs.push(system_instruction::create_account(
+                payer,
+                &vault.pubkey(),
+                lamports,
+                len as u64,
+                &spl_token::ID,
+            ));
+            ixs.push(spl_token::instruction::initialize_account(
+                &spl_token::ID,
+                &vault.pubkey(),
+                mint,
+                &self.vault_signer,
+            )?);
+        }
+        Ok(ixs)
+    }
+
+    /// Returns the instructions creating the market's accounts, rent exempt,
+    /// and initializing the market, to be signed by the accounts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn market_ixs(
+        &self,
+        client: &RpcClient,
+        program_id: &Pubkey,
+        payer: &Pubkey,
+        coin_mint: &Pubkey,
+        pc_mint: &Pubkey,
+        params: &ListingParams,
+        authorities: Option<&MarketAuthorities>,
+    ) -> Result<Vec<Instruction>> {
+        let accounts = [
+            (&self.market, market_account_len(authorities.is_some())),
+            (
+                &self.req_q,
+                request_queue_len(params.request_queue_capacity),
+            ),
+            (&self.event_q, event_queue_len(params.event_queue_capacity)),
+            (&self.bids, book_side_len(params.book_capacity)),
+            (&self.asks, book_side_len(params.book_capacity)),
+        ];
+        let mut ixs = Vec::new();
+        for (account, len) in accounts.iter() {
+            let lamports = client.get_minimum_balance_for_rent_exemption(*len)?;
+            ixs.push(system_instruction::create_account(
+                payer,
+                &account.pubkey(),
+                lamports,
+                *len as u64,
+                program_id,
+            ));
+        }
+        ixs.push(dex_instruction::initialize_market(
+            &self.market.pubkey(),
+            program_id,
+            coin_mint,
+            pc_mint,
+            &self.coin_vault.pubkey(),
+            &self.pc_vault.pubkey(),
+            authorities.map(|a| &a.open_orders_authority),
+            authorities.map(|a| &a.prune_authority),
+            authorities.and_then(|a| a.consume_events_authority.as_ref()),
+            &self.bids.pubkey(),
+            &self.asks.pubkey(),
+            &self.req_q.pubkey(),
+            &self.event_q.pubkey(),
+            params.coin_lot_size,
+            params.pc_lot_size,
+            self.vault_signer_nonce,
+            params.pc_dust_threshold,
+        )?);
+        Ok(ixs)
+    }
+
+    /// Lists the market, paid by `payer`: creates its vaults, then its
+    /// accounts and initializes it, in a second transaction.
+    #[allow(clippy::too_many_arguments)]
+    pub fn list(
+        &self,
+        client: &RpcClient,
+        program_id: &Pubkey,
+        payer: &Keypair,
+        coin_mint: &Pubkey,
+        pc_mint: &Pubkey,
+        params: &ListingParams,
+        authorities: Option<&MarketAuthorities>,
+    ) -> Result<MarketInfo> {
+        let vault_ixs = self.vault_ixs(client, &payer.pubkey(), coin_mint, pc_mint)?;
+        let signers: [&dyn Signer; 3] = [payer, &self.coin_vault, &self.pc_vault];
+        send(client, payer, &vault_ixs, &signers)?;
+
+        let market_ixs = self.market_ixs(
+            client,
+            program_id,
+            &payer.pubkey(),
+            coin_mint,
+            pc_mint,
+            params,
+            authorities,
+        )?;
+        let signers: [&dyn Signer; 6] = [
+            payer,
+            &self.market,
+            &self.req_q,
+            &self.event_q,
+            &self.bids,
+            &self.asks,
+        ];
+        send(client, payer, &market_ixs, &signers)?;
+
+        let data = client.get_account_data(&self.market.pubkey())?;
+        let header = MarketHeader::unpack(&data)
+            .map_err(|e| anyhow!("invalid market {}: {:?}", self.market.pubkey(), e))?;
+        let decimals = |key: &Pubkey| -> Result<u8> {
+            let data = client.get_account_data(key)?;
+            Ok(spl_token::state::Mint::unpack(&data)?.decimals)
+        };
+        MarketInfo::new(program_id, header, decimals(coin_mint)?, decimals(pc_mint)?)
+    }
+}
+
+fn send(
+    client: &RpcClient,
+    payer: &Keypair,
+    ixs: &[Instruction],
+    signers: &[&dyn Signer],
+) -> Result<()> {
+    let tx = Transaction::new_signed_with_payer(
+        ixs,
+        Some(&payer.pubkey()),
+        signers,
+        client.get_latest_blockhash()?,
+    );
+    client.send_and_confirm_transaction(&tx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_lens() {
+        // About the sizes the crank lists markets with.
+        let params = ListingParams::new(1, 1);
+        assert_eq!(market_account_len(false), 388);
+        assert_eq!(request_queue_len(params.request_queue_capacity), 604);
+        assert_eq!(event_queue_len(params.event_queue_capacity), 1_048_564);
+        assert_eq!(book_side_len(params.book_capacity), 65_500);
+        // The DEX requires accounts of a whole number of words.
+        for len in [
+            market_account_len(true),
+            event_queue_len(128),
+            book_side_len(100),
+        ]
+        .iter()
+        {
+            assert_eq!(len % 8, 4);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{
+    associated_token_address, create_associated_token_account_ix, DeployManifest, DeployParams,
+    Deployer, ListingParams, MarketInfo, MarketListing,
+};
+use anyhow::{anyhow, Result};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use solana_system_interface::instruction as system_instruction;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A compiled program loaded into the validator at genesis.
+#[derive(Clone, Debug)]
+pub struct LocalnetProgram {
+    pub program_id: Pubkey,
+    /// Path to the program's `.so`, e.g. under `target/deploy`.
+    pub path: PathBuf,
+}
+
+/// How the `Localnet` validator is started.
+#[derive(Clone, Debug)]
+pub struct LocalnetConfig {
+    pub dex: LocalnetProgram,
+    /// The `MarketProxy` program markets are deployed behind, if any.
+    pub proxy: Option<LocalnetProgram>,
+    pub rpc_port: u16,
+    /// Reset at each start.
+    pub ledger: PathBuf,
+    pub validator: String,
+    pub startup_timeout: Duration,
+}
+
+impl LocalnetConfig {
+    /// Loads the given DEX into a validator on the default port, with a
+    /// ledger in the temporary directory.
+    pub fn new(dex: LocalnetProgram) -> Self {
+        Self {
+            dex,
+            proxy: None,
+            rpc_port: 8899,
+            ledger: std::env::temp_dir().join("openbook-localnet"),
+            validator: "solana-test-validator".to_string(),
+            startup_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Returns the arguments of the validator. The proxy is upgradeable by
+    /// the given authority, which initializes its config.
+    pub fn validator_args(&self, upgrade_authority: &Pubkey) -> Vec<String> {
+        let mut args = vec![
+            "--reset".to_string(),
+            "--quiet".to_string(),
+            "--ledger".to_string(),
+            self.ledger.display().to_string(),
+            "--rpc-port".to_string(),
+            self.rpc_port.to_string(),
+        ];
+        args.push("--bpf-program".to_string());
+        args.push(self.dex.program_id.to_string());
+        args.push(self.dex.path.display().to_string());
+        if let Some(proxy) = &self.proxy {
+            args.push("--upgradeable-program".to_string());
+            args.push(proxy.program_id.to_string());
+            args.push(proxy.path.display().to_string());
+            args.push(upgrade_authority.to_string());
+        }
+        args
+    }
+}
+
+/// A market listed on the `Localnet`, of mints it created.
+pub struct LocalnetMarket {
+    pub market: MarketInfo,
+    /// The addresses of the proxy accounts, for markets deployed behind the
+    /// configured proxy.
+    pub manifest: Option<DeployManifest>,
+}
+
+/// A funded wallet, with associated token accounts of a market's mints.
+pub struct LocalnetUser {
+    pub keypair: Keypair,
+    pub coin_wallet: Pubkey,
+    pub pc_wallet: Pubkey,
+}
+
+/// A `solana-test-validator` with the DEX, and proxy, loaded, killed when
+/// dropped, for integration tests of custom middlewares. The payer is
+/// funded at start, and is the authority of the mints created.
+pub struct Localnet {
+    pub config: LocalnetConfig,
+    pub client: RpcClient,
+    pub payer: Keypair,
+    validator: Child,
+}
+
+impl Localnet {
+    /// Starts the validator, waiting for it to serve RPC requests.
+    pub fn start(config: LocalnetConfig) -> Result<Self> {
+        let payer = Keypair::new();
+        let validator = Command::new(&config.validator)
+            .args(config.validator_args(&payer.pubkey()))
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("failed to start {}: {}", config.validator, e))?;
+        let client = RpcClient::new(format!("http://127.0.0.1:{}", config.rpc_port));
+        let localnet = Self {
+            config,
+            client,
+            payer,
+            validator,
+        };
+        let started = Instant::now();
+        while localnet.client.get_health().is_err() {
+            if started.elapsed() > localnet.config.startup_timeout {
+                return Err(anyhow!("validator didn't start"));
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        localnet.airdrop(&localnet.payer.pubkey(), 1_000 * LAMPORTS_PER_SOL)?;
+        Ok(localnet)
+    }
+
+    /// Airdrops the given lamports, waiting for their confirmation.
+    pub fn airdrop(&self, to: &Pubkey, lamports: u64) -> Result<()> {
+        let signature = self.client.request_airdrop(to, lamports)?;
+        self.client.poll_for_signature(&signature)?;
+        Ok(())
+    }
+
+    /// Creates a mint of the given decimals, of which the payer is the
+    /// authority.
+    pub fn create_mint(&self, decimals: u8) -> Result<Pubkey> {
+        let mint = Keypair::new();
+        let len = spl_token::state::Mint::LEN;
+        let ixs = [
+            system_instruction::create_account(
+                &self.payer.pubkey(),
+                &mint.pubkey(),
+                self.client.get_minimum_balance_for_rent_exemption(len)?,
+                len as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &self.payer.pubkey(),
+                None,
+                decimals,
+            )?,
+        ];
+        self.send(&ixs, &[&mint])?;
+        Ok(mint.pubkey())
+    }
+
+    /// Lists a market of two new mints of the given decimals, deployed
+    /// behind the configured proxy, if any, with its config created and the
+    /// payer as its admin.
+    pub fn list_market(
+        &self,
+        coin_decimals: u8,
+        pc_decimals: u8,
+        params: &ListingParams,
+    ) -> Result<LocalnetMarket> {
+        let coin_mint = self.create_mint(coin_decimals)?;
+        let pc_mint = self.create_mint(pc_decimals)?;
+        let dex_program_id = self.config.dex.program_id;
+        match &self.config.proxy {
+            Some(proxy) => {
+                let deployer = Deployer {
+                    client: RpcClient::new(self.client.url()),
+                    payer: self.payer.insecure_clone(),
+                    proxy_program_id: proxy.program_id,
+                    dex_program_id,
+                };
+                let params = DeployParams {
+                    listing: *params,
+                    fee_bps: 0,
+                    fee_receiver: self.payer.pubkey(),
+                    prune_authority: None,
+                    consume_events_authority: None,
+                    referrals: Vec::new(),
+                };
+                let (market, manifest) = deployer.deploy(&coin_mint, &pc_mint, &params)?;
+                Ok(LocalnetMarket {
+                    market,
+                    manifest: Some(manifest),
+                })
+            }
+            None => {
+                let listing = MarketListing::generate(&dex_program_id);
+                let market = listing.list(
+                    &self.client,
+                    &dex_program_id,
+                    &self.payer,
+                    &coin_mint,
+                    &pc_mint,
+                    params,
+                    None,
+                )?;
+                Ok(LocalnetMarket {
+                    market,
+                    manifest: None,
+                })
+            }
+        }
+    }
+
+    /// Creates a wallet funded with the given SOL, along with its associated
+    /// token accounts of the market's mints, minted the given native amounts.
+    pub fn create_user(
+        &self,
+        market: &MarketInfo,
+        sol: u64,
+        native_coin: u64,
+        native_pc: u64,
+    ) -> Result<LocalnetUser> {
+        let keypair = Keypair::new();
+        let wallet = keypair.pubkey();
+        self.airdrop(&wallet, sol * LAMPORTS_PER_SOL)?;
+        let header = &market.header;
+        let mut ixs = Vec::new();
+        let mints = [
+            (&header.coin_mint, native_coin),
+            (&header.pc_mint, native_pc),
+        ];
+        for (mint, amount) in mints.iter() {
+            ixs.push(create_associated_token_account_ix(
+                &self.payer.pubkey(),
+                &wallet,
+                mint,
+            ));
+            ixs.push(spl_token::instruction::mint_to(
+                &spl_token::ID,
+                mint,
+                &associated_token_address(&wallet, mint),
+                &self.payer.pubkey(),
+                &[],
+                *amount,
+            )?);
+        }
+        self.send(&ixs, &[])?;
+        Ok(LocalnetUser {
+            keypair,
+            coin_wallet: associated_token_address(&wallet, &header.coin_mint),
+            pc_wallet: associated_token_address(&wallet, &header.pc_mint),
+        })
+    }
+
+    /// Sends the given instructions, paid by the payer and signed by
+    /// `signers` too.
+    pub fn send(&self, ixs: &[Instruction], signers: &[&Keypair]) -> Result<()> {
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+        let tx = Transaction::new_signed_with_payer(
+            ixs,
+            Some(&self.payer.pubkey()),
+            &all_signers,
+            self.client.get_latest_blockhash()?,
+        );
+        self.client.send_and_confirm_transaction(&tx)?;
+        Ok(())
+    }
+}
+
+impl Drop for Localnet {
+    fn drop(&mut self) {
+        let _ = self.validator.kill();
+        let _ = self.validator.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validator_args() {
+        let dex = LocalnetProgram {
+            program_id: Pubkey::new_unique(),
+            path: PathBuf::from("target/deploy/serum_dex.so"),
+        };
+        let mut config = LocalnetConfig::new(dex.clone());
+        config.rpc_port = 8999;
+        let authority = Pubkey::new_unique();
+        let args = config.validator_args(&authority);
+        assert_eq!(&args[4..6], &["--rpc-port".to_string(), "8999".to_string()]);
+        assert_eq!(args.len(), 9);
+
+        config.proxy = Some(LocalnetProgram {
+            program_id: Pubkey::new_unique(),
+            path: PathBuf::from("target/deploy/proxy.so"),
+        });
+        let args = config.validator_args(&authority);
+        assert_eq!(args[7], dex.program_id.to_string());
+        assert_eq!(args[9], "--upgradeable-program");
+        assert_eq!(args[11], "target/deploy/proxy.so");
+        assert_eq!(args.last().unwrap(), &authority.to_string());
+    }
+}
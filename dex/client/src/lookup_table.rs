@@ -0,0 +1,220 @@
+use crate::MarketInfo;
+use anyhow::{anyhow, Result};
+use serum_dex_permissioned::{
+    compile_proxy_message, create_market_lookup_table, extend_market_lookup_table,
+    lookup_table_account,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::AddressLookupTableAccount;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::sysvar;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
+
+/// Returns the accounts shared by all instructions on the given market,
+/// routed through the given proxy if any, to be stored in its lookup table
+/// (see `create_lookup_table`).
+pub fn market_lookup_table_keys(
+    market: &MarketInfo,
+    proxy_program_id: Option<&Pubkey>,
+) -> Vec<Pubkey> {
+    let header = &market.header;
+    let mut keys: Vec<_> = proxy_program_id.into_iter().copied().collect();
+    keys.extend_from_slice(&[
+        market.program_id,
+        header.own_address,
+        header.req_q,
+        header.event_q,
+        header.bids,
+        header.asks,
+        header.coin_vault,
+        header.pc_vault,
+        market.vault_signer,
+        spl_token::ID,
+        sysvar::rent::ID,
+    ]);
+    keys.extend(header.oracle);
+    keys
+}
+
+/// Creates a lookup table of the given addresses, of which `authority` is
+/// the authority, sending one transaction per instruction, and returns its
+/// address. The table can be used from the slot after the last transaction.
+pub fn create_lookup_table(
+    client: &RpcClient,
+    authority: &Keypair,
+    payer: &Keypair,
+    addresses: &[Pubkey],
+) -> Result<Pubkey> {
+    let recent_slot = client.get_slot()?;
+    let (table, ixs) =
+        create_market_lookup_table(&authority.pubkey(), &payer.pubkey(), recent_slot, addresses);
+    for ix in ixs {
+        send_ix(client, authority, payer, ix)?;
+    }
+    Ok(table)
+}
+
+/// Adds those of the given addresses missing from the lookup table, e.g. the
+/// shared accounts of a market's middleware.
+pub fn extend_lookup_table(
+    client: &RpcClient,
+    table: &Pubkey,
+    authority: &Keypair,
+    payer: &Keypair,
+    addresses: &[Pubkey],
+) -> Result<()> {
+    let account = load_lookup_tables(client, &[*table])?.remove(0);
+    let missing: Vec<_> = addresses
+        .iter()
+        .filter(|address| !account.addresses.contains(address))
+        .copied()
+        .collect();
+    let ixs = extend_market_lookup_table(table, &authority.pubkey(), &payer.pubkey(), &missing);
+    for ix in ixs {
+        send_ix(client, authority, payer, ix)?;
+    }
+    Ok(())
+}
+
+/// Loads the given lookup tables, failing if any is missing.
+pub fn load_lookup_tables(
+    client: &RpcClient,
+    tables: &[Pubkey],
+) -> Result<Vec<AddressLookupTableAccount>> {
+    let accounts = client.get_multiple_accounts(tables)?;
+    tables
+        .iter()
+        .zip(accounts)
+        .map(|(key, account)| {
+            let account = account.ok_or_else(|| anyhow!("missing lookup table {}", key))?;
+            lookup_table_account(*key, &account.data)
+                .map_err(|e| anyhow!("invalid lookup table {}: {:?}", key, e))
+        })
+        .collect()
+}
+
+/// Returns a v0 transaction of the given instructions, paid for by `payer`
+/// and signed by `signers` too, looking up the accounts found in the given
+/// tables rather than listing them, so that proxied orders with middleware
+/// accounts fit in a transaction.
+pub fn versioned_transaction(
+    payer: &Keypair,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+    lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> Result<VersionedTransaction> {
+    let message = compile_proxy_message(&payer.pubkey(), ixs, lookup_tables, blockhash)
+        .map_err(|e| anyhow!("failed to compile message: {:?}", e))?;
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    Ok(VersionedTransaction::try_new(message, &all_signers)?)
+}
+
+/// Sends the given instructions in a v0 transaction (see
+/// `versioned_transaction`), waiting for its confirmation.
+pub fn send_versioned_ixs(
+    client: &RpcClient,
+    payer: &Keypair,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<Signature> {
+    let blockhash = client.get_latest_blockhash()?;
+    let tx = versioned_transaction(payer, ixs, signers, lookup_tables, blockhash)?;
+    Ok(client.send_and_confirm_transaction(&tx)?)
+}
+
+fn send_ix(
+    client: &RpcClient,
+    authority: &Keypair,
+    payer: &Keypair,
+    ix: Instruction,
+) -> Result<()> {
+    let mut signers = vec![payer];
+    if authority.pubkey() != payer.pubkey() {
+        signers.push(authority);
+    }
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&payer.pubkey()),
+        &signers,
+        client.get_latest_blockhash()?,
+    );
+    client.send_and_confirm_transaction(&tx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_order_ix, ProxyRoute};
+    use serum_dex::matching::{OrderType, Side};
+    use serum_dex_permissioned::MarketHeader;
+    use solana_sdk::message::VersionedMessage;
+
+    #[test]
+    fn test_versioned_transaction() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: Some(Pubkey::new_unique()),
+        };
+        let market = MarketInfo::new(&program_id, header, 0, 0).unwrap();
+        let proxy = ProxyRoute::new(Pubkey::new_unique());
+        let keys = market_lookup_table_keys(&market, Some(&proxy.program_id));
+        assert_eq!(keys.len(), 13);
+        assert_eq!(market_lookup_table_keys(&market, None)[..], keys[1..]);
+
+        let owner = Keypair::new();
+        let order = market
+            .new_order(Side::Bid, 10.0, 1.0, OrderType::Limit, 1)
+            .unwrap();
+        let ix = new_order_ix(
+            &market,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &owner.pubkey(),
+            order,
+        )
+        .unwrap();
+        assert_eq!(ix.accounts.last().unwrap().pubkey, header.oracle.unwrap());
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: keys,
+        };
+        let tx = versioned_transaction(&owner, &[proxy.route(ix)], &[], &[table], Hash::default())
+            .unwrap();
+        match &tx.message {
+            // The owner, open orders and order payer, then the proxy.
+            VersionedMessage::V0(message) => assert_eq!(message.account_keys.len(), 4),
+            _ => panic!("not a v0 message"),
+        }
+        assert!(tx.verify_with_results().iter().all(|ok| *ok));
+    }
+}
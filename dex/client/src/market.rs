@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use serum_dex::instruction::{NewOrderInstructionV3, SelfTradeBehavior};
+use serum_dex::matching::{OrderType, Side};
+use serum_dex::state::gen_vault_signer_key;
+use serum_dex_permissioned::MarketHeader;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use spl_token::state::Mint;
+use std::num::NonZeroU64;
+
+/// Taker fee of the base fee tier, in tenths of a bps, with which the pc
+/// paid by bids is bounded.
+pub const TAKER_FEE_TENTH_OF_BPS: u64 = 40;
+
+/// A market's accounts and parameters, along with the decimals of its mints
+/// for converting between UI amounts and the lots the DEX works in.
+#[derive(Clone, Debug)]
+pub struct MarketInfo {
+    pub program_id: Pubkey,
+    pub header: MarketHeader,
+    pub vault_signer: Pubkey,
+    pub coin_decimals: u8,
+    pub pc_decimals: u8,
+}
+
+impl MarketInfo {
+    /// Creates the market info from the given market state, deriving the
+    /// vault signer.
+    pub fn new(
+        program_id: &Pubkey,
+        header: MarketHeader,
+        coin_decimals: u8,
+        pc_decimals: u8,
+    ) -> Result<Self> {
+        let vault_signer =
+            gen_vault_signer_key(header.vault_signer_nonce, &header.own_address, program_id)
+                .map_err(|e| anyhow!("invalid vault signer nonce: {:?}", e))?;
+        Ok(Self {
+            program_id: *program_id,
+            header,
+            vault_signer,
+            coin_decimals,
+            pc_decimals,
+        })
+    }
+
+    /// Loads the given market, and its mints, from the cluster.
+    pub fn load(client: &RpcClient, program_id: &Pubkey, market: &Pubkey) -> Result<Self> {
+        let header = unpack_market(program_id, market, &client.get_account(market)?)?;
+        let decimals =
+            |key: &Pubkey| -> Result<u8> { unpack_decimals(key, &client.get_account_data(key)?) };
+        let coin_decimals = decimals(&header.coin_mint)?;
+        let pc_decimals = decimals(&header.pc_mint)?;
+        Self::new(program_id, header, coin_decimals, pc_decimals)
+    }
+
+    /// The market's address.
+    pub fn address(&self) -> &Pubkey {
+        &self.header.own_address
+    }
+
+    /// Converts a UI price, in pc per coin, to price lots, rounding to the
+    /// nearest lot.
+    pub fn price_to_lots(&self, price: f64) -> u64 {
+        let lots = price * pow10(self.pc_decimals) * self.header.coin_lot_size as f64
+            / (pow10(self.coin_decimals) * self.header.pc_lot_size as f64);
+        lots.round() as u64
+    }
+
+    /// Converts price lots to a UI price, in pc per coin.
+    pub fn lots_to_price(&self, lots: u64) -> f64 {
+        lots as f64 * self.header.pc_lot_size as f64 * pow10(self.coin_decimals)
+            / (self.header.coin_lot_size as f64 * pow10(self.pc_decimals))
+    }
+
+    /// Converts a UI coin amount to coin lots, rounding to the nearest lot.
+    pub fn size_to_lots(&self, size: f64) -> u64 {
+        (size * pow10(self.coin_decimals) / self.header.coin_lot_size as f64).round() as u64
+    }
+
+    /// Converts coin lots to a UI coin amount.
+    pub fn lots_to_size(&self, lots: u64) -> f64 {
+        lots as f64 * self.header.coin_lot_size as f64 / pow10(self.coin_decimals)
+    }
+
+    /// Returns the most native pc a bid of the given lots can pay, taker fee
+    /// included.
+    pub fn max_native_pc_qty(&self, price_lots: u64, coin_lots: u64) -> u64 {
+        let pc_qty = price_lots as u128 * coin_lots as u128 * self.header.pc_lot_size as u128;
+        let with_fee = pc_qty * (100_000 + TAKER_FEE_TENTH_OF_BPS) as u128;
+        with_fee.div_ceil(100_000).min(u64::MAX as u128) as u64
+    }
+
+    /// Returns a `NewOrderV3` at the given UI price and size, which must both
+    /// be at least a lot.
+    pub fn new_order(
+        &self,
+        side: Side,
+        price: f64,
+        size: f64,
+        order_type: OrderType,
+        client_order_id: u64,
+    ) -> Result<NewOrderInstructionV3> {
+        let price_lots = self.price_to_lots(price);
+        let coin_lots = self.size_to_lots(size);
+        let non_zero = |lots: u64, what: &str| {
+            NonZeroU64::new(lots).ok_or_else(|| anyhow!("{} is less than a lot", what))
+        };
+        Ok(NewOrderInstructionV3 {
+            side,
+            limit_price: non_zero(price_lots, "price")?,
+            max_coin_qty: non_zero(coin_lots, "size")?,
+            max_native_pc_qty_including_fees: non_zero(
+                self.max_native_pc_qty(price_lots, coin_lots),
+                "price",
+            )?,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type,
+            client_order_id,
+            limit: u16::MAX,
+            max_ts: i64::MAX,
+        })
+    }
+}
+
+/// Parses the header of the given market account, checking it's a market of
+/// the given DEX stored at its own address.
+pub(crate) fn unpack_market(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    account: &Account,
+) -> Result<MarketHeader> {
+    if &account.owner != program_id {
+        return Err(anyhow!("market {} isn't owned by {}", market, program_id));
+    }
+    let header = MarketHeader::unpack(&account.data)
+        .map_err(|e| anyhow!("invalid market {}: {:?}", market, e))?;
+    if &header.own_address != market {
+        return Err(anyhow!("market {} has another address", market));
+    }
+    Ok(header)
+}
+
+/// Parses the decimals of the given mint account.
+pub(crate) fn unpack_decimals(mint: &Pubkey, data: &[u8]) -> Result<u8> {
+    let mint = Mint::unpack(data).map_err(|e| anyhow!("invalid mint {}: {:?}", mint, e))?;
+    Ok(mint.decimals)
+}
+
+fn pow10(decimals: u8) -> f64 {
+    10f64.powi(decimals as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market() -> MarketInfo {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok())
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            // 0.1 coin and 0.001 pc.
+            coin_lot_size: 100_000_000,
+            pc_lot_size: 1_000,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        MarketInfo::new(&program_id, header, 9, 6).unwrap()
+    }
+
+    #[test]
+    fn test_lot_conversions() {
+        let market = market();
+        // A lot of 0.1 coin at a price lot of 0.001 pc is 0.01 pc per coin.
+        assert_eq!(market.price_to_lots(25.5), 2_550);
+        assert_eq!(market.lots_to_price(2_550), 25.5);
+        assert_eq!(market.size_to_lots(1.24), 12);
+        assert_eq!(market.lots_to_size(12), 1.2);
+
+        // 12 lots at 2550 is 30.6 pc, plus 0.04%.
+        assert_eq!(market.max_native_pc_qty(2_550, 12), 30_612_240);
+
+        let order = market
+            .new_order(Side::Bid, 25.5, 1.2, OrderType::Limit, 7)
+            .unwrap();
+        assert_eq!(order.limit_price.get(), 2_550);
+        assert_eq!(order.max_coin_qty.get(), 12);
+        assert_eq!(order.max_native_pc_qty_including_fees.get(), 30_612_240);
+        assert!(market
+            .new_order(Side::Bid, 25.5, 0.01, OrderType::Limit, 7)
+            .is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Responses the tests queue on mock `RpcClient`s, for the methods the mock
+//! doesn't answer as needed, see `RpcClient::new_mock_with_mocks_map`.
+
+use serde_json::{json, Value};
+use solana_sdk::bs58;
+use solana_sdk::pubkey::Pubkey;
+
+/// The response to a `getMultipleAccounts` of the given accounts, as the
+/// owner and data of each, if any.
+pub fn multiple_accounts(accounts: &[Option<(&Pubkey, &[u8])>]) -> Value {
+    let accounts: Vec<_> = accounts
+        .iter()
+        .map(|account| {
+            account.map(|(owner, data)| {
+                json!({
+                    "lamports": 1,
+                    "data": [bs58::encode(data).into_string(), "base58"],
+                    "owner": owner.to_string(),
+                    "executable": false,
+                    "rentEpoch": 0,
+                    "space": data.len(),
+                })
+            })
+        })
+        .collect();
+    json!({ "context": { "slot": 1 }, "value": accounts })
+}
@@ -0,0 +1,110 @@
+//! `async` variants of the client APIs, on `solana-client`'s nonblocking
+//! RPC, for services running on tokio.
+
+use crate::market::{unpack_decimals, unpack_market};
+use crate::{cancel_order_by_client_id_ix, new_order_ix, settle_funds_ix, MarketInfo, ProxyRoute};
+use anyhow::{anyhow, Result};
+use serum_dex::instruction::NewOrderInstructionV3;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+
+/// Loads the given market, and its mints, from the cluster.
+pub async fn load_market(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    market: &Pubkey,
+) -> Result<MarketInfo> {
+    let header = unpack_market(program_id, market, &client.get_account(market).await?)?;
+    let mints = client
+        .get_multiple_accounts(&[header.coin_mint, header.pc_mint])
+        .await?;
+    let decimals = |key: &Pubkey, account: &Option<Account>| -> Result<u8> {
+        let account = account
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing mint {}", key))?;
+        unpack_decimals(key, &account.data)
+    };
+    let coin_decimals = decimals(&header.coin_mint, &mints[0])?;
+    let pc_decimals = decimals(&header.pc_mint, &mints[1])?;
+    MarketInfo::new(program_id, header, coin_decimals, pc_decimals)
+}
+
+/// Sends the given instructions in a transaction paid for by `payer` and
+/// signed by `signers` too, waiting for its confirmation.
+pub async fn send_ixs(
+    client: &RpcClient,
+    payer: &Keypair,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<Signature> {
+    let blockhash = client.get_latest_blockhash().await?;
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    let tx =
+        Transaction::new_signed_with_payer(ixs, Some(&payer.pubkey()), &all_signers, blockhash);
+    Ok(client.send_and_confirm_transaction(&tx).await?)
+}
+
+/// Places the given order with `owner`'s open orders account, through the
+/// given proxy if any, paid from `order_payer` (see `new_order_ix`).
+pub async fn place_order(
+    client: &RpcClient,
+    owner: &Keypair,
+    market: &MarketInfo,
+    proxy: Option<&ProxyRoute>,
+    open_orders: &Pubkey,
+    order_payer: &Pubkey,
+    order: NewOrderInstructionV3,
+) -> Result<Signature> {
+    let ix = new_order_ix(market, open_orders, order_payer, &owner.pubkey(), order)?;
+    send_ixs(client, owner, &[routed(proxy, ix)], &[]).await
+}
+
+/// Cancels the order of `owner` with the given client order id, through the
+/// given proxy if any.
+pub async fn cancel_order_by_client_id(
+    client: &RpcClient,
+    owner: &Keypair,
+    market: &MarketInfo,
+    proxy: Option<&ProxyRoute>,
+    open_orders: &Pubkey,
+    client_order_id: u64,
+) -> Result<Signature> {
+    let ix = cancel_order_by_client_id_ix(market, open_orders, &owner.pubkey(), client_order_id)?;
+    send_ixs(client, owner, &[routed(proxy, ix)], &[]).await
+}
+
+/// Settles the free balances of `owner`'s open orders account into the
+/// given wallets, through the given proxy if any.
+#[allow(clippy::too_many_arguments)]
+pub async fn settle_funds(
+    client: &RpcClient,
+    owner: &Keypair,
+    market: &MarketInfo,
+    proxy: Option<&ProxyRoute>,
+    open_orders: &Pubkey,
+    coin_wallet: &Pubkey,
+    pc_wallet: &Pubkey,
+    referrer_pc_wallet: Option<&Pubkey>,
+) -> Result<Signature> {
+    let ix = settle_funds_ix(
+        market,
+        open_orders,
+        &owner.pubkey(),
+        coin_wallet,
+        pc_wallet,
+        referrer_pc_wallet,
+    )?;
+    send_ixs(client, owner, &[routed(proxy, ix)], &[]).await
+}
+
+fn routed(proxy: Option<&ProxyRoute>, ix: Instruction) -> Instruction {
+    match proxy {
+        Some(proxy) => proxy.route(ix),
+        None => ix,
+    }
+}
@@ -0,0 +1,114 @@
+use crate::indexer::{parse_side, side_name};
+use crate::{FillStore, IndexedFill};
+use anyhow::Result;
+use postgres::{Client, NoTls};
+use solana_sdk::pubkey::Pubkey;
+use std::convert::TryFrom;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS fills (
+    market TEXT NOT NULL,
+    seq_num BIGINT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    side TEXT NOT NULL,
+    maker BOOLEAN NOT NULL,
+    open_orders TEXT NOT NULL,
+    wallet TEXT,
+    order_id NUMERIC(39) NOT NULL,
+    client_order_id NUMERIC(20) NOT NULL,
+    native_coin_qty BIGINT NOT NULL,
+    native_pc_qty BIGINT NOT NULL,
+    native_fee_or_rebate BIGINT NOT NULL,
+    PRIMARY KEY (market, seq_num)
+);
+CREATE INDEX IF NOT EXISTS fills_wallet ON fills (wallet);
+";
+
+/// Stores fills in the `fills` table of a Postgres database, with the same
+/// columns as the `SqliteFillStore`, the ids being numerics.
+pub struct PostgresFillStore {
+    pub client: Client,
+}
+
+impl PostgresFillStore {
+    /// Connects to the database with the given parameters, without TLS,
+    /// creating the table if needed.
+    pub fn connect(params: &str) -> Result<Self> {
+        Self::new(Client::connect(params, NoTls)?)
+    }
+
+    /// Stores fills in the given database, creating the table if needed.
+    pub fn new(mut client: Client) -> Result<Self> {
+        client.batch_execute(SCHEMA)?;
+        Ok(Self { client })
+    }
+}
+
+impl FillStore for PostgresFillStore {
+    fn next_seq_num(&mut self, market: &Pubkey) -> Result<Option<u64>> {
+        let row = self.client.query_one(
+            "SELECT MAX(seq_num) FROM fills WHERE market = $1",
+            &[&market.to_string()],
+        )?;
+        let last: Option<i64> = row.get(0);
+        Ok(last.map(|seq_num| seq_num as u64 + 1))
+    }
+
+    fn insert(&mut self, fills: &[IndexedFill]) -> Result<()> {
+        let mut tx = self.client.transaction()?;
+        let statement = tx.prepare(
+            "INSERT INTO fills VALUES
+             ($1, $2, $3, $4, $5, $6, $7, $8::TEXT::NUMERIC, $9::TEXT::NUMERIC, $10, $11, $12)
+             ON CONFLICT DO NOTHING",
+        )?;
+        for fill in fills {
+            tx.execute(
+                &statement,
+                &[
+                    &fill.market.to_string(),
+                    &i64::try_from(fill.seq_num)?,
+                    &fill.timestamp,
+                    &side_name(fill.side),
+                    &fill.maker,
+                    &fill.open_orders.to_string(),
+                    &fill.wallet.map(|wallet| wallet.to_string()),
+                    &fill.order_id.to_string(),
+                    &fill.client_order_id.to_string(),
+                    &i64::try_from(fill.native_coin_qty)?,
+                    &i64::try_from(fill.native_pc_qty)?,
+                    &i64::try_from(fill.native_fee_or_rebate)?,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn fills(&mut self, market: &Pubkey, from: i64, to: i64) -> Result<Vec<IndexedFill>> {
+        let rows = self.client.query(
+            "SELECT seq_num, timestamp, side, maker, open_orders, wallet, order_id::TEXT,
+             client_order_id::TEXT, native_coin_qty, native_pc_qty, native_fee_or_rebate
+             FROM fills WHERE market = $1 AND timestamp BETWEEN $2 AND $3 ORDER BY seq_num",
+            &[&market.to_string(), &from, &to],
+        )?;
+        let mut fills = Vec::with_capacity(rows.len());
+        for row in rows {
+            let wallet: Option<String> = row.get(5);
+            fills.push(IndexedFill {
+                market: *market,
+                seq_num: u64::try_from(row.get::<_, i64>(0))?,
+                timestamp: row.get(1),
+                side: parse_side(row.get(2))?,
+                maker: row.get(3),
+                open_orders: row.get::<_, String>(4).parse()?,
+                wallet: wallet.map(|wallet| wallet.parse()).transpose()?,
+                order_id: row.get::<_, String>(6).parse()?,
+                client_order_id: row.get::<_, String>(7).parse()?,
+                native_coin_qty: u64::try_from(row.get::<_, i64>(8))?,
+                native_pc_qty: u64::try_from(row.get::<_, i64>(9))?,
+                native_fee_or_rebate: u64::try_from(row.get::<_, i64>(10))?,
+            });
+        }
+        Ok(fills)
+    }
+}
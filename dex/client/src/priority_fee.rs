@@ -0,0 +1,116 @@
+use anyhow::Result;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+/// Compute units of the proxy relaying an instruction, middleware included.
+pub const PROXY_COMPUTE_UNITS: u32 = 40_000;
+
+/// Compute units of a `SettleFunds`, with a margin.
+pub const SETTLE_FUNDS_COMPUTE_UNITS: u32 = 40_000;
+
+// Compute units of a `ConsumeEvents`, with a margin: a base, then per open
+// orders account loaded and per event consumed.
+const CONSUME_EVENTS_BASE_UNITS: u32 = 20_000;
+const CONSUME_EVENTS_UNITS_PER_OPEN_ORDERS: u32 = 2_000;
+const CONSUME_EVENTS_UNITS_PER_EVENT: u32 = 8_000;
+
+/// The most compute units a transaction may request.
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Returns the compute units to request for a `ConsumeEvents` of the given
+/// open orders accounts, processing up to `limit` events, through a proxy if
+/// `proxied`, so that the priority fee is paid on the units it can use
+/// rather than on the default limit.
+pub fn consume_events_compute_units(open_orders: usize, limit: u16, proxied: bool) -> u32 {
+    let units = CONSUME_EVENTS_BASE_UNITS
+        .saturating_add(CONSUME_EVENTS_UNITS_PER_OPEN_ORDERS.saturating_mul(open_orders as u32))
+        .saturating_add(CONSUME_EVENTS_UNITS_PER_EVENT.saturating_mul(limit as u32));
+    with_proxy(units, proxied)
+}
+
+/// Returns the compute units to request for a `SettleFunds`, through a proxy
+/// if `proxied`.
+pub fn settle_funds_compute_units(proxied: bool) -> u32 {
+    with_proxy(SETTLE_FUNDS_COMPUTE_UNITS, proxied)
+}
+
+fn with_proxy(units: u32, proxied: bool) -> u32 {
+    let units = if proxied {
+        units.saturating_add(PROXY_COMPUTE_UNITS)
+    } else {
+        units
+    };
+    units.min(MAX_COMPUTE_UNITS)
+}
+
+/// Estimates the priority fee a transaction needs to land from those paid
+/// in recent slots by transactions writing to the same accounts, e.g. a
+/// market's event queue, within bounds so that bots don't overpay during
+/// congestion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PriorityFeeEstimator {
+    /// The percentile of the recent fees to pay, from 0 to 100.
+    pub percentile: u8,
+    /// Bounds of the price, in micro-lamports per compute unit.
+    pub min_price: u64,
+    pub max_price: u64,
+}
+
+impl Default for PriorityFeeEstimator {
+    fn default() -> Self {
+        Self {
+            percentile: 75,
+            min_price: 0,
+            max_price: 100_000,
+        }
+    }
+}
+
+impl PriorityFeeEstimator {
+    /// Returns the price to pay, in micro-lamports per compute unit, for a
+    /// transaction writing to the given accounts.
+    pub fn estimate(&self, client: &RpcClient, writable_accounts: &[Pubkey]) -> Result<u64> {
+        let fees = client.get_recent_prioritization_fees(writable_accounts)?;
+        Ok(self.price(fees.iter().map(|fee| fee.prioritization_fee).collect()))
+    }
+
+    /// Returns the configured percentile of the given recent fees, within the
+    /// bounds.
+    pub fn price(&self, mut fees: Vec<u64>) -> u64 {
+        fees.sort_unstable();
+        let percentile = self.percentile.min(100) as usize;
+        let fee = match fees.len() {
+            0 => 0,
+            len => fees[(len - 1) * percentile / 100],
+        };
+        fee.max(self.min_price).min(self.max_price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_fee() {
+        let estimator = PriorityFeeEstimator {
+            percentile: 50,
+            min_price: 10,
+            max_price: 1_000,
+        };
+        assert_eq!(estimator.price(vec![300, 0, 100, 200, 5_000]), 200);
+        assert_eq!(estimator.price(Vec::new()), 10);
+        let estimator = PriorityFeeEstimator {
+            percentile: 100,
+            ..estimator
+        };
+        assert_eq!(estimator.price(vec![300, 0, 100, 200, 5_000]), 1_000);
+
+        assert_eq!(consume_events_compute_units(10, 32, false), 296_000);
+        assert_eq!(
+            consume_events_compute_units(10, u16::MAX, true),
+            MAX_COMPUTE_UNITS
+        );
+        assert_eq!(settle_funds_compute_units(true), 80_000);
+    }
+}
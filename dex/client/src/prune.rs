@@ -0,0 +1,402 @@
+use crate::{prune_ix, MarketInfo, ProxyRoute};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serum_dex::instruction::{MarketInstruction, NewOrderInstructionV3};
+use serum_dex::state::ACCOUNT_HEAD_PADDING;
+use serum_dex_permissioned::{BookSide, ProxyHeader, RestingOrder};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Why the orders of an open orders account are pruned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PruneReason {
+    /// One of its orders rests past its `max_ts`.
+    Expired { client_order_id: u64, max_ts: i64 },
+    /// Its last heartbeat is older than the heartbeat timeout.
+    MissedHeartbeat { last_heartbeat: i64 },
+}
+
+/// An open orders account whose orders are to be pruned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PruneCandidate {
+    pub open_orders: Pubkey,
+    pub reason: PruneReason,
+}
+
+/// Finds the open orders accounts of a book to prune: those with an order
+/// resting past its `max_ts`, and those whose owner's dead-man heartbeat
+/// timed out.
+///
+/// The DEX only checks the `max_ts` of an order when placing it, and doesn't
+/// store it on the book, so expiries are recorded from the orders placed,
+/// e.g. by the services of a venue or from a transaction stream, with
+/// `record_order` or `record_instruction`. Likewise, heartbeats are recorded
+/// with `heartbeat`, and only the accounts with one are pruned for missing
+/// it. `Prune` cancels all the orders of an account, not only the expired
+/// one.
+#[derive(Clone, Debug, Default)]
+pub struct PruneScanner {
+    /// Seconds after an account's last heartbeat from which its orders are
+    /// pruned, if any.
+    pub heartbeat_timeout: Option<i64>,
+    // The `max_ts` of the orders, by open orders account and client order
+    // id.
+    expiries: HashMap<(Pubkey, u64), i64>,
+    heartbeats: HashMap<Pubkey, i64>,
+}
+
+impl PruneScanner {
+    pub fn new(heartbeat_timeout: Option<i64>) -> Self {
+        Self {
+            heartbeat_timeout,
+            ..Self::default()
+        }
+    }
+
+    /// Records the `max_ts` of an order placed from the given open orders
+    /// account, unless it never expires or has no client order id to find it
+    /// on the book with.
+    pub fn record_order(&mut self, open_orders: &Pubkey, order: &NewOrderInstructionV3) {
+        if order.max_ts < i64::MAX && order.client_order_id != 0 {
+            self.expiries
+                .insert((*open_orders, order.client_order_id), order.max_ts);
+        }
+    }
+
+    /// Records the order placed by the given instruction, if a `NewOrderV3`, a
+    /// `NewOraclePeggedOrder` or a `NewGoodTilTimeOrder` sent to the given
+    /// DEX, either directly or through a proxy, returning whether it was one.
+    pub fn record_instruction(&mut self, dex_program_id: &Pubkey, ix: &Instruction) -> bool {
+        let mut data = &ix.data[..];
+        let accounts = if ix.program_id == *dex_program_id {
+            &ix.accounts[..]
+        } else {
+            // Proxied requests start with the DEX program and the header.
+            match ix.accounts.split_first() {
+                Some((dex, accounts)) if dex.pubkey == *dex_program_id => {
+                    if ProxyHeader::unpack(&mut data).is_err() {
+                        return false;
+                    }
+                    accounts
+                }
+                _ => return false,
+            }
+        };
+        match (MarketInstruction::unpack(data), accounts.get(1)) {
+            (Some(MarketInstruction::NewOrderV3(order)), Some(open_orders)) => {
+                self.record_order(&open_orders.pubkey, &order);
+                true
+            }
+            (Some(MarketInstruction::NewOraclePeggedOrder(pegged)), Some(open_orders)) => {
+                self.record_order(&open_orders.pubkey, &pegged.order);
+                true
+            }
+            (Some(MarketInstruction::NewGoodTilTimeOrder(gtt)), Some(open_orders)) => {
+                self.record_order(&open_orders.pubkey, &gtt.order);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records a heartbeat of the owner of the given open orders account at
+    /// the given unix timestamp.
+    pub fn heartbeat(&mut self, open_orders: &Pubkey, unix_timestamp: i64) {
+        let last = self
+            .heartbeats
+            .entry(*open_orders)
+            .or_insert(unix_timestamp);
+        *last = unix_timestamp.max(*last);
+    }
+
+    /// Stops pruning the given open orders account for missing heartbeats.
+    pub fn remove_heartbeat(&mut self, open_orders: &Pubkey) -> Option<i64> {
+        self.heartbeats.remove(open_orders)
+    }
+
+    /// Returns the accounts to prune of the given resting orders, e.g. of
+    /// `BookSide::orders`, at the given unix timestamp, once each, and forgets
+    /// the expired orders no longer resting.
+    pub fn scan(
+        &mut self,
+        resting: impl IntoIterator<Item = RestingOrder>,
+        now: i64,
+    ) -> Vec<PruneCandidate> {
+        let mut live = HashSet::new();
+        let mut candidates = Vec::new();
+        let mut pruned = HashSet::new();
+        for order in resting {
+            live.insert((order.owner, order.client_order_id));
+            if pruned.contains(&order.owner) {
+                continue;
+            }
+            let reason = match self.reason(&order, now) {
+                Some(reason) => reason,
+                None => continue,
+            };
+            pruned.insert(order.owner);
+            candidates.push(PruneCandidate {
+                open_orders: order.owner,
+                reason,
+            });
+        }
+        // Orders not yet resting may still land.
+        self.expiries
+            .retain(|key, max_ts| *max_ts >= now || live.contains(key));
+        candidates
+    }
+
+    fn reason(&self, order: &RestingOrder, now: i64) -> Option<PruneReason> {
+        if let Some(max_ts) = self.expiries.get(&(order.owner, order.client_order_id)) {
+            if now > *max_ts {
+                return Some(PruneReason::Expired {
+                    client_order_id: order.client_order_id,
+                    max_ts: *max_ts,
+                });
+            }
+        }
+        let timeout = self.heartbeat_timeout?;
+        let last_heartbeat = *self.heartbeats.get(&order.owner)?;
+        if now.saturating_sub(last_heartbeat) > timeout {
+            return Some(PruneReason::MissedHeartbeat { last_heartbeat });
+        }
+        None
+    }
+}
+
+/// Prunes the accounts the `scanner` finds on a market, with `Prune`s
+/// signed by the market's prune authority, through the market's proxy if
+/// any, paid by `payer`.
+pub struct PruneBot {
+    pub client: RpcClient,
+    pub payer: Keypair,
+    pub prune_authority: Keypair,
+    pub market: MarketInfo,
+    pub proxy: Option<ProxyRoute>,
+    pub scanner: PruneScanner,
+    /// Orders cancelled by each `Prune`.
+    pub limit: u16,
+    /// Priority fee, in micro-lamports per compute unit.
+    pub compute_unit_price: Option<u64>,
+}
+
+impl PruneBot {
+    /// Prunes the market's book at the given interval, until an RPC error
+    /// other than a failed transaction.
+    pub fn run(&mut self, interval: Duration) -> Result<()> {
+        loop {
+            self.poll()?;
+            thread::sleep(interval);
+        }
+    }
+
+    /// Scans the market's book, then prunes the accounts found, returning
+    /// those pruned.
+    pub fn poll(&mut self) -> Result<Vec<PruneCandidate>> {
+        let header = &self.market.header;
+        let accounts = self
+            .client
+            .get_multiple_accounts(&[header.bids, header.asks])?;
+        let data = |i: usize, key: &Pubkey| {
+            accounts[i]
+                .as_ref()
+                .map(|account| &account.data[..])
+                .ok_or_else(|| anyhow!("missing book side {}", key))
+        };
+        let bids = BookSide::unpack(data(0, &header.bids)?)
+            .map_err(|e| anyhow!("invalid bids {}: {:?}", header.bids, e))?;
+        let asks = BookSide::unpack(data(1, &header.asks)?)
+            .map_err(|e| anyhow!("invalid asks {}: {:?}", header.asks, e))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let candidates = self.scanner.scan(bids.orders().chain(asks.orders()), now);
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+
+        let keys: Vec<_> = candidates.iter().map(|c| c.open_orders).collect();
+        let mut accounts = Vec::with_capacity(keys.len());
+        for keys in keys.chunks(100) {
+            accounts.extend(self.client.get_multiple_accounts(keys)?);
+        }
+        let mut pruned = Vec::new();
+        for (candidate, account) in candidates.into_iter().zip(accounts) {
+            let owner = account.and_then(|account| open_orders_owner(&account.data));
+            let owner = match owner {
+                Some(owner) => owner,
+                None => {
+                    warn!("invalid open orders {}", candidate.open_orders);
+                    continue;
+                }
+            };
+            match self.prune(&candidate.open_orders, &owner) {
+                Ok(signature) => {
+                    let (open_orders, reason) = (candidate.open_orders, candidate.reason);
+                    info!("pruned {} ({:?}): {}", open_orders, reason, signature);
+                    pruned.push(candidate);
+                }
+                Err(e) => warn!("pruning {} failed: {}", candidate.open_orders, e),
+            }
+        }
+        Ok(pruned)
+    }
+
+    fn prune(&self, open_orders: &Pubkey, owner: &Pubkey) -> Result<Signature> {
+        let ix = prune_ix(
+            &self.market,
+            &self.prune_authority.pubkey(),
+            open_orders,
+            owner,
+            self.limit,
+        )?;
+        let mut ixs = Vec::new();
+        if let Some(price) = self.compute_unit_price {
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+        }
+        ixs.push(match &self.proxy {
+            Some(proxy) => proxy.route(ix),
+            None => ix,
+        });
+        let mut signers = vec![&self.payer];
+        if self.prune_authority.pubkey() != self.payer.pubkey() {
+            signers.push(&self.prune_authority);
+        }
+        let tx = Transaction::new_signed_with_payer(
+            &ixs,
+            Some(&self.payer.pubkey()),
+            &signers,
+            self.client.get_latest_blockhash()?,
+        );
+        Ok(self.client.send_and_confirm_transaction(&tx)?)
+    }
+}
+
+// Returns the owner of the given open orders account data, which follows
+// its account flags and market.
+fn open_orders_owner(data: &[u8]) -> Option<Pubkey> {
+    let data = data.strip_prefix(ACCOUNT_HEAD_PADDING)?;
+    let owner: [u8; 32] = data.get(40..72)?.try_into().ok()?;
+    Some(Pubkey::new_from_array(owner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_order_ix;
+    use serum_dex::matching::{OrderType, Side};
+    use serum_dex_permissioned::MarketHeader;
+
+    fn resting(owner: Pubkey, client_order_id: u64) -> RestingOrder {
+        RestingOrder {
+            order_id: client_order_id as u128,
+            owner,
+            owner_slot: 0,
+            client_order_id,
+            price: 10,
+            size: 1,
+            side: Side::Bid,
+            peg_offset: None,
+            expiry_ts: None,
+        }
+    }
+
+    #[test]
+    fn test_prune_scanner() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        let market = MarketInfo::new(&program_id, header, 0, 0).unwrap();
+        let alice = Pubkey::new_unique();
+        let bob = Pubkey::new_unique();
+        let carol = Pubkey::new_unique();
+        let mut scanner = PruneScanner::new(Some(30));
+
+        // Alice's order 1 expires at 100, through a proxy.
+        let mut order = market
+            .new_order(Side::Bid, 10.0, 1.0, OrderType::Limit, 1)
+            .unwrap();
+        order.max_ts = 100;
+        let ix = new_order_ix(
+            &market,
+            &alice,
+            &Pubkey::new_unique(),
+            &alice,
+            order.clone(),
+        );
+        let ix = ix.unwrap();
+        let proxy = ProxyRoute::new(Pubkey::new_unique());
+        assert!(scanner.record_instruction(&program_id, &proxy.route(ix)));
+        let prune = prune_ix(&market, &alice, &alice, &alice, 1).unwrap();
+        assert!(!scanner.record_instruction(&program_id, &prune));
+        // Bob's order 2 expires at 200, but isn't resting yet.
+        order.client_order_id = 2;
+        order.max_ts = 200;
+        scanner.record_order(&bob, &order);
+        scanner.heartbeat(&carol, 50);
+
+        let book = || vec![resting(alice, 1), resting(alice, 3), resting(carol, 4)];
+        assert_eq!(scanner.scan(book(), 80), vec![]);
+        assert_eq!(
+            scanner.scan(book(), 101),
+            vec![
+                PruneCandidate {
+                    open_orders: alice,
+                    reason: PruneReason::Expired {
+                        client_order_id: 1,
+                        max_ts: 100,
+                    },
+                },
+                PruneCandidate {
+                    open_orders: carol,
+                    reason: PruneReason::MissedHeartbeat { last_heartbeat: 50 },
+                },
+            ]
+        );
+        assert_eq!(scanner.expiries.len(), 2);
+
+        // Once pruned, and Bob's order landed and expired.
+        scanner.heartbeat(&carol, 200);
+        let book = vec![resting(bob, 2), resting(carol, 4)];
+        let candidates = scanner.scan(book, 201);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].open_orders, bob);
+        assert_eq!(scanner.expiries.len(), 1);
+
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        data.extend_from_slice(&[0; 40]);
+        data.extend_from_slice(carol.as_ref());
+        assert_eq!(open_orders_owner(&data), Some(carol));
+        assert_eq!(open_orders_owner(&data[..50]), None);
+    }
+}
@@ -0,0 +1,763 @@
+use crate::{IndexedFill, MarketInfo};
+use anyhow::{anyhow, Result};
+use log::warn;
+use serum_dex::instruction::MarketInstruction;
+use serum_dex::matching::Side;
+use serum_dex::state::{AccountFlag, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
+use serum_dex_permissioned::{market_state_view, ProxyHeader};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient};
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionConfig,
+};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::instruction::CompiledInstruction;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status_client_types::{
+    UiLoadedAddresses, UiTransactionEncoding, UiTransactionTokenBalance,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::ops::{Add, Sub};
+
+/// Coin and pc amounts in native units, or changes of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NativeAmounts {
+    pub coin: i128,
+    pub pc: i128,
+}
+
+impl NativeAmounts {
+    pub fn is_zero(&self) -> bool {
+        self.coin == 0 && self.pc == 0
+    }
+}
+
+impl Add for NativeAmounts {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            coin: self.coin + other.coin,
+            pc: self.pc + other.pc,
+        }
+    }
+}
+
+impl Sub for NativeAmounts {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            coin: self.coin - other.coin,
+            pc: self.pc - other.pc,
+        }
+    }
+}
+
+/// The change the given fill makes to the totals of its open orders
+/// account once its event is consumed: the maker pays the pc less its
+/// rebate, or receives it with the rebate, and the taker pays it with its
+/// fee, or receives it less the fee.
+pub fn fill_amounts(fill: &IndexedFill) -> NativeAmounts {
+    let coin = fill.native_coin_qty as i128;
+    let pc = fill.native_pc_qty as i128;
+    let fee = fill.native_fee_or_rebate as i128;
+    match (fill.side, fill.maker) {
+        (Side::Bid, true) => NativeAmounts { coin, pc: fee - pc },
+        (Side::Bid, false) => NativeAmounts {
+            coin,
+            pc: -pc - fee,
+        },
+        (Side::Ask, true) => NativeAmounts {
+            coin: -coin,
+            pc: pc + fee,
+        },
+        (Side::Ask, false) => NativeAmounts {
+            coin: -coin,
+            pc: pc - fee,
+        },
+    }
+}
+
+/// The tokens a transaction moved into the market's vaults, when placing
+/// orders, or out of them, when settling, for the owner of an open orders
+/// account: deposits are positive and settlements negative.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VaultTransfer {
+    pub signature: Signature,
+    pub block_time: i64,
+    /// The open orders account the transfer is for, unless the transaction
+    /// moved the vaults' tokens otherwise, e.g. with `SweepFees` or
+    /// `SendTake`, or for several accounts.
+    pub open_orders: Option<Pubkey>,
+    pub amounts: NativeAmounts,
+}
+
+/// The totals of an open orders account over a time range: those at the
+/// start, plus the fills and transfers of the range, are expected to be
+/// those on chain at its end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountReconciliation {
+    pub open_orders: Pubkey,
+    pub start: NativeAmounts,
+    pub fills: NativeAmounts,
+    pub transfers: NativeAmounts,
+    pub actual: NativeAmounts,
+}
+
+impl AccountReconciliation {
+    pub fn expected(&self) -> NativeAmounts {
+        self.start + self.fills + self.transfers
+    }
+
+    /// The totals on chain beyond those expected, negative when missing.
+    pub fn discrepancy(&self) -> NativeAmounts {
+        self.actual - self.expected()
+    }
+}
+
+/// Reconciles the totals of the open orders accounts of a market starting
+/// with `start` with the given fills and transfers of a time range, by
+/// account. Transfers not attributed to an account are skipped.
+pub fn reconcile_accounts(
+    start: &HashMap<Pubkey, NativeAmounts>,
+    fills: &[IndexedFill],
+    transfers: &[VaultTransfer],
+    actual: &HashMap<Pubkey, NativeAmounts>,
+) -> Vec<AccountReconciliation> {
+    fn account<'a>(
+        accounts: &'a mut BTreeMap<Pubkey, AccountReconciliation>,
+        open_orders: &Pubkey,
+    ) -> &'a mut AccountReconciliation {
+        accounts
+            .entry(*open_orders)
+            .or_insert_with(|| AccountReconciliation {
+                open_orders: *open_orders,
+                start: NativeAmounts::default(),
+                fills: NativeAmounts::default(),
+                transfers: NativeAmounts::default(),
+                actual: NativeAmounts::default(),
+            })
+    }
+    let mut accounts = BTreeMap::new();
+    for (open_orders, amounts) in start {
+        account(&mut accounts, open_orders).start = *amounts;
+    }
+    for (open_orders, amounts) in actual {
+        account(&mut accounts, open_orders).actual = *amounts;
+    }
+    for fill in fills {
+        let account = account(&mut accounts, &fill.open_orders);
+        account.fills = account.fills + fill_amounts(fill);
+    }
+    for transfer in transfers {
+        if let Some(open_orders) = &transfer.open_orders {
+            let account = account(&mut accounts, open_orders);
+            account.transfers = account.transfers + transfer.amounts;
+        }
+    }
+    accounts.into_values().collect()
+}
+
+/// The balances of a market's vaults against what the market owes: the
+/// deposits of its open orders accounts, and the fees and referrer rebates
+/// accrued and not yet swept or settled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaultReconciliation {
+    pub coin_vault: u64,
+    pub pc_vault: u64,
+    pub coin_deposits_total: u64,
+    pub pc_deposits_total: u64,
+    pub pc_fees_accrued: u64,
+    pub referrer_rebates_accrued: u64,
+    /// The sum of the totals of the market's open orders accounts.
+    pub open_orders_total: NativeAmounts,
+}
+
+impl VaultReconciliation {
+    /// The vaults' balances beyond what the market owes, negative when
+    /// missing.
+    pub fn discrepancy(&self) -> NativeAmounts {
+        let owed_pc = self.pc_deposits_total as i128
+            + self.pc_fees_accrued as i128
+            + self.referrer_rebates_accrued as i128;
+        NativeAmounts {
+            coin: self.coin_vault as i128 - self.coin_deposits_total as i128,
+            pc: self.pc_vault as i128 - owed_pc,
+        }
+    }
+
+    /// The deposits of the market not in the totals of its open orders
+    /// accounts, which are those of fills whose events aren't consumed yet.
+    pub fn unconsumed(&self) -> NativeAmounts {
+        let deposits = NativeAmounts {
+            coin: self.coin_deposits_total as i128,
+            pc: self.pc_deposits_total as i128,
+        };
+        deposits - self.open_orders_total
+    }
+}
+
+/// The reconciliation of a market over a time range ending now.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconciliationReport {
+    pub market: Pubkey,
+    pub from: i64,
+    pub to: i64,
+    pub accounts: Vec<AccountReconciliation>,
+    /// The transfers not attributed to an open orders account.
+    pub unattributed: Vec<VaultTransfer>,
+    pub vaults: VaultReconciliation,
+}
+
+impl ReconciliationReport {
+    /// The accounts whose totals on chain aren't those expected.
+    pub fn discrepancies(&self) -> impl Iterator<Item = &AccountReconciliation> {
+        self.accounts
+            .iter()
+            .filter(|account| !account.discrepancy().is_zero())
+    }
+
+    /// Whether the accounts and vaults all reconcile.
+    pub fn is_reconciled(&self) -> bool {
+        self.discrepancies().next().is_none() && self.vaults.discrepancy().is_zero()
+    }
+}
+
+/// Reconciles the fills of a market's open orders accounts, e.g. of a
+/// `FillStore`, and the deposits and settlements of the market's
+/// transactions with the accounts' totals and the vaults' balances on chain,
+/// for operators auditing a venue. Transfers are the changes of the vaults'
+/// balances in the transactions placing orders or settling funds, directly
+/// or through a proxy, less the referrer rebates settled.
+///
+/// The totals on chain are those at the time of the report, so the range
+/// reconciled ends then, and the fills of events not yet consumed show up
+/// as discrepancies until the crank consumes them.
+pub struct Reconciler {
+    pub client: RpcClient,
+    pub market: MarketInfo,
+}
+
+impl Reconciler {
+    /// Reconciles the market from `from`, with the given totals at that time
+    /// (none for a new market), and the given fills since.
+    pub fn report(
+        &self,
+        from: i64,
+        start: &HashMap<Pubkey, NativeAmounts>,
+        fills: &[IndexedFill],
+    ) -> Result<ReconciliationReport> {
+        let actual = self.open_orders_totals()?;
+        let open_orders_total = actual
+            .values()
+            .fold(NativeAmounts::default(), |total, amounts| total + *amounts);
+        let vaults = self.vaults(open_orders_total)?;
+        let slot = self.client.get_slot()?;
+        let to = self.client.get_block_time(slot)?;
+        let transfers = self.transfers(from, to)?;
+        let (attributed, unattributed) = transfers
+            .into_iter()
+            .partition::<Vec<_>, _>(|transfer| transfer.open_orders.is_some());
+        Ok(ReconciliationReport {
+            market: *self.market.address(),
+            from,
+            to,
+            accounts: reconcile_accounts(start, fills, &attributed, &actual),
+            unattributed,
+            vaults,
+        })
+    }
+
+    /// Returns the totals of the market's open orders accounts.
+    pub fn open_orders_totals(&self) -> Result<HashMap<Pubkey, NativeAmounts>> {
+        let market = self.market.address().to_bytes().to_vec();
+        // The account flags precede the market.
+        let filters = vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            ACCOUNT_HEAD_PADDING.len() + 8,
+            market,
+        ))];
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = self
+            .client
+            .get_program_accounts_with_config(&self.market.program_id, config)?;
+        Ok(accounts
+            .iter()
+            .filter_map(|(key, account)| Some((*key, unpack_totals(&account.data)?)))
+            .collect())
+    }
+
+    /// Returns the balances of the market's vaults and its accounting, with
+    /// the given sum of the totals of its open orders accounts.
+    pub fn vaults(&self, open_orders_total: NativeAmounts) -> Result<VaultReconciliation> {
+        let header = &self.market.header;
+        let keys = [header.own_address, header.coin_vault, header.pc_vault];
+        let mut accounts = self.client.get_multiple_accounts(&keys)?.into_iter();
+        let mut data = |key: &Pubkey| {
+            accounts
+                .next()
+                .flatten()
+                .map(|account| account.data)
+                .ok_or_else(|| anyhow!("missing account {}", key))
+        };
+        let market = data(&keys[0])?;
+        let state = market_state_view(&market)
+            .map_err(|e| anyhow!("invalid market {}: {:?}", keys[0], e))?;
+        let vault = |key: &Pubkey, data: &[u8]| {
+            spl_token::state::Account::unpack(data)
+                .map(|account| account.amount)
+                .map_err(|e| anyhow!("invalid vault {}: {:?}", key, e))
+        };
+        Ok(VaultReconciliation {
+            coin_vault: vault(&keys[1], &data(&keys[1])?)?,
+            pc_vault: vault(&keys[2], &data(&keys[2])?)?,
+            coin_deposits_total: state.coin_deposits_total,
+            pc_deposits_total: state.pc_deposits_total,
+            pc_fees_accrued: state.pc_fees_accrued,
+            referrer_rebates_accrued: state.referrer_rebates_accrued,
+            open_orders_total,
+        })
+    }
+
+    /// Returns the transfers of the market's successful transactions between
+    /// the given unix timestamps, inclusive, oldest first.
+    pub fn transfers(&self, from: i64, to: i64) -> Result<Vec<VaultTransfer>> {
+        let mut transfers = Vec::new();
+        let mut before = None;
+        loop {
+            let config = GetConfirmedSignaturesForAddress2Config {
+                before,
+                commitment: Some(self.client.commitment()),
+                ..GetConfirmedSignaturesForAddress2Config::default()
+            };
+            let statuses = self
+                .client
+                .get_signatures_for_address_with_config(self.market.address(), config)?;
+            let last = match statuses.last() {
+                Some(status) => status.signature.parse()?,
+                None => break,
+            };
+            for status in &statuses {
+                let block_time = match status.block_time {
+                    Some(block_time) => block_time,
+                    None => {
+                        warn!(
+                            "skipping transaction {} without block time",
+                            status.signature
+                        );
+                        continue;
+                    }
+                };
+                if block_time < from {
+                    transfers.reverse();
+                    return Ok(transfers);
+                }
+                if block_time > to || status.err.is_some() {
+                    continue;
+                }
+                let signature = status.signature.parse()?;
+                transfers.extend(self.transfer(&signature, block_time)?);
+            }
+            before = Some(last);
+        }
+        transfers.reverse();
+        Ok(transfers)
+    }
+
+    // Returns the transfer of the given transaction, if it moved the vaults'
+    // tokens.
+    fn transfer(&self, signature: &Signature, block_time: i64) -> Result<Option<VaultTransfer>> {
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(self.client.commitment()),
+            max_supported_transaction_version: Some(0),
+        };
+        let tx = self
+            .client
+            .get_transaction_with_config(signature, config)?
+            .transaction;
+        let meta = tx
+            .meta
+            .ok_or_else(|| anyhow!("missing status of transaction {}", signature))?;
+        let message = tx
+            .transaction
+            .decode()
+            .ok_or_else(|| anyhow!("invalid transaction {}", signature))?
+            .message;
+        let mut keys = message.static_account_keys().to_vec();
+        let loaded: Option<UiLoadedAddresses> = meta.loaded_addresses.into();
+        if let Some(loaded) = loaded {
+            let loaded = loaded.writable.iter().chain(&loaded.readonly);
+            keys.extend(
+                loaded
+                    .map(|key| key.parse())
+                    .collect::<Result<Vec<Pubkey>, _>>()?,
+            );
+        }
+        let pre: Option<Vec<_>> = meta.pre_token_balances.into();
+        let post: Option<Vec<_>> = meta.post_token_balances.into();
+        let delta = |key: &Pubkey| -> Result<i128> {
+            let index = match keys.iter().position(|k| k == key) {
+                Some(index) => index,
+                None => return Ok(0),
+            };
+            let pre = token_balance(pre.as_deref().unwrap_or_default(), index)?;
+            let post = token_balance(post.as_deref().unwrap_or_default(), index)?;
+            Ok(post - pre)
+        };
+        let header = &self.market.header;
+        let mut amounts = NativeAmounts {
+            coin: delta(&header.coin_vault)?,
+            pc: delta(&header.pc_vault)?,
+        };
+        if amounts.is_zero() {
+            return Ok(None);
+        }
+        let (open_orders, referrers) = market_accounts(
+            &self.market.program_id,
+            &header.own_address,
+            &keys,
+            message.instructions(),
+        );
+        // The referrer rebates settled are paid from the vault, but not from
+        // the open orders account totals.
+        for referrer in referrers {
+            amounts.pc += delta(&referrer)?;
+        }
+        Ok(Some(VaultTransfer {
+            signature: *signature,
+            block_time,
+            open_orders: match open_orders[..] {
+                [open_orders] => Some(open_orders),
+                _ => None,
+            },
+            amounts,
+        }))
+    }
+}
+
+// Parses the totals of the given open orders account data.
+fn unpack_totals(data: &[u8]) -> Option<NativeAmounts> {
+    let data = data
+        .strip_prefix(ACCOUNT_HEAD_PADDING)?
+        .strip_suffix(ACCOUNT_TAIL_PADDING)?;
+    let u64_at = |offset: usize| {
+        let bytes = data.get(offset..offset + 8)?;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let flags = AccountFlag::Initialized as u64 | AccountFlag::OpenOrders as u64;
+    if u64_at(0)? & flags != flags || u64_at(0)? & AccountFlag::Closed as u64 != 0 {
+        return None;
+    }
+    Some(NativeAmounts {
+        coin: u64_at(80)? as i128,
+        pc: u64_at(96)? as i128,
+    })
+}
+
+// The token balance of the account of the given index, zero if none.
+fn token_balance(balances: &[UiTransactionTokenBalance], index: usize) -> Result<i128> {
+    match balances
+        .iter()
+        .find(|balance| balance.account_index as usize == index)
+    {
+        Some(balance) => Ok(balance.ui_token_amount.amount.parse()?),
+        None => Ok(0),
+    }
+}
+
+// Returns the open orders accounts the given instructions place orders or
+// settle funds of on the given market, directly or through a proxy, and the
+// referrer pc wallets settled to.
+fn market_accounts(
+    dex_program_id: &Pubkey,
+    market: &Pubkey,
+    keys: &[Pubkey],
+    ixs: &[CompiledInstruction],
+) -> (Vec<Pubkey>, Vec<Pubkey>) {
+    let (mut open_orders, mut referrers) = (Vec::new(), Vec::new());
+    for ix in ixs {
+        let key = |index: &u8| keys.get(*index as usize).copied().unwrap_or_default();
+        let mut data = &ix.data[..];
+        let mut accounts: Vec<Pubkey> = ix.accounts.iter().map(key).collect();
+        if key(&ix.program_id_index) != *dex_program_id {
+            // Proxied requests start with the DEX program and the header.
+            if accounts.first() != Some(dex_program_id) || ProxyHeader::unpack(&mut data).is_err() {
+                continue;
+            }
+            accounts.remove(0);
+        }
+        if accounts.first() != Some(market) || accounts.len() < 2 {
+            continue;
+        }
+        match MarketInstruction::unpack(data) {
+            Some(MarketInstruction::NewOrder(_))
+            | Some(MarketInstruction::NewOrderV2(_))
+            | Some(MarketInstruction::NewOrderV3(_))
+            | Some(MarketInstruction::NewOraclePeggedOrder(_))
+            | Some(MarketInstruction::NewGoodTilTimeOrder(_)) => {}
+            Some(MarketInstruction::SettleFunds) => {
+                // The optional referrer pc wallet follows the token program.
+                if let Some(referrer) = accounts.get(9) {
+                    if Some(referrer) != accounts.get(6) && !referrers.contains(referrer) {
+                        referrers.push(*referrer);
+                    }
+                }
+            }
+            _ => continue,
+        }
+        if !open_orders.contains(&accounts[1]) {
+            open_orders.push(accounts[1]);
+        }
+    }
+    (open_orders, referrers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use serde_json::{json, Value};
+    use serum_dex::instruction::settle_funds;
+    use serum_dex_permissioned::MarketHeader;
+    use solana_client::rpc_request::RpcRequest;
+
+    fn market() -> MarketInfo {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 10,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        MarketInfo::new(&program_id, header, 0, 0).unwrap()
+    }
+
+    // The response to a `getSignaturesForAddress` of the given block times
+    // and whether each transaction failed.
+    fn signatures(statuses: &[(Option<i64>, bool)]) -> (RpcRequest, Value) {
+        let statuses: Vec<_> = statuses
+            .iter()
+            .map(|(block_time, failed)| {
+                let err = failed.then(|| json!({ "InstructionError": [0, { "Custom": 0 }] }));
+                json!({
+                    "signature": Signature::new_unique().to_string(),
+                    "slot": 1,
+                    "err": err,
+                    "memo": null,
+                    "blockTime": block_time,
+                    "confirmationStatus": "finalized",
+                })
+            })
+            .collect();
+        (RpcRequest::GetSignaturesForAddress, json!(statuses))
+    }
+
+    #[test]
+    fn test_reconcile_accounts() {
+        let market = Pubkey::new_unique();
+        let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let fill = |side, maker, open_orders| IndexedFill {
+            market,
+            seq_num: 0,
+            timestamp: 0,
+            side,
+            maker,
+            open_orders,
+            wallet: None,
+            order_id: 0,
+            client_order_id: 0,
+            native_coin_qty: 10,
+            native_pc_qty: 100,
+            native_fee_or_rebate: 1,
+        };
+        let fills = [fill(Side::Bid, true, maker), fill(Side::Ask, false, taker)];
+        assert_eq!(fill_amounts(&fills[0]), NativeAmounts { coin: 10, pc: -99 });
+        assert_eq!(fill_amounts(&fills[1]), NativeAmounts { coin: -10, pc: 99 });
+
+        // The maker deposited its pc, and the taker its coin then settled.
+        let transfer = |open_orders, coin, pc| VaultTransfer {
+            signature: Signature::default(),
+            block_time: 0,
+            open_orders,
+            amounts: NativeAmounts { coin, pc },
+        };
+        let transfers = [
+            transfer(Some(maker), 0, 99),
+            transfer(Some(taker), 10, 0),
+            transfer(Some(taker), 0, -90),
+            transfer(None, 0, -1),
+        ];
+        let start = HashMap::from([(maker, NativeAmounts { coin: 5, pc: 0 })]);
+        let actual = HashMap::from([
+            (maker, NativeAmounts { coin: 15, pc: 0 }),
+            (taker, NativeAmounts { coin: 0, pc: 8 }),
+        ]);
+        let mut accounts = reconcile_accounts(&start, &fills, &transfers, &actual);
+        accounts.sort_by_key(|account| account.open_orders != maker);
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].expected(), NativeAmounts { coin: 15, pc: 0 });
+        assert!(accounts[0].discrepancy().is_zero());
+        assert_eq!(accounts[1].discrepancy(), NativeAmounts { coin: 0, pc: -1 });
+
+        let vaults = VaultReconciliation {
+            coin_vault: 15,
+            pc_vault: 12,
+            coin_deposits_total: 15,
+            pc_deposits_total: 9,
+            pc_fees_accrued: 2,
+            referrer_rebates_accrued: 1,
+            open_orders_total: NativeAmounts { coin: 15, pc: 8 },
+        };
+        assert!(vaults.discrepancy().is_zero());
+        assert_eq!(vaults.unconsumed(), NativeAmounts { coin: 0, pc: 1 });
+    }
+
+    #[test]
+    fn test_market_accounts() {
+        let (dex, market) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let open_orders = Pubkey::new_unique();
+        let referrer = Pubkey::new_unique();
+        let key = || Pubkey::new_unique();
+        let ix = settle_funds(
+            &dex,
+            &market,
+            &spl_token::ID,
+            &open_orders,
+            &key(),
+            &key(),
+            &key(),
+            &key(),
+            &key(),
+            Some(&referrer),
+            &key(),
+        )
+        .unwrap();
+        let mut keys = vec![dex];
+        let accounts = ix
+            .accounts
+            .iter()
+            .map(|meta| {
+                keys.push(meta.pubkey);
+                keys.len() as u8 - 1
+            })
+            .collect();
+        let compiled = CompiledInstruction::new_from_raw_parts(0, ix.data, accounts);
+        assert_eq!(
+            market_accounts(&dex, &market, &keys, std::slice::from_ref(&compiled)),
+            (vec![open_orders], vec![referrer])
+        );
+        assert_eq!(
+            market_accounts(&dex, &key(), &keys, &[compiled]),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn test_unpack_totals() {
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::OpenOrders as u64;
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.resize(ACCOUNT_HEAD_PADDING.len() + 104, 0);
+        data[5 + 80..5 + 88].copy_from_slice(&3u64.to_le_bytes());
+        data[5 + 96..5 + 104].copy_from_slice(&4u64.to_le_bytes());
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        assert_eq!(unpack_totals(&data), Some(NativeAmounts { coin: 3, pc: 4 }));
+
+        // Truncated, closed and other accounts aren't open orders accounts.
+        let len = data.len();
+        let mut truncated = data[..len - 16].to_vec();
+        truncated.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        assert_eq!(unpack_totals(&truncated), None);
+        let mut closed = data.clone();
+        let flags = flags | AccountFlag::Closed as u64;
+        closed[5..13].copy_from_slice(&flags.to_le_bytes());
+        assert_eq!(unpack_totals(&closed), None);
+        data[5] = AccountFlag::Initialized as u8;
+        assert_eq!(unpack_totals(&data), None);
+    }
+
+    #[test]
+    fn test_reconciler_failures() {
+        let market = market();
+        let reconciler = |client| Reconciler {
+            client,
+            market: market.clone(),
+        };
+
+        // RPC errors are returned, as are missing or invalid accounts.
+        let failing = reconciler(RpcClient::new_mock("fails".to_string()));
+        assert!(failing.open_orders_totals().is_err());
+        assert!(failing.vaults(NativeAmounts::default()).is_err());
+        assert!(failing.transfers(0, 10).is_err());
+        let missing = reconciler(RpcClient::new_mock("succeeds".to_string()));
+        let err = missing.vaults(NativeAmounts::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("missing account {}", market.address())
+        );
+        let data = [0; 8];
+        let accounts = [Some((&market.program_id, &data[..])), None, None];
+        let mocks = vec![(
+            RpcRequest::GetMultipleAccounts,
+            mock::multiple_accounts(&accounts),
+        )];
+        let invalid = reconciler(RpcClient::new_mock_with_mocks_map(
+            "succeeds".to_string(),
+            mocks.into_iter().collect(),
+        ));
+        let err = invalid.vaults(NativeAmounts::default()).unwrap_err();
+        assert!(err.to_string().starts_with("invalid market"));
+
+        // Transactions after the range, failed, or without a block time are
+        // skipped, over pages of signatures, down to the first before the
+        // range, without fetching any transaction.
+        let mocks = vec![
+            signatures(&[(Some(30), false), (Some(15), true), (None, false)]),
+            signatures(&[(Some(5), false), (Some(4), false)]),
+        ];
+        let client =
+            RpcClient::new_mock_with_mocks_map("fails".to_string(), mocks.into_iter().collect());
+        assert_eq!(reconciler(client).transfers(10, 20).unwrap(), vec![]);
+        // Nor past the last page.
+        let mocks = vec![signatures(&[(Some(30), false)]), signatures(&[])];
+        let client =
+            RpcClient::new_mock_with_mocks_map("fails".to_string(), mocks.into_iter().collect());
+        assert_eq!(reconciler(client).transfers(10, 20).unwrap(), vec![]);
+        // The transactions of the range are fetched.
+        let mocks = vec![signatures(&[(Some(15), false)])];
+        let client =
+            RpcClient::new_mock_with_mocks_map("fails".to_string(), mocks.into_iter().collect());
+        assert!(reconciler(client).transfers(10, 20).is_err());
+    }
+}
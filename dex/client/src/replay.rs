@@ -0,0 +1,701 @@
+use crate::{simulate_order, Fill, FillTracker, MarketInfo};
+use anyhow::{anyhow, Result};
+use log::warn;
+use serum_dex::fees::FeeTier;
+use serum_dex::instruction::NewOrderInstructionV3;
+use serum_dex::matching::{extract_peg_offset_from_order_id, OrderType, Side};
+use serum_dex_permissioned::{BookSide, EventQueueReader, L2Level, QueueEvent};
+use solana_client::rpc_client::RpcClient;
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The orders an open orders account holds at once.
+const ORDER_SLOTS: usize = 128;
+
+/// The data of a market's bids, asks and event queue accounts at a slot, as
+/// recorded for `ReplaySimulator`s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookSnapshot {
+    pub slot: u64,
+    /// Unix time the snapshot was taken at, in seconds.
+    pub timestamp: i64,
+    pub bids: Vec<u8>,
+    pub asks: Vec<u8>,
+    pub event_queue: Vec<u8>,
+}
+
+impl BookSnapshot {
+    /// Fetches the market's book and event queue, in a single request so
+    /// that they're of the same slot.
+    pub fn load(client: &RpcClient, market: &MarketInfo) -> Result<Self> {
+        let header = &market.header;
+        let keys = [header.bids, header.asks, header.event_q];
+        let response = client.get_multiple_accounts_with_commitment(&keys, client.commitment())?;
+        let mut values = response.value.into_iter();
+        let mut accounts = keys.iter().map(|key| {
+            values
+                .next()
+                .flatten()
+                .map(|account| account.data)
+                .ok_or_else(|| anyhow!("missing account {}", key))
+        });
+        Ok(Self {
+            slot: response.context.slot,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            bids: accounts.next().unwrap()?,
+            asks: accounts.next().unwrap()?,
+            event_queue: accounts.next().unwrap()?,
+        })
+    }
+
+    /// Appends the snapshot to a recording: the little endian slot and
+    /// timestamp, followed by the data of each account, prefixed with its
+    /// little endian `u32` length.
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.slot.to_le_bytes())?;
+        writer.write_all(&self.timestamp.to_le_bytes())?;
+        for data in [&self.bids, &self.asks, &self.event_queue] {
+            writer.write_all(&u32::try_from(data.len())?.to_le_bytes())?;
+            writer.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next snapshot of a recording, if any.
+    pub fn read(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut slot = [0; 8];
+        match reader.read_exact(&mut slot) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut timestamp = [0; 8];
+        reader.read_exact(&mut timestamp)?;
+        let mut data = || -> Result<Vec<u8>> {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let mut data = vec![0; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut data)?;
+            Ok(data)
+        };
+        Ok(Some(Self {
+            slot: u64::from_le_bytes(slot),
+            timestamp: i64::from_le_bytes(timestamp),
+            bids: data()?,
+            asks: data()?,
+            event_queue: data()?,
+        }))
+    }
+}
+
+/// The paper balances of a `ReplaySimulator`, in native units, as those of
+/// an open orders account: the free balances fund orders, and the totals
+/// include those locked in resting orders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PaperBalances {
+    pub native_coin_free: u64,
+    pub native_coin_total: u64,
+    pub native_pc_free: u64,
+    pub native_pc_total: u64,
+}
+
+impl PaperBalances {
+    /// Free balances of the given native coin and pc.
+    pub fn new(native_coin: u64, native_pc: u64) -> Self {
+        Self {
+            native_coin_free: native_coin,
+            native_coin_total: native_coin,
+            native_pc_free: native_pc,
+            native_pc_total: native_pc,
+        }
+    }
+}
+
+/// A paper order resting on the book, in lots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaperOrder {
+    pub client_order_id: u64,
+    pub side: Side,
+    pub price: u64,
+    /// The coin lots left to fill.
+    pub size: u64,
+    /// The coin lots resting at the price ahead of the order, filled first.
+    pub queue_ahead: u64,
+}
+
+/// A fill of a paper order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaperFill {
+    /// The slot of the snapshot the fill was seen at.
+    pub slot: u64,
+    pub client_order_id: u64,
+    pub side: Side,
+    pub maker: bool,
+    /// The price lots of the fill, the worst traded with for takers.
+    pub price: u64,
+    pub coin_lots: u64,
+    /// Before the fee or rebate, as `Fill::native_pc_qty`.
+    pub native_pc_qty: u64,
+    pub native_fee_or_rebate: u64,
+}
+
+/// An action of a `Strategy` at a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaperAction {
+    Place(NewOrderInstructionV3),
+    /// Cancels the paper order of the given client order id.
+    Cancel(u64),
+}
+
+/// A trading strategy backtested by a `ReplaySimulator`.
+pub trait Strategy {
+    /// Returns the actions to take at the given snapshot, once the fills of
+    /// the paper orders since the previous one are applied to `sim`.
+    fn on_snapshot(&mut self, snapshot: &BookSnapshot, sim: &ReplaySimulator) -> Vec<PaperAction>;
+}
+
+impl<F: FnMut(&BookSnapshot, &ReplaySimulator) -> Vec<PaperAction>> Strategy for F {
+    fn on_snapshot(&mut self, snapshot: &BookSnapshot, sim: &ReplaySimulator) -> Vec<PaperAction> {
+        self(snapshot, sim)
+    }
+}
+
+/// Paper trades a `Strategy` against recorded `BookSnapshot`s of a market,
+/// with the lot math and fees of the DEX's matching engine at the base fee
+/// tier (see `simulate_order`).
+///
+/// Orders take the liquidity of the snapshot they're placed at, which is
+/// used up until the next snapshot, and their remainder rests as the DEX
+/// would post it, behind the size resting at its price. Resting orders are
+/// filled by the maker fills of the recorded event queue: a fill at their
+/// price fills them once the size ahead of them is filled, and a fill at a
+/// worse price fills them entirely. The recorded market never saw the paper
+/// orders, so their impact on it isn't simulated, and snapshots must be
+/// recorded before the crank consumes the events they follow.
+pub struct ReplaySimulator {
+    pub market: MarketInfo,
+    pub balances: PaperBalances,
+    pub fills: Vec<PaperFill>,
+    orders: Vec<PaperOrder>,
+    bids: Vec<L2Level>,
+    asks: Vec<L2Level>,
+    tracker: Option<FillTracker>,
+    fee_tier: FeeTier,
+}
+
+impl ReplaySimulator {
+    pub fn new(market: MarketInfo, balances: PaperBalances) -> Self {
+        let fee_tier = FeeTier::from_srm_and_msrm_balances(market.address(), 0, 0);
+        Self {
+            market,
+            balances,
+            fills: Vec::new(),
+            orders: Vec::new(),
+            bids: Vec::new(),
+            asks: Vec::new(),
+            tracker: None,
+            fee_tier,
+        }
+    }
+
+    /// The paper orders resting on the book.
+    pub fn orders(&self) -> &[PaperOrder] {
+        &self.orders
+    }
+
+    /// The L2 levels of the bids, best first, less the liquidity the paper
+    /// orders took since the last snapshot.
+    pub fn bids(&self) -> &[L2Level] {
+        &self.bids
+    }
+
+    /// The L2 levels of the asks, see `bids`.
+    pub fn asks(&self) -> &[L2Level] {
+        &self.asks
+    }
+
+    /// Replays the given snapshots, oldest first.
+    pub fn replay(
+        &mut self,
+        snapshots: impl IntoIterator<Item = BookSnapshot>,
+        strategy: &mut impl Strategy,
+    ) -> Result<()> {
+        for snapshot in snapshots {
+            self.step(&snapshot, strategy)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the maker fills of the given snapshot to the paper orders,
+    /// then the strategy's actions, skipping the orders the DEX would
+    /// reject.
+    pub fn step(&mut self, snapshot: &BookSnapshot, strategy: &mut impl Strategy) -> Result<()> {
+        let levels = |data: &[u8]| -> Result<Vec<L2Level>> {
+            let book = BookSide::unpack(data).map_err(|e| anyhow!("invalid book: {:?}", e))?;
+            Ok(book.l2(usize::MAX))
+        };
+        self.bids = levels(&snapshot.bids)?;
+        self.asks = levels(&snapshot.asks)?;
+        let queue = EventQueueReader::unpack(&snapshot.event_queue)
+            .map_err(|e| anyhow!("invalid event queue: {:?}", e))?;
+        // The fills queued at the first snapshot precede the replay.
+        let tracker = self
+            .tracker
+            .get_or_insert_with(|| FillTracker::new(Some(queue.seq_num())));
+        for fill in tracker.update(&queue)? {
+            self.fill_resting(snapshot.slot, &fill);
+        }
+        for action in strategy.on_snapshot(snapshot, self) {
+            let result = match &action {
+                PaperAction::Place(order) => self.place(snapshot.slot, order),
+                PaperAction::Cancel(client_order_id) => self
+                    .cancel(*client_order_id)
+                    .map(|_| ())
+                    .ok_or_else(|| anyhow!("no order {}", client_order_id)),
+            };
+            if let Err(e) = result {
+                warn!("rejecting {:?} at slot {}: {}", action, snapshot.slot, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Places the given order at the given slot, against the book of the
+    /// last snapshot. The order's funds must be free, as the DEX locks them
+    /// before matching.
+    pub fn place(&mut self, slot: u64, order: &NewOrderInstructionV3) -> Result<()> {
+        let (coin_lot_size, pc_lot_size) = (
+            self.market.header.coin_lot_size,
+            self.market.header.pc_lot_size,
+        );
+        if self.orders.len() == ORDER_SLOTS {
+            return Err(anyhow!("no free order slot"));
+        }
+        if self.order(order.client_order_id).is_some() {
+            return Err(anyhow!("order {} already resting", order.client_order_id));
+        }
+        let locked = match order.side {
+            Side::Bid => order.max_native_pc_qty_including_fees.get(),
+            Side::Ask => order.max_coin_qty.get().saturating_mul(coin_lot_size),
+        };
+        let free = match order.side {
+            Side::Bid => self.balances.native_pc_free,
+            Side::Ask => self.balances.native_coin_free,
+        };
+        if locked > free {
+            return Err(anyhow!("insufficient funds"));
+        }
+
+        let (levels, resting) = match order.side {
+            Side::Bid => (&mut self.asks, &self.bids),
+            Side::Ask => (&mut self.bids, &self.asks),
+        };
+        let sim = simulate_order(&self.market, levels, order);
+        consume(levels, sim.coin_lots);
+        // Post-only-slide orders crossing the book post one tick behind it,
+        // bids no lower than the first tick.
+        let limit_price = order.limit_price.get();
+        let price = match (order.order_type, order.side, levels.first()) {
+            (OrderType::PostOnlySlide, Side::Bid, Some(best))
+                if limit_price >= best.price && best.price > 1 =>
+            {
+                best.price - 1
+            }
+            (OrderType::PostOnlySlide, Side::Ask, Some(best)) if limit_price <= best.price => {
+                best.price + 1
+            }
+            _ => limit_price,
+        };
+        let crossed = levels.first().is_some_and(|level| match order.side {
+            Side::Bid => level.price <= price,
+            Side::Ask => level.price >= price,
+        });
+        // The DEX posts the remainder of orders no longer crossing, bids
+        // bounded by the pc left once the taker fee is paid.
+        let native_coin_qty = sim.coin_lots * coin_lot_size;
+        let posted = match order.order_type {
+            OrderType::ImmediateOrCancel => 0,
+            _ if crossed => 0,
+            _ => match order.side {
+                Side::Bid => {
+                    let pc_left = locked - sim.net_native_pc_qty();
+                    sim.coin_lots_remaining.min(pc_left / pc_lot_size / price)
+                }
+                Side::Ask => sim.coin_lots_remaining,
+            },
+        };
+        let queue_ahead = resting
+            .iter()
+            .find(|level| level.price == price)
+            .map_or(0, |level| level.size);
+
+        let balances = &mut self.balances;
+        match order.side {
+            Side::Bid => {
+                balances.native_coin_free += native_coin_qty;
+                balances.native_coin_total += native_coin_qty;
+                balances.native_pc_free -= sim.net_native_pc_qty() + posted * price * pc_lot_size;
+                balances.native_pc_total -= sim.net_native_pc_qty();
+            }
+            Side::Ask => {
+                balances.native_coin_free -= native_coin_qty + posted * coin_lot_size;
+                balances.native_coin_total -= native_coin_qty;
+                balances.native_pc_free += sim.net_native_pc_qty();
+                balances.native_pc_total += sim.net_native_pc_qty();
+            }
+        }
+        if sim.coin_lots > 0 {
+            self.fills.push(PaperFill {
+                slot,
+                client_order_id: order.client_order_id,
+                side: order.side,
+                maker: false,
+                price: sim.worst_price.unwrap(),
+                coin_lots: sim.coin_lots,
+                native_pc_qty: sim.native_pc_qty,
+                native_fee_or_rebate: sim.native_taker_fee,
+            });
+        }
+        if posted > 0 {
+            self.orders.push(PaperOrder {
+                client_order_id: order.client_order_id,
+                side: order.side,
+                price,
+                size: posted,
+                queue_ahead,
+            });
+        }
+        Ok(())
+    }
+
+    /// Cancels the paper order of the given client order id, unlocking its
+    /// funds.
+    pub fn cancel(&mut self, client_order_id: u64) -> Option<PaperOrder> {
+        let idx = self.order(client_order_id)?;
+        let order = self.orders.remove(idx);
+        match order.side {
+            Side::Bid => {
+                self.balances.native_pc_free +=
+                    order.size * order.price * self.market.header.pc_lot_size;
+            }
+            Side::Ask => {
+                self.balances.native_coin_free += order.size * self.market.header.coin_lot_size;
+            }
+        }
+        Some(order)
+    }
+
+    fn order(&self, client_order_id: u64) -> Option<usize> {
+        self.orders
+            .iter()
+            .position(|order| order.client_order_id == client_order_id)
+    }
+
+    // Fills the paper orders the given maker fill of the recorded market
+    // reached.
+    fn fill_resting(&mut self, slot: u64, fill: &Fill) {
+        let (side, order_id) = match fill.event {
+            QueueEvent::Fill {
+                side,
+                maker: true,
+                order_id,
+                ..
+            } => (side, order_id),
+            _ => return,
+        };
+        let (coin_lot_size, pc_lot_size) = (
+            self.market.header.coin_lot_size,
+            self.market.header.pc_lot_size,
+        );
+        let fill_qty = fill.native_coin_qty() / coin_lot_size;
+        // Order ids carry the price in their upper half, but for oracle pegged
+        // orders, whose price follows the oracle.
+        let fill_price = match extract_peg_offset_from_order_id(order_id) {
+            None => (order_id >> 64) as u64,
+            Some(_) if fill_qty == 0 => return,
+            Some(_) => fill.native_pc_qty() / (fill_qty * pc_lot_size),
+        };
+        let (fee_tier, balances) = (self.fee_tier, &mut self.balances);
+        let mut fills = Vec::new();
+        for order in self.orders.iter_mut().filter(|order| order.side == side) {
+            let worse = match side {
+                Side::Bid => fill_price < order.price,
+                Side::Ask => fill_price > order.price,
+            };
+            let coin_lots = if worse {
+                order.size
+            } else if fill_price == order.price {
+                let filled = fill_qty.saturating_sub(order.queue_ahead).min(order.size);
+                order.queue_ahead = order.queue_ahead.saturating_sub(fill_qty);
+                filled
+            } else {
+                0
+            };
+            if coin_lots == 0 {
+                continue;
+            }
+            order.size -= coin_lots;
+            let native_coin_qty = coin_lots * coin_lot_size;
+            let native_pc_qty = coin_lots * order.price * pc_lot_size;
+            let rebate = fee_tier.maker_rebate(native_pc_qty);
+            match side {
+                Side::Bid => {
+                    balances.native_coin_free += native_coin_qty;
+                    balances.native_coin_total += native_coin_qty;
+                    balances.native_pc_free += rebate;
+                    balances.native_pc_total = balances.native_pc_total + rebate - native_pc_qty;
+                }
+                Side::Ask => {
+                    balances.native_coin_total -= native_coin_qty;
+                    balances.native_pc_free += native_pc_qty + rebate;
+                    balances.native_pc_total += native_pc_qty + rebate;
+                }
+            }
+            fills.push(PaperFill {
+                slot,
+                client_order_id: order.client_order_id,
+                side,
+                maker: true,
+                price: order.price,
+                coin_lots,
+                native_pc_qty,
+                native_fee_or_rebate: rebate,
+            });
+        }
+        self.orders.retain(|order| order.size > 0);
+        self.fills.extend(fills);
+    }
+}
+
+// Takes the given coin lots off the best levels.
+fn consume(levels: &mut Vec<L2Level>, mut coin_lots: u64) {
+    while coin_lots > 0 && !levels.is_empty() {
+        let taken = levels[0].size.min(coin_lots);
+        levels[0].size -= taken;
+        coin_lots -= taken;
+        if levels[0].size == 0 {
+            levels.remove(0);
+        }
+    }
+    let mut cumulative_size = 0u64;
+    for level in levels {
+        cumulative_size = cumulative_size.saturating_add(level.size);
+        level.cumulative_size = cumulative_size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use serum_dex_permissioned::MarketHeader;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn level(price: u64, size: u64) -> L2Level {
+        L2Level {
+            price,
+            size,
+            cumulative_size: 0,
+        }
+    }
+
+    #[test]
+    fn test_replay_simulator() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            // Lots of 1 coin, and price lots of 1 pc per coin.
+            coin_lot_size: 10,
+            pc_lot_size: 100,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        let market = MarketInfo::new(&program_id, header, 1, 2).unwrap();
+        let mut sim = ReplaySimulator::new(market.clone(), PaperBalances::new(1_000, 1_000_000));
+        sim.bids = vec![level(99, 4), level(98, 10)];
+        sim.asks = vec![level(100, 5), level(101, 10)];
+
+        // Takes the 5 lots at 100, then posts the rest at 100.
+        let bid = market
+            .new_order(Side::Bid, 100.0, 8.0, OrderType::Limit, 1)
+            .unwrap();
+        sim.place(7, &bid).unwrap();
+        assert_eq!(
+            sim.asks(),
+            &[L2Level {
+                price: 101,
+                size: 10,
+                cumulative_size: 10
+            }]
+        );
+        let taker = sim.fills[0];
+        assert_eq!((taker.coin_lots, taker.native_pc_qty), (5, 50_000));
+        assert_eq!(sim.orders().len(), 1);
+        assert_eq!((sim.orders()[0].size, sim.orders()[0].queue_ahead), (3, 0));
+        let paid = 50_000 + taker.native_fee_or_rebate;
+        assert_eq!(sim.balances.native_pc_total, 1_000_000 - paid);
+        assert_eq!(sim.balances.native_pc_free, 1_000_000 - paid - 30_000);
+        assert_eq!(sim.balances.native_coin_free, 1_050);
+
+        // Post only asks crossing the book don't post, and asks need coin.
+        let ask = market
+            .new_order(Side::Ask, 99.0, 1.0, OrderType::PostOnly, 2)
+            .unwrap();
+        sim.place(7, &ask).unwrap();
+        assert_eq!(sim.orders().len(), 1);
+        // Post-only-slide asks crossing the book post one tick behind it.
+        let ask = market
+            .new_order(Side::Ask, 99.0, 1.0, OrderType::PostOnlySlide, 4)
+            .unwrap();
+        sim.place(7, &ask).unwrap();
+        assert_eq!(sim.orders()[1].price, 100);
+        sim.cancel(4).unwrap();
+        let ask = market
+            .new_order(Side::Ask, 102.0, 200.0, OrderType::Limit, 3)
+            .unwrap();
+        assert!(sim.place(7, &ask).is_err());
+
+        // Maker fills at the bid's price fill it, then at worse prices.
+        let maker_fill = |side, price: u64, native_coin_qty| Fill {
+            seq_num: 0,
+            event: QueueEvent::Fill {
+                side,
+                maker: true,
+                native_qty_paid: 0,
+                native_qty_received: native_coin_qty,
+                native_fee_or_rebate: 0,
+                order_id: (price as u128) << 64,
+                owner: Pubkey::new_unique(),
+                owner_slot: 0,
+                fee_tier: 0,
+                client_order_id: 0,
+            },
+        };
+        sim.fill_resting(8, &maker_fill(Side::Bid, 100, 10));
+        sim.fill_resting(8, &maker_fill(Side::Ask, 98, 100));
+        assert_eq!(sim.orders()[0].size, 2);
+        sim.fill_resting(9, &maker_fill(Side::Bid, 98, 10));
+        assert!(sim.orders().is_empty());
+        let makers: Vec<_> = sim.fills[1..].iter().map(|fill| fill.coin_lots).collect();
+        assert_eq!(makers, vec![1, 2]);
+        assert_eq!(sim.balances.native_coin_total, 1_080);
+        assert_eq!(sim.balances.native_pc_free, sim.balances.native_pc_total);
+        assert!(sim.cancel(1).is_none());
+
+        let snapshot = BookSnapshot {
+            slot: 1,
+            timestamp: 2,
+            bids: vec![3],
+            asks: vec![],
+            event_queue: vec![4, 5],
+        };
+        let mut recording = Vec::new();
+        snapshot.write(&mut recording).unwrap();
+        snapshot.write(&mut recording).unwrap();
+        let mut reader = &recording[..];
+        assert_eq!(
+            BookSnapshot::read(&mut reader).unwrap(),
+            Some(snapshot.clone())
+        );
+        assert_eq!(BookSnapshot::read(&mut reader).unwrap(), Some(snapshot));
+        assert_eq!(BookSnapshot::read(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_replay_failures() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 10,
+            pc_lot_size: 100,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        let market = MarketInfo::new(&program_id, header, 1, 2).unwrap();
+
+        // RPC errors are returned, as are missing accounts, even when the
+        // node returns fewer accounts than requested.
+        let client = RpcClient::new_mock("fails".to_string());
+        assert!(BookSnapshot::load(&client, &market).is_err());
+        let data = [0; 8];
+        let accounts = [
+            Some((&program_id, &data[..])),
+            Some((&program_id, &data[..])),
+        ];
+        let mocks = vec![(
+            RpcRequest::GetMultipleAccounts,
+            mock::multiple_accounts(&accounts),
+        )];
+        let client =
+            RpcClient::new_mock_with_mocks_map("succeeds".to_string(), mocks.into_iter().collect());
+        let err = BookSnapshot::load(&client, &market).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("missing account {}", header.event_q)
+        );
+
+        // Truncated recordings fail rather than end.
+        let snapshot = BookSnapshot {
+            slot: 1,
+            timestamp: 2,
+            bids: vec![3],
+            asks: vec![],
+            event_queue: vec![4, 5],
+        };
+        let mut recording = Vec::new();
+        snapshot.write(&mut recording).unwrap();
+        let mut reader = &recording[..recording.len() - 1];
+        assert!(BookSnapshot::read(&mut reader).is_err());
+
+        // Invalid snapshots stop the replay before the strategy sees them.
+        let mut sim = ReplaySimulator::new(market, PaperBalances::new(1_000, 1_000));
+        let mut seen = 0;
+        let mut strategy = |_: &BookSnapshot, _: &ReplaySimulator| {
+            seen += 1;
+            vec![]
+        };
+        let err = sim.replay(vec![snapshot], &mut strategy).unwrap_err();
+        assert!(err.to_string().starts_with("invalid book"));
+        assert_eq!(seen, 0);
+    }
+}
@@ -0,0 +1,376 @@
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSendTransactionConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SendConfig {
+    /// The most transactions signed, each with a fresh blockhash, before
+    /// giving up.
+    pub max_attempts: usize,
+    /// The delay before the second attempt, doubled on each attempt up to
+    /// `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How often the statuses of the transactions sent are polled.
+    pub poll_interval: Duration,
+    /// How long a blockhash fetched is reused for new transactions.
+    pub blockhash_ttl: Duration,
+    pub skip_preflight: bool,
+}
+
+impl Default for SendConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            poll_interval: Duration::from_millis(500),
+            blockhash_ttl: Duration::from_secs(10),
+            skip_preflight: false,
+        }
+    }
+}
+
+impl SendConfig {
+    /// The delay before the given attempt, from 0.
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        if attempt == 0 {
+            return Duration::ZERO;
+        }
+        let factor = 1u32.checked_shl(attempt as u32 - 1).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CachedBlockhash {
+    blockhash: Hash,
+    last_valid_block_height: u64,
+    fetched_at: Instant,
+}
+
+/// Sends transactions until they land: each attempt is signed with a recent
+/// blockhash, shared by the transactions sent within `blockhash_ttl`, and
+/// polled for until confirmed, failed, or its blockhash expired, and the
+/// transactions of all the attempts are polled for, so that one landing late
+/// isn't sent again. Errors of the transaction itself, e.g. of the DEX, fail
+/// the send rather than being retried.
+///
+/// Orders sent with `send_order` are sent at most once per client order id:
+/// sending an order again returns the signature of the transaction that
+/// placed it.
+pub struct TransactionSender {
+    pub client: RpcClient,
+    pub config: SendConfig,
+    blockhash: Mutex<Option<CachedBlockhash>>,
+    orders: Mutex<HashMap<u64, Signature>>,
+}
+
+impl TransactionSender {
+    pub fn new(client: RpcClient, config: SendConfig) -> Self {
+        Self {
+            client,
+            config,
+            blockhash: Mutex::new(None),
+            orders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sends the given instructions in a transaction paid for by `payer` and
+    /// signed by `signers` too, returning its signature once confirmed.
+    pub fn send(
+        &self,
+        ixs: &[Instruction],
+        payer: &Keypair,
+        signers: &[&Keypair],
+    ) -> Result<Signature> {
+        let mut all_signers = vec![payer];
+        all_signers.extend(
+            signers
+                .iter()
+                .filter(|signer| signer.pubkey() != payer.pubkey()),
+        );
+        let mut signatures = Vec::new();
+        for attempt in 0..self.config.max_attempts {
+            thread::sleep(self.config.backoff(attempt));
+            // A failed attempt may be due to a stale blockhash.
+            let blockhash = match self.blockhash(attempt > 0) {
+                Ok(blockhash) => blockhash,
+                Err(e) => {
+                    warn!("fetching blockhash failed: {}", e);
+                    continue;
+                }
+            };
+            let tx = Transaction::new_signed_with_payer(
+                ixs,
+                Some(&payer.pubkey()),
+                &all_signers,
+                blockhash.blockhash,
+            );
+            signatures.push(tx.signatures[0]);
+            let config = RpcSendTransactionConfig {
+                skip_preflight: self.config.skip_preflight,
+                preflight_commitment: Some(self.client.commitment().commitment),
+                ..RpcSendTransactionConfig::default()
+            };
+            if let Err(e) = self.client.send_transaction_with_config(&tx, config) {
+                match e.get_transaction_error() {
+                    Some(err) if !is_retryable(&err) => {
+                        return Err(anyhow!("transaction {} failed: {}", tx.signatures[0], err));
+                    }
+                    _ => warn!("sending transaction {} failed: {}", tx.signatures[0], e),
+                }
+            }
+            match self.confirm(&signatures, blockhash.last_valid_block_height) {
+                Ok(Some(signature)) => return Ok(signature),
+                Ok(None) => debug!("transaction {} expired", tx.signatures[0]),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(anyhow!(
+            "transaction not confirmed after {} attempts",
+            self.config.max_attempts
+        ))
+    }
+
+    /// Sends the given instructions, placing the order of the given client
+    /// order id, unless a transaction this sender sent already placed it.
+    pub fn send_order(
+        &self,
+        client_order_id: u64,
+        ixs: &[Instruction],
+        payer: &Keypair,
+        signers: &[&Keypair],
+    ) -> Result<Signature> {
+        if let Some(signature) = self.order_signature(client_order_id) {
+            debug!("order {} already placed by {}", client_order_id, signature);
+            return Ok(signature);
+        }
+        let signature = self.send(ixs, payer, signers)?;
+        self.orders
+            .lock()
+            .unwrap()
+            .insert(client_order_id, signature);
+        Ok(signature)
+    }
+
+    /// The signature of the transaction that placed the order of the given
+    /// client order id, if sent with `send_order`.
+    pub fn order_signature(&self, client_order_id: u64) -> Option<Signature> {
+        self.orders.lock().unwrap().get(&client_order_id).copied()
+    }
+
+    /// Forgets the order of the given client order id, e.g. once closed, so
+    /// that the id can be reused.
+    pub fn forget_order(&self, client_order_id: u64) -> Option<Signature> {
+        self.orders.lock().unwrap().remove(&client_order_id)
+    }
+
+    fn blockhash(&self, refresh: bool) -> Result<CachedBlockhash> {
+        let mut cached = self.blockhash.lock().unwrap();
+        if let Some(blockhash) = *cached {
+            if !refresh && blockhash.fetched_at.elapsed() < self.config.blockhash_ttl {
+                return Ok(blockhash);
+            }
+        }
+        let (blockhash, last_valid_block_height) = self
+            .client
+            .get_latest_blockhash_with_commitment(self.client.commitment())?;
+        let blockhash = CachedBlockhash {
+            blockhash,
+            last_valid_block_height,
+            fetched_at: Instant::now(),
+        };
+        *cached = Some(blockhash);
+        Ok(blockhash)
+    }
+
+    // Polls the statuses of the given transactions until one is confirmed,
+    // returning its signature, or fails, or the last of them expires.
+    fn confirm(
+        &self,
+        signatures: &[Signature],
+        last_valid_block_height: u64,
+    ) -> Result<Option<Signature>> {
+        let commitment = self.client.commitment();
+        loop {
+            thread::sleep(self.config.poll_interval);
+            let statuses = match self.client.get_signature_statuses(signatures) {
+                Ok(statuses) => statuses.value,
+                Err(e) => {
+                    warn!("polling transaction statuses failed: {}", e);
+                    continue;
+                }
+            };
+            for (signature, status) in signatures.iter().zip(statuses) {
+                let status = match status {
+                    Some(status) => status,
+                    None => continue,
+                };
+                if let Some(err) = status.err {
+                    return Err(anyhow!("transaction {} failed: {}", signature, err));
+                }
+                if status.satisfies_commitment(commitment) {
+                    return Ok(Some(*signature));
+                }
+            }
+            match self.client.get_block_height() {
+                Ok(height) if height > last_valid_block_height => return Ok(None),
+                Ok(_) => {}
+                Err(e) => warn!("fetching block height failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Whether a transaction rejected with the given error may land if signed
+/// again with a fresh blockhash.
+pub fn is_retryable(err: &TransactionError) -> bool {
+    matches!(
+        err,
+        TransactionError::BlockhashNotFound
+            | TransactionError::AlreadyProcessed
+            | TransactionError::WouldExceedMaxBlockCostLimit
+            | TransactionError::WouldExceedMaxAccountCostLimit
+            | TransactionError::WouldExceedMaxVoteCostLimit
+            | TransactionError::WouldExceedAccountDataBlockLimit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::instruction::InstructionError;
+
+    fn sender(client: RpcClient, max_attempts: usize) -> TransactionSender {
+        let config = SendConfig {
+            max_attempts,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            poll_interval: Duration::ZERO,
+            ..SendConfig::default()
+        };
+        TransactionSender::new(client, config)
+    }
+
+    // The response to a `getLatestBlockhash` of the given blockhash, expired
+    // at the mock's block height.
+    fn latest_blockhash(blockhash: &Hash) -> (RpcRequest, Value) {
+        let value = json!({ "blockhash": blockhash.to_string(), "lastValidBlockHeight": 1233 });
+        let response = json!({ "context": { "slot": 1 }, "value": value });
+        (RpcRequest::GetLatestBlockhash, response)
+    }
+
+    #[test]
+    fn test_send_config() {
+        let config = SendConfig::default();
+        let backoffs: Vec<_> = (0..7).map(|attempt| config.backoff(attempt)).collect();
+        assert_eq!(
+            backoffs,
+            [0, 500, 1_000, 2_000, 4_000, 8_000, 8_000]
+                .iter()
+                .map(|millis| Duration::from_millis(*millis))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(config.backoff(usize::MAX), config.max_backoff);
+
+        assert!(is_retryable(&TransactionError::BlockhashNotFound));
+        assert!(!is_retryable(&TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(0)
+        )));
+    }
+
+    #[test]
+    fn test_send_failures() {
+        let payer = Keypair::new();
+        let signature = |blockhash| {
+            Transaction::new_signed_with_payer(&[], Some(&payer.pubkey()), &[&payer], blockhash)
+                .signatures[0]
+        };
+
+        // Attempts without a blockhash are skipped.
+        let client = RpcClient::new_mock("fails".to_string());
+        let err = sender(client, 3).send(&[], &payer, &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "transaction not confirmed after 3 attempts"
+        );
+
+        // Failed transactions aren't sent again.
+        let blockhash = Hash::new_unique();
+        let mocks = vec![latest_blockhash(&blockhash)].into_iter().collect();
+        let client = RpcClient::new_mock_with_mocks_map("instruction_error".to_string(), mocks);
+        let err = sender(client, 3).send(&[], &payer, &[]).unwrap_err();
+        let prefix = format!("transaction {} failed:", signature(blockhash));
+        assert!(err.to_string().starts_with(&prefix));
+
+        // Each attempt expires unconfirmed.
+        let mocks = vec![
+            latest_blockhash(&Hash::new_unique()),
+            latest_blockhash(&Hash::new_unique()),
+        ];
+        let client = RpcClient::new_mock_with_mocks_map(
+            "sig_not_found".to_string(),
+            mocks.into_iter().collect(),
+        );
+        let err = sender(client, 2).send(&[], &payer, &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "transaction not confirmed after 2 attempts"
+        );
+
+        // The first attempt lands while the second is polled for.
+        let blockhashes = [Hash::new_unique(), Hash::new_unique()];
+        let status = json!({
+            "slot": 1,
+            "confirmations": null,
+            "status": { "Ok": null },
+            "err": null,
+            "confirmationStatus": "finalized",
+        });
+        let statuses = |value| json!({ "context": { "slot": 1 }, "value": value });
+        let mocks = vec![
+            latest_blockhash(&blockhashes[0]),
+            latest_blockhash(&blockhashes[1]),
+            (RpcRequest::GetSignatureStatuses, statuses(json!([null]))),
+            (
+                RpcRequest::GetSignatureStatuses,
+                statuses(json!([status, null])),
+            ),
+        ];
+        let client = RpcClient::new_mock_with_mocks_map(
+            "sig_not_found".to_string(),
+            mocks.into_iter().collect(),
+        );
+        let landed = sender(client, 3).send(&[], &payer, &[]).unwrap();
+        assert_eq!(landed, signature(blockhashes[0]));
+    }
+
+    #[test]
+    fn test_send_order_once() {
+        let sender = sender(RpcClient::new_mock("succeeds".to_string()), 1);
+        let (payer, other) = (Keypair::new(), Keypair::new());
+        let signature = sender.send_order(1, &[], &payer, &[]).unwrap();
+        assert_eq!(sender.send_order(1, &[], &other, &[]).unwrap(), signature);
+        assert_eq!(sender.order_signature(1), Some(signature));
+
+        // Once forgotten, the order is sent again.
+        assert_eq!(sender.forget_order(1), Some(signature));
+        assert_ne!(sender.send_order(1, &[], &other, &[]).unwrap(), signature);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::{
+    associated_token_address, settle_funds_compute_units, settle_funds_ix, MarketInfo,
+    PriorityFeeEstimator, ProxyRoute,
+};
+use anyhow::Result;
+use log::{info, warn};
+use serum_dex::state::{AccountFlag, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
+use serum_dex_permissioned::{open_orders_address, OpenOrdersRegistry};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use std::convert::TryInto;
+use std::thread;
+use std::time::Duration;
+
+/// The balances of a user's open orders PDA free to settle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreeBalances {
+    pub user: Pubkey,
+    pub open_orders: Pubkey,
+    pub native_coin_free: u64,
+    pub native_pc_free: u64,
+    pub referrer_rebates_accrued: u64,
+}
+
+impl FreeBalances {
+    // The open orders account flags, market and owner precede the balances,
+    // and the referrer rebates end the account.
+    const LEN: usize = 3216;
+
+    /// Parses the free balances of the given open orders account data.
+    pub fn unpack(user: &Pubkey, open_orders: &Pubkey, data: &[u8]) -> Option<Self> {
+        let data = data
+            .strip_prefix(ACCOUNT_HEAD_PADDING)?
+            .strip_suffix(ACCOUNT_TAIL_PADDING)
+            .filter(|data| data.len() == Self::LEN)?;
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::OpenOrders as u64;
+        if u64_at(0) != flags {
+            return None;
+        }
+        Some(Self {
+            user: *user,
+            open_orders: *open_orders,
+            native_coin_free: u64_at(72),
+            native_pc_free: u64_at(88),
+            referrer_rebates_accrued: u64_at(Self::LEN - 8),
+        })
+    }
+
+    /// Whether there's anything to settle.
+    pub fn is_empty(&self) -> bool {
+        self.native_coin_free == 0 && self.native_pc_free == 0 && self.referrer_rebates_accrued == 0
+    }
+}
+
+/// Settles the free balances of the open orders PDAs the users of a proxy
+/// initialized on a market into the users' associated token accounts, paid
+/// by `payer`. The proxy's `OpenOrdersPda` must allow it, see
+/// `OpenOrdersPda::permissionless_settle`.
+pub struct Settler {
+    pub client: RpcClient,
+    pub payer: Keypair,
+    pub market: MarketInfo,
+    pub proxy: ProxyRoute,
+    /// The pc wallet credited with the referral share of the fees.
+    pub referrer_pc_wallet: Option<Pubkey>,
+    /// Priority fee, in micro-lamports per compute unit.
+    pub compute_unit_price: Option<u64>,
+    /// Estimates the priority fee from the recent fees paid on the market's
+    /// vaults, instead of `compute_unit_price`, which is paid when the
+    /// estimation fails.
+    pub priority_fee: Option<PriorityFeeEstimator>,
+}
+
+impl Settler {
+    /// Returns the users with an `OpenOrdersRegistry` on the market, i.e.
+    /// who initialized open orders through a proxy with
+    /// `OpenOrdersPda::registry`.
+    pub fn users(&self) -> Result<Vec<Pubkey>> {
+        let discriminator = OpenOrdersRegistry::DISCRIMINATOR.to_vec();
+        let market = self.market.address().to_bytes().to_vec();
+        let filters = vec![
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, discriminator)),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(8, market)),
+        ];
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let accounts = self
+            .client
+            .get_program_accounts_with_config(&self.proxy.program_id, config)?;
+        Ok(accounts
+            .iter()
+            .filter_map(|(_, account)| OpenOrdersRegistry::unpack(&account.data).ok())
+            .map(|registry| registry.owner)
+            .collect())
+    }
+
+    /// Returns the free balances of the given users' open orders PDAs,
+    /// skipping those without any.
+    pub fn free_balances(&self, users: &[Pubkey]) -> Result<Vec<FreeBalances>> {
+        let mut balances = Vec::new();
+        // The most accounts fetched by a `getMultipleAccounts`.
+        for users in users.chunks(100) {
+            let open_orders: Vec<_> = users.iter().map(|user| self.open_orders(user).0).collect();
+            let accounts = self.client.get_multiple_accounts(&open_orders)?;
+            for ((user, open_orders), account) in users.iter().zip(&open_orders).zip(accounts) {
+                let free = account
+                    .filter(|account| account.owner == self.market.program_id)
+                    .and_then(|account| FreeBalances::unpack(user, open_orders, &account.data))
+                    .filter(|free| !free.is_empty());
+                balances.extend(free);
+            }
+        }
+        Ok(balances)
+    }
+
+    /// Returns the proxied `SettleFunds` of the given user's open orders PDA,
+    /// which the user doesn't sign.
+    pub fn settle_ix(&self, user: &Pubkey) -> Result<Instruction> {
+        let (open_orders, bump) = self.open_orders(user);
+        let header = &self.market.header;
+        let mut ix = settle_funds_ix(
+            &self.market,
+            &open_orders,
+            user,
+            &associated_token_address(user, &header.coin_mint),
+            &associated_token_address(user, &header.pc_mint),
+            self.referrer_pc_wallet.as_ref(),
+        )?;
+        // The owner, replaced with the open orders PDA by the proxy.
+        ix.accounts[2].is_signer = false;
+        let mut proxy = self.proxy.clone();
+        proxy.header.bumps.open_orders = bump;
+        Ok(proxy.route(ix))
+    }
+
+    /// Settles the free balances of the market's users every `interval`,
+    /// until an RPC error other than a failed transaction.
+    pub fn run(&self, interval: Duration) -> Result<()> {
+        loop {
+            let users = self.users()?;
+            let price = self.compute_unit_price();
+            for free in self.free_balances(&users)? {
+                if let Err(e) = self.settle_with_price(&free.user, price) {
+                    warn!("settling {} failed: {}", free.open_orders, e);
+                }
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    /// Settles the free balances of the given user's open orders PDA.
+    pub fn settle(&self, user: &Pubkey) -> Result<()> {
+        self.settle_with_price(user, self.compute_unit_price())
+    }
+
+    /// Returns the priority fee of settlements, estimated if configured.
+    pub fn compute_unit_price(&self) -> Option<u64> {
+        let estimator = match &self.priority_fee {
+            Some(estimator) => estimator,
+            None => return self.compute_unit_price,
+        };
+        let header = &self.market.header;
+        match estimator.estimate(&self.client, &[header.coin_vault, header.pc_vault]) {
+            Ok(price) => Some(price),
+            Err(e) => {
+                warn!("priority fee estimation failed: {}", e);
+                self.compute_unit_price
+            }
+        }
+    }
+
+    fn settle_with_price(&self, user: &Pubkey, price: Option<u64>) -> Result<()> {
+        let units = settle_funds_compute_units(true);
+        let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(units)];
+        if let Some(price) = price {
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+        }
+        ixs.push(self.settle_ix(user)?);
+        let tx = Transaction::new_signed_with_payer(
+            &ixs,
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            self.client.get_latest_blockhash()?,
+        );
+        let signature = self.client.send_and_confirm_transaction(&tx)?;
+        info!("settled {}: {}", user, signature);
+        Ok(())
+    }
+
+    fn open_orders(&self, user: &Pubkey) -> (Pubkey, u8) {
+        open_orders_address(
+            &self.proxy.program_id,
+            &self.market.program_id,
+            self.market.address(),
+            user,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use serum_dex_permissioned::MarketHeader;
+    use solana_client::rpc_request::RpcRequest;
+
+    // Returns the data of an open orders account of the given free balances.
+    fn open_orders_data(native_coin_free: u64, native_pc_free: u64) -> Vec<u8> {
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        data.resize(ACCOUNT_HEAD_PADDING.len() + FreeBalances::LEN, 0);
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::OpenOrders as u64;
+        data[5..13].copy_from_slice(&flags.to_le_bytes());
+        data[5 + 72..5 + 80].copy_from_slice(&native_coin_free.to_le_bytes());
+        data[5 + 88..5 + 96].copy_from_slice(&native_pc_free.to_le_bytes());
+        data
+    }
+
+    fn market() -> MarketInfo {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 10,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        MarketInfo::new(&program_id, header, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_free_balances() {
+        let (user, open_orders) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut data = open_orders_data(3, 4);
+
+        let free = FreeBalances::unpack(&user, &open_orders, &data).unwrap();
+        assert_eq!(
+            free,
+            FreeBalances {
+                user,
+                open_orders,
+                native_coin_free: 3,
+                native_pc_free: 4,
+                referrer_rebates_accrued: 0,
+            }
+        );
+        assert!(!free.is_empty());
+        assert!(FreeBalances::unpack(&user, &open_orders, &data[1..]).is_none());
+        data[5] = AccountFlag::Initialized as u8;
+        assert!(FreeBalances::unpack(&user, &open_orders, &data).is_none());
+    }
+
+    #[test]
+    fn test_settler_failures() {
+        let market = market();
+        let settler = |client| Settler {
+            client,
+            payer: Keypair::new(),
+            market: market.clone(),
+            proxy: ProxyRoute::new(Pubkey::new_unique()),
+            referrer_pc_wallet: None,
+            compute_unit_price: Some(5),
+            priority_fee: Some(PriorityFeeEstimator::default()),
+        };
+        let users: Vec<_> = (0..101).map(|_| Pubkey::new_unique()).collect();
+
+        // RPC errors are returned, but for the priority fee estimation.
+        let failing = settler(RpcClient::new_mock("fails".to_string()));
+        assert!(failing.users().is_err());
+        assert!(failing.free_balances(&users).is_err());
+        assert_eq!(failing.compute_unit_price(), Some(5));
+        // The mock's program account isn't a registry.
+        let settler = settler(RpcClient::new_mock("succeeds".to_string()));
+        assert!(settler.users().unwrap().is_empty());
+
+        // Missing, foreign and empty accounts are skipped, over each batch of
+        // accounts fetched.
+        let (dex, foreign) = (market.program_id, Pubkey::new_unique());
+        let (data, empty) = (open_orders_data(3, 4), open_orders_data(0, 0));
+        let mut accounts = vec![
+            Some((&dex, &data[..])),
+            None,
+            Some((&foreign, &data[..])),
+            Some((&dex, &empty[..])),
+        ];
+        accounts.resize(100, None);
+        let mocks = vec![
+            (
+                RpcRequest::GetMultipleAccounts,
+                mock::multiple_accounts(&accounts),
+            ),
+            (
+                RpcRequest::GetMultipleAccounts,
+                mock::multiple_accounts(&[Some((&dex, &data))]),
+            ),
+        ];
+        let settler = Settler {
+            client: RpcClient::new_mock_with_mocks_map(
+                "fails".to_string(),
+                mocks.into_iter().collect(),
+            ),
+            ..settler
+        };
+        let free: Vec<_> = settler
+            .free_balances(&users)
+            .unwrap()
+            .iter()
+            .map(|free| (free.user, free.open_orders, free.native_pc_free))
+            .collect();
+        let expected = |user: &Pubkey| (*user, settler.open_orders(user).0, 4);
+        assert_eq!(free, vec![expected(&users[0]), expected(&users[100])]);
+    }
+}
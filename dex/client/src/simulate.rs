@@ -0,0 +1,228 @@
+use crate::MarketInfo;
+use serum_dex::fees::FeeTier;
+use serum_dex::instruction::NewOrderInstructionV3;
+use serum_dex::matching::{OrderType, Side};
+use serum_dex_permissioned::L2Level;
+
+/// The expected outcome of an order matching against the book, as computed
+/// by `simulate_order`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FillSimulation {
+    pub side: Side,
+    /// The coin lots filled.
+    pub coin_lots: u64,
+    /// The sum of the coin lots filled at each price times the price lots.
+    pub pc_lots: u64,
+    /// The native pc traded, before the taker fee.
+    pub native_pc_qty: u64,
+    pub native_taker_fee: u64,
+    /// The coin lots left unfilled, which limit orders post, and immediate
+    /// or cancel orders cancel.
+    pub coin_lots_remaining: u64,
+    /// The price lots of the best level of the book, if any.
+    pub best_price: Option<u64>,
+    /// The price lots of the last level traded with, if any.
+    pub worst_price: Option<u64>,
+}
+
+impl FillSimulation {
+    /// The native pc bids pay, or asks receive, taker fee included.
+    pub fn net_native_pc_qty(&self) -> u64 {
+        match self.side {
+            Side::Bid => self.native_pc_qty.saturating_add(self.native_taker_fee),
+            Side::Ask => self.native_pc_qty.saturating_sub(self.native_taker_fee),
+        }
+    }
+
+    /// The average UI price of the fills, before fees, if any.
+    pub fn average_price(&self, market: &MarketInfo) -> Option<f64> {
+        self.ui_price(market, self.native_pc_qty)
+    }
+
+    /// The average UI price of the fills, taker fee included, if any.
+    pub fn effective_price(&self, market: &MarketInfo) -> Option<f64> {
+        self.ui_price(market, self.net_native_pc_qty())
+    }
+
+    /// How much worse than the best price of the book the average price of
+    /// the fills is, before fees, in bps, if any.
+    pub fn slippage_bps(&self) -> Option<f64> {
+        let best_price = self.best_price? as f64;
+        if self.coin_lots == 0 {
+            return None;
+        }
+        let average_price = self.pc_lots as f64 / self.coin_lots as f64;
+        let slippage = match self.side {
+            Side::Bid => average_price - best_price,
+            Side::Ask => best_price - average_price,
+        };
+        Some(slippage / best_price * 10_000.0)
+    }
+
+    fn ui_price(&self, market: &MarketInfo, native_pc_qty: u64) -> Option<f64> {
+        if self.coin_lots == 0 {
+            return None;
+        }
+        let pc = native_pc_qty as f64 / 10f64.powi(market.pc_decimals as i32);
+        Some(pc / market.lots_to_size(self.coin_lots))
+    }
+}
+
+/// Simulates matching the given order against `levels`, an L2 snapshot of
+/// the opposite side of the market's book, best first (see `BookSide::l2`),
+/// with the lot math and taker fee of the DEX's matching engine: bids are
+/// bounded by their pc, net of the taker fee they lock, and the fee is
+/// charged on the total pc traded, rounded up.
+///
+/// L2 snapshots don't record whose orders rest on the book, so self-trades
+/// are matched as any other trade, nor how many orders a level holds, so the
+/// order's `limit` on the orders matched is ignored.
+pub fn simulate_order(
+    market: &MarketInfo,
+    levels: &[L2Level],
+    order: &NewOrderInstructionV3,
+) -> FillSimulation {
+    let fee_tier = FeeTier::from_srm_and_msrm_balances(market.address(), 0, 0);
+    let pc_lot_size = market.header.pc_lot_size;
+    let limit_price = order.limit_price.get();
+    let crosses = |price: u64| match order.side {
+        Side::Bid => limit_price >= price,
+        Side::Ask => limit_price <= price,
+    };
+    let mut coin_lots_remaining = order.max_coin_qty.get();
+    // Bids are also bounded by the pc they lock, in pc lots.
+    let mut pc_lots_remaining = match order.side {
+        Side::Bid => {
+            let native_pc_qty = order.max_native_pc_qty_including_fees.get();
+            fee_tier.remove_taker_fee(native_pc_qty) / pc_lot_size
+        }
+        Side::Ask => u64::MAX,
+    };
+    let mut pc_lots = 0u64;
+    let mut worst_price = None;
+    if !matches!(
+        order.order_type,
+        OrderType::PostOnly | OrderType::PostOnlySlide
+    ) {
+        for level in levels {
+            if !crosses(level.price) {
+                break;
+            }
+            let trade_qty = level
+                .size
+                .min(coin_lots_remaining)
+                .min(pc_lots_remaining / level.price);
+            if trade_qty == 0 {
+                break;
+            }
+            let trade_pc_lots = trade_qty.saturating_mul(level.price);
+            coin_lots_remaining -= trade_qty;
+            pc_lots_remaining -= trade_pc_lots;
+            pc_lots = pc_lots.saturating_add(trade_pc_lots);
+            worst_price = Some(level.price);
+        }
+    }
+    let native_pc_qty = pc_lots.saturating_mul(pc_lot_size);
+    FillSimulation {
+        side: order.side,
+        coin_lots: order.max_coin_qty.get() - coin_lots_remaining,
+        pc_lots,
+        native_pc_qty,
+        native_taker_fee: fee_tier.taker_fee(native_pc_qty),
+        coin_lots_remaining,
+        best_price: levels.first().map(|level| level.price),
+        worst_price,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex_permissioned::MarketHeader;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn level(price: u64, size: u64) -> L2Level {
+        L2Level {
+            price,
+            size,
+            cumulative_size: 0,
+        }
+    }
+
+    #[test]
+    fn test_simulate_order() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            // Lots of 1 coin, and price lots of 1 pc per coin.
+            coin_lot_size: 10,
+            pc_lot_size: 100,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        let market = MarketInfo::new(&program_id, header, 1, 2).unwrap();
+        let asks = [level(100, 5), level(101, 10), level(103, 10)];
+
+        let bid = market
+            .new_order(Side::Bid, 102.0, 12.0, OrderType::Limit, 0)
+            .unwrap();
+        let sim = simulate_order(&market, &asks, &bid);
+        assert_eq!(
+            (sim.coin_lots, sim.pc_lots, sim.coin_lots_remaining),
+            (12, 1_207, 0)
+        );
+        assert_eq!((sim.native_pc_qty, sim.native_taker_fee), (120_700, 49));
+        assert_eq!(sim.worst_price, Some(101));
+        assert_eq!(sim.effective_price(&market), Some(120_749.0 / 100.0 / 12.0));
+        assert!((sim.slippage_bps().unwrap() - 7.0 / 12.0 * 100.0).abs() < 1e-9);
+
+        // Bounded by the 999 pc lots such a bid locks net of fees.
+        let mut bid = market
+            .new_order(Side::Bid, 200.0, 100.0, OrderType::ImmediateOrCancel, 0)
+            .unwrap();
+        bid.max_native_pc_qty_including_fees = std::num::NonZeroU64::new(100_000).unwrap();
+        let sim = simulate_order(&market, &asks, &bid);
+        assert_eq!(
+            (sim.coin_lots, sim.pc_lots, sim.coin_lots_remaining),
+            (9, 904, 91)
+        );
+
+        let bids = [level(101, 3), level(100, 4), level(98, 10)];
+        let ask = market
+            .new_order(Side::Ask, 99.0, 10.0, OrderType::Limit, 0)
+            .unwrap();
+        let sim = simulate_order(&market, &bids, &ask);
+        assert_eq!(
+            (sim.coin_lots, sim.pc_lots, sim.coin_lots_remaining),
+            (7, 703, 3)
+        );
+        assert_eq!(sim.net_native_pc_qty(), 70_300 - 29);
+        assert_eq!(sim.average_price(&market), Some(703.0 / 7.0));
+
+        let post_only = market
+            .new_order(Side::Ask, 99.0, 10.0, OrderType::PostOnly, 0)
+            .unwrap();
+        let sim = simulate_order(&market, &bids, &post_only);
+        assert_eq!((sim.coin_lots, sim.coin_lots_remaining), (0, 10));
+        assert_eq!(sim.slippage_bps(), None);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::indexer::{parse_side, side_name};
+use crate::{FillStore, IndexedFill};
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use solana_sdk::pubkey::Pubkey;
+use std::convert::TryFrom;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS fills (
+    market TEXT NOT NULL,
+    seq_num INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    side TEXT NOT NULL,
+    maker BOOLEAN NOT NULL,
+    open_orders TEXT NOT NULL,
+    wallet TEXT,
+    order_id TEXT NOT NULL,
+    client_order_id TEXT NOT NULL,
+    native_coin_qty INTEGER NOT NULL,
+    native_pc_qty INTEGER NOT NULL,
+    native_fee_or_rebate INTEGER NOT NULL,
+    PRIMARY KEY (market, seq_num)
+);
+CREATE INDEX IF NOT EXISTS fills_wallet ON fills (wallet);
+";
+
+/// Stores fills in the `fills` table of a SQLite database. Identifiers are
+/// stored as base58 or decimal text, and quantities as integers.
+pub struct SqliteFillStore {
+    pub conn: Connection,
+}
+
+impl SqliteFillStore {
+    /// Opens the database at the given path, creating the table if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Stores fills in the given database, creating the table if needed.
+    pub fn new(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+}
+
+impl FillStore for SqliteFillStore {
+    fn next_seq_num(&mut self, market: &Pubkey) -> Result<Option<u64>> {
+        let last: Option<i64> = self.conn.query_row(
+            "SELECT MAX(seq_num) FROM fills WHERE market = ?1",
+            params![market.to_string()],
+            |row| row.get(0),
+        )?;
+        Ok(last.map(|seq_num| seq_num as u64 + 1))
+    }
+
+    fn insert(&mut self, fills: &[IndexedFill]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for fill in fills {
+            tx.execute(
+                "INSERT INTO fills VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                 ON CONFLICT DO NOTHING",
+                params![
+                    fill.market.to_string(),
+                    i64::try_from(fill.seq_num)?,
+                    fill.timestamp,
+                    side_name(fill.side),
+                    fill.maker,
+                    fill.open_orders.to_string(),
+                    fill.wallet.map(|wallet| wallet.to_string()),
+                    fill.order_id.to_string(),
+                    fill.client_order_id.to_string(),
+                    i64::try_from(fill.native_coin_qty)?,
+                    i64::try_from(fill.native_pc_qty)?,
+                    i64::try_from(fill.native_fee_or_rebate)?,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn fills(&mut self, market: &Pubkey, from: i64, to: i64) -> Result<Vec<IndexedFill>> {
+        let mut statement = self.conn.prepare(
+            "SELECT * FROM fills WHERE market = ?1 AND timestamp BETWEEN ?2 AND ?3
+             ORDER BY seq_num",
+        )?;
+        let mut rows = statement.query(params![market.to_string(), from, to])?;
+        let mut fills = Vec::new();
+        while let Some(row) = rows.next()? {
+            let wallet: Option<String> = row.get(6)?;
+            fills.push(IndexedFill {
+                market: *market,
+                seq_num: u64::try_from(row.get::<_, i64>(1)?)?,
+                timestamp: row.get(2)?,
+                side: parse_side(&row.get::<_, String>(3)?)?,
+                maker: row.get(4)?,
+                open_orders: row.get::<_, String>(5)?.parse()?,
+                wallet: wallet.map(|wallet| wallet.parse()).transpose()?,
+                order_id: row.get::<_, String>(7)?.parse()?,
+                client_order_id: row.get::<_, String>(8)?.parse()?,
+                native_coin_qty: u64::try_from(row.get::<_, i64>(9)?)?,
+                native_pc_qty: u64::try_from(row.get::<_, i64>(10)?)?,
+                native_fee_or_rebate: u64::try_from(row.get::<_, i64>(11)?)?,
+            });
+        }
+        Ok(fills)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex::matching::Side;
+    use serum_dex_permissioned::QueueEvent;
+
+    #[test]
+    fn test_sqlite_store() {
+        let mut store = SqliteFillStore::new(Connection::open_in_memory().unwrap()).unwrap();
+        let market = Pubkey::new_unique();
+        let event = QueueEvent::Fill {
+            side: Side::Bid,
+            maker: true,
+            native_qty_paid: 100,
+            native_qty_received: 10,
+            native_fee_or_rebate: 1,
+            order_id: u128::MAX,
+            owner: Pubkey::new_unique(),
+            owner_slot: 0,
+            fee_tier: 0,
+            client_order_id: u64::MAX,
+        };
+        let fill = IndexedFill::new(&market, &crate::Fill { seq_num: 5, event }, None, 0);
+        assert_eq!(store.next_seq_num(&market).unwrap(), None);
+        store.insert(&[fill.clone(), fill.clone()]).unwrap();
+        assert_eq!(store.next_seq_num(&market).unwrap(), Some(6));
+        assert_eq!(store.fills(&market, 0, 0).unwrap(), vec![fill]);
+        let count: i64 = store
+            .conn
+            .query_row("SELECT COUNT(*) FROM fills", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Result};
+use futures::future;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use log::warn;
+use serum_dex::matching::Side;
+use serum_dex_permissioned::{EventQueueReader, QueueEvent};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+
+/// A fill event of a market's event queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fill {
+    /// The sequence number of the event in the queue.
+    pub seq_num: u64,
+    /// A `QueueEvent::Fill`.
+    pub event: QueueEvent,
+}
+
+impl Fill {
+    /// The coin traded, in native units.
+    pub fn native_coin_qty(&self) -> u64 {
+        match self.event {
+            QueueEvent::Fill {
+                side: Side::Bid,
+                native_qty_received,
+                ..
+            } => native_qty_received,
+            QueueEvent::Fill {
+                native_qty_paid, ..
+            } => native_qty_paid,
+            QueueEvent::Out { .. } => 0,
+        }
+    }
+
+    /// The pc traded, in native units, before the taker fee or maker rebate.
+    pub fn native_pc_qty(&self) -> u64 {
+        match self.event {
+            QueueEvent::Fill {
+                side,
+                maker,
+                native_qty_paid,
+                native_qty_received,
+                native_fee_or_rebate,
+                ..
+            } => match (side, maker) {
+                (Side::Bid, true) => native_qty_paid.saturating_add(native_fee_or_rebate),
+                (Side::Bid, false) => native_qty_paid.saturating_sub(native_fee_or_rebate),
+                (Side::Ask, true) => native_qty_received.saturating_sub(native_fee_or_rebate),
+                (Side::Ask, false) => native_qty_received.saturating_add(native_fee_or_rebate),
+            },
+            QueueEvent::Out { .. } => 0,
+        }
+    }
+}
+
+/// Tracks the sequence numbers of the events of a queue across updates of
+/// its account, to return each fill once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FillTracker {
+    next_seq_num: Option<u64>,
+}
+
+impl FillTracker {
+    /// Tracks the fills numbered from `next_seq_num`, e.g. the next fill to
+    /// index, or, without one, those queued at the first update.
+    pub fn new(next_seq_num: Option<u64>) -> Self {
+        Self { next_seq_num }
+    }
+
+    /// The sequence number of the next event to return.
+    pub fn next_seq_num(&self) -> Option<u64> {
+        self.next_seq_num
+    }
+
+    /// Returns the fills pushed to the queue since the previous update,
+    /// warning about those consumed before being seen.
+    pub fn update(&mut self, queue: &EventQueueReader) -> Result<Vec<Fill>> {
+        let fills = self
+            .update_events(queue)?
+            .into_iter()
+            .filter(|(_, event)| matches!(event, QueueEvent::Fill { .. }))
+            .map(|(seq_num, event)| Fill { seq_num, event })
+            .collect();
+        Ok(fills)
+    }
+
+    /// Returns the events, fills and outs, pushed to the queue since the
+    /// previous update, along with their sequence numbers.
+    pub fn update_events(&mut self, queue: &EventQueueReader) -> Result<Vec<(u64, QueueEvent)>> {
+        let first = queue.seq_num().saturating_sub(queue.len() as u64);
+        let next = self.next_seq_num.unwrap_or(first);
+        if next < first {
+            warn!("missed {} events consumed between updates", first - next);
+        }
+        let mut events = Vec::new();
+        let seen = next.saturating_sub(first) as usize;
+        for (seq_num, event) in (first..).zip(queue.events()).skip(seen) {
+            let event = event.map_err(|e| anyhow!("invalid event {}: {:?}", seq_num, e))?;
+            events.push((seq_num, event));
+        }
+        self.next_seq_num = Some(next.max(queue.seq_num()));
+        Ok(events)
+    }
+}
+
+/// Decodes the fills of a stream of event queue account data, e.g. from
+/// `subscribe_event_queue` or a Geyser plugin, yielding an error for each
+/// update that doesn't decode.
+pub fn fill_stream<'a, S>(updates: S, tracker: FillTracker) -> impl Stream<Item = Result<Fill>> + 'a
+where
+    S: Stream<Item = Vec<u8>> + 'a,
+{
+    updates
+        .scan(tracker, |tracker, data| {
+            let fills: Vec<Result<Fill>> = match EventQueueReader::unpack(&data)
+                .map_err(|e| anyhow!("invalid event queue: {:?}", e))
+                .and_then(|queue| tracker.update(&queue))
+            {
+                Ok(fills) => fills.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            future::ready(Some(stream::iter(fills)))
+        })
+        .flatten()
+}
+
+/// Subscribes to the updates of the given event queue account over the
+/// websocket of `client`, returning the stream of its data along with the
+/// function unsubscribing from it.
+pub async fn subscribe_event_queue<'a>(
+    client: &'a PubsubClient,
+    event_q: &Pubkey,
+    commitment: CommitmentConfig,
+) -> Result<(
+    BoxStream<'a, Vec<u8>>,
+    impl FnOnce() -> future::BoxFuture<'static, ()>,
+)> {
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(commitment),
+        ..RpcAccountInfoConfig::default()
+    };
+    let (updates, unsubscribe) = client.account_subscribe(event_q, Some(config)).await?;
+    let updates = updates
+        .filter_map(|response| future::ready(response.value.data.decode()))
+        .boxed();
+    Ok((updates, unsubscribe))
+}
+
+/// Subscribes to the fills of the given event queue over the websocket of
+/// `client`, see `fill_stream`.
+pub async fn subscribe_fills<'a>(
+    client: &'a PubsubClient,
+    event_q: &Pubkey,
+    commitment: CommitmentConfig,
+    tracker: FillTracker,
+) -> Result<(
+    impl Stream<Item = Result<Fill>> + 'a,
+    impl FnOnce() -> future::BoxFuture<'static, ()>,
+)> {
+    let (updates, unsubscribe) = subscribe_event_queue(client, event_q, commitment).await?;
+    Ok((fill_stream(updates, tracker), unsubscribe))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use serum_dex::state::{AccountFlag, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
+
+    // Returns the data of a queue of four fills, of the client order id of
+    // their slot, with the given head, count and sequence number.
+    fn queue_data(head: u64, count: u64, seq_num: u64) -> Vec<u8> {
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::EventQueue as u64;
+        for word in [flags, head, count, seq_num].iter() {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for client_order_id in 0..4u64 {
+            let mut event = vec![0; 88];
+            event[0] = 0x1;
+            event[80..].copy_from_slice(&client_order_id.to_le_bytes());
+            data.extend_from_slice(&event);
+        }
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        data
+    }
+
+    #[test]
+    fn test_fill_stream() {
+        // Two fills queued, then one consumed and two pushed, then three
+        // more pushed, two of which were consumed unseen.
+        let updates = vec![
+            queue_data(0, 2, 2),
+            queue_data(1, 3, 4),
+            vec![0; 8],
+            queue_data(2, 1, 7),
+        ];
+        let fills = fill_stream(stream::iter(updates), FillTracker::new(None));
+        let fills: Vec<_> = block_on(fills.collect());
+        assert_eq!(fills.len(), 6);
+        assert!(fills[4].is_err());
+        let seq_nums: Vec<_> = fills
+            .iter()
+            .filter_map(|fill| fill.as_ref().ok())
+            .map(|fill| (fill.seq_num, fill.event.client_order_id()))
+            .collect();
+        assert_eq!(seq_nums, vec![(0, 0), (1, 1), (2, 2), (3, 3), (6, 2)]);
+
+        let mut tracker = FillTracker::new(Some(3));
+        let data = queue_data(1, 3, 4);
+        let queue = EventQueueReader::unpack(&data).unwrap();
+        assert_eq!(tracker.update(&queue).unwrap().len(), 1);
+        assert_eq!(tracker.next_seq_num(), Some(4));
+        assert!(tracker.update(&queue).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,477 @@
+use crate::{Fill, FillTracker, MarketInfo};
+use anyhow::{anyhow, Result};
+use serum_dex::instruction::NewOrderInstructionV3;
+use serum_dex::matching::Side;
+use serum_dex_permissioned::{BookSide, EventQueueReader, QueueEvent, RestingOrder};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How many markets `OrderTracker::poll` loads the accounts of per request,
+/// at three accounts per market.
+const MARKETS_PER_REQUEST: usize = 33;
+
+/// Where a tracked order stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderStatus {
+    /// Sent, but neither seen resting on the book nor filled yet.
+    Pending,
+    /// Resting on the book, or partly filled.
+    Open,
+    /// Fully filled.
+    Filled,
+    /// Closed with lots left unfilled, e.g. cancelled, or the remainder of
+    /// an immediate or cancel order.
+    Cancelled,
+}
+
+/// An order of the `OrderTracker`, identified by its client order id, with
+/// its prices and sizes in lots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackedOrder {
+    pub market: Pubkey,
+    pub client_order_id: u64,
+    /// Known once the order is seen on the book or filled.
+    pub order_id: Option<u128>,
+    pub side: Side,
+    pub limit_price: u64,
+    pub max_coin_qty: u64,
+    pub filled_coin_qty: u64,
+    /// The pc traded by the fills, before fees or rebates.
+    pub filled_native_pc_qty: u64,
+    pub status: OrderStatus,
+    sent_at: Instant,
+}
+
+impl TrackedOrder {
+    /// The coin lots left to fill.
+    pub fn remaining_coin_qty(&self) -> u64 {
+        self.max_coin_qty.saturating_sub(self.filled_coin_qty)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.status, OrderStatus::Filled | OrderStatus::Cancelled)
+    }
+
+    fn close(&mut self) {
+        self.status = match self.remaining_coin_qty() {
+            0 => OrderStatus::Filled,
+            _ => OrderStatus::Cancelled,
+        };
+    }
+}
+
+/// Callbacks of the `OrderTracker`, all doing nothing by default. Orders
+/// are passed with their status updated.
+pub trait OrderHandler {
+    /// Called when a pending order, or one placed before the tracker
+    /// started, is seen resting on the book.
+    fn on_open(&mut self, _order: &TrackedOrder) {}
+
+    /// Called for each fill of an order, the last one leaving it `Filled`.
+    fn on_fill(&mut self, _order: &TrackedOrder, _fill: &Fill) {}
+
+    /// Called for each `QueueEvent::Out` of an order, e.g. when it leaves
+    /// the book.
+    fn on_out(&mut self, _order: &TrackedOrder, _event: &QueueEvent) {}
+
+    /// Called when an order closes with lots left unfilled.
+    fn on_cancel(&mut self, _order: &TrackedOrder) {}
+}
+
+impl OrderHandler for () {}
+
+struct MarketOrders {
+    market: MarketInfo,
+    open_orders: Pubkey,
+    events: FillTracker,
+    orders: HashMap<u64, TrackedOrder>,
+}
+
+impl MarketOrders {
+    fn process_events(&mut self, events: &[(u64, QueueEvent)], handler: &mut impl OrderHandler) {
+        let open_orders = self.open_orders;
+        let events = events
+            .iter()
+            .filter(|(_, event)| event.owner() == &open_orders);
+        for (seq_num, event) in events {
+            let order = match self.orders.get_mut(&event.client_order_id()) {
+                Some(order) => order,
+                None => continue,
+            };
+            order.order_id = Some(event.order_id());
+            let fill = Fill {
+                seq_num: *seq_num,
+                event: *event,
+            };
+            match event {
+                QueueEvent::Fill { .. } => {
+                    let coin_lot_size = self.market.header.coin_lot_size;
+                    let filled = fill.native_coin_qty() / coin_lot_size;
+                    order.filled_coin_qty = order.filled_coin_qty.saturating_add(filled);
+                    order.filled_native_pc_qty = order
+                        .filled_native_pc_qty
+                        .saturating_add(fill.native_pc_qty());
+                    order.status = match order.remaining_coin_qty() {
+                        0 => OrderStatus::Filled,
+                        _ => OrderStatus::Open,
+                    };
+                    handler.on_fill(order, &fill);
+                }
+                QueueEvent::Out {
+                    native_qty_still_locked,
+                    ..
+                } => {
+                    let closes = *native_qty_still_locked == 0 && !order.is_closed();
+                    if closes {
+                        order.close();
+                    }
+                    handler.on_out(order, event);
+                    if closes && order.status == OrderStatus::Cancelled {
+                        handler.on_cancel(order);
+                    }
+                }
+            }
+        }
+    }
+
+    // Reconciles the orders with those of the open orders account resting on
+    // the book, then forgets those closed.
+    fn reconcile(
+        &mut self,
+        resting: impl Iterator<Item = RestingOrder>,
+        pending_timeout: Duration,
+        handler: &mut impl OrderHandler,
+    ) {
+        let mut resting: HashMap<u64, RestingOrder> = resting
+            .filter(|order| order.client_order_id != 0)
+            .map(|order| (order.client_order_id, order))
+            .collect();
+        for order in self.orders.values_mut() {
+            match resting.remove(&order.client_order_id) {
+                Some(resting) if !order.is_closed() => {
+                    order.order_id = Some(resting.order_id);
+                    if order.status == OrderStatus::Pending {
+                        order.status = OrderStatus::Open;
+                        handler.on_open(order);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    // Orders leaving the book without an out seen, e.g. one
+                    // consumed between updates, or taking without posting.
+                    let left = match order.status {
+                        OrderStatus::Open => true,
+                        OrderStatus::Pending => order.sent_at.elapsed() >= pending_timeout,
+                        _ => false,
+                    };
+                    if left {
+                        order.close();
+                        if order.status == OrderStatus::Cancelled {
+                            handler.on_cancel(order);
+                        }
+                    }
+                }
+            }
+        }
+        // Orders placed before the tracker started.
+        for (client_order_id, resting) in resting {
+            let order = TrackedOrder {
+                market: *self.market.address(),
+                client_order_id,
+                order_id: Some(resting.order_id),
+                side: resting.side,
+                limit_price: resting.price,
+                max_coin_qty: resting.size,
+                filled_coin_qty: 0,
+                filled_native_pc_qty: 0,
+                status: OrderStatus::Open,
+                sent_at: Instant::now(),
+            };
+            handler.on_open(&order);
+            self.orders.insert(client_order_id, order);
+        }
+        self.orders.retain(|_, order| !order.is_closed());
+    }
+}
+
+/// Tracks the orders of an open orders account per market, by client order
+/// id, reconciling them with the market's event queue and order book, for
+/// bots trading on several markets. Client order ids should be non-zero and
+/// unique per market among open orders.
+///
+/// The events consumed by a crank between two updates are missed, so the
+/// orders leaving the book meanwhile close with the fills known so far.
+pub struct OrderTracker {
+    /// How long pending orders may stay neither on the book nor filled, e.g.
+    /// if their transaction failed, before being cancelled.
+    pub pending_timeout: Duration,
+    markets: HashMap<Pubkey, MarketOrders>,
+}
+
+impl OrderTracker {
+    pub fn new(pending_timeout: Duration) -> Self {
+        Self {
+            pending_timeout,
+            markets: HashMap::new(),
+        }
+    }
+
+    /// Tracks the orders of the given open orders account on the market,
+    /// from the events queued at the next update.
+    pub fn add_market(&mut self, market: MarketInfo, open_orders: Pubkey) {
+        let orders = MarketOrders {
+            market,
+            open_orders,
+            events: FillTracker::new(None),
+            orders: HashMap::new(),
+        };
+        self.markets.insert(*orders.market.address(), orders);
+    }
+
+    /// Tracks the given order, about to be sent to the given market.
+    pub fn track(&mut self, market: &Pubkey, order: &NewOrderInstructionV3) -> Result<()> {
+        let orders = self
+            .markets
+            .get_mut(market)
+            .ok_or_else(|| anyhow!("market {} isn't tracked", market))?;
+        if order.client_order_id == 0 || orders.orders.contains_key(&order.client_order_id) {
+            return Err(anyhow!("invalid client order id {}", order.client_order_id));
+        }
+        let tracked = TrackedOrder {
+            market: *market,
+            client_order_id: order.client_order_id,
+            order_id: None,
+            side: order.side,
+            limit_price: order.limit_price.get(),
+            max_coin_qty: order.max_coin_qty.get(),
+            filled_coin_qty: 0,
+            filled_native_pc_qty: 0,
+            status: OrderStatus::Pending,
+            sent_at: Instant::now(),
+        };
+        orders.orders.insert(order.client_order_id, tracked);
+        Ok(())
+    }
+
+    /// Returns the given order, unless closed by the last update.
+    pub fn get(&self, market: &Pubkey, client_order_id: u64) -> Option<&TrackedOrder> {
+        self.markets.get(market)?.orders.get(&client_order_id)
+    }
+
+    /// Returns the pending and open orders of all markets.
+    pub fn orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.markets
+            .values()
+            .flat_map(|orders| orders.orders.values())
+    }
+
+    /// Updates the orders of the given market with the events queued since
+    /// the previous update, then its book, which should be of the same slot,
+    /// e.g. loaded in one `get_multiple_accounts` request.
+    pub fn update(
+        &mut self,
+        market: &Pubkey,
+        bids: &BookSide,
+        asks: &BookSide,
+        queue: &EventQueueReader,
+        handler: &mut impl OrderHandler,
+    ) -> Result<()> {
+        let pending_timeout = self.pending_timeout;
+        let orders = self
+            .markets
+            .get_mut(market)
+            .ok_or_else(|| anyhow!("market {} isn't tracked", market))?;
+        let events = orders.events.update_events(queue)?;
+        orders.process_events(&events, handler);
+        let open_orders = orders.open_orders;
+        let resting = bids
+            .orders_of(&open_orders)
+            .chain(asks.orders_of(&open_orders));
+        orders.reconcile(resting, pending_timeout, handler);
+        Ok(())
+    }
+
+    /// Loads the books and event queues of all markets, then updates them.
+    pub fn poll(&mut self, client: &RpcClient, handler: &mut impl OrderHandler) -> Result<()> {
+        let markets: Vec<_> = self
+            .markets
+            .values()
+            .map(|orders| orders.market.header)
+            .collect();
+        for markets in markets.chunks(MARKETS_PER_REQUEST) {
+            let keys: Vec<_> = markets
+                .iter()
+                .flat_map(|header| vec![header.bids, header.asks, header.event_q])
+                .collect();
+            let accounts = client.get_multiple_accounts(&keys)?;
+            for (header, accounts) in markets.iter().zip(accounts.chunks(3)) {
+                let data = |i: usize| {
+                    accounts[i]
+                        .as_ref()
+                        .map(|account| &account.data[..])
+                        .ok_or_else(|| anyhow!("accounts of {} not found", header.own_address))
+                };
+                let bids = BookSide::unpack(data(0)?)
+                    .map_err(|e| anyhow!("invalid bids {}: {:?}", header.bids, e))?;
+                let asks = BookSide::unpack(data(1)?)
+                    .map_err(|e| anyhow!("invalid asks {}: {:?}", header.asks, e))?;
+                let queue = EventQueueReader::unpack(data(2)?)
+                    .map_err(|e| anyhow!("invalid event queue {}: {:?}", header.event_q, e))?;
+                self.update(&header.own_address, &bids, &asks, &queue, handler)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex::matching::OrderType;
+    use serum_dex_permissioned::MarketHeader;
+
+    #[derive(Default)]
+    struct Recorder {
+        opened: Vec<u64>,
+        filled: Vec<(u64, OrderStatus)>,
+        outs: Vec<u64>,
+        cancelled: Vec<u64>,
+    }
+
+    impl OrderHandler for Recorder {
+        fn on_open(&mut self, order: &TrackedOrder) {
+            self.opened.push(order.client_order_id);
+        }
+
+        fn on_fill(&mut self, order: &TrackedOrder, _fill: &Fill) {
+            self.filled.push((order.client_order_id, order.status));
+        }
+
+        fn on_out(&mut self, order: &TrackedOrder, _event: &QueueEvent) {
+            self.outs.push(order.client_order_id);
+        }
+
+        fn on_cancel(&mut self, order: &TrackedOrder) {
+            self.cancelled.push(order.client_order_id);
+        }
+    }
+
+    fn market() -> MarketInfo {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 10,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        MarketInfo::new(&program_id, header, 0, 0).unwrap()
+    }
+
+    fn resting(owner: Pubkey, client_order_id: u64) -> RestingOrder {
+        RestingOrder {
+            order_id: client_order_id as u128,
+            owner,
+            owner_slot: 0,
+            client_order_id,
+            price: 10,
+            size: 4,
+            side: Side::Bid,
+            peg_offset: None,
+            expiry_ts: None,
+        }
+    }
+
+    #[test]
+    fn test_order_tracker() {
+        let market = market();
+        let address = *market.address();
+        let open_orders = Pubkey::new_unique();
+        let mut tracker = OrderTracker::new(Duration::from_secs(0));
+        tracker.add_market(market.clone(), open_orders);
+        for client_order_id in 1..=3 {
+            let order = market
+                .new_order(Side::Bid, 10.0, 40.0, OrderType::Limit, client_order_id)
+                .unwrap();
+            tracker.track(&address, &order).unwrap();
+        }
+        let order = market
+            .new_order(Side::Bid, 10.0, 40.0, OrderType::Limit, 1)
+            .unwrap();
+        assert!(tracker.track(&address, &order).is_err());
+
+        // 1 fills fully, 2 rests after a partial fill, then is cancelled,
+        // and 3 never lands. 4 was placed before.
+        let fill = |client_order_id, native_coin| QueueEvent::Fill {
+            side: Side::Bid,
+            maker: false,
+            native_qty_paid: native_coin,
+            native_qty_received: native_coin,
+            native_fee_or_rebate: 0,
+            order_id: client_order_id as u128,
+            owner: open_orders,
+            owner_slot: 0,
+            fee_tier: 0,
+            client_order_id,
+        };
+        let out = |client_order_id| QueueEvent::Out {
+            side: Side::Bid,
+            release_funds: true,
+            native_qty_unlocked: 0,
+            native_qty_still_locked: 0,
+            order_id: client_order_id as u128,
+            owner: open_orders,
+            owner_slot: 0,
+            client_order_id,
+        };
+        let mut recorder = Recorder::default();
+        let orders = tracker.markets.get_mut(&address).unwrap();
+        orders.process_events(&[(0, fill(1, 40)), (1, fill(2, 10))], &mut recorder);
+        let book = vec![resting(open_orders, 2), resting(open_orders, 4)];
+        orders.reconcile(book.into_iter(), Duration::from_secs(60), &mut recorder);
+        assert_eq!(
+            recorder.filled,
+            vec![(1, OrderStatus::Filled), (2, OrderStatus::Open)]
+        );
+        assert_eq!(recorder.opened, vec![4]);
+        assert!(tracker.get(&address, 1).is_none());
+        assert_eq!(tracker.get(&address, 2).unwrap().filled_coin_qty, 1);
+        assert_eq!(
+            tracker.get(&address, 3).unwrap().status,
+            OrderStatus::Pending
+        );
+
+        let orders = tracker.markets.get_mut(&address).unwrap();
+        orders.process_events(&[(2, out(2))], &mut recorder);
+        let book = vec![resting(open_orders, 4)];
+        orders.reconcile(book.into_iter(), Duration::from_secs(0), &mut recorder);
+        assert_eq!(recorder.outs, vec![2]);
+        assert_eq!(recorder.cancelled, vec![2, 3]);
+        let open: Vec<_> = tracker
+            .orders()
+            .map(|order| order.client_order_id)
+            .collect();
+        assert_eq!(open, vec![4]);
+    }
+}
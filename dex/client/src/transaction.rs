@@ -0,0 +1,271 @@
+use crate::{
+    associated_token_address, create_associated_token_account_ix, new_order_ix, settle_funds_ix,
+    MarketInfo, ProxyRoute,
+};
+use anyhow::{anyhow, Result};
+use serum_dex::instruction::NewOrderInstructionV3;
+use serum_dex::matching::Side;
+use serum_dex_permissioned::compile_proxy_message;
+use solana_compute_budget_interface::ComputeBudgetInstruction;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{AddressLookupTableAccount, Message, VersionedMessage};
+use solana_sdk::pubkey::Pubkey;
+use solana_system_interface::instruction as system_instruction;
+
+/// Builds the transaction placing an order from the owner's associated token
+/// accounts, in the order the DEX needs: the compute budget, then the
+/// idempotent creation of the wallets, the wrapping of the SOL paid, if the
+/// order pays in the native mint, the order, through the proxy if any, and
+/// finally, optionally, the settlement of the open orders account.
+#[derive(Clone, Debug)]
+pub struct OrderTransactionBuilder<'a> {
+    market: &'a MarketInfo,
+    owner: Pubkey,
+    open_orders: Pubkey,
+    payer: Pubkey,
+    proxy: Option<&'a ProxyRoute>,
+    compute_unit_limit: Option<u32>,
+    compute_unit_price: Option<u64>,
+    wrap_sol: bool,
+    settle: bool,
+    referrer_pc_wallet: Option<Pubkey>,
+}
+
+impl<'a> OrderTransactionBuilder<'a> {
+    /// Places orders of `owner`, who pays for the transaction, with the given
+    /// open orders account, wrapping SOL.
+    pub fn new(market: &'a MarketInfo, owner: Pubkey, open_orders: Pubkey) -> Self {
+        Self {
+            market,
+            owner,
+            open_orders,
+            payer: owner,
+            proxy: None,
+            compute_unit_limit: None,
+            compute_unit_price: None,
+            wrap_sol: true,
+            settle: false,
+            referrer_pc_wallet: None,
+        }
+    }
+
+    /// Pays for the transaction, and the wallets created, from `payer`.
+    pub fn payer(mut self, payer: Pubkey) -> Self {
+        self.payer = payer;
+        self
+    }
+
+    /// Routes the order, and settlement, through the given proxy.
+    pub fn proxy(mut self, proxy: &'a ProxyRoute) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn compute_unit_limit(mut self, units: u32) -> Self {
+        self.compute_unit_limit = Some(units);
+        self
+    }
+
+    /// Priority fee, in micro-lamports per compute unit.
+    pub fn compute_unit_price(mut self, micro_lamports: u64) -> Self {
+        self.compute_unit_price = Some(micro_lamports);
+        self
+    }
+
+    /// Whether to wrap the most SOL the order can pay into the owner's
+    /// wrapped SOL account, when it pays in the native mint, rather than
+    /// paying from its balance.
+    pub fn wrap_sol(mut self, wrap_sol: bool) -> Self {
+        self.wrap_sol = wrap_sol;
+        self
+    }
+
+    /// Settles the open orders account after the order, crediting the
+    /// referrer's pc wallet, if any.
+    pub fn settle(mut self, referrer_pc_wallet: Option<Pubkey>) -> Self {
+        self.settle = true;
+        self.referrer_pc_wallet = referrer_pc_wallet;
+        self
+    }
+
+    /// Returns the instructions placing the given order.
+    pub fn instructions(&self, order: NewOrderInstructionV3) -> Result<Vec<Instruction>> {
+        let header = &self.market.header;
+        let (pay_mint, native_qty) = match order.side {
+            Side::Bid => (header.pc_mint, order.max_native_pc_qty_including_fees.get()),
+            Side::Ask => (
+                header.coin_mint,
+                order
+                    .max_coin_qty
+                    .get()
+                    .saturating_mul(header.coin_lot_size),
+            ),
+        };
+        let coin_wallet = associated_token_address(&self.owner, &header.coin_mint);
+        let pc_wallet = associated_token_address(&self.owner, &header.pc_mint);
+        let order_payer = associated_token_address(&self.owner, &pay_mint);
+
+        let mut ixs = Vec::new();
+        if let Some(units) = self.compute_unit_limit {
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(units));
+        }
+        if let Some(price) = self.compute_unit_price {
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+        }
+        // Settling credits both wallets, while the order only debits one.
+        let mints = if self.settle {
+            vec![header.coin_mint, header.pc_mint]
+        } else {
+            vec![pay_mint]
+        };
+        for mint in &mints {
+            ixs.push(create_associated_token_account_ix(
+                &self.payer,
+                &self.owner,
+                mint,
+            ));
+        }
+        if self.wrap_sol && pay_mint == spl_token::native_mint::ID {
+            ixs.push(system_instruction::transfer(
+                &self.owner,
+                &order_payer,
+                native_qty,
+            ));
+            ixs.push(spl_token::instruction::sync_native(
+                &spl_token::ID,
+                &order_payer,
+            )?);
+        }
+        let ix = new_order_ix(
+            self.market,
+            &self.open_orders,
+            &order_payer,
+            &self.owner,
+            order,
+        )?;
+        ixs.push(self.routed(ix));
+        if self.settle {
+            let ix = settle_funds_ix(
+                self.market,
+                &self.open_orders,
+                &self.owner,
+                &coin_wallet,
+                &pc_wallet,
+                self.referrer_pc_wallet.as_ref(),
+            )?;
+            ixs.push(self.routed(ix));
+        }
+        Ok(ixs)
+    }
+
+    /// Returns the message placing the given order, to be signed by the
+    /// owner and payer.
+    pub fn message(&self, order: NewOrderInstructionV3, blockhash: &Hash) -> Result<Message> {
+        let ixs = self.instructions(order)?;
+        Ok(Message::new_with_blockhash(
+            &ixs,
+            Some(&self.payer),
+            blockhash,
+        ))
+    }
+
+    /// Returns the v0 message placing the given order, looking up the
+    /// accounts found in the given tables (see `market_lookup_table_keys`).
+    pub fn versioned_message(
+        &self,
+        order: NewOrderInstructionV3,
+        lookup_tables: &[AddressLookupTableAccount],
+        blockhash: &Hash,
+    ) -> Result<VersionedMessage> {
+        let ixs = self.instructions(order)?;
+        compile_proxy_message(&self.payer, &ixs, lookup_tables, *blockhash)
+            .map_err(|e| anyhow!("failed to compile message: {:?}", e))
+    }
+
+    fn routed(&self, ix: Instruction) -> Instruction {
+        match self.proxy {
+            Some(proxy) => proxy.route(ix),
+            None => ix,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex::matching::OrderType;
+    use serum_dex_permissioned::MarketHeader;
+
+    #[test]
+    fn test_order_transaction() {
+        let program_id = Pubkey::new_unique();
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| {
+                serum_dex::state::gen_vault_signer_key(*nonce, &own_address, &program_id).is_ok()
+            })
+            .unwrap();
+        let header = MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: spl_token::native_mint::ID,
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 100_000_000,
+            pc_lot_size: 1_000,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        };
+        let market = MarketInfo::new(&program_id, header, 9, 6).unwrap();
+        let (owner, open_orders) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let proxy = ProxyRoute::new(Pubkey::new_unique());
+        let builder = OrderTransactionBuilder::new(&market, owner, open_orders)
+            .proxy(&proxy)
+            .compute_unit_price(1_000)
+            .settle(None);
+
+        // Asks of SOL wrap their size.
+        let ask = market
+            .new_order(Side::Ask, 25.0, 1.5, OrderType::Limit, 1)
+            .unwrap();
+        let ixs = builder.instructions(ask).unwrap();
+        let programs: Vec<_> = ixs.iter().map(|ix| ix.program_id).collect();
+        assert_eq!(
+            programs,
+            vec![
+                solana_compute_budget_interface::ID,
+                crate::ASSOCIATED_TOKEN_PROGRAM_ID,
+                crate::ASSOCIATED_TOKEN_PROGRAM_ID,
+                solana_system_interface::program::ID,
+                spl_token::ID,
+                proxy.program_id,
+                proxy.program_id,
+            ]
+        );
+        let wrapped = associated_token_address(&owner, &spl_token::native_mint::ID);
+        assert_eq!(
+            ixs[3],
+            system_instruction::transfer(&owner, &wrapped, 1_500_000_000)
+        );
+
+        // Bids of the pc mint only create the pc wallet without settling.
+        let bid = market
+            .new_order(Side::Bid, 25.0, 1.5, OrderType::Limit, 2)
+            .unwrap();
+        let builder = OrderTransactionBuilder::new(&market, owner, open_orders);
+        let ixs = builder.instructions(bid).unwrap();
+        assert_eq!(ixs.len(), 2);
+        assert_eq!(ixs[0].accounts[3].pubkey, market.header.pc_mint);
+        assert_eq!(ixs[1].program_id, program_id);
+    }
+}
@@ -12,13 +12,13 @@ use std::time::SystemTime;
 use std::{thread, time};
 
 use anyhow::{format_err, Result};
+use bytemuck::cast;
 use clap::Parser;
 use debug_print::debug_println;
 use log::{error, info};
 use safe_transmute::{
-    guard::SingleManyGuard,
-    to_bytes:: transmute_to_bytes,
-    transmute_many, transmute_many_pedantic, transmute_one_pedantic,
+    guard::SingleManyGuard, to_bytes::transmute_to_bytes, transmute_many, transmute_many_pedantic,
+    transmute_one_pedantic,
 };
 use sloggers::file::FileLoggerBuilder;
 use sloggers::types::Severity;
@@ -34,7 +34,6 @@ use solana_sdk::signature::{Keypair, Signer};
 use solana_sdk::transaction::Transaction;
 use spl_token::instruction as token_instruction;
 use warp::Filter;
-use bytemuck::cast;
 
 use serum_common::client::rpc::{
     create_and_init_mint, create_token_account, mint_to_new_account, send_txn, simulate_transaction,
@@ -446,14 +445,19 @@ fn get_keys_for_market<'a>(
         transmute_to_bytes(&identity(market_state.own_address)),
         market.as_ref()
     );
-    Ok(MarketPubkeys { // using new_from_array instead of new to avoid the need for a const constructor
+    Ok(MarketPubkeys {
+        // using new_from_array instead of new to avoid the need for a const constructor
         market: Box::new(*market),
         req_q: Box::new(Pubkey::new_from_array(cast(identity(market_state.req_q)))),
         event_q: Box::new(Pubkey::new_from_array(cast(identity(market_state.event_q)))),
         bids: Box::new(Pubkey::new_from_array(cast(identity(market_state.bids)))),
         asks: Box::new(Pubkey::new_from_array(cast(identity(market_state.asks)))),
-        coin_vault: Box::new(Pubkey::new_from_array(cast(identity(market_state.coin_vault)))),
-        pc_vault: Box::new(Pubkey::new_from_array(cast(identity(market_state.pc_vault)))),
+        coin_vault: Box::new(Pubkey::new_from_array(cast(identity(
+            market_state.coin_vault,
+        )))),
+        pc_vault: Box::new(Pubkey::new_from_array(cast(identity(
+            market_state.pc_vault,
+        )))),
         vault_signer_key: Box::new(vault_signer_key),
     })
 }
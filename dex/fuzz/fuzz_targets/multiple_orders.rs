@@ -406,6 +406,8 @@ fn run_action<'bump>(
                 DexError::ErrorCode(DexErrorCode::InsufficientFunds) => {}
                 DexError::ErrorCode(DexErrorCode::RequestQueueFull) => {}
                 DexError::ErrorCode(DexErrorCode::OrdersNotRentExempt) => {}
+                DexError::ErrorCode(DexErrorCode::InvalidLimitPrice)
+                    if instruction.limit_price.get() >= 1 << 63 => {}
                 DexError::ErrorCode(DexErrorCode::WouldSelfTrade)
                     if instruction.self_trade_behavior == SelfTradeBehavior::AbortTransaction => {}
                 DexError::ErrorCode(DexErrorCode::WrongOrdersAccount)
@@ -0,0 +1,158 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use spl_token::solana_program::entrypoint::ProgramResult;
+use std::ops::Index;
+
+/// The accounts relayed to the DEX, kept as a view over the accounts given
+/// to the proxy. Middleware strip, reorder, and sign for accounts through
+/// the view, so that relaying an instruction doesn't clone every
+/// `AccountInfo`.
+#[derive(Clone)]
+pub struct RelayAccounts<'a, 'info> {
+    infos: &'a [AccountInfo<'info>],
+    // Index in `infos` of each relayed account.
+    indices: Vec<usize>,
+    // Accounts marked as signers by middleware, e.g., PDAs of the proxy.
+    signers: Vec<AccountInfo<'info>>,
+}
+
+impl<'a, 'info> RelayAccounts<'a, 'info> {
+    /// Constructs a view relaying all of the given accounts, in order.
+    pub fn new(infos: &'a [AccountInfo<'info>]) -> Self {
+        Self {
+            infos,
+            indices: (0..infos.len()).collect(),
+            signers: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Returns the account at the given index, marked as a signer if a
+    /// middleware signed for it.
+    pub fn get(&self, idx: usize) -> Option<&AccountInfo<'info>> {
+        let info = &self.infos[*self.indices.get(idx)?];
+        let signer = self.signers.iter().find(|signer| signer.key == info.key);
+        Some(signer.unwrap_or(info))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AccountInfo<'info>> + '_ {
+        (0..self.len()).filter_map(move |idx| self.get(idx))
+    }
+
+    /// Removes the first `n` accounts, e.g., accounts only needed by the
+    /// middleware.
+    pub fn strip(&mut self, n: usize) -> ProgramResult {
+        if self.len() < n {
+            return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+        }
+        self.indices.drain(..n);
+        Ok(())
+    }
+
+    /// Removes the account at the given index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn remove(&mut self, idx: usize) {
+        self.indices.remove(idx);
+    }
+
+    /// Relays the account at index `src` in place of the account at index
+    /// `idx`, e.g., to make the open orders account its own authority.
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn replace(&mut self, idx: usize, src: usize) {
+        self.indices[idx] = self.indices[src];
+    }
+
+    /// Marks the account at the given index as a signer of the relayed
+    /// instruction. The middleware must push the seeds signing for it to
+    /// `Context::seeds`.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn sign(&mut self, idx: usize) {
+        let info = &self.infos[self.indices[idx]];
+        if self.signers.iter().all(|signer| signer.key != info.key) {
+            let mut info = info.clone();
+            info.is_signer = true;
+            self.signers.push(info);
+        }
+    }
+
+    /// Returns the metas of the relayed accounts, in order.
+    pub fn metas(&self) -> Vec<AccountMeta> {
+        self.iter()
+            .map(|acc| AccountMeta {
+                pubkey: *acc.key,
+                is_signer: acc.is_signer,
+                is_writable: acc.is_writable,
+            })
+            .collect()
+    }
+
+    /// Returns the accounts the view was constructed from, which include
+    /// every relayed account.
+    pub fn infos(&self) -> &'a [AccountInfo<'info>] {
+        self.infos
+    }
+
+    /// Returns the relayed accounts, cloned.
+    pub fn to_vec(&self) -> Vec<AccountInfo<'info>> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<'a, 'info> Index<usize> for RelayAccounts<'a, 'info> {
+    type Output = AccountInfo<'info>;
+
+    fn index(&self, idx: usize) -> &Self::Output {
+        self.get(idx).expect("account index out of bounds")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    fn dummy_account() -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(Vec::new().into_boxed_slice()),
+            Box::leak(Box::new(Pubkey::default())),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_relay_accounts() {
+        let infos: Vec<_> = (0..4).map(|_| dummy_account()).collect();
+        let mut accounts = RelayAccounts::new(&infos);
+        accounts.strip(1).unwrap();
+        assert_eq!(accounts.len(), 3);
+        assert_eq!(accounts[0].key, infos[1].key);
+
+        accounts.replace(2, 0);
+        accounts.sign(2);
+        let keys: Vec<_> = accounts.iter().map(|acc| *acc.key).collect();
+        assert_eq!(keys, vec![*infos[1].key, *infos[2].key, *infos[1].key]);
+        let signers: Vec<_> = accounts.metas().iter().map(|meta| meta.is_signer).collect();
+        assert_eq!(signers, vec![true, false, true]);
+        assert!(!infos[1].is_signer);
+
+        accounts.remove(1);
+        assert_eq!(accounts.len(), 2);
+        assert!(accounts.get(2).is_none());
+        assert!(accounts.strip(3).is_err());
+    }
+}
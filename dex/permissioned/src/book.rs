@@ -0,0 +1,263 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use serum_dex::critbit::{LeafNode, Slab};
+use serum_dex::matching::Side;
+use serum_dex::state::{AccountFlag, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
+use std::convert::TryInto;
+use std::num::NonZeroU32;
+
+/// One side of a market's order book, read from the data of its bids or asks
+/// account, e.g. fetched over RPC or borrowed by a middleware.
+#[derive(Clone, Copy)]
+pub struct BookSide<'a> {
+    side: Side,
+    slab: &'a Slab,
+}
+
+/// A price level of an L2 snapshot, in lots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct L2Level {
+    pub price: u64,
+    /// Size resting at the price.
+    pub size: u64,
+    /// Size resting at the price and all better ones.
+    pub cumulative_size: u64,
+}
+
+/// An order resting on the book, with its price and size in lots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub order_id: u128,
+    /// The open orders account the order was placed from.
+    pub owner: Pubkey,
+    /// The order's slot in the owner's open orders account.
+    pub owner_slot: u8,
+    pub client_order_id: u64,
+    /// The price of the order, or the peg limit of an oracle pegged one.
+    pub price: u64,
+    pub size: u64,
+    pub side: Side,
+    /// The offset from the oracle price of an oracle pegged order.
+    pub peg_offset: Option<i16>,
+    /// The unix timestamp after which a good-til-time order expires.
+    pub expiry_ts: Option<u32>,
+}
+
+impl RestingOrder {
+    fn new(side: Side, leaf: &LeafNode) -> Self {
+        Self {
+            order_id: leaf.order_id(),
+            owner: Pubkey::new_from_array(bytemuck::cast(leaf.owner())),
+            owner_slot: leaf.owner_slot(),
+            client_order_id: leaf.client_order_id(),
+            price: leaf.price().get(),
+            size: leaf.quantity(),
+            side,
+            peg_offset: leaf.peg_offset(),
+            expiry_ts: leaf.expiry_ts().map(NonZeroU32::get),
+        }
+    }
+}
+
+impl<'a> BookSide<'a> {
+    // Account flags, followed by the slab header.
+    const MIN_LEN: usize = 8 + 32;
+
+    /// Parses the given bids or asks account data.
+    pub fn unpack(data: &'a [u8]) -> std::result::Result<Self, ProgramError> {
+        let padding = ACCOUNT_HEAD_PADDING.len() + ACCOUNT_TAIL_PADDING.len();
+        if data.len() < padding + Self::MIN_LEN
+            || !data.starts_with(ACCOUNT_HEAD_PADDING)
+            || !data.ends_with(ACCOUNT_TAIL_PADDING)
+        {
+            return Err(invalid_order_book());
+        }
+        let data = &data[ACCOUNT_HEAD_PADDING.len()..data.len() - ACCOUNT_TAIL_PADDING.len()];
+        let flags = u64::from_le_bytes(data[..8].try_into().unwrap());
+        let side = match flags ^ AccountFlag::Initialized as u64 {
+            flag if flag == AccountFlag::Bids as u64 => Side::Bid,
+            flag if flag == AccountFlag::Asks as u64 => Side::Ask,
+            _ => return Err(invalid_order_book()),
+        };
+        Ok(Self {
+            side,
+            slab: Slab::new_check(&data[8..]),
+        })
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// Returns the resting orders, best price first, and by time priority
+    /// within a price, followed by the oracle pegged orders, best offset
+    /// first.
+    fn leaves(&self, owner: Option<&Pubkey>) -> Vec<LeafNode> {
+        // In ascending order of key, i.e. of price, then of time for asks,
+        // whose keys carry the sequence number, and of reverse time for
+        // bids, whose keys carry its complement. The keys of oracle pegged
+        // orders have their top bit set, and sort by offset instead.
+        let owner = owner.map(|owner| bytemuck::cast(owner.to_bytes()));
+        let mut leaves = self.slab.traverse_orders(owner);
+        if self.side == Side::Bid {
+            leaves.reverse();
+            leaves.sort_by_key(|leaf| leaf.peg_offset().is_some());
+        }
+        leaves
+    }
+
+    /// Returns the book's `depth` best price levels, best first, of the
+    /// orders at a fixed price.
+    pub fn l2(&self, depth: usize) -> Vec<L2Level> {
+        let mut levels: Vec<L2Level> = Vec::new();
+        let mut cumulative_size = 0u64;
+        let leaves = self.leaves(None).into_iter();
+        for leaf in leaves.filter(|leaf| leaf.peg_offset().is_none()) {
+            let price = leaf.price().get();
+            cumulative_size = cumulative_size.saturating_add(leaf.quantity());
+            if let Some(level) = levels.last_mut().filter(|level| level.price == price) {
+                level.size = level.size.saturating_add(leaf.quantity());
+                level.cumulative_size = cumulative_size;
+                continue;
+            }
+            if levels.len() == depth {
+                break;
+            }
+            levels.push(L2Level {
+                price,
+                size: leaf.quantity(),
+                cumulative_size,
+            });
+        }
+        levels
+    }
+
+    /// Returns the resting orders, best price first, and by time priority
+    /// within a price, followed by the oracle pegged orders, best offset
+    /// first.
+    pub fn orders(&self) -> impl Iterator<Item = RestingOrder> {
+        let side = self.side;
+        self.leaves(None)
+            .into_iter()
+            .map(move |leaf| RestingOrder::new(side, &leaf))
+    }
+
+    /// Returns the orders placed from the given open orders account, in the
+    /// order of `orders`.
+    pub fn orders_of(&self, open_orders: &Pubkey) -> impl Iterator<Item = RestingOrder> {
+        let side = self.side;
+        self.leaves(Some(open_orders))
+            .into_iter()
+            .map(move |leaf| RestingOrder::new(side, &leaf))
+    }
+}
+
+fn invalid_order_book() -> ProgramError {
+    anchor_lang::error!(ErrorCode::InvalidOrderBook).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the data of a bids or asks account holding the given orders,
+    // as (price, quantity, owner, client order id), in time priority.
+    fn book_data(side: Side, orders: &[(u64, u64, Pubkey, u64)]) -> Vec<u8> {
+        let flag = match side {
+            Side::Bid => AccountFlag::Bids,
+            Side::Ask => AccountFlag::Asks,
+        };
+        let mut slab_data = vec![0u8; 32 + 72 * 32];
+        let slab = Slab::new(&mut slab_data);
+        for (seq_num, (price, quantity, owner, client_order_id)) in orders.iter().enumerate() {
+            let seq_num = match side {
+                Side::Bid => !(seq_num as u64),
+                Side::Ask => seq_num as u64,
+            };
+            let key = ((*price as u128) << 64) | seq_num as u128;
+            // The packed `LeafNode`: tag, owner slot, fee tier and padding,
+            // then the key, owner, quantity and client order id.
+            let mut leaf = 2u32.to_le_bytes().to_vec();
+            leaf.extend_from_slice(&[0; 4]);
+            leaf.extend_from_slice(&key.to_le_bytes());
+            leaf.extend_from_slice(owner.as_ref());
+            leaf.extend_from_slice(&quantity.to_le_bytes());
+            leaf.extend_from_slice(&client_order_id.to_le_bytes());
+            assert!(slab.insert_leaf(bytemuck::from_bytes(&leaf)).is_ok());
+        }
+
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        data.extend_from_slice(&(AccountFlag::Initialized as u64 | flag as u64).to_le_bytes());
+        data.extend_from_slice(&slab_data);
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        data
+    }
+
+    #[test]
+    fn test_l2() {
+        let owner = Pubkey::new_unique();
+        let orders = [
+            (10, 1, owner, 0),
+            (12, 2, owner, 0),
+            (10, 3, owner, 0),
+            (9, 4, owner, 0),
+        ];
+
+        let data = book_data(Side::Bid, &orders);
+        let bids = BookSide::unpack(&data).unwrap();
+        assert_eq!(bids.side(), Side::Bid);
+        let level = |price, size, cumulative_size| L2Level {
+            price,
+            size,
+            cumulative_size,
+        };
+        assert_eq!(
+            bids.l2(usize::MAX),
+            vec![level(12, 2, 2), level(10, 4, 6), level(9, 4, 10)]
+        );
+        assert_eq!(bids.l2(2), vec![level(12, 2, 2), level(10, 4, 6)]);
+
+        let data = book_data(Side::Ask, &orders);
+        let asks = BookSide::unpack(&data).unwrap();
+        assert_eq!(asks.side(), Side::Ask);
+        assert_eq!(asks.l2(1), vec![level(9, 4, 4)]);
+
+        assert!(BookSide::unpack(&data[1..]).is_err());
+        let mut data = data;
+        data[5] = AccountFlag::Initialized as u8;
+        assert!(BookSide::unpack(&data).is_err());
+    }
+
+    #[test]
+    fn test_orders() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let orders = [(10, 1, alice, 1), (12, 2, bob, 2), (10, 3, bob, 3)];
+        let data = book_data(Side::Bid, &orders);
+        let bids = BookSide::unpack(&data).unwrap();
+
+        let resting: Vec<_> = bids.orders().collect();
+        let client_order_ids: Vec<_> = resting.iter().map(|order| order.client_order_id).collect();
+        assert_eq!(client_order_ids, vec![2, 1, 3]);
+        assert_eq!(
+            resting[1],
+            RestingOrder {
+                order_id: (10 << 64) | !0u64 as u128,
+                owner: alice,
+                owner_slot: 0,
+                client_order_id: 1,
+                price: 10,
+                size: 1,
+                side: Side::Bid,
+                peg_offset: None,
+                expiry_ts: None,
+            }
+        );
+
+        let bobs: Vec<_> = bids
+            .orders_of(&bob)
+            .map(|order| order.client_order_id)
+            .collect();
+        assert_eq!(bobs, vec![2, 3]);
+        assert_eq!(bids.orders_of(&Pubkey::new_unique()).count(), 0);
+    }
+}
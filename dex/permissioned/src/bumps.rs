@@ -0,0 +1,192 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Seed of the `MarketBumps` PDAs.
+pub const MARKET_BUMPS_SEED: &[u8] = b"market-bumps";
+
+/// Canonical bumps of a market's PDAs, resolved on-chain once and cached in
+/// a PDA of the proxy program derived from the DEX program and the market,
+/// so that middleware resolving bumps on-chain (see
+/// `OpenOrdersPda::canonical_bumps`) needn't search for them per request.
+/// Since the bumps are computed by the proxy, anyone may create the cache.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketBumps {
+    pub dex_program_id: Pubkey,
+    pub market: Pubkey,
+    /// Bump of the market's open orders init authority.
+    pub open_orders_init: u8,
+    pub bump: u8,
+}
+
+impl MarketBumps {
+    /// Size of the cache account.
+    pub const LEN: usize = 8 + 32 + 32 + 1 + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxbumps_";
+
+    /// Returns the address and bump of the cache PDA of the given market.
+    pub fn address(program_id: &Pubkey, dex_program_id: &Pubkey, market: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[MARKET_BUMPS_SEED, dex_program_id.as_ref(), market.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Resolves the bumps of the given market.
+    pub fn resolve(program_id: &Pubkey, dex_program_id: &Pubkey, market: &Pubkey) -> Self {
+        let (_, open_orders_init) = Pubkey::find_program_address(
+            &[
+                b"open-orders-init",
+                dex_program_id.as_ref(),
+                market.as_ref(),
+            ],
+            program_id,
+        );
+        let (_, bump) = Self::address(program_id, dex_program_id, market);
+        Self {
+            dex_program_id: *dex_program_id,
+            market: *market,
+            open_orders_init,
+            bump,
+        }
+    }
+
+    /// Loads the cache from the given account, checking it's the program's
+    /// cache PDA of the DEX program and market it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let bumps = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[
+                MARKET_BUMPS_SEED,
+                bumps.dex_program_id.as_ref(),
+                bumps.market.as_ref(),
+                &[bumps.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(bumps)
+    }
+
+    fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidBumpCache).into()),
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidBumpCache).into()
+        })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Creates the `MarketBumps` cache of the given market.
+///
+/// Accounts:
+///
+/// 0. Cache PDA (writable).
+/// 1. Payer (signer, writable).
+/// 2. System program.
+pub(crate) fn process_cache_market_bumps(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    dex_program_id: Pubkey,
+    market: Pubkey,
+) -> ProgramResult {
+    let cache_info = &accounts[0];
+    let payer = &accounts[1];
+    let system = accounts
+        .get(2)
+        .filter(|acc| acc.key == &system_program::ID)
+        .ok_or_else(|| -> ProgramError {
+            anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+        })?;
+    let bumps = MarketBumps::resolve(program_id, &dex_program_id, &market);
+    let (address, _) = MarketBumps::address(program_id, &dex_program_id, &market);
+    if &address != cache_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    system_program::create_account(
+        CpiContext::new_with_signer(
+            system.clone(),
+            system_program::CreateAccount {
+                from: payer.clone(),
+                to: cache_info.clone(),
+            },
+            &[&[
+                MARKET_BUMPS_SEED,
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                &[bumps.bump],
+            ]],
+        ),
+        Rent::get()?.minimum_balance(MarketBumps::LEN),
+        MarketBumps::LEN as u64,
+        program_id,
+    )?;
+    bumps.store(cache_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    #[test]
+    fn test_market_bumps() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let bumps = MarketBumps::resolve(&program_id, &dex_program_id, &market);
+        let (_, open_orders_init) = Pubkey::find_program_address(
+            &[
+                b"open-orders-init",
+                dex_program_id.as_ref(),
+                market.as_ref(),
+            ],
+            &program_id,
+        );
+        assert_eq!(bumps.open_orders_init, open_orders_init);
+
+        let (address, _) = MarketBumps::address(&program_id, &dex_program_id, &market);
+        let cache_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; MarketBumps::LEN].into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        );
+        assert!(MarketBumps::load(&program_id, &cache_info).is_err());
+        bumps.store(&cache_info).unwrap();
+        assert_eq!(MarketBumps::load(&program_id, &cache_info).unwrap(), bumps);
+
+        // A cache copied to another address is rejected.
+        let mut spoofed = cache_info.clone();
+        spoofed.key = Box::leak(Box::new(Pubkey::new_unique()));
+        assert!(MarketBumps::load(&program_id, &spoofed).is_err());
+    }
+}
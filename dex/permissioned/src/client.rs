@@ -0,0 +1,472 @@
+use crate::{
+    program_data_address, ConfigInstruction, MarketBumps, MarketHeader, MarketMetadata,
+    MarketMetadataArgs, ProxyConfig, ProxyHeader, ReferralSet, UserInstruction,
+};
+use serum_dex::state::gen_vault_signer_key;
+use solana_address_lookup_table_interface::instruction::{
+    create_lookup_table, extend_lookup_table,
+};
+use solana_address_lookup_table_interface::state::AddressLookupTable;
+use solana_program::hash::Hash;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::message::{v0, AddressLookupTableAccount, CompileError, VersionedMessage};
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use solana_program::sysvar;
+use solana_sdk_ids::system_program;
+
+/// Maximum number of addresses added to a lookup table per instruction, so
+/// that each extension fits in a transaction of its own.
+pub const MAX_ADDRESSES_PER_EXTEND: usize = 20;
+
+/// Returns the accounts shared by all proxied instructions on the given
+/// market, to be stored in an address lookup table. Proxied `NewOrderV3`s
+/// routinely exceed the account limit of legacy transactions once
+/// middleware accounts are appended, while with these accounts looked up,
+/// only user accounts take space in the transaction.
+pub fn market_lookup_table_addresses(
+    proxy_program_id: &Pubkey,
+    dex_program_id: &Pubkey,
+    market: &MarketHeader,
+) -> Result<Vec<Pubkey>, ProgramError> {
+    let vault_signer = gen_vault_signer_key(
+        market.vault_signer_nonce,
+        &market.own_address,
+        dex_program_id,
+    )?;
+    let mut addresses = vec![
+        *proxy_program_id,
+        *dex_program_id,
+        market.own_address,
+        market.req_q,
+        market.event_q,
+        market.bids,
+        market.asks,
+        market.coin_vault,
+        market.pc_vault,
+        vault_signer,
+        spl_token::ID,
+        sysvar::rent::ID,
+    ];
+    addresses.extend(market.oracle);
+    Ok(addresses)
+}
+
+/// Returns the address of a new lookup table holding the given addresses,
+/// along with the instructions creating it: one creating the table followed
+/// by as many extending it as needed. The table can be used from the slot
+/// after it was last extended.
+pub fn create_market_lookup_table(
+    authority: &Pubkey,
+    payer: &Pubkey,
+    recent_slot: u64,
+    addresses: &[Pubkey],
+) -> (Pubkey, Vec<Instruction>) {
+    let (create_ix, table) = create_lookup_table(*authority, *payer, recent_slot);
+    let mut ixs = vec![create_ix];
+    ixs.extend(extend_market_lookup_table(
+        &table, authority, payer, addresses,
+    ));
+    (table, ixs)
+}
+
+/// Returns the instructions adding the given addresses to a lookup table,
+/// e.g. the accounts of the middleware of its market, each adding up to
+/// `MAX_ADDRESSES_PER_EXTEND` addresses.
+pub fn extend_market_lookup_table(
+    table: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    addresses: &[Pubkey],
+) -> Vec<Instruction> {
+    addresses
+        .chunks(MAX_ADDRESSES_PER_EXTEND)
+        .map(|chunk| extend_lookup_table(*table, *authority, Some(*payer), chunk.to_vec()))
+        .collect()
+}
+
+/// Deserializes a lookup table fetched from the cluster, for compiling
+/// messages with `compile_proxy_message`.
+pub fn lookup_table_account(
+    key: Pubkey,
+    data: &[u8],
+) -> Result<AddressLookupTableAccount, ProgramError> {
+    let table =
+        AddressLookupTable::deserialize(data).map_err(|_| ProgramError::InvalidAccountData)?;
+    Ok(AddressLookupTableAccount {
+        key,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// Compiles the given (proxy) instructions into a v0 message, loading all
+/// accounts found in the lookup tables from them rather than listing them
+/// in the message. Signers and invoked programs are always listed.
+pub fn compile_proxy_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<VersionedMessage, CompileError> {
+    let message = v0::Message::try_compile(payer, instructions, lookup_tables, recent_blockhash)?;
+    Ok(VersionedMessage::V0(message))
+}
+
+/// Returns the proxied request of the given packed DEX instruction, e.g.
+/// `MarketInstruction::pack`: the DEX program is inserted as the first
+/// account, the header is prepended to the instruction data, and the
+/// middleware accounts are appended after the DEX accounts, with their
+/// number recorded in the header.
+pub fn proxy_instruction(
+    proxy_program_id: &Pubkey,
+    dex_program_id: &Pubkey,
+    header: &ProxyHeader,
+    dex_ix_data: &[u8],
+    dex_accounts: Vec<AccountMeta>,
+    trailing_accounts: Vec<AccountMeta>,
+) -> Instruction {
+    let header = ProxyHeader {
+        trailing_accounts: trailing_accounts.len() as u8,
+        ..header.clone()
+    };
+    let mut data = header.pack();
+    data.extend_from_slice(dex_ix_data);
+    let mut accounts = vec![AccountMeta::new_readonly(*dex_program_id, false)];
+    accounts.extend(dex_accounts);
+    accounts.extend(trailing_accounts);
+    Instruction {
+        program_id: *proxy_program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Returns the proxied request of the given DEX instruction, e.g. built by
+/// `serum_dex::instruction::new_order`. See `proxy_instruction`.
+pub fn proxy_dex_instruction(
+    proxy_program_id: &Pubkey,
+    header: &ProxyHeader,
+    dex_ix: Instruction,
+    trailing_accounts: Vec<AccountMeta>,
+) -> Instruction {
+    proxy_instruction(
+        proxy_program_id,
+        &dex_ix.program_id,
+        header,
+        &dex_ix.data,
+        dex_ix.accounts,
+        trailing_accounts,
+    )
+}
+
+/// Returns the given `ConfigInstruction` sent to the proxy with the given
+/// accounts.
+pub fn config_instruction(
+    proxy_program_id: &Pubkey,
+    ix: &ConfigInstruction,
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction {
+        program_id: *proxy_program_id,
+        accounts,
+        data: ix.pack(),
+    }
+}
+
+/// Returns the given `UserInstruction` sent to the proxy with the given
+/// accounts.
+pub fn user_instruction(
+    proxy_program_id: &Pubkey,
+    ix: &UserInstruction,
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction {
+        program_id: *proxy_program_id,
+        accounts,
+        data: ix.pack(),
+    }
+}
+
+/// Returns an `InitializeConfig` creating the proxy's config, administered
+/// and paid for by `admin`, the proxy program's upgrade authority.
+pub fn initialize_config_instruction(
+    proxy_program_id: &Pubkey,
+    admin: &Pubkey,
+    fee_bps: u16,
+    fee_receiver: &Pubkey,
+    dex_program_id: &Pubkey,
+) -> Instruction {
+    let ix = ConfigInstruction::InitializeConfig {
+        fee_bps,
+        fee_receiver: *fee_receiver,
+        dex_program_id: *dex_program_id,
+    };
+    let accounts = vec![
+        AccountMeta::new(ProxyConfig::address(proxy_program_id).0, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(program_data_address(proxy_program_id), false),
+    ];
+    config_instruction(proxy_program_id, &ix, accounts)
+}
+
+/// Returns an `UpdateConfig`, `TransferAdmin` or other instruction signed
+/// by the config's admin alone.
+pub fn admin_config_instruction(
+    proxy_program_id: &Pubkey,
+    admin: &Pubkey,
+    ix: &ConfigInstruction,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(ProxyConfig::address(proxy_program_id).0, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    config_instruction(proxy_program_id, ix, accounts)
+}
+
+/// Returns a `CacheMarketBumps` creating the given market's `MarketBumps`,
+/// paid for by `payer`.
+pub fn cache_market_bumps_instruction(
+    proxy_program_id: &Pubkey,
+    payer: &Pubkey,
+    dex_program_id: &Pubkey,
+    market: &Pubkey,
+) -> Instruction {
+    let ix = UserInstruction::CacheMarketBumps {
+        dex_program_id: *dex_program_id,
+        market: *market,
+    };
+    let (cache, _) = MarketBumps::address(proxy_program_id, dex_program_id, market);
+    let accounts = vec![
+        AccountMeta::new(cache, false),
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    user_instruction(proxy_program_id, &ix, accounts)
+}
+
+/// Returns an `AddReferral`, or a `RemoveReferral` if `allowed` is unset,
+/// updating the `ReferralSet`, whose account the admin pays for.
+pub fn set_referral_instruction(
+    proxy_program_id: &Pubkey,
+    admin: &Pubkey,
+    referral: &Pubkey,
+    allowed: bool,
+) -> Instruction {
+    let referral = *referral;
+    let ix = if allowed {
+        ConfigInstruction::AddReferral { referral }
+    } else {
+        ConfigInstruction::RemoveReferral { referral }
+    };
+    let accounts = vec![
+        AccountMeta::new_readonly(ProxyConfig::address(proxy_program_id).0, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new(ReferralSet::address(proxy_program_id).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    config_instruction(proxy_program_id, &ix, accounts)
+}
+
+/// Returns a `SetMarketMetadata` setting, or (given `None`) removing, the
+/// given market's `MarketMetadata`, whose account the admin pays for.
+pub fn set_market_metadata_instruction(
+    proxy_program_id: &Pubkey,
+    admin: &Pubkey,
+    market: &Pubkey,
+    metadata: Option<MarketMetadataArgs>,
+) -> Instruction {
+    let ix = ConfigInstruction::SetMarketMetadata {
+        market: *market,
+        metadata,
+    };
+    let accounts = vec![
+        AccountMeta::new_readonly(ProxyConfig::address(proxy_program_id).0, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new(MarketMetadata::address(proxy_program_id, market).0, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    config_instruction(proxy_program_id, &ix, accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bumps;
+    use anchor_lang::AnchorDeserialize;
+    use serum_dex::instruction::MarketInstruction;
+
+    fn market(dex_program_id: &Pubkey) -> MarketHeader {
+        let own_address = Pubkey::new_unique();
+        let vault_signer_nonce = (0..)
+            .find(|nonce| gen_vault_signer_key(*nonce, &own_address, dex_program_id).is_ok())
+            .unwrap();
+        MarketHeader {
+            account_flags: 0,
+            own_address,
+            vault_signer_nonce,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            fee_rate_bps: 0,
+            open_orders_authority: None,
+            prune_authority: None,
+            consume_events_authority: None,
+            oracle: None,
+        }
+    }
+
+    #[test]
+    fn test_compile_with_market_lookup_table() {
+        let proxy_program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = market(&dex_program_id);
+        let addresses =
+            market_lookup_table_addresses(&proxy_program_id, &dex_program_id, &market).unwrap();
+
+        let payer = Pubkey::new_unique();
+        let (table, ixs) = create_market_lookup_table(&payer, &payer, 0, &addresses);
+        assert_eq!(ixs.len(), 2);
+
+        // All market accounts but the invoked proxy are looked up.
+        let mut accounts: Vec<_> = addresses
+            .iter()
+            .map(|key| AccountMeta::new(*key, false))
+            .collect();
+        accounts.push(AccountMeta::new_readonly(payer, true));
+        let ix = Instruction::new_with_bytes(proxy_program_id, &[], accounts);
+        let tables = [AddressLookupTableAccount {
+            key: table,
+            addresses,
+        }];
+        let message = compile_proxy_message(&payer, &[ix], &tables, Hash::default()).unwrap();
+        let message = match message {
+            VersionedMessage::V0(message) => message,
+            _ => unreachable!(),
+        };
+        assert_eq!(message.account_keys, vec![payer, proxy_program_id]);
+        assert_eq!(message.address_table_lookups.len(), 1);
+        // The oracle of a market with one is looked up too.
+        let oracle = Pubkey::new_unique();
+        let market = MarketHeader {
+            oracle: Some(oracle),
+            ..market
+        };
+        let with_oracle =
+            market_lookup_table_addresses(&proxy_program_id, &dex_program_id, &market).unwrap();
+        assert_eq!(with_oracle.len(), 13);
+        assert_eq!(with_oracle.last(), Some(&oracle));
+    }
+
+    #[test]
+    fn test_proxy_instruction() {
+        let proxy_program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let user = AccountMeta::new_readonly(Pubkey::new_unique(), true);
+        let trailing = AccountMeta::new(Pubkey::new_unique(), false);
+        let header = ProxyHeader {
+            bumps: Bumps {
+                open_orders: 255,
+                open_orders_init: 254,
+            },
+            middleware_data: vec![vec![1]],
+            ..ProxyHeader::default()
+        };
+        let dex_ix_data = MarketInstruction::SettleFunds.pack();
+        let ix = proxy_instruction(
+            &proxy_program_id,
+            &dex_program_id,
+            &header,
+            &dex_ix_data,
+            vec![user.clone()],
+            vec![trailing.clone()],
+        );
+
+        assert_eq!(ix.program_id, proxy_program_id);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new_readonly(dex_program_id, false),
+                user,
+                trailing
+            ]
+        );
+        let mut data = &ix.data[..];
+        let unpacked = ProxyHeader::unpack(&mut data).unwrap();
+        assert_eq!(unpacked.trailing_accounts, 1);
+        assert_eq!(unpacked.bumps, header.bumps);
+        assert_eq!(unpacked.data_for(0), &[1]);
+        assert_eq!(data, &dex_ix_data[..]);
+    }
+
+    #[test]
+    fn test_config_instructions() {
+        let proxy_program_id = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let (config, _) = ProxyConfig::address(&proxy_program_id);
+
+        let fee_receiver = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let ix = initialize_config_instruction(
+            &proxy_program_id,
+            &admin,
+            5,
+            &fee_receiver,
+            &dex_program_id,
+        );
+        assert_eq!(ix.accounts[0].pubkey, config);
+        assert!(ix.accounts[1].is_signer);
+        assert_eq!(
+            ix.accounts[3].pubkey,
+            program_data_address(&proxy_program_id)
+        );
+        assert_eq!(ix.data[0], crate::CONFIG_INSTRUCTION_TAG);
+        assert_eq!(
+            ConfigInstruction::deserialize(&mut &ix.data[1..]).unwrap(),
+            ConfigInstruction::InitializeConfig {
+                fee_bps: 5,
+                fee_receiver,
+                dex_program_id,
+            }
+        );
+
+        let referral = Pubkey::new_unique();
+        let ix = set_referral_instruction(&proxy_program_id, &admin, &referral, false);
+        assert_eq!(
+            ix.accounts[2].pubkey,
+            ReferralSet::address(&proxy_program_id).0
+        );
+        assert_eq!(
+            ConfigInstruction::deserialize(&mut &ix.data[1..]).unwrap(),
+            ConfigInstruction::RemoveReferral { referral }
+        );
+
+        let market = Pubkey::new_unique();
+        let ix = set_market_metadata_instruction(&proxy_program_id, &admin, &market, None);
+        assert_eq!(
+            ix.accounts[2].pubkey,
+            MarketMetadata::address(&proxy_program_id, &market).0
+        );
+        assert!(ix.accounts[1].is_writable);
+
+        // Bumps are cached by anyone, with a user instruction.
+        let payer = Pubkey::new_unique();
+        let ix =
+            cache_market_bumps_instruction(&proxy_program_id, &payer, &dex_program_id, &market);
+        assert_eq!(ix.data[0], crate::USER_INSTRUCTION_TAG);
+        assert_eq!(
+            UserInstruction::deserialize(&mut &ix.data[1..]).unwrap(),
+            UserInstruction::CacheMarketBumps {
+                dex_program_id,
+                market,
+            }
+        );
+    }
+}
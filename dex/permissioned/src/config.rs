@@ -0,0 +1,475 @@
+use crate::{process_set_market_metadata, process_set_referral, ErrorCode, MarketMetadataArgs};
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use solana_sdk_ids::bpf_loader_upgradeable;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Tag prefixing instruction data addressed to the proxy's config
+/// instructions. The rest of the data is a Borsh serialized
+/// `ConfigInstruction`. Handled by the proxy itself, before any middleware
+/// runs.
+pub const CONFIG_INSTRUCTION_TAG: u8 = 0xc0;
+
+/// Seed of the `ProxyConfig` PDA.
+pub const PROXY_CONFIG_SEED: &[u8] = b"proxy-config";
+
+/// Operator parameters of a proxy, stored in a PDA of the proxy program, so
+/// that middleware have a standard place to read them from.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Key allowed to update the config.
+    pub admin: Pubkey,
+    /// Rejects relayed requests while set. See `MarketProxy::config`.
+    pub paused: bool,
+    /// Fee charged by the proxy, in basis points.
+    pub fee_bps: u16,
+    /// Owner of the `ReferralBalance`s the proxy's fees are credited to. See
+    /// `ReferralFees::proxy_fee`.
+    pub fee_receiver: Pubkey,
+    /// The DEX relayed requests must be addressed to.
+    pub dex_program_id: Pubkey,
+    pub bump: u8,
+}
+
+impl ProxyConfig {
+    /// Size of the config account.
+    pub const LEN: usize = 8 + 32 + 1 + 2 + 32 + 32 + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxconfig";
+
+    /// Returns the address and bump of the config PDA.
+    pub fn address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PROXY_CONFIG_SEED], program_id)
+    }
+
+    /// Loads the config from the given account, checking it's the program's
+    /// config PDA.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let config = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address =
+            Pubkey::create_program_address(&[PROXY_CONFIG_SEED, &[config.bump]], program_id)
+                .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(config)
+    }
+
+    /// Parses the given config account data, without checking its address.
+    pub fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidConfig).into()),
+        }
+        Self::deserialize(&mut &data[8..])
+            .map_err(|_| -> ProgramError { anchor_lang::error!(ErrorCode::InvalidConfig).into() })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+
+    fn validate(&self) -> ProgramResult {
+        if self.fee_bps > 10_000 {
+            return Err(anchor_lang::error!(ErrorCode::InvalidConfig).into());
+        }
+        Ok(())
+    }
+}
+
+/// Instructions handled by the proxy itself, managing the `ProxyConfig`, the
+/// referrals allowed, and the markets' metadata. Each is signed by the
+/// config's admin, or, to create the config, the program's upgrade
+/// authority. Instructions signed by users are `UserInstruction`s.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigInstruction {
+    /// Creates the config, with the signer, the program's upgrade
+    /// authority, as its admin.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Config PDA (writable).
+    /// 1. Admin (signer, writable), paying for the account.
+    /// 2. System program.
+    /// 3. The program's ProgramData account.
+    InitializeConfig {
+        fee_bps: u16,
+        fee_receiver: Pubkey,
+        dex_program_id: Pubkey,
+    },
+    /// Updates the given config parameters.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Config PDA (writable).
+    /// 1. Admin (signer).
+    UpdateConfig {
+        paused: Option<bool>,
+        fee_bps: Option<u16>,
+        fee_receiver: Option<Pubkey>,
+    },
+    /// Hands the config over to a new admin.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Config PDA (writable).
+    /// 1. Admin (signer).
+    TransferAdmin { new_admin: Pubkey },
+    /// Allows the given referral in the `ReferralSet`.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Config PDA.
+    /// 1. Admin (signer, writable), paying for the set account.
+    /// 2. Set PDA (writable).
+    /// 3. System program, when adding the first referral.
+    AddReferral { referral: Pubkey },
+    /// Removes the given referral from the `ReferralSet`.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Config PDA.
+    /// 1. Admin (signer).
+    /// 2. Set PDA (writable).
+    RemoveReferral { referral: Pubkey },
+    /// Sets, or (given `None`) removes, the `MarketMetadata` of the given
+    /// market.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Config PDA.
+    /// 1. Admin (signer, writable), paying for the metadata account.
+    /// 2. Metadata PDA (writable).
+    /// 3. System program, when creating the metadata.
+    SetMarketMetadata {
+        market: Pubkey,
+        metadata: Option<MarketMetadataArgs>,
+    },
+}
+
+impl ConfigInstruction {
+    /// Packs the instruction data to send to the proxy.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![CONFIG_INSTRUCTION_TAG];
+        self.serialize(&mut data).unwrap();
+        data
+    }
+}
+
+/// Processes a `ConfigInstruction`, given the data following the tag.
+pub fn process_config_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let ix = ConfigInstruction::deserialize(&mut &data[..]).map_err(|_| -> ProgramError {
+        anchor_lang::error!(ErrorCode::InvalidInstruction).into()
+    })?;
+    if accounts.len() < 2 {
+        return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+    }
+    let config_info = &accounts[0];
+    let admin = &accounts[1];
+    if !admin.is_signer {
+        return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+    }
+
+    match ix {
+        ConfigInstruction::InitializeConfig {
+            fee_bps,
+            fee_receiver,
+            dex_program_id,
+        } => {
+            let system = accounts
+                .get(2)
+                .filter(|acc| acc.key == &system_program::ID)
+                .ok_or_else(|| -> ProgramError {
+                    anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+                })?;
+            let program_data = accounts.get(3).ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+            assert_upgrade_authority(program_id, program_data, admin.key)?;
+            let (address, bump) = ProxyConfig::address(program_id);
+            if &address != config_info.key {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            let config = ProxyConfig {
+                admin: *admin.key,
+                paused: false,
+                fee_bps,
+                fee_receiver,
+                dex_program_id,
+                bump,
+            };
+            config.validate()?;
+            system_program::create_account(
+                CpiContext::new_with_signer(
+                    system.clone(),
+                    system_program::CreateAccount {
+                        from: admin.clone(),
+                        to: config_info.clone(),
+                    },
+                    &[&[PROXY_CONFIG_SEED, &[bump]]],
+                ),
+                Rent::get()?.minimum_balance(ProxyConfig::LEN),
+                ProxyConfig::LEN as u64,
+                program_id,
+            )?;
+            config.store(config_info)
+        }
+        ConfigInstruction::UpdateConfig {
+            paused,
+            fee_bps,
+            fee_receiver,
+        } => {
+            let mut config = load_as_admin(program_id, config_info, admin)?;
+            if let Some(paused) = paused {
+                config.paused = paused;
+            }
+            if let Some(fee_bps) = fee_bps {
+                config.fee_bps = fee_bps;
+            }
+            if let Some(fee_receiver) = fee_receiver {
+                config.fee_receiver = fee_receiver;
+            }
+            config.validate()?;
+            config.store(config_info)
+        }
+        ConfigInstruction::TransferAdmin { new_admin } => {
+            let mut config = load_as_admin(program_id, config_info, admin)?;
+            config.admin = new_admin;
+            config.store(config_info)
+        }
+        ConfigInstruction::AddReferral { referral } => {
+            load_as_admin(program_id, config_info, admin)?;
+            process_set_referral(program_id, accounts, referral, true)
+        }
+        ConfigInstruction::RemoveReferral { referral } => {
+            load_as_admin(program_id, config_info, admin)?;
+            process_set_referral(program_id, accounts, referral, false)
+        }
+        ConfigInstruction::SetMarketMetadata { market, metadata } => {
+            load_as_admin(program_id, config_info, admin)?;
+            process_set_market_metadata(program_id, accounts, market, metadata)
+        }
+    }
+}
+
+/// Returns the address of the given program's ProgramData account.
+pub fn program_data_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::ID).0
+}
+
+/// Checks the given account to be the program's ProgramData account, with
+/// the given key as its upgrade authority, so that the config can't be
+/// initialized by someone front-running the deployment.
+fn assert_upgrade_authority(
+    program_id: &Pubkey,
+    program_data: &AccountInfo,
+    authority: &Pubkey,
+) -> ProgramResult {
+    // `UpgradeableLoaderState::ProgramData`: a u32 tag, the slot of the last
+    // deployment, then the optional authority.
+    const PROGRAM_DATA: u32 = 3;
+    if program_data.key != &program_data_address(program_id) {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if program_data.owner != &bpf_loader_upgradeable::ID {
+        return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+    }
+    let data = program_data.try_borrow_data()?;
+    if data.get(..4) != Some(&PROGRAM_DATA.to_le_bytes()[..]) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if data.get(12) != Some(&1) || data.get(13..45) != Some(authority.as_ref()) {
+        return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+    }
+    Ok(())
+}
+
+fn load_as_admin(
+    program_id: &Pubkey,
+    config_info: &AccountInfo,
+    admin: &AccountInfo,
+) -> std::result::Result<ProxyConfig, ProgramError> {
+    let config = ProxyConfig::load(program_id, config_info)?;
+    if &config.admin != admin.key {
+        return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    fn account(key: Pubkey, owner: Pubkey, is_signer: bool, len: usize) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            is_signer,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; len].into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    fn initialized_config(program_id: &Pubkey, admin: &Pubkey) -> AccountInfo<'static> {
+        let (address, bump) = ProxyConfig::address(program_id);
+        let config_info = account(address, *program_id, false, ProxyConfig::LEN);
+        let config = ProxyConfig {
+            admin: *admin,
+            paused: false,
+            fee_bps: 10,
+            fee_receiver: Pubkey::new_unique(),
+            dex_program_id: Pubkey::new_unique(),
+            bump,
+        };
+        config.store(&config_info).unwrap();
+        config_info
+    }
+
+    #[test]
+    fn test_config_len() {
+        let (_, bump) = ProxyConfig::address(&Pubkey::new_unique());
+        let config = ProxyConfig {
+            admin: Pubkey::new_unique(),
+            paused: true,
+            fee_bps: 0,
+            fee_receiver: Pubkey::new_unique(),
+            dex_program_id: Pubkey::new_unique(),
+            bump,
+        };
+        let mut data = ProxyConfig::DISCRIMINATOR.to_vec();
+        config.serialize(&mut data).unwrap();
+        assert_eq!(data.len(), ProxyConfig::LEN);
+        assert_eq!(ProxyConfig::unpack(&data).unwrap(), config);
+    }
+
+    #[test]
+    fn test_update_and_transfer_admin() {
+        let program_id = Pubkey::new_unique();
+        let admin = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        let config_info = initialized_config(&program_id, admin.key);
+        let accounts = [config_info.clone(), admin.clone()];
+
+        let ix = ConfigInstruction::UpdateConfig {
+            paused: Some(true),
+            fee_bps: None,
+            fee_receiver: None,
+        };
+        process_config_instruction(&program_id, &accounts, &ix.pack()[1..]).unwrap();
+        let config = ProxyConfig::load(&program_id, &config_info).unwrap();
+        assert!(config.paused);
+        assert_eq!(config.fee_bps, 10);
+
+        let ix = ConfigInstruction::UpdateConfig {
+            paused: None,
+            fee_bps: Some(10_001),
+            fee_receiver: None,
+        };
+        assert!(process_config_instruction(&program_id, &accounts, &ix.pack()[1..]).is_err());
+
+        let new_admin = Pubkey::new_unique();
+        let ix = ConfigInstruction::TransferAdmin { new_admin };
+        process_config_instruction(&program_id, &accounts, &ix.pack()[1..]).unwrap();
+        let config = ProxyConfig::load(&program_id, &config_info).unwrap();
+        assert_eq!(config.admin, new_admin);
+
+        // The previous admin can no longer update the config.
+        assert!(process_config_instruction(&program_id, &accounts, &ix.pack()[1..]).is_err());
+    }
+
+    fn program_data(program_id: &Pubkey, authority: Option<&Pubkey>) -> AccountInfo<'static> {
+        let address = program_data_address(program_id);
+        let acc_info = account(address, bpf_loader_upgradeable::ID, false, 45);
+        let mut data = acc_info.try_borrow_mut_data().unwrap();
+        data[..4].copy_from_slice(&3u32.to_le_bytes());
+        if let Some(authority) = authority {
+            data[12] = 1;
+            data[13..45].copy_from_slice(authority.as_ref());
+        }
+        drop(data);
+        acc_info
+    }
+
+    #[test]
+    fn test_initialize_requires_upgrade_authority() {
+        let program_id = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let program_data_info = program_data(&program_id, Some(&admin));
+        assert!(assert_upgrade_authority(&program_id, &program_data_info, &admin).is_ok());
+
+        // Other signers, immutable programs and spoofed accounts are rejected.
+        let unauthorized: ProgramResult =
+            Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            assert_upgrade_authority(&program_id, &program_data_info, &other),
+            unauthorized
+        );
+        let immutable = program_data(&program_id, None);
+        assert_eq!(
+            assert_upgrade_authority(&program_id, &immutable, &admin),
+            unauthorized
+        );
+        let other_program = program_data(&Pubkey::new_unique(), Some(&admin));
+        assert_eq!(
+            assert_upgrade_authority(&program_id, &other_program, &admin),
+            Err(ProgramError::InvalidSeeds)
+        );
+        let mut spoofed = program_data_info.clone();
+        spoofed.owner = Box::leak(Box::new(Pubkey::new_unique()));
+        assert!(assert_upgrade_authority(&program_id, &spoofed, &admin).is_err());
+
+        // The program data is checked before the config is created.
+        let (address, _) = ProxyConfig::address(&program_id);
+        let accounts = |program_data_info: AccountInfo<'static>| {
+            vec![
+                account(address, Pubkey::default(), false, 0),
+                account(other, Pubkey::default(), true, 0),
+                account(system_program::ID, Pubkey::default(), false, 0),
+                program_data_info,
+            ]
+        };
+        let ix = ConfigInstruction::InitializeConfig {
+            fee_bps: 0,
+            fee_receiver: other,
+            dex_program_id: Pubkey::new_unique(),
+        };
+        let mut missing = accounts(program_data_info.clone());
+        missing.pop();
+        assert!(process_config_instruction(&program_id, &missing, &ix.pack()[1..]).is_err());
+        let result =
+            process_config_instruction(&program_id, &accounts(program_data_info), &ix.pack()[1..]);
+        assert_eq!(result, unauthorized);
+    }
+
+    #[test]
+    fn test_load_checks_address() {
+        let program_id = Pubkey::new_unique();
+        let config_info = initialized_config(&program_id, &Pubkey::new_unique());
+        assert!(ProxyConfig::load(&program_id, &config_info).is_ok());
+        assert!(ProxyConfig::load(&Pubkey::new_unique(), &config_info).is_err());
+        let mut spoofed = config_info.clone();
+        spoofed.key = Box::leak(Box::new(Pubkey::new_unique()));
+        assert!(ProxyConfig::load(&program_id, &spoofed).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Typed CPI builders for programs trading through a `MarketProxy`, e.g.
+//! vaults whose PDAs own open orders accounts of a permissioned market.
+//!
+//! Each builder takes a `CpiContext` of the proxy program, the accounts of
+//! the DEX instruction, and the `ProxyHeader` of the request. Middleware
+//! accounts are passed as the context's remaining accounts, their number
+//! being recorded in the header.
+
+use crate::ProxyHeader;
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program;
+use serum_dex::instruction::{CancelOrderInstructionV2, MarketInstruction, NewOrderInstructionV3};
+use serum_dex::matching::Side;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+macro_rules! proxy_accounts {
+    (
+        $(#[$doc:meta])*
+        $name:ident {
+            $($(#[$field_doc:meta])* $field:ident: $meta:ident,)*
+        }
+    ) => {
+        $(#[$doc])*
+        pub struct $name<'info> {
+            /// The DEX program the proxy relays to.
+            pub dex_program: AccountInfo<'info>,
+            $($(#[$field_doc])* pub $field: AccountInfo<'info>,)*
+        }
+
+        impl<'info> ToAccountMetas for $name<'info> {
+            fn to_account_metas(&self, _is_signer: Option<bool>) -> Vec<AccountMeta> {
+                vec![readonly(&self.dex_program), $($meta(&self.$field),)*]
+            }
+        }
+
+        impl<'info> ToAccountInfos<'info> for $name<'info> {
+            fn to_account_infos(&self) -> Vec<AccountInfo<'info>> {
+                vec![self.dex_program.clone(), $(self.$field.clone(),)*]
+            }
+        }
+    };
+}
+
+fn writable(acc: &AccountInfo) -> AccountMeta {
+    AccountMeta::new(*acc.key, false)
+}
+
+fn readonly(acc: &AccountInfo) -> AccountMeta {
+    AccountMeta::new_readonly(*acc.key, false)
+}
+
+fn signer(acc: &AccountInfo) -> AccountMeta {
+    AccountMeta::new_readonly(*acc.key, true)
+}
+
+proxy_accounts! {
+    /// Accounts of a proxied `InitOpenOrders`, through `OpenOrdersPda`.
+    InitOpenOrders {
+        system_program: readonly,
+        /// The open orders PDA of the owner.
+        open_orders: writable,
+        owner: signer,
+        market: readonly,
+        rent: readonly,
+        /// The proxy's open orders init authority of the market, signed for
+        /// by the proxy.
+        market_authority: readonly,
+    }
+}
+
+proxy_accounts! {
+    /// Accounts of a proxied `NewOrderV3`.
+    NewOrderV3 {
+        market: writable,
+        open_orders: writable,
+        request_queue: writable,
+        event_queue: writable,
+        bids: writable,
+        asks: writable,
+        /// The token account paying for the order.
+        order_payer: writable,
+        owner: signer,
+        coin_vault: writable,
+        pc_vault: writable,
+        token_program: readonly,
+        rent: readonly,
+    }
+}
+
+proxy_accounts! {
+    /// Accounts of a proxied `CancelOrderV2` or `CancelOrderByClientIdV2`.
+    CancelOrderV2 {
+        market: writable,
+        bids: writable,
+        asks: writable,
+        open_orders: writable,
+        owner: signer,
+        event_queue: writable,
+    }
+}
+
+proxy_accounts! {
+    /// Accounts of a proxied `SettleFunds`, without a referrer.
+    SettleFunds {
+        market: writable,
+        open_orders: writable,
+        owner: signer,
+        coin_vault: writable,
+        pc_vault: writable,
+        coin_wallet: writable,
+        pc_wallet: writable,
+        vault_signer: readonly,
+        token_program: readonly,
+    }
+}
+
+proxy_accounts! {
+    /// Accounts of a proxied `CloseOpenOrders`.
+    CloseOpenOrders {
+        open_orders: writable,
+        owner: signer,
+        /// Receives the lamports of the open orders account.
+        destination: writable,
+        market: readonly,
+    }
+}
+
+/// Initializes the owner's open orders PDA, see `OpenOrdersPda`. The
+/// market's `MarketBumps` is the first remaining account, unless the header
+/// sets `FLAG_PERMISSIONLESS_INIT`.
+pub fn init_open_orders<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, InitOpenOrders<'info>>,
+    header: &ProxyHeader,
+) -> ProgramResult {
+    invoke_proxy(ctx, header, MarketInstruction::InitOpenOrders)
+}
+
+/// Places the given order.
+pub fn new_order_v3<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, NewOrderV3<'info>>,
+    header: &ProxyHeader,
+    order: NewOrderInstructionV3,
+) -> ProgramResult {
+    invoke_proxy(ctx, header, MarketInstruction::NewOrderV3(order))
+}
+
+/// Cancels the order of the given id.
+pub fn cancel_order_v2<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, CancelOrderV2<'info>>,
+    header: &ProxyHeader,
+    side: Side,
+    order_id: u128,
+) -> ProgramResult {
+    let ix = MarketInstruction::CancelOrderV2(CancelOrderInstructionV2 { side, order_id });
+    invoke_proxy(ctx, header, ix)
+}
+
+/// Cancels the order of the given client order id.
+pub fn cancel_order_by_client_id_v2<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, CancelOrderV2<'info>>,
+    header: &ProxyHeader,
+    client_order_id: u64,
+) -> ProgramResult {
+    invoke_proxy(
+        ctx,
+        header,
+        MarketInstruction::CancelOrderByClientIdV2(client_order_id),
+    )
+}
+
+/// Settles the free balances of the open orders account into the wallets.
+pub fn settle_funds<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, SettleFunds<'info>>,
+    header: &ProxyHeader,
+) -> ProgramResult {
+    invoke_proxy(ctx, header, MarketInstruction::SettleFunds)
+}
+
+/// Closes the open orders account.
+pub fn close_open_orders<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, CloseOpenOrders<'info>>,
+    header: &ProxyHeader,
+) -> ProgramResult {
+    invoke_proxy(ctx, header, MarketInstruction::CloseOpenOrders)
+}
+
+/// Returns the proxied request of the given DEX instruction with the given
+/// accounts, the middleware accounts last.
+pub fn proxy_cpi_instruction(
+    proxy_program_id: &Pubkey,
+    header: &ProxyHeader,
+    dex_ix: &MarketInstruction,
+    mut accounts: Vec<AccountMeta>,
+    trailing_accounts: Vec<AccountMeta>,
+) -> Instruction {
+    let header = ProxyHeader {
+        trailing_accounts: trailing_accounts.len() as u8,
+        ..header.clone()
+    };
+    let mut data = header.pack();
+    data.extend_from_slice(&dex_ix.pack());
+    accounts.extend(trailing_accounts);
+    Instruction {
+        program_id: *proxy_program_id,
+        accounts,
+        data,
+    }
+}
+
+fn invoke_proxy<'info, T: ToAccountMetas + ToAccountInfos<'info>>(
+    ctx: CpiContext<'_, '_, '_, 'info, T>,
+    header: &ProxyHeader,
+    dex_ix: MarketInstruction,
+) -> ProgramResult {
+    let ix = proxy_cpi_instruction(
+        ctx.program.key,
+        header,
+        &dex_ix,
+        ctx.accounts.to_account_metas(None),
+        ctx.remaining_accounts.to_account_metas(None),
+    );
+    let mut infos = ctx.accounts.to_account_infos();
+    infos.extend_from_slice(&ctx.remaining_accounts);
+    infos.push(ctx.program);
+    program::invoke_signed(&ix, &infos, ctx.signer_seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    fn dummy_account() -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(Vec::new().into_boxed_slice()),
+            Box::leak(Box::new(Pubkey::default())),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_proxy_cpi_instruction() {
+        let accounts = CancelOrderV2 {
+            dex_program: dummy_account(),
+            market: dummy_account(),
+            bids: dummy_account(),
+            asks: dummy_account(),
+            open_orders: dummy_account(),
+            owner: dummy_account(),
+            event_queue: dummy_account(),
+        };
+        let metas = accounts.to_account_metas(None);
+        assert_eq!(metas[0], readonly(&accounts.dex_program));
+        assert_eq!(metas[5], signer(&accounts.owner));
+        assert_eq!(accounts.to_account_infos().len(), metas.len());
+
+        let proxy_program_id = Pubkey::new_unique();
+        let trailing = vec![AccountMeta::new(Pubkey::new_unique(), false)];
+        let dex_ix = MarketInstruction::CancelOrderByClientIdV2(7);
+        let ix = proxy_cpi_instruction(
+            &proxy_program_id,
+            &ProxyHeader::default(),
+            &dex_ix,
+            metas.clone(),
+            trailing.clone(),
+        );
+        assert_eq!(ix.accounts.len(), metas.len() + 1);
+        assert_eq!(ix.accounts[metas.len()], trailing[0]);
+        let mut data = &ix.data[..];
+        let header = ProxyHeader::unpack(&mut data).unwrap();
+        assert_eq!(header.trailing_accounts, 1);
+        assert_eq!(data, &dex_ix.pack()[..]);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Seed of the `TradingDelegate` PDAs.
+pub const TRADING_DELEGATE_SEED: &[u8] = b"trading-delegate";
+
+/// A key allowed to place and cancel orders on behalf of a user on a market,
+/// e.g., a bot or a custodian, stored in a PDA of the proxy program derived
+/// from the market and the user. Accepted by `OpenOrdersPda` in place of the
+/// user's signature.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TradingDelegate {
+    /// The user who registered the delegate.
+    pub owner: Pubkey,
+    pub market: Pubkey,
+    pub delegate: Pubkey,
+    pub bump: u8,
+}
+
+impl TradingDelegate {
+    /// Size of the delegate account.
+    pub const LEN: usize = 8 + 32 + 32 + 32 + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxdelegt";
+
+    /// Returns the address and bump of the delegate PDA of the given user on
+    /// the given market.
+    pub fn address(program_id: &Pubkey, market: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[TRADING_DELEGATE_SEED, market.as_ref(), owner.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Loads the delegate from the given account, checking it's the
+    /// program's delegate PDA of the owner and market it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let delegate = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[
+                TRADING_DELEGATE_SEED,
+                delegate.market.as_ref(),
+                delegate.owner.as_ref(),
+                &[delegate.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(delegate)
+    }
+
+    fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidDelegate).into()),
+        }
+        Self::deserialize(&mut &data[8..])
+            .map_err(|_| -> ProgramError { anchor_lang::error!(ErrorCode::InvalidDelegate).into() })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Registers, replaces, or (given `None`) removes the trading delegate of the
+/// signing user on the given market. Removing the delegate closes its
+/// account, refunding the rent to the user.
+///
+/// Accounts:
+///
+/// 0. Delegate PDA (writable).
+/// 1. User (signer, writable), paying for the account.
+/// 2. System program, when registering the first delegate.
+pub(crate) fn process_set_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: Pubkey,
+    delegate: Option<Pubkey>,
+) -> ProgramResult {
+    let delegate_info = &accounts[0];
+    let owner = &accounts[1];
+    let (address, bump) = TradingDelegate::address(program_id, &market, owner.key);
+    if &address != delegate_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let delegate = match delegate {
+        Some(delegate) => delegate,
+        None => return close_delegate(program_id, delegate_info, owner.key, owner),
+    };
+
+    if delegate_info.owner == program_id {
+        TradingDelegate::load(program_id, delegate_info)?;
+    } else {
+        let system = accounts
+            .get(2)
+            .filter(|acc| acc.key == &system_program::ID)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: owner.clone(),
+                    to: delegate_info.clone(),
+                },
+                &[&[
+                    TRADING_DELEGATE_SEED,
+                    market.as_ref(),
+                    owner.key.as_ref(),
+                    &[bump],
+                ]],
+            ),
+            Rent::get()?.minimum_balance(TradingDelegate::LEN),
+            TradingDelegate::LEN as u64,
+            program_id,
+        )?;
+    }
+    TradingDelegate {
+        owner: *owner.key,
+        market,
+        delegate,
+        bump,
+    }
+    .store(delegate_info)
+}
+
+/// Closes the given delegate account of the given user, refunding its rent
+/// to the given recipient.
+pub(crate) fn close_delegate(
+    program_id: &Pubkey,
+    delegate_info: &AccountInfo,
+    owner: &Pubkey,
+    recipient: &AccountInfo,
+) -> ProgramResult {
+    let delegate = TradingDelegate::load(program_id, delegate_info)?;
+    if &delegate.owner != owner {
+        return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+    }
+    let lamports = delegate_info.lamports();
+    **delegate_info.try_borrow_mut_lamports()? = 0;
+    let balance = recipient.lamports().checked_add(lamports).unwrap();
+    **recipient.try_borrow_mut_lamports()? = balance;
+    // The runtime purges the account once it's without rent, but it must not
+    // be loaded again within the transaction.
+    delegate_info.try_borrow_mut_data()?.fill(0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    fn account(key: Pubkey, owner: Pubkey, lamports: u64, len: usize) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            true,
+            true,
+            Box::leak(Box::new(lamports)),
+            Box::leak(vec![0u8; len].into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_replace_and_remove_delegate() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let owner = account(Pubkey::new_unique(), Pubkey::default(), 5, 0);
+        let (address, bump) = TradingDelegate::address(&program_id, &market, owner.key);
+        let delegate_info = account(address, program_id, 10, TradingDelegate::LEN);
+        let record = TradingDelegate {
+            owner: *owner.key,
+            market,
+            delegate: Pubkey::new_unique(),
+            bump,
+        };
+        record.store(&delegate_info).unwrap();
+        assert_eq!(
+            TradingDelegate::load(&program_id, &delegate_info).unwrap(),
+            record
+        );
+
+        let accounts = [delegate_info.clone(), owner.clone()];
+        let delegate = Pubkey::new_unique();
+        process_set_delegate(&program_id, &accounts, market, Some(delegate)).unwrap();
+        let loaded = TradingDelegate::load(&program_id, &delegate_info).unwrap();
+        assert_eq!(loaded.delegate, delegate);
+
+        // Another user's delegate can't be touched.
+        let other = account(Pubkey::new_unique(), Pubkey::default(), 0, 0);
+        let spoofed = [delegate_info.clone(), other];
+        assert!(process_set_delegate(&program_id, &spoofed, market, None).is_err());
+
+        process_set_delegate(&program_id, &accounts, market, None).unwrap();
+        assert_eq!(delegate_info.lamports(), 0);
+        assert_eq!(owner.lamports(), 15);
+        assert!(TradingDelegate::load(&program_id, &delegate_info).is_err());
+    }
+}
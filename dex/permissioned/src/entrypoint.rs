@@ -0,0 +1,119 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Tag prefixing instruction data addressed to one of the proxy program's
+/// own instructions, rather than to the DEX. The rest of the data is laid
+/// out as
+///
+/// ```text
+/// 0.   Index of the instruction in the program's instruction list (u8).
+/// ..   Data passed to the instruction.
+/// ```
+///
+/// Since proxied requests start with the `ProxyHeader` version, the tag
+/// never collides with a DEX request.
+pub const CUSTOM_INSTRUCTION_TAG: u8 = 0xad;
+
+/// Handler for a custom (e.g. administrative) instruction exposed by a proxy
+/// program alongside the DEX relay.
+pub type CustomInstruction = for<'info> fn(&Pubkey, &[AccountInfo<'info>], &[u8]) -> ProgramResult;
+
+/// Invokes the custom instruction addressed by the given instruction data,
+/// if any. Returns `None` if the data should be relayed to the DEX instead.
+#[doc(hidden)]
+pub fn dispatch_custom_instruction(
+    instructions: &[CustomInstruction],
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> Option<ProgramResult> {
+    match data {
+        [CUSTOM_INSTRUCTION_TAG, idx, ix_data @ ..] => {
+            let result = match instructions.get(*idx as usize) {
+                Some(ix) => ix(program_id, accounts, ix_data),
+                None => Err(anchor_lang::error!(ErrorCode::InvalidInstruction).into()),
+            };
+            Some(result)
+        }
+        _ => None,
+    }
+}
+
+/// Packs the data for the custom instruction at the given index.
+pub fn pack_custom_instruction(idx: u8, ix_data: &[u8]) -> Vec<u8> {
+    let mut data = vec![CUSTOM_INSTRUCTION_TAG, idx];
+    data.extend_from_slice(ix_data);
+    data
+}
+
+// Macros.
+
+/// Declares the program entrypoint of a market proxy, relaying every request
+/// through a `MarketProxy` with the given middleware, in order. Optionally,
+/// the program can expose its own instructions, e.g., for administration,
+/// which are addressed by their position in the list (see
+/// `CUSTOM_INSTRUCTION_TAG`).
+///
+/// ```ignore
+/// declare_market_proxy! {
+///     middleware = [Logger::new(), Identity, OpenOrdersPda::new()],
+///     instructions = [set_config, close_config],
+/// }
+/// ```
+///
+/// The entrypoint is omitted when the program is built with the
+/// `no-entrypoint` feature.
+#[macro_export]
+macro_rules! declare_market_proxy {
+    (
+        middleware = [$($mw:expr),* $(,)?]
+        $(, instructions = [$($ix:path),* $(,)?])?
+        $(,)?
+    ) => {
+        #[cfg(not(feature = "no-entrypoint"))]
+        $crate::solana_program::entrypoint!(process_instruction);
+
+        pub fn process_instruction(
+            program_id: &$crate::solana_program::pubkey::Pubkey,
+            accounts: &[$crate::solana_program::account_info::AccountInfo],
+            data: &[u8],
+        ) -> $crate::solana_program::entrypoint::ProgramResult {
+            let instructions: &[$crate::CustomInstruction] = &[$($($ix),*)?];
+            if let Some(result) =
+                $crate::dispatch_custom_instruction(instructions, program_id, accounts, data)
+            {
+                return result;
+            }
+            $crate::MarketProxy::new()
+                $(.middleware_boxed(Box::new($mw)))*
+                .run(program_id, accounts, data)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin(_program_id: &Pubkey, _accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        match data {
+            [42] => Ok(()),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+
+    #[test]
+    fn test_dispatch_custom_instruction() {
+        let program_id = Pubkey::new_unique();
+        let instructions: &[CustomInstruction] = &[admin];
+        let data = pack_custom_instruction(0, &[42]);
+        let result = dispatch_custom_instruction(instructions, &program_id, &[], &data);
+        assert!(matches!(result, Some(Ok(()))));
+        let data = pack_custom_instruction(1, &[42]);
+        let result = dispatch_custom_instruction(instructions, &program_id, &[], &data);
+        assert!(matches!(result, Some(Err(_))));
+        let result = dispatch_custom_instruction(instructions, &program_id, &[], &[1, 0]);
+        assert!(result.is_none());
+    }
+}
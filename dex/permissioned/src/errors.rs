@@ -0,0 +1,138 @@
+use anchor_lang::prelude::*;
+
+/// A range of custom error codes reserved by a middleware, so that errors
+/// returned by different middleware (and by the proxy itself) never collide
+/// and can be attributed unambiguously, e.g., by explorers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorNamespace {
+    pub name: &'static str,
+    /// First error code of the range.
+    pub offset: u32,
+    /// Number of error codes in the range.
+    pub len: u32,
+}
+
+/// Namespace of the proxy's own `ErrorCode`.
+pub const PROXY_ERROR_NAMESPACE: ErrorNamespace = ErrorNamespace::new("proxy", 500, 100);
+
+impl ErrorNamespace {
+    pub const fn new(name: &'static str, offset: u32, len: u32) -> Self {
+        Self { name, offset, len }
+    }
+
+    /// Returns true if the given custom error code is in the namespace.
+    pub fn contains(&self, code: u32) -> bool {
+        code >= self.offset && code - self.offset < self.len
+    }
+
+    /// Returns true if the two namespaces share any error code.
+    pub fn overlaps(&self, other: &ErrorNamespace) -> bool {
+        self.offset < other.offset.saturating_add(other.len)
+            && other.offset < self.offset.saturating_add(self.len)
+    }
+
+    /// Returns the custom program error for the given code, relative to the
+    /// namespace.
+    pub fn error(&self, code: u32) -> ProgramError {
+        debug_assert!(code < self.len);
+        ProgramError::Custom(self.offset + code)
+    }
+}
+
+/// A typed middleware error, converted into a `ProgramError` within the
+/// middleware's namespace.
+///
+/// ```ignore
+/// #[derive(Clone, Copy)]
+/// enum IdentityError {
+///     InvalidAuth,
+///     TokenNotRevoked,
+/// }
+///
+/// impl MiddlewareError for IdentityError {
+///     const NAMESPACE: ErrorNamespace = ErrorNamespace::new("identity", 1000, 100);
+///
+///     fn code(self) -> u32 {
+///         self as u32
+///     }
+/// }
+///
+/// return Err(IdentityError::InvalidAuth.into_program_error());
+/// ```
+pub trait MiddlewareError: Copy {
+    const NAMESPACE: ErrorNamespace;
+
+    /// The error code, relative to the namespace.
+    fn code(self) -> u32;
+
+    fn into_program_error(self) -> ProgramError {
+        Self::NAMESPACE.error(self.code())
+    }
+}
+
+/// Checks that none of the given namespaces overlap, returning the first
+/// overlapping pair otherwise.
+pub fn check_error_namespaces(
+    namespaces: &[ErrorNamespace],
+) -> std::result::Result<(), (ErrorNamespace, ErrorNamespace)> {
+    for (idx, a) in namespaces.iter().enumerate() {
+        if let Some(b) = namespaces[idx + 1..].iter().find(|b| a.overlaps(b)) {
+            return Err((*a, *b));
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a custom error code to its namespace and the code relative to
+/// it.
+pub fn decode_error(namespaces: &[ErrorNamespace], code: u32) -> Option<(&ErrorNamespace, u32)> {
+    namespaces
+        .iter()
+        .find(|ns| ns.contains(code))
+        .map(|ns| (ns, code - ns.offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    enum TestError {
+        First,
+        Second,
+    }
+
+    impl MiddlewareError for TestError {
+        const NAMESPACE: ErrorNamespace = ErrorNamespace::new("test", 1000, 10);
+
+        fn code(self) -> u32 {
+            self as u32
+        }
+    }
+
+    #[test]
+    fn test_middleware_error() {
+        assert_eq!(
+            TestError::First.into_program_error(),
+            ProgramError::Custom(1000)
+        );
+        assert_eq!(
+            TestError::Second.into_program_error(),
+            ProgramError::Custom(1001)
+        );
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let namespaces = [PROXY_ERROR_NAMESPACE, TestError::NAMESPACE];
+        assert!(check_error_namespaces(&namespaces).is_ok());
+        assert_eq!(
+            decode_error(&namespaces, 1003),
+            Some((&TestError::NAMESPACE, 3))
+        );
+        assert_eq!(decode_error(&namespaces, 1010), None);
+
+        let overlapping = ErrorNamespace::new("overlapping", 590, 20);
+        assert!(check_error_namespaces(&[PROXY_ERROR_NAMESPACE, overlapping]).is_err());
+    }
+}
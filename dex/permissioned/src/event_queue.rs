@@ -0,0 +1,287 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use serum_dex::matching::Side;
+use serum_dex::state::{AccountFlag, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING};
+use std::convert::TryInto;
+
+// `EventFlag`s of the DEX.
+const EVENT_FLAG_FILL: u8 = 0x1;
+const EVENT_FLAG_OUT: u8 = 0x2;
+const EVENT_FLAG_BID: u8 = 0x4;
+const EVENT_FLAG_MAKER: u8 = 0x8;
+const EVENT_FLAG_RELEASE_FUNDS: u8 = 0x10;
+
+/// A market's event queue, read from the data of its account, e.g. fetched
+/// over RPC or borrowed by a middleware.
+#[derive(Clone, Copy)]
+pub struct EventQueueReader<'a> {
+    head: usize,
+    count: usize,
+    seq_num: u64,
+    events: &'a [u8],
+}
+
+/// An event of the queue, with its native quantities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueEvent {
+    /// An order was, partly or fully, matched.
+    Fill {
+        side: Side,
+        /// Whether the order was resting on the book, rather than taking.
+        maker: bool,
+        native_qty_paid: u64,
+        native_qty_received: u64,
+        /// The fee paid by a taker, or the rebate earned by a maker, in pc.
+        native_fee_or_rebate: u64,
+        order_id: u128,
+        /// The open orders account the order was placed from.
+        owner: Pubkey,
+        owner_slot: u8,
+        fee_tier: u8,
+        client_order_id: u64,
+    },
+    /// An order left the book, or didn't rest on it.
+    Out {
+        side: Side,
+        /// Whether the funds unlocked by the order are free to settle.
+        release_funds: bool,
+        native_qty_unlocked: u64,
+        native_qty_still_locked: u64,
+        order_id: u128,
+        /// The open orders account the order was placed from.
+        owner: Pubkey,
+        owner_slot: u8,
+        client_order_id: u64,
+    },
+}
+
+impl QueueEvent {
+    // The packed `Event`: flags, owner slot, fee tier and padding, then the
+    // native quantities, order id, owner and client order id.
+    const LEN: usize = 88;
+
+    fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        let u64_at = |offset| read_u64(data, offset);
+        let flags = data[0];
+        let side = match flags & EVENT_FLAG_BID {
+            0 => Side::Ask,
+            _ => Side::Bid,
+        };
+        let order_id = u128::from_le_bytes(data[32..48].try_into().unwrap());
+        let owner = Pubkey::new_from_array(data[48..80].try_into().unwrap());
+        let flags = flags & !EVENT_FLAG_BID;
+        if flags & !EVENT_FLAG_MAKER == EVENT_FLAG_FILL {
+            return Ok(QueueEvent::Fill {
+                side,
+                maker: flags & EVENT_FLAG_MAKER != 0,
+                native_qty_paid: u64_at(16),
+                native_qty_received: u64_at(8),
+                native_fee_or_rebate: u64_at(24),
+                order_id,
+                owner,
+                owner_slot: data[1],
+                fee_tier: data[2],
+                client_order_id: u64_at(80),
+            });
+        }
+        if flags & !EVENT_FLAG_RELEASE_FUNDS == EVENT_FLAG_OUT {
+            return Ok(QueueEvent::Out {
+                side,
+                release_funds: flags & EVENT_FLAG_RELEASE_FUNDS != 0,
+                native_qty_unlocked: u64_at(8),
+                native_qty_still_locked: u64_at(16),
+                order_id,
+                owner,
+                owner_slot: data[1],
+                client_order_id: u64_at(80),
+            });
+        }
+        Err(invalid_event_queue())
+    }
+
+    pub fn side(&self) -> Side {
+        match self {
+            QueueEvent::Fill { side, .. } | QueueEvent::Out { side, .. } => *side,
+        }
+    }
+
+    pub fn order_id(&self) -> u128 {
+        match self {
+            QueueEvent::Fill { order_id, .. } | QueueEvent::Out { order_id, .. } => *order_id,
+        }
+    }
+
+    pub fn owner(&self) -> &Pubkey {
+        match self {
+            QueueEvent::Fill { owner, .. } | QueueEvent::Out { owner, .. } => owner,
+        }
+    }
+
+    pub fn client_order_id(&self) -> u64 {
+        match self {
+            QueueEvent::Fill {
+                client_order_id, ..
+            }
+            | QueueEvent::Out {
+                client_order_id, ..
+            } => *client_order_id,
+        }
+    }
+}
+
+impl<'a> EventQueueReader<'a> {
+    // Account flags, head, count and sequence number.
+    const HEADER_LEN: usize = 32;
+
+    /// Parses the given event queue account data.
+    pub fn unpack(data: &'a [u8]) -> std::result::Result<Self, ProgramError> {
+        let padding = ACCOUNT_HEAD_PADDING.len() + ACCOUNT_TAIL_PADDING.len();
+        if data.len() < padding + Self::HEADER_LEN
+            || !data.starts_with(ACCOUNT_HEAD_PADDING)
+            || !data.ends_with(ACCOUNT_TAIL_PADDING)
+        {
+            return Err(invalid_event_queue());
+        }
+        let data = &data[ACCOUNT_HEAD_PADDING.len()..data.len() - ACCOUNT_TAIL_PADDING.len()];
+        let u64_at = |offset| read_u64(data, offset);
+        if u64_at(0) != AccountFlag::Initialized as u64 | AccountFlag::EventQueue as u64 {
+            return Err(invalid_event_queue());
+        }
+        let events = &data[Self::HEADER_LEN..];
+        let capacity = events.len() / QueueEvent::LEN;
+        let (head, count) = (u64_at(8) as usize, u64_at(16) as usize);
+        if count > capacity || (capacity > 0 && head >= capacity) {
+            return Err(invalid_event_queue());
+        }
+        Ok(Self {
+            head,
+            count,
+            seq_num: u64_at(24),
+            events: &events[..capacity * QueueEvent::LEN],
+        })
+    }
+
+    /// The number of events waiting to be consumed.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The sequence number of the next event pushed, the `i`th event of the
+    /// queue being numbered `seq_num - len + i`.
+    pub fn seq_num(&self) -> u64 {
+        self.seq_num
+    }
+
+    /// Returns the events waiting to be consumed, oldest first.
+    pub fn events(
+        &self,
+    ) -> impl Iterator<Item = std::result::Result<QueueEvent, ProgramError>> + 'a {
+        let (events, head) = (self.events, self.head);
+        let capacity = events.len() / QueueEvent::LEN;
+        (0..self.count).map(move |i| {
+            let offset = (head + i) % capacity * QueueEvent::LEN;
+            QueueEvent::unpack(&events[offset..offset + QueueEvent::LEN])
+        })
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid_event_queue() -> ProgramError {
+    anchor_lang::error!(ErrorCode::InvalidEventQueue).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the packed `Event` with the given flags, quantities, owner and
+    // client order id.
+    fn event_data(flags: u8, qtys: [u64; 3], owner: &Pubkey, client_order_id: u64) -> Vec<u8> {
+        let mut data = vec![flags, 1, 2, 0, 0, 0, 0, 0];
+        for qty in qtys.iter() {
+            data.extend_from_slice(&qty.to_le_bytes());
+        }
+        data.extend_from_slice(&(client_order_id as u128 + 100).to_le_bytes());
+        data.extend_from_slice(owner.as_ref());
+        data.extend_from_slice(&client_order_id.to_le_bytes());
+        data
+    }
+
+    // Returns the data of an event queue of the given events, starting at
+    // `head`.
+    fn queue_data(head: u64, count: u64, events: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::EventQueue as u64;
+        for word in [flags, head, count, 10].iter() {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for event in events {
+            data.extend_from_slice(event);
+        }
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        data
+    }
+
+    #[test]
+    fn test_events() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let fill = EVENT_FLAG_FILL | EVENT_FLAG_BID | EVENT_FLAG_MAKER;
+        let out = EVENT_FLAG_OUT | EVENT_FLAG_RELEASE_FUNDS;
+        let events = [
+            event_data(out, [5, 6, 0], &bob, 2),
+            vec![0; QueueEvent::LEN],
+            event_data(fill, [3, 4, 1], &alice, 1),
+        ];
+
+        // The queue wraps around, from the last event to the first.
+        let data = queue_data(2, 2, &events);
+        let queue = EventQueueReader::unpack(&data).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.seq_num(), 10);
+        let decoded: Vec<_> = queue.events().map(|event| event.unwrap()).collect();
+        assert_eq!(
+            decoded,
+            vec![
+                QueueEvent::Fill {
+                    side: Side::Bid,
+                    maker: true,
+                    native_qty_paid: 4,
+                    native_qty_received: 3,
+                    native_fee_or_rebate: 1,
+                    order_id: 101,
+                    owner: alice,
+                    owner_slot: 1,
+                    fee_tier: 2,
+                    client_order_id: 1,
+                },
+                QueueEvent::Out {
+                    side: Side::Ask,
+                    release_funds: true,
+                    native_qty_unlocked: 5,
+                    native_qty_still_locked: 6,
+                    order_id: 102,
+                    owner: bob,
+                    owner_slot: 1,
+                    client_order_id: 2,
+                },
+            ]
+        );
+        assert_eq!(decoded[1].owner(), &bob);
+
+        // Events of unknown flags don't decode.
+        let data = queue_data(1, 2, &events);
+        let queue = EventQueueReader::unpack(&data).unwrap();
+        assert!(queue.events().next().unwrap().is_err());
+
+        assert!(EventQueueReader::unpack(&queue_data(0, 4, &events)).is_err());
+        assert!(EventQueueReader::unpack(&queue_data(3, 0, &events)).is_err());
+        assert!(EventQueueReader::unpack(&data[1..]).is_err());
+    }
+}
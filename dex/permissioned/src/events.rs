@@ -0,0 +1,299 @@
+use crate::{Context, InstructionKind, Role};
+use anchor_lang::prelude::*;
+use serum_dex::instruction::{MarketInstruction, NewOrderInstructionV3};
+
+/// The user on whose behalf a request is made. Published to the `Context` by
+/// middleware that replace the user as the authority of the DEX instruction
+/// (e.g. `OpenOrdersPda`), so that events still report the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventUser(pub Pubkey);
+
+#[event]
+#[derive(Debug, PartialEq, Eq)]
+pub struct OpenOrdersInitialized {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub open_orders: Pubkey,
+}
+
+#[event]
+#[derive(Debug, PartialEq, Eq)]
+pub struct OrderPlaced {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub open_orders: Pubkey,
+    /// 0 for bids, 1 for asks.
+    pub side: u8,
+    pub limit_price: u64,
+    pub max_coin_qty: u64,
+    pub max_native_pc_qty_including_fees: u64,
+    pub client_order_id: u64,
+}
+
+#[event]
+#[derive(Debug, PartialEq, Eq)]
+pub struct OrderCancelled {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub open_orders: Pubkey,
+    /// Set when cancelling by order id.
+    pub order_id: Option<u128>,
+    /// Set when cancelling by client order id.
+    pub client_order_id: Option<u64>,
+}
+
+#[event]
+#[derive(Debug, PartialEq, Eq)]
+pub struct FundsSettled {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub open_orders: Pubkey,
+}
+
+/// A request observed by `Logger::events`, logged before it's relayed.
+#[event]
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestLogged {
+    /// Tag of the DEX instruction.
+    pub instruction: u32,
+    /// Unset for crank instructions.
+    pub user: Option<Pubkey>,
+    pub market: Option<Pubkey>,
+    /// 0 for bids, 1 for asks, set for new orders.
+    pub side: Option<u8>,
+    /// Set for new orders.
+    pub limit_price: Option<u64>,
+    /// Set for new orders.
+    pub max_coin_qty: Option<u64>,
+    /// Set for new orders and cancels by client order id.
+    pub client_order_id: Option<u64>,
+    /// Set for cancels by order id.
+    pub order_id: Option<u128>,
+    /// Set for crank instructions.
+    pub limit: Option<u16>,
+}
+
+impl RequestLogged {
+    /// Returns the event of the request with the given context, leaving the
+    /// instruction arguments to the caller.
+    pub fn of(ctx: &Context) -> Self {
+        let instruction = match ctx.data.get(1..5) {
+            Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]),
+            _ => u32::MAX,
+        };
+        let user = match ctx.get::<EventUser>() {
+            Some(user) => Some(user.0),
+            None => ctx.user().ok().map(|user| *user.key),
+        };
+        Self {
+            instruction,
+            user,
+            market: ctx.account(Role::Market).ok().map(|market| *market.key),
+            side: None,
+            limit_price: None,
+            max_coin_qty: None,
+            client_order_id: None,
+            order_id: None,
+            limit: None,
+        }
+    }
+}
+
+/// Event emitted by the proxy after successfully relaying an instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RelayEvent {
+    OpenOrdersInitialized(OpenOrdersInitialized),
+    OrderPlaced(OrderPlaced),
+    OrderCancelled(OrderCancelled),
+    FundsSettled(FundsSettled),
+}
+
+impl RelayEvent {
+    /// Returns the event for the instruction relayed with the given context,
+    /// if it has one.
+    pub fn of(ctx: &Context) -> Option<Self> {
+        let market = *ctx.account(Role::Market).ok()?.key;
+        let open_orders = *ctx.open_orders().ok()?.key;
+        let user = match ctx.get::<EventUser>() {
+            Some(user) => user.0,
+            None => *ctx.user().ok()?.key,
+        };
+        if let Some(ix) = ctx.instruction.as_ref().and_then(new_order) {
+            return Some(Self::OrderPlaced(OrderPlaced {
+                user,
+                market,
+                open_orders,
+                side: ix.side as u8,
+                limit_price: ix.limit_price.get(),
+                max_coin_qty: ix.max_coin_qty.get(),
+                max_native_pc_qty_including_fees: ix.max_native_pc_qty_including_fees.get(),
+                client_order_id: ix.client_order_id,
+            }));
+        }
+        // Instructions without arguments may not have been unpacked.
+        let event = match (ctx.kind?, ctx.instruction.as_ref()) {
+            (InstructionKind::InitOpenOrders, _) => {
+                Self::OpenOrdersInitialized(OpenOrdersInitialized {
+                    user,
+                    market,
+                    open_orders,
+                })
+            }
+            (_, Some(MarketInstruction::CancelOrderV2(ix))) => {
+                Self::OrderCancelled(OrderCancelled {
+                    user,
+                    market,
+                    open_orders,
+                    order_id: Some(ix.order_id),
+                    client_order_id: None,
+                })
+            }
+            (_, Some(MarketInstruction::CancelOrderByClientIdV2(client_order_id))) => {
+                Self::OrderCancelled(OrderCancelled {
+                    user,
+                    market,
+                    open_orders,
+                    order_id: None,
+                    client_order_id: Some(*client_order_id),
+                })
+            }
+            (InstructionKind::SettleFunds, _) => Self::FundsSettled(FundsSettled {
+                user,
+                market,
+                open_orders,
+            }),
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    /// Logs the event.
+    pub fn emit(self) {
+        match self {
+            Self::OpenOrdersInitialized(event) => emit!(event),
+            Self::OrderPlaced(event) => emit!(event),
+            Self::OrderCancelled(event) => emit!(event),
+            Self::FundsSettled(event) => emit!(event),
+        }
+    }
+}
+
+// Returns the order placed by the given instruction, if any, oracle pegged and
+// good-til-time orders wrapping a `NewOrderV3`.
+fn new_order(ix: &MarketInstruction) -> Option<&NewOrderInstructionV3> {
+    match ix {
+        MarketInstruction::NewOrderV3(ix) => Some(ix),
+        MarketInstruction::NewOraclePeggedOrder(ix) => Some(&ix.order),
+        MarketInstruction::NewGoodTilTimeOrder(ix) => Some(&ix.order),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serum_dex::instruction::{
+        CancelOrderInstructionV2, NewOraclePeggedOrderInstruction, SelfTradeBehavior,
+    };
+    use serum_dex::matching::{OrderType, Side};
+    use solana_program::clock::Epoch;
+    use std::convert::TryInto;
+
+    fn dummy_account() -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(Vec::new().into_boxed_slice()),
+            Box::leak(Box::new(Pubkey::default())),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_relay_event() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..6).map(|_| dummy_account()).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CancelOrderV2);
+        ctx.set_instruction(MarketInstruction::CancelOrderV2(CancelOrderInstructionV2 {
+            side: Side::Ask,
+            order_id: 7,
+        }));
+        let user = Pubkey::new_unique();
+        ctx.insert(EventUser(user));
+        let expected = RelayEvent::OrderCancelled(OrderCancelled {
+            user,
+            market: *ctx.accounts[0].key,
+            open_orders: *ctx.accounts[3].key,
+            order_id: Some(7),
+            client_order_id: None,
+        });
+        assert_eq!(RelayEvent::of(&ctx), Some(expected));
+
+        ctx.set_instruction(MarketInstruction::ConsumeEvents(1));
+        assert_eq!(RelayEvent::of(&ctx), None);
+    }
+
+    #[test]
+    fn test_pegged_order_event() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..13).map(|_| dummy_account()).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::NewOraclePeggedOrder);
+        let order = NewOrderInstructionV3 {
+            side: Side::Bid,
+            limit_price: 10u64.try_into().unwrap(),
+            max_coin_qty: 2u64.try_into().unwrap(),
+            max_native_pc_qty_including_fees: 20u64.try_into().unwrap(),
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            order_type: OrderType::Limit,
+            client_order_id: 3,
+            limit: 1,
+            max_ts: i64::MAX,
+        };
+        ctx.set_instruction(MarketInstruction::NewOraclePeggedOrder(
+            NewOraclePeggedOrderInstruction {
+                order,
+                peg_offset: -1,
+            },
+        ));
+        let expected = RelayEvent::OrderPlaced(OrderPlaced {
+            user: *ctx.accounts[7].key,
+            market: *ctx.accounts[0].key,
+            open_orders: *ctx.accounts[1].key,
+            side: Side::Bid as u8,
+            limit_price: 10,
+            max_coin_qty: 2,
+            max_native_pc_qty_including_fees: 20,
+            client_order_id: 3,
+        });
+        assert_eq!(RelayEvent::of(&ctx), Some(expected));
+    }
+
+    #[test]
+    fn test_request_logged() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..6).map(|_| dummy_account()).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CancelOrderByClientIdV2);
+        ctx.data = &[0, 12, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0];
+        let event = RequestLogged::of(&ctx);
+        assert_eq!(event.instruction, 12);
+        assert_eq!(event.user, Some(*ctx.accounts[4].key));
+        assert_eq!(event.market, Some(*ctx.accounts[0].key));
+
+        let user = Pubkey::new_unique();
+        ctx.insert(EventUser(user));
+        ctx.kind = Some(InstructionKind::ConsumeEvents);
+        ctx.data = &[];
+        let event = RequestLogged::of(&ctx);
+        assert_eq!(event.instruction, u32::MAX);
+        assert_eq!(event.user, Some(user));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+
+/// Version of the `ProxyHeader` layout understood by this crate.
+pub const PROXY_HEADER_VERSION: u8 = 1;
+
+/// `ProxyHeader::flags` bit running the middleware pipeline and account
+/// validation without relaying anything, so that clients can cheaply check a
+/// request would pass the proxy's policies.
+pub const FLAG_DRY_RUN: u8 = 1 << 0;
+
+/// `ProxyHeader::flags` bit settling the user's funds right after their
+/// immediate-or-cancel orders are placed through `OpenOrdersPda`, so that
+/// taking liquidity takes a single transaction.
+pub const FLAG_AUTO_SETTLE: u8 = 1 << 1;
+
+/// `ProxyHeader::flags` bit initializing open orders accounts without the
+/// market's open orders init authority, for markets that don't gate account
+/// creation, so that `OpenOrdersPda` serves both gated and open markets.
+pub const FLAG_PERMISSIONLESS_INIT: u8 = 1 << 2;
+
+/// Borsh serialized header prepended by clients to the DEX instruction data
+/// of every proxied request. Parsed once by the `MarketProxy` and handed to
+/// each middleware, so that multiple middleware can carry their own data
+/// without trampling each other.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub version: u8,
+    /// Request wide option bits.
+    pub flags: u8,
+    pub bumps: Bumps,
+    /// Number of accounts appended after the DEX accounts for use by the
+    /// middleware. These are never relayed to the DEX.
+    pub trailing_accounts: u8,
+    /// Opaque data for each middleware, indexed by the middleware's position
+    /// in the proxy's pipeline.
+    pub middleware_data: Vec<Vec<u8>>,
+}
+
+/// Bump seeds of the PDAs used to sign for the user.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bumps {
+    pub open_orders: u8,
+    pub open_orders_init: u8,
+}
+
+impl Default for ProxyHeader {
+    fn default() -> Self {
+        Self {
+            version: PROXY_HEADER_VERSION,
+            flags: 0,
+            bumps: Bumps::default(),
+            trailing_accounts: 0,
+            middleware_data: Vec::new(),
+        }
+    }
+}
+
+impl ProxyHeader {
+    /// Reads the header from the front of the given instruction data,
+    /// advancing it to the start of the DEX instruction.
+    pub fn unpack(data: &mut &[u8]) -> std::result::Result<Self, ProgramError> {
+        let header = Self::deserialize(data).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidProxyHeader).into()
+        })?;
+        if header.version != PROXY_HEADER_VERSION {
+            return Err(anchor_lang::error!(ErrorCode::UnsupportedProxyHeaderVersion).into());
+        }
+        Ok(header)
+    }
+
+    /// Serializes the header, to be prepended to the DEX instruction data.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.serialize(&mut data).unwrap();
+        data
+    }
+
+    /// Returns true if the request is a dry run (see `FLAG_DRY_RUN`).
+    pub fn is_dry_run(&self) -> bool {
+        self.flags & FLAG_DRY_RUN != 0
+    }
+
+    /// Returns true if IOC orders are to be settled (see `FLAG_AUTO_SETTLE`).
+    pub fn is_auto_settle(&self) -> bool {
+        self.flags & FLAG_AUTO_SETTLE != 0
+    }
+
+    /// Returns true if open orders accounts are initialized without the init
+    /// authority (see `FLAG_PERMISSIONLESS_INIT`).
+    pub fn is_permissionless_init(&self) -> bool {
+        self.flags & FLAG_PERMISSIONLESS_INIT != 0
+    }
+
+    /// Returns the data for the middleware at the given pipeline position.
+    pub fn data_for(&self, idx: usize) -> &[u8] {
+        self.middleware_data
+            .get(idx)
+            .map(|data| data.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Builder method setting the data of the middleware at the given
+    /// pipeline position, the middleware before it without data getting
+    /// none.
+    pub fn with_data<D: MiddlewareData>(mut self, idx: usize, data: &D) -> Self {
+        if self.middleware_data.len() <= idx {
+            self.middleware_data.resize(idx + 1, Vec::new());
+        }
+        self.middleware_data[idx] = data.encode();
+        self
+    }
+
+    /// Decodes the data of the middleware at the given pipeline position.
+    pub fn decode_data<D: MiddlewareData>(
+        &self,
+        idx: usize,
+    ) -> std::result::Result<D, ProgramError> {
+        D::decode(self.data_for(idx))
+    }
+}
+
+/// Layout of the header data a middleware consumes, decoded by the
+/// middleware in its `header` hook and encoded by clients building its
+/// requests with `ProxyHeader::with_data`, so that both sides share a single
+/// layout. Middleware consuming header data ship theirs, e.g.
+/// `OpenOrdersPdaData`.
+pub trait MiddlewareData: Sized {
+    /// Decodes the data addressed to the middleware, which is empty if the
+    /// client sent none.
+    fn decode(data: &[u8]) -> std::result::Result<Self, ProgramError>;
+
+    /// Encodes the data, to be decoded by `decode`.
+    fn encode(&self) -> Vec<u8>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = ProxyHeader {
+            bumps: Bumps {
+                open_orders: 254,
+                open_orders_init: 253,
+            },
+            middleware_data: vec![vec![], vec![1, 2, 3]],
+            ..ProxyHeader::default()
+        };
+        let mut data = header.pack();
+        data.extend_from_slice(&[9, 9]);
+        let mut cursor = &data[..];
+        assert_eq!(ProxyHeader::unpack(&mut cursor).unwrap(), header);
+        assert_eq!(cursor, &[9, 9]);
+        assert_eq!(header.data_for(1), &[1, 2, 3]);
+        assert!(header.data_for(2).is_empty());
+    }
+
+    #[test]
+    fn test_header_middleware_data() {
+        #[derive(Debug, PartialEq)]
+        struct Data(u16);
+
+        impl MiddlewareData for Data {
+            fn decode(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+                match data {
+                    [lo, hi] => Ok(Data(u16::from_le_bytes([*lo, *hi]))),
+                    _ => Err(ProgramError::InvalidInstructionData),
+                }
+            }
+
+            fn encode(&self) -> Vec<u8> {
+                self.0.to_le_bytes().to_vec()
+            }
+        }
+
+        let header = ProxyHeader::default().with_data(2, &Data(513));
+        assert_eq!(header.middleware_data, vec![vec![], vec![], vec![1, 2]]);
+        let header = header.with_data(0, &Data(1));
+        assert_eq!(header.middleware_data.len(), 3);
+        assert_eq!(header.decode_data::<Data>(0).unwrap(), Data(1));
+        assert_eq!(header.decode_data::<Data>(2).unwrap(), Data(513));
+        assert!(header.decode_data::<Data>(1).is_err());
+    }
+
+    #[test]
+    fn test_header_version_mismatch() {
+        let header = ProxyHeader {
+            version: PROXY_HEADER_VERSION + 1,
+            ..ProxyHeader::default()
+        };
+        let data = header.pack();
+        assert!(ProxyHeader::unpack(&mut &data[..]).is_err());
+        assert!(ProxyHeader::unpack(&mut &[][..]).is_err());
+    }
+}
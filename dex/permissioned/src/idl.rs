@@ -0,0 +1,93 @@
+/// The IDL of the proxy's config and user instructions, extended with a
+/// `proxy` section describing the framing of proxied requests: the tags, the
+/// `ProxyHeader` layout and flags, and the order of the request's accounts,
+/// so that clients in other languages can generate their builders.
+pub const PROXY_IDL: &str = include_str!("../idl/market_proxy.json");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ConfigInstruction, MarketMetadata, UserInstruction, CONFIG_INSTRUCTION_TAG,
+        FLAG_AUTO_SETTLE, FLAG_DRY_RUN, FLAG_PERMISSIONLESS_INIT, PROXY_HEADER_VERSION,
+        USER_INSTRUCTION_TAG,
+    };
+    use anchor_lang::prelude::Pubkey;
+    use serde_json::Value;
+
+    #[test]
+    fn test_idl_matches_layout() {
+        let idl: Value = serde_json::from_str(PROXY_IDL).unwrap();
+        let proxy = &idl["proxy"];
+        assert_eq!(proxy["configTag"], CONFIG_INSTRUCTION_TAG);
+        assert_eq!(proxy["userTag"], USER_INSTRUCTION_TAG);
+        assert_eq!(proxy["headerVersion"], PROXY_HEADER_VERSION);
+        let flags: Vec<_> = proxy["flags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|flag| flag["value"].as_u64().unwrap() as u8)
+            .collect();
+        assert_eq!(
+            flags,
+            vec![FLAG_DRY_RUN, FLAG_AUTO_SETTLE, FLAG_PERMISSIONLESS_INIT]
+        );
+
+        // Each instruction's discriminator prefixes its packed data.
+        let key = Pubkey::default();
+        let config_ixs = [
+            ConfigInstruction::InitializeConfig {
+                fee_bps: 0,
+                fee_receiver: key,
+                dex_program_id: key,
+            },
+            ConfigInstruction::UpdateConfig {
+                paused: None,
+                fee_bps: None,
+                fee_receiver: None,
+            },
+            ConfigInstruction::TransferAdmin { new_admin: key },
+            ConfigInstruction::AddReferral { referral: key },
+            ConfigInstruction::RemoveReferral { referral: key },
+            ConfigInstruction::SetMarketMetadata {
+                market: key,
+                metadata: None,
+            },
+        ];
+        let user_ixs = [
+            UserInstruction::SetDelegate {
+                market: key,
+                delegate: None,
+            },
+            UserInstruction::CacheMarketBumps {
+                dex_program_id: key,
+                market: key,
+            },
+            UserInstruction::ClaimReferralFees { mint: key },
+            UserInstruction::SetReferralCode {
+                code: [0; 8],
+                referral: None,
+            },
+        ];
+        let ixs: Vec<_> = config_ixs
+            .iter()
+            .map(ConfigInstruction::pack)
+            .chain(user_ixs.iter().map(UserInstruction::pack))
+            .collect();
+        let idl_ixs = idl["instructions"].as_array().unwrap();
+        assert_eq!(idl_ixs.len(), ixs.len());
+        for (idl_ix, ix) in idl_ixs.iter().zip(ixs.iter()) {
+            let discriminator: Vec<_> = idl_ix["discriminator"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|byte| byte.as_u64().unwrap() as u8)
+                .collect();
+            assert!(ix.starts_with(&discriminator), "{}", idl_ix["name"]);
+        }
+        assert_eq!(
+            idl["accounts"][0]["discriminator"],
+            serde_json::json!(MarketMetadata::DISCRIMINATOR)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{ErrorCode, InstructionKind, RelayAccounts, Role};
+use anchor_lang::prelude::*;
+use serum_dex::instruction::MarketInstruction;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Expected properties of an account in a DEX instruction's account layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountSpec {
+    pub role: Role,
+    pub signer: bool,
+    pub writable: bool,
+    /// Whether the DEX accepts the instruction without the account. Optional
+    /// accounts always trail the layout.
+    pub optional: bool,
+}
+
+/// Returns whether the given program is one of the token programs, i.e.,
+/// SPL Token or Token-2022.
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    program_id == &spl_token::ID || program_id == &anchor_spl::token_2022::ID
+}
+
+/// Program expected to own an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Owner {
+    Dex,
+    TokenProgram,
+}
+
+impl Role {
+    /// Returns the program expected to own accounts with this role, if the
+    /// role implies one.
+    pub fn owner(self) -> Option<Owner> {
+        match self {
+            Role::Market
+            | Role::OpenOrders
+            | Role::RequestQueue
+            | Role::EventQueue
+            | Role::Bids
+            | Role::Asks => Some(Owner::Dex),
+            Role::OrderPayer
+            | Role::CoinVault
+            | Role::PcVault
+            | Role::CoinWallet
+            | Role::PcWallet
+            | Role::FeeDiscount
+            | Role::Referral => Some(Owner::TokenProgram),
+            _ => None,
+        }
+    }
+}
+
+const fn required(role: Role, signer: bool, writable: bool) -> AccountSpec {
+    AccountSpec {
+        role,
+        signer,
+        writable,
+        optional: false,
+    }
+}
+
+const fn optional(role: Role, signer: bool, writable: bool) -> AccountSpec {
+    AccountSpec {
+        role,
+        signer,
+        writable,
+        optional: true,
+    }
+}
+
+const INIT_OPEN_ORDERS: &[AccountSpec] = &[
+    required(Role::OpenOrders, false, true),
+    required(Role::Authority, true, false),
+    required(Role::Market, false, false),
+    required(Role::Rent, false, false),
+    optional(Role::MarketAuthority, true, false),
+];
+
+const NEW_ORDER_V3: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::OpenOrders, false, true),
+    required(Role::RequestQueue, false, true),
+    required(Role::EventQueue, false, true),
+    required(Role::Bids, false, true),
+    required(Role::Asks, false, true),
+    required(Role::OrderPayer, false, true),
+    required(Role::Authority, true, false),
+    required(Role::CoinVault, false, true),
+    required(Role::PcVault, false, true),
+    required(Role::TokenProgram, false, false),
+    required(Role::Rent, false, false),
+    optional(Role::FeeDiscount, false, false),
+];
+
+const EXPIRE_ORDERS: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::Bids, false, true),
+    required(Role::Asks, false, true),
+    required(Role::EventQueue, false, true),
+];
+
+const CANCEL_ORDER_V2: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::Bids, false, true),
+    required(Role::Asks, false, true),
+    required(Role::OpenOrders, false, true),
+    required(Role::Authority, true, false),
+    required(Role::EventQueue, false, true),
+];
+
+const SETTLE_FUNDS: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::OpenOrders, false, true),
+    required(Role::Authority, true, false),
+    required(Role::CoinVault, false, true),
+    required(Role::PcVault, false, true),
+    required(Role::CoinWallet, false, true),
+    required(Role::PcWallet, false, true),
+    required(Role::VaultSigner, false, false),
+    required(Role::TokenProgram, false, false),
+    optional(Role::Referral, false, true),
+];
+
+const CLOSE_OPEN_ORDERS: &[AccountSpec] = &[
+    required(Role::OpenOrders, false, true),
+    required(Role::Authority, true, false),
+    required(Role::Destination, false, true),
+    required(Role::Market, false, false),
+];
+
+// The open orders accounts preceding the consume events layouts are variable
+// in number and not described.
+const CONSUME_EVENTS: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::EventQueue, false, true),
+    required(Role::CoinWallet, false, true),
+    required(Role::PcWallet, false, true),
+];
+
+const CONSUME_EVENTS_PERMISSIONED: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::EventQueue, false, true),
+    required(Role::CrankAuthority, true, false),
+];
+
+const PRUNE: &[AccountSpec] = &[
+    required(Role::Market, false, true),
+    required(Role::Bids, false, true),
+    required(Role::Asks, false, true),
+    required(Role::PruneAuthority, true, false),
+    required(Role::OpenOrders, false, true),
+    required(Role::Authority, false, false),
+    required(Role::EventQueue, false, true),
+];
+
+impl InstructionKind {
+    /// Returns the account layout expected by the DEX for this instruction.
+    pub const fn layout(self) -> &'static [AccountSpec] {
+        match self {
+            Self::InitOpenOrders => INIT_OPEN_ORDERS,
+            Self::NewOrderV3 | Self::NewOraclePeggedOrder | Self::NewGoodTilTimeOrder => {
+                NEW_ORDER_V3
+            }
+            Self::CancelOrderV2 | Self::CancelOrderByClientIdV2 => CANCEL_ORDER_V2,
+            Self::SettleFunds => SETTLE_FUNDS,
+            Self::CloseOpenOrders => CLOSE_OPEN_ORDERS,
+            Self::ConsumeEvents => CONSUME_EVENTS,
+            Self::ConsumeEventsPermissioned => CONSUME_EVENTS_PERMISSIONED,
+            Self::Prune => PRUNE,
+            Self::ExpireOrders => EXPIRE_ORDERS,
+        }
+    }
+
+    /// Returns the minimum number of accounts required by the DEX for this
+    /// instruction.
+    pub const fn min_accounts(self) -> usize {
+        // Optional accounts trail the layout.
+        let layout = self.layout();
+        let mut len = 0;
+        while len < layout.len() && !layout[len].optional {
+            len += 1;
+        }
+        len
+    }
+}
+
+/// Returns the minimum number of accounts required by the DEX for the given
+/// instruction, or 0 if the proxy doesn't relay it, so that clients can check
+/// an account list before sending a transaction.
+pub fn min_accounts(ix: &MarketInstruction) -> usize {
+    InstructionKind::of(ix).map_or(0, InstructionKind::min_accounts)
+}
+
+/// Checks the given accounts against the layout of the instruction, i.e.,
+/// that all required accounts are present, that each account signs and is
+/// writable as expected by the DEX, and that DEX and token accounts are owned
+/// by the respective programs.
+///
+/// The DEX tells the market's oracle, which may trail the accounts of new
+/// orders, by its key rather than its position, so the last account is left
+/// out of the layout when it's the given oracle.
+pub fn validate_layout(
+    kind: InstructionKind,
+    accounts: &RelayAccounts,
+    dex_program_id: &Pubkey,
+    oracle: Option<&Pubkey>,
+) -> ProgramResult {
+    if accounts.len() < kind.min_accounts() {
+        return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+    }
+    let trailing_oracle = kind.accepts_oracle()
+        && accounts.len() > kind.min_accounts()
+        && Some(accounts[accounts.len() - 1].key) == oracle;
+    let len = accounts.len() - trailing_oracle as usize;
+    for spec in kind.layout() {
+        let acc = match kind.index_of(spec.role, len) {
+            Some(idx) if idx < len => &accounts[idx],
+            _ => continue,
+        };
+        if spec.signer && !acc.is_signer {
+            msg!("{:?} account {} must sign", spec.role, acc.key);
+            return Err(anchor_lang::error!(ErrorCode::AccountNotSigner).into());
+        }
+        if spec.writable && !acc.is_writable {
+            msg!("{:?} account {} must be writable", spec.role, acc.key);
+            return Err(anchor_lang::error!(ErrorCode::AccountNotWritable).into());
+        }
+        let owned = match spec.role.owner() {
+            Some(Owner::Dex) => acc.owner == dex_program_id,
+            Some(Owner::TokenProgram) => is_token_program(acc.owner),
+            None => continue,
+        };
+        if !owned {
+            msg!(
+                "{:?} account {} can't be owned by {}",
+                spec.role,
+                acc.key,
+                acc.owner
+            );
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_accounts() {
+        assert_eq!(InstructionKind::InitOpenOrders.min_accounts(), 4);
+        assert_eq!(InstructionKind::NewOrderV3.min_accounts(), 12);
+        assert_eq!(InstructionKind::SettleFunds.min_accounts(), 9);
+        assert_eq!(InstructionKind::ConsumeEventsPermissioned.min_accounts(), 3);
+
+        const NEW_ORDER_MIN_ACCOUNTS: usize = InstructionKind::NewOrderV3.min_accounts();
+        assert_eq!(NEW_ORDER_MIN_ACCOUNTS, 12);
+        assert_eq!(min_accounts(&MarketInstruction::SettleFunds), 9);
+        assert_eq!(min_accounts(&MarketInstruction::ConsumeEvents(1)), 4);
+        assert_eq!(min_accounts(&MarketInstruction::MatchOrders(1)), 0);
+    }
+
+    #[test]
+    fn test_layout_roles_resolve() {
+        let kinds = [
+            InstructionKind::InitOpenOrders,
+            InstructionKind::NewOrderV3,
+            InstructionKind::CancelOrderV2,
+            InstructionKind::SettleFunds,
+            InstructionKind::CloseOpenOrders,
+            InstructionKind::ConsumeEvents,
+            InstructionKind::Prune,
+            InstructionKind::NewOraclePeggedOrder,
+            InstructionKind::ExpireOrders,
+        ];
+        for kind in kinds {
+            let len = kind.layout().len();
+            for spec in kind.layout() {
+                assert!(kind.index_of(spec.role, len).is_some());
+            }
+        }
+    }
+}
@@ -1,6 +1,54 @@
+mod accounts;
+mod book;
+mod bumps;
+#[cfg(feature = "client")]
+mod client;
+mod config;
+#[cfg(feature = "cpi")]
+pub mod cpi;
+mod delegate;
+mod entrypoint;
+mod errors;
+mod event_queue;
+mod events;
+mod header;
+mod idl;
+mod layouts;
+mod metadata;
 mod middleware;
+mod migration;
 mod proxy;
+mod referrals;
+mod registry;
+mod roles;
+mod seeds;
+mod state;
+mod user;
 
+pub use accounts::*;
+pub use book::*;
+pub use bumps::*;
+#[cfg(feature = "client")]
+pub use client::*;
+pub use config::*;
+pub use delegate::*;
+pub use entrypoint::*;
+pub use errors::*;
+pub use event_queue::*;
+pub use events::*;
+pub use header::*;
+pub use idl::*;
+pub use layouts::*;
+pub use metadata::*;
 pub use middleware::*;
+pub use migration::*;
 pub use proxy::*;
+pub use referrals::*;
+pub use registry::*;
+pub use roles::*;
+pub use seeds::*;
 pub use serum_dex;
+#[doc(hidden)]
+pub use solana_program;
+pub use state::*;
+pub use user::*;
@@ -0,0 +1,238 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use solana_program::hash::hash;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Seed of the `MarketMetadata` PDAs.
+pub const MARKET_METADATA_SEED: &[u8] = b"market-metadata";
+
+/// How a market is displayed, set by the proxy's admin and stored in a PDA
+/// of the proxy program derived from the market, so that UIs discovering the
+/// proxy's markets (e.g. by filtering the program's accounts on the
+/// discriminator) can render them without a centrally maintained list.
+/// Symbols are UTF-8, padded with zeros.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketMetadata {
+    pub market: Pubkey,
+    /// E.g. `SOL/USDC`.
+    pub symbol: [u8; 16],
+    pub base_symbol: [u8; 8],
+    pub quote_symbol: [u8; 8],
+    /// Hash of the configuration of the proxy's middleware on the market,
+    /// see `middleware_config_hash`, so that UIs can tell when the policies
+    /// a market trades under change.
+    pub middleware_hash: [u8; 32],
+    pub bump: u8,
+}
+
+impl MarketMetadata {
+    /// Size of the metadata account.
+    pub const LEN: usize = 8 + 32 + 16 + 8 + 8 + 32 + 1;
+
+    /// Prefix of the metadata account data, e.g. for filtering the program's
+    /// accounts by type.
+    pub const DISCRIMINATOR: [u8; 8] = *b"pxmktmet";
+
+    /// Returns the address and bump of the metadata PDA of the given market.
+    pub fn address(program_id: &Pubkey, market: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[MARKET_METADATA_SEED, market.as_ref()], program_id)
+    }
+
+    /// Loads the metadata from the given account, checking it's the
+    /// program's PDA of the market it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let metadata = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[
+                MARKET_METADATA_SEED,
+                metadata.market.as_ref(),
+                &[metadata.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(metadata)
+    }
+
+    /// Parses the given metadata account data, without checking its address.
+    pub fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidMarketMetadata).into()),
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidMarketMetadata).into()
+        })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+
+    /// The market's symbol, e.g. `SOL/USDC`.
+    pub fn symbol(&self) -> &str {
+        unpad_symbol(&self.symbol)
+    }
+
+    pub fn base_symbol(&self) -> &str {
+        unpad_symbol(&self.base_symbol)
+    }
+
+    pub fn quote_symbol(&self) -> &str {
+        unpad_symbol(&self.quote_symbol)
+    }
+}
+
+/// Pads the given symbol with zeros, if it fits.
+pub fn pad_symbol<const N: usize>(symbol: &str) -> Option<[u8; N]> {
+    let mut padded = [0; N];
+    padded
+        .get_mut(..symbol.len())?
+        .copy_from_slice(symbol.as_bytes());
+    Some(padded)
+}
+
+fn unpad_symbol(symbol: &[u8]) -> &str {
+    let len = symbol.iter().position(|b| *b == 0).unwrap_or(symbol.len());
+    std::str::from_utf8(&symbol[..len]).unwrap_or("")
+}
+
+/// Returns the hash of the given serialized middleware configuration, e.g.
+/// the Borsh serialized parameters of the proxy's middleware on a market.
+pub fn middleware_config_hash(config: &[u8]) -> [u8; 32] {
+    hash(config).to_bytes()
+}
+
+/// The fields of a `MarketMetadata` set by `SetMarketMetadata`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketMetadataArgs {
+    pub symbol: [u8; 16],
+    pub base_symbol: [u8; 8],
+    pub quote_symbol: [u8; 8],
+    pub middleware_hash: [u8; 32],
+}
+
+/// Sets the metadata of a market to the given one, creating its account,
+/// paid for by the admin, if needed, or (given `None`) removes it, refunding
+/// the rent to the admin. The caller checks the admin.
+///
+/// Accounts:
+///
+/// 0. Config PDA.
+/// 1. Admin (signer, writable).
+/// 2. Metadata PDA (writable).
+/// 3. System program, when creating the metadata.
+pub(crate) fn process_set_market_metadata(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    market: Pubkey,
+    metadata: Option<MarketMetadataArgs>,
+) -> ProgramResult {
+    let admin = &accounts[1];
+    let metadata_info = accounts.get(2).ok_or_else(|| -> ProgramError {
+        anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+    })?;
+    let (address, bump) = MarketMetadata::address(program_id, &market);
+    if &address != metadata_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let args = match metadata {
+        Some(args) => args,
+        None => {
+            if metadata_info.owner == program_id {
+                let lamports = metadata_info.lamports();
+                **metadata_info.try_borrow_mut_lamports()? = 0;
+                let balance = admin.lamports().checked_add(lamports).unwrap();
+                **admin.try_borrow_mut_lamports()? = balance;
+                metadata_info.try_borrow_mut_data()?.fill(0);
+            }
+            return Ok(());
+        }
+    };
+    if metadata_info.owner != program_id {
+        let system = accounts
+            .get(3)
+            .filter(|acc| acc.key == &system_program::ID)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: admin.clone(),
+                    to: metadata_info.clone(),
+                },
+                &[&[MARKET_METADATA_SEED, market.as_ref(), &[bump]]],
+            ),
+            Rent::get()?.minimum_balance(MarketMetadata::LEN),
+            MarketMetadata::LEN as u64,
+            program_id,
+        )?;
+    }
+    MarketMetadata {
+        market,
+        symbol: args.symbol,
+        base_symbol: args.base_symbol,
+        quote_symbol: args.quote_symbol,
+        middleware_hash: args.middleware_hash,
+        bump,
+    }
+    .store(metadata_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    #[test]
+    fn test_market_metadata() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let (address, bump) = MarketMetadata::address(&program_id, &market);
+        let metadata = MarketMetadata {
+            market,
+            symbol: pad_symbol("SOL/USDC").unwrap(),
+            base_symbol: pad_symbol("SOL").unwrap(),
+            quote_symbol: pad_symbol("USDC").unwrap(),
+            middleware_hash: middleware_config_hash(&[1, 2, 3]),
+            bump,
+        };
+        assert_eq!(metadata.symbol(), "SOL/USDC");
+        assert_eq!(metadata.quote_symbol(), "USDC");
+        assert!(pad_symbol::<8>("TOO-LONG-SYMBOL").is_none());
+
+        let info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0; MarketMetadata::LEN].into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        );
+        metadata.store(&info).unwrap();
+        assert_eq!(MarketMetadata::load(&program_id, &info).unwrap(), metadata);
+        assert!(MarketMetadata::load(&Pubkey::new_unique(), &info).is_err());
+    }
+}
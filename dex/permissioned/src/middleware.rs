@@ -1,17 +1,28 @@
-use crate::{open_orders_authority, open_orders_init_authority};
-use anchor_lang::prelude::*;
-use solana_program::{
-    msg, 
-    pubkey::Pubkey, 
-    entrypoint::ProgramResult, 
-    account_info::AccountInfo,
-    program_error::ProgramError,
+use crate::{
+    close_delegate, credit_referral_fees, open_orders_init_authority, referral_treasury_address,
+    register_open_orders, AuthorityMigration, ErrorNamespace, EventUser, InstructionKind,
+    MarketBumps, MarketHeader, MiddlewareData, ProxyConfig, ProxyHeader, ReferralBalance,
+    ReferralCode, ReferralSet, RelayAccounts, RequestLogged, Role, SignerSeeds, TradingDelegate,
+    PROXY_ERROR_NAMESPACE,
 };
+use anchor_lang::prelude::*;
 use anchor_lang::solana_program::instruction::Instruction;
 use anchor_spl::token;
+use anchor_spl::token_2022::spl_token_2022;
 use serum_dex;
 use serum_dex::instruction::*;
-use serum_dex::matching::Side;
+use serum_dex::matching::{OrderType, Side};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use spl_token_2022::extension::transfer_fee::TransferFeeConfig;
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use spl_token_2022::state::Mint;
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
 
 declare_id!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
 
@@ -19,49 +30,259 @@ declare_id!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
 pub struct Context<'a, 'info> {
     pub program_id: &'a Pubkey,
     pub dex_program_id: &'a Pubkey,
-    pub accounts: Vec<AccountInfo<'info>>,
+    pub accounts: RelayAccounts<'a, 'info>,
+    // The header the client prepended to the request.
+    pub header: ProxyHeader,
+    // The relayed DEX instruction, set at dispatch time. Used to resolve
+    // account roles.
+    pub kind: Option<InstructionKind>,
+    // The relayed DEX instruction data, as sent by the client.
+    pub(crate) data: &'a [u8],
+    // The decoded DEX instruction, unpacked from `data` on first use or set
+    // by a middleware.
+    pub(crate) instruction: Option<MarketInstruction>,
+    // Set when a middleware replaced the instruction during dispatch.
+    pub(crate) replaced: bool,
+    // Set when a middleware modified or replaced the instruction, in which
+    // case it's packed and relayed in place of `data`.
+    pub(crate) dirty: bool,
     pub seeds: Seeds,
+    // Set by a middleware that fully handled the request, in which case
+    // nothing is relayed to the DEX once all middleware have run.
+    pub skip_relay: bool,
     // Instructions to execute *prior* to the DEX relay CPI.
     pub pre_instructions: Vec<(Instruction, Vec<AccountInfo<'info>>, Seeds)>,
     // Instructions to execution *after* the DEX relay CPI.
     pub post_instructions: Vec<(Instruction, Vec<AccountInfo<'info>>, Seeds)>,
-    pub post_callbacks: Vec<(PostCallback<'a, 'info>, Vec<AccountInfo<'info>>, Vec<u8>)>,
+    // Callbacks to execute *prior* to the pre instructions.
+    pub pre_callbacks: Vec<(Callback<'a, 'info>, Vec<AccountInfo<'info>>, Vec<u8>)>,
+    // Callbacks to execute *after* the post instructions.
+    pub post_callbacks: Vec<(Callback<'a, 'info>, Vec<AccountInfo<'info>>, Vec<u8>)>,
+    // Arbitrary data published by middleware for downstream middleware,
+    // keyed by type.
+    extensions: BTreeMap<TypeId, Box<dyn Any>>,
+    // Accounts appended after the DEX accounts, not yet taken by a
+    // middleware.
+    trailing_accounts: &'a [AccountInfo<'info>],
+    // The market account's state, parsed on the first `market_state` call.
+    market_state: Option<MarketHeader>,
 }
 
-type PostCallback<'a, 'info> = fn(
+type Callback<'a, 'info> = fn(
     // program_id
     &'a Pubkey,
-    // AccountInfos needed for post callback.
+    // AccountInfos needed for the callback.
     Vec<AccountInfo<'info>>,
     // Market instruction.
     Vec<u8>,
-    // Arguments to post callback.
+    // Arguments to the callback.
     Vec<u8>,
+    // Return data set by the DEX relay. Always `None` for pre callbacks.
+    Option<Vec<u8>>,
 ) -> ProgramResult;
 
-type Seeds = Vec<Vec<Vec<u8>>>;
+type Seeds = Vec<SignerSeeds>;
 
 impl<'a, 'info> Context<'a, 'info> {
     pub fn new(
         program_id: &'a Pubkey,
         dex_program_id: &'a Pubkey,
-        accounts: Vec<AccountInfo<'info>>,
+        accounts: &'a [AccountInfo<'info>],
     ) -> Self {
         Self {
             program_id,
             dex_program_id,
-            accounts,
+            accounts: RelayAccounts::new(accounts),
+            header: ProxyHeader::default(),
+            kind: None,
+            data: &[],
+            instruction: None,
+            replaced: false,
+            dirty: false,
             seeds: Vec::new(),
+            skip_relay: false,
             pre_instructions: Vec::new(),
             post_instructions: Vec::new(),
+            pre_callbacks: Vec::new(),
             post_callbacks: Vec::new(),
+            extensions: BTreeMap::new(),
+            trailing_accounts: &[],
+            market_state: None,
+        }
+    }
+
+    /// Sets the accounts appended after the DEX accounts.
+    pub(crate) fn set_trailing_accounts(&mut self, accounts: &'a [AccountInfo<'info>]) {
+        self.trailing_accounts = accounts;
+    }
+
+    /// Takes the next `n` accounts appended after the DEX accounts, e.g.,
+    /// config or oracle accounts needed by a middleware. Middleware take
+    /// their accounts in pipeline order, and the client appends them in the
+    /// same order.
+    pub fn take_trailing(
+        &mut self,
+        n: usize,
+    ) -> std::result::Result<&'a [AccountInfo<'info>], ProgramError> {
+        if self.trailing_accounts.len() < n {
+            return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+        }
+        let (taken, rest) = self.trailing_accounts.split_at(n);
+        self.trailing_accounts = rest;
+        Ok(taken)
+    }
+
+    /// Returns the decoded DEX instruction, unpacking it if no middleware
+    /// did so yet.
+    pub fn instruction(&mut self) -> std::result::Result<&MarketInstruction, ProgramError> {
+        if self.instruction.is_none() {
+            let ix = MarketInstruction::unpack(self.data).ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::CannotUnpack).into()
+            })?;
+            self.instruction = Some(ix);
+        }
+        Ok(self.instruction.as_ref().unwrap())
+    }
+
+    /// Returns the state of the market the instruction is relayed to. The
+    /// market account is parsed once per request and shared by all
+    /// middleware.
+    pub fn market_state(&mut self) -> std::result::Result<&MarketHeader, ProgramError> {
+        if self.market_state.is_none() {
+            let market = self.account(Role::Market)?;
+            if market.owner != self.dex_program_id {
+                return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+            }
+            let state = MarketHeader::unpack(&market.try_borrow_data()?)?;
+            if state.own_address != *market.key {
+                return Err(anchor_lang::error!(ErrorCode::InvalidMarket).into());
+            }
+            self.market_state = Some(state);
         }
+        Ok(self.market_state.as_ref().unwrap())
+    }
+
+    /// Returns the instruction data to relay to the DEX. The client's data
+    /// is relayed as is unless a middleware changed the instruction, which
+    /// saves packing it and keeps instructions the DEX accepts in several
+    /// encodings (e.g. `NewOrderV3` without `max_ts`) untouched.
+    pub(crate) fn relayed_data(&self) -> Cow<'a, [u8]> {
+        match &self.instruction {
+            Some(ix) if self.dirty => Cow::Owned(ix.pack()),
+            _ => Cow::Borrowed(self.data),
+        }
+    }
+
+    /// Replaces the instruction relayed to the DEX, e.g., to turn a custom
+    /// instruction into a computed `NewOrderV3`. Middleware later in the
+    /// pipeline are dispatched on the replacement.
+    pub fn set_instruction(&mut self, ix: MarketInstruction) {
+        self.instruction = Some(ix);
+        self.replaced = true;
+        self.dirty = true;
+    }
+
+    /// Publishes a value for downstream middleware, returning the value of
+    /// the same type previously stored, if any.
+    pub fn insert<T: 'static>(&mut self, val: T) -> Option<T> {
+        self.extensions
+            .insert(TypeId::of::<T>(), Box::new(val))
+            .and_then(|prev| prev.downcast::<T>().ok())
+            .map(|prev| *prev)
+    }
+
+    /// Returns the value of the given type published by a previous
+    /// middleware.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|val| val.downcast_ref::<T>())
+    }
+
+    /// Mutable variant of `get`.
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.extensions
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|val| val.downcast_mut::<T>())
+    }
+
+    /// Removes the value of the given type from the context.
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.extensions
+            .remove(&TypeId::of::<T>())
+            .and_then(|val| val.downcast::<T>().ok())
+            .map(|val| *val)
+    }
+
+    /// Returns the index of the account with the given role for the
+    /// instruction being relayed.
+    pub fn account_index(&self, role: Role) -> std::result::Result<usize, ProgramError> {
+        self.kind
+            .and_then(|kind| kind.index_of(role, self.accounts.len()))
+            .filter(|idx| *idx < self.accounts.len())
+            .ok_or_else(|| anchor_lang::error!(ErrorCode::InvalidAccountRole).into())
+    }
+
+    /// Returns the account with the given role for the instruction being
+    /// relayed.
+    pub fn account(&self, role: Role) -> std::result::Result<&AccountInfo<'info>, ProgramError> {
+        let idx = self.account_index(role)?;
+        Ok(&self.accounts[idx])
+    }
+
+    /// Returns the owner of the open orders account, i.e., the user.
+    pub fn user(&self) -> std::result::Result<&AccountInfo<'info>, ProgramError> {
+        self.account(Role::Authority)
+    }
+
+    /// Returns the open orders account.
+    pub fn open_orders(&self) -> std::result::Result<&AccountInfo<'info>, ProgramError> {
+        self.account(Role::OpenOrders)
     }
 }
 
+/// Where a middleware runs within the pipeline. Middleware run in order of
+/// their stage, and in the order they were added within a stage, so that,
+/// e.g., accounts are never rewritten before the request is authorized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Access control, e.g., identity checks.
+    Auth,
+    Default,
+    /// Middleware signing for the user with the proxy's PDAs, e.g.,
+    /// `OpenOrdersPda`, which must run once the request is authorized.
+    Sign,
+    /// Middleware only observing the request, e.g., `Logger`.
+    Observe,
+}
+
 /// Implementing this trait allows one to hook into requests to the Serum DEX
 /// via a frontend proxy.
 pub trait MarketMiddleware {
+    /// Name of the middleware, logged when one of its hooks fails.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Range of custom error codes returned by the middleware, if it
+    /// declares one. See `MiddlewareError`.
+    fn error_namespace(&self) -> Option<ErrorNamespace> {
+        None
+    }
+
+    /// Stage of the pipeline the middleware runs in.
+    fn stage(&self) -> Stage {
+        Stage::Default
+    }
+
+    /// Called before any instruction with the header prepended to the
+    /// request and the header data addressed to this middleware, i.e., the
+    /// entry of `middleware_data` at the middleware's position in the
+    /// pipeline, to be decoded with the middleware's `MiddlewareData`.
+    fn header(&mut self, _header: &ProxyHeader, _data: &[u8]) -> ProgramResult {
+        Ok(())
+    }
+
     /// Called before any instruction, giving middleware access to the raw
     /// instruction data. This can be used to access extra data that is
     /// prepended to the DEX data, allowing one to expand the capabilities of
@@ -75,6 +296,8 @@ pub trait MarketMiddleware {
         Ok(())
     }
 
+    /// Called for all new orders, including oracle pegged and good-til-time
+    /// orders, with the order they wrap.
     fn new_order_v3(&self, _ctx: &mut Context, _ix: &mut NewOrderInstructionV3) -> ProgramResult {
         Ok(())
     }
@@ -115,18 +338,53 @@ pub trait MarketMiddleware {
         Ok(())
     }
 
+    fn expire_orders(&self, _ctx: &mut Context, _limit: &mut u16) -> ProgramResult {
+        Ok(())
+    }
+
     /// Called when the instruction data doesn't match any DEX instruction.
     fn fallback(&self, _ctx: &mut Context) -> ProgramResult {
         Ok(())
     }
+
+    /// Called immediately before the DEX relay CPI, once every middleware
+    /// has finished processing the request.
+    fn before_cpi(&self, _ctx: &mut Context) -> ProgramResult {
+        Ok(())
+    }
+
+    /// Called immediately after the DEX relay CPI with its result and any
+    /// return data set by the DEX, before the post instructions execute.
+    fn after_cpi(
+        &self,
+        _ctx: &mut Context,
+        _result: &ProgramResult,
+        _return_data: Option<&[u8]>,
+    ) -> ProgramResult {
+        Ok(())
+    }
 }
 
 /// Checks that the given open orders account signs the transaction and then
 /// replaces it with the open orders account, which must be a PDA.
+///
+/// Orders may also be placed and cancelled by the user's `TradingDelegate`
+/// on the market, in which case the user doesn't sign, and the delegate PDA
+/// followed by the delegate (signer) are appended as trailing accounts.
 #[derive(Default)]
 pub struct OpenOrdersPda {
     bump: u8,
     bump_init: u8,
+    index: u8,
+    canonical_bumps: bool,
+    sweep_on_close: bool,
+    native_sol: bool,
+    approval_buffer: Option<ApprovalBuffer>,
+    migrations: bool,
+    sweep_dust: bool,
+    dust_destination: Option<Pubkey>,
+    registry: bool,
+    permissionless_settle: bool,
 }
 
 impl OpenOrdersPda {
@@ -134,18 +392,116 @@ impl OpenOrdersPda {
         Self {
             bump: 0,
             bump_init: 0,
+            index: 0,
+            canonical_bumps: false,
+            sweep_on_close: false,
+            native_sol: false,
+            approval_buffer: None,
+            migrations: false,
+            sweep_dust: false,
+            dust_destination: None,
+            registry: false,
+            permissionless_settle: false,
         }
     }
 
-    fn prepare_pda<'info>(acc_info: &AccountInfo<'info>) -> AccountInfo<'info> {
-        let mut acc_info = acc_info.clone();
-        acc_info.is_signer = true;
-        acc_info
+    /// Builder method for resolving the PDAs' canonical bumps on-chain,
+    /// ignoring the bumps in the header, so that a malformed client bump
+    /// can't cause signing failures. The open orders init authority's bump
+    /// is read from the market's `MarketBumps` cache, appended as a trailing
+    /// account to `InitOpenOrders` requests.
+    pub fn canonical_bumps(mut self) -> Self {
+        self.canonical_bumps = true;
+        self
+    }
+
+    /// Builder method for settling the user's residual funds, which would
+    /// fail the DEX's `CloseOpenOrders`, before closing their open orders,
+    /// and for closing their `TradingDelegate` on the market along with it.
+    /// See `close_open_orders` for the trailing accounts this requires.
+    pub fn sweep_on_close(mut self) -> Self {
+        self.sweep_on_close = true;
+        self
+    }
+
+    /// Builder method for sweeping, on top of `sweep_on_close`, the dust
+    /// (i.e., less than a lot) left in the user's wallets once their open
+    /// orders are closed: into token accounts of the given destination, if
+    /// any, appended as trailing accounts, or else left to the user.
+    /// Wallets left empty are closed, refunding their rent to the user.
+    pub fn sweep_dust(mut self, destination: Option<Pubkey>) -> Self {
+        self.sweep_on_close = true;
+        self.sweep_dust = true;
+        self.dust_destination = destination;
+        self
     }
 
-    /// Validates the accounts structure for init_open_orders
-    fn validate_init_accounts<'info>(accounts: &[AccountInfo<'info>]) -> ProgramResult {
-        if accounts.len() < 5 {
+    /// Builder method for paying orders in native SOL: when an order is paid
+    /// from a wrapped SOL account, the account is topped up with the order
+    /// amount from the trader's lamports and synced beforehand, and closed
+    /// back to the trader afterwards, so that clients only need to create
+    /// the account (e.g. right before the order) rather than wrap SOL.
+    /// Proceeds to be settled into the account must be settled by the same
+    /// request, see `FLAG_AUTO_SETTLE`.
+    pub fn native_sol(mut self) -> Self {
+        self.native_sol = true;
+        self
+    }
+
+    /// Builder method for approving the open orders PDA for more than the
+    /// order size, so that orders whose debit differs from their size, e.g.
+    /// due to rounding to lots, don't fail for lack of allowance. The
+    /// allowance is revoked after the order either way.
+    pub fn approval_buffer(mut self, buffer: ApprovalBuffer) -> Self {
+        self.approval_buffer = Some(buffer);
+        self
+    }
+
+    /// Builder method for honoring `AuthorityMigration`s, so that users can
+    /// hand the control of their open orders over to another wallet with
+    /// `migrate_authority`. Every request signed for a user then has the
+    /// user's migration PDA as first trailing account, whether the user
+    /// migrated or not, followed by the new authority (signer) if they did.
+    pub fn migrations(mut self) -> Self {
+        self.migrations = true;
+        self
+    }
+
+    /// Builder method for listing the open orders accounts users initialize
+    /// in their `OpenOrdersRegistry` on the market, created on their first
+    /// initialization, so that their open orders can be resolved on-chain.
+    /// See `init_open_orders` for the trailing accounts this requires.
+    pub fn registry(mut self) -> Self {
+        self.registry = true;
+        self
+    }
+
+    /// Builder method for letting anyone, e.g. an auto-settlement service,
+    /// settle a user's funds without their signature, as long as both of the
+    /// wallets they're settled into are owned by the wallet controlling the
+    /// user's open orders.
+    pub fn permissionless_settle(mut self) -> Self {
+        self.permissionless_settle = true;
+        self
+    }
+
+    /// Marks the account at the given index as a signer, checking it's the
+    /// program's PDA for the given seeds.
+    fn sign_pda(ctx: &mut Context, idx: usize, seeds: SignerSeeds) -> ProgramResult {
+        match seeds.create_program_address(ctx.program_id) {
+            Ok(address) if &address == ctx.accounts[idx].key => {}
+            _ => return Err(ProgramError::InvalidSeeds),
+        }
+        ctx.seeds.push(seeds);
+        ctx.accounts.sign(idx);
+        Ok(())
+    }
+
+    /// Validates the accounts structure for init_open_orders, which has the
+    /// market authority unless permissionless.
+    fn validate_init_accounts(accounts: &RelayAccounts, permissionless: bool) -> ProgramResult {
+        let min_accounts = if permissionless { 4 } else { 5 };
+        if accounts.len() < min_accounts {
             msg!("Not enough accounts provided for init_open_orders");
             return Err(ProgramError::NotEnoughAccountKeys.into());
         }
@@ -158,20 +514,360 @@ impl OpenOrdersPda {
 
         Ok(())
     }
+
+    /// Checks the wallet controlling the user's open orders signed the
+    /// transaction and replaces the user with the open orders PDA as the
+    /// signing authority of the DEX instruction.
+    fn sign_as_user(&self, ctx: &mut Context) -> ProgramResult {
+        self.signed_controller(ctx)?;
+        let market = ctx.account(Role::Market)?.key;
+        let user = ctx.user()?.key;
+        self.sign_with_open_orders(ctx, market, user)
+    }
+
+    /// Like `sign_as_user`, but also accepting the user's trading delegate.
+    fn sign_as_trader(&self, ctx: &mut Context) -> ProgramResult {
+        self.trader(ctx)?;
+        let market = ctx.account(Role::Market)?.key;
+        let user = ctx.user()?.key;
+        self.sign_with_open_orders(ctx, market, user)
+    }
+
+    /// Resolves the wallet controlling the user's open orders: the user, or
+    /// the authority they migrated to, whose accounts are then taken from
+    /// the trailing accounts (see `migrations`).
+    fn controller<'info>(
+        &self,
+        ctx: &mut Context<'_, 'info>,
+    ) -> std::result::Result<Controller<'info>, ProgramError> {
+        let user = ctx.user()?.clone();
+        if !self.migrations {
+            return Ok(Controller::of(user));
+        }
+        let market = *ctx.account(Role::Market)?.key;
+        let migration_info = &ctx.take_trailing(1)?[0];
+        if migration_info.owner != ctx.program_id {
+            let (address, _) = AuthorityMigration::address(ctx.program_id, &market, user.key);
+            if &address != migration_info.key {
+                return Err(ProgramError::InvalidSeeds);
+            }
+            return Ok(Controller::of(user));
+        }
+        let migration = AuthorityMigration::load(ctx.program_id, migration_info)?;
+        if migration.market != market || migration.original != *user.key {
+            return Err(anchor_lang::error!(ErrorCode::InvalidMigration).into());
+        }
+        let authority = &ctx.take_trailing(1)?[0];
+        if authority.key != &migration.authority {
+            return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+        }
+        Ok(Controller::of(authority.clone()))
+    }
+
+    /// Like `controller`, but failing unless the controlling wallet signed.
+    fn signed_controller<'info>(
+        &self,
+        ctx: &mut Context<'_, 'info>,
+    ) -> std::result::Result<AccountInfo<'info>, ProgramError> {
+        self.controller(ctx)?.signer.ok_or_else(|| -> ProgramError {
+            anchor_lang::error!(ErrorCode::UnauthorizedUser).into()
+        })
+    }
+
+    /// Returns the account trading on behalf of the user: the wallet
+    /// controlling their open orders, if it signed, or else its trading
+    /// delegate on the market, whose accounts are taken from the trailing
+    /// accounts.
+    fn trader<'info>(
+        &self,
+        ctx: &mut Context<'_, 'info>,
+    ) -> std::result::Result<AccountInfo<'info>, ProgramError> {
+        let unauthorized =
+            || -> ProgramError { anchor_lang::error!(ErrorCode::UnauthorizedUser).into() };
+        let controller = self.controller(ctx)?;
+        if let Some(signer) = controller.signer {
+            return Ok(signer);
+        }
+        let market = *ctx.account(Role::Market)?.key;
+        let (delegate_info, delegate) = match ctx.take_trailing(2).map_err(|_| unauthorized())? {
+            [delegate_info, delegate] => (delegate_info, delegate),
+            _ => unreachable!(),
+        };
+        let record = TradingDelegate::load(ctx.program_id, delegate_info)?;
+        if record.owner != controller.key
+            || record.market != market
+            || record.delegate != *delegate.key
+            || !delegate.is_signer
+        {
+            return Err(unauthorized());
+        }
+        Ok(delegate.clone())
+    }
+
+    /// Returns the amount the open orders PDA must be approved to debit from
+    /// the given Token-2022 payer for its order to be credited `amount`,
+    /// i.e., including the transfer fee of the payer's mint, if any. The
+    /// mint is taken from the trailing accounts.
+    fn amount_with_transfer_fee(
+        ctx: &mut Context,
+        payer: &AccountInfo,
+        amount: u64,
+    ) -> std::result::Result<u64, ProgramError> {
+        let mint = &ctx.take_trailing(1)?[0];
+        let payer_mint = token::accessor::mint(payer).map_err(Into::<ProgramError>::into)?;
+        if mint.owner != &spl_token_2022::ID || mint.key != &payer_mint {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let epoch = Clock::get()?.epoch;
+        transfer_fee_inclusive(&mint.try_borrow_data()?, amount, epoch)
+    }
+
+    /// Replaces the authority of the DEX instruction with the open orders
+    /// account, marked as a signer, after checking it's the open orders PDA
+    /// of the given market and user.
+    fn sign_with_open_orders(
+        &self,
+        ctx: &mut Context,
+        market: &Pubkey,
+        user: &Pubkey,
+    ) -> ProgramResult {
+        let seeds = self.open_orders_seeds(ctx, market, user);
+        let open_orders = ctx.account_index(Role::OpenOrders)?;
+        Self::sign_pda(ctx, open_orders, seeds)?;
+        ctx.insert(EventUser(*user));
+        let authority = ctx.account_index(Role::Authority)?;
+        ctx.accounts.replace(authority, open_orders);
+        Ok(())
+    }
+
+    /// Returns the seeds of the open orders PDA of the given market and user,
+    /// at the index given in the header.
+    fn open_orders_seeds(&self, ctx: &Context, market: &Pubkey, user: &Pubkey) -> SignerSeeds {
+        let (program_id, dex_program) = (ctx.program_id, ctx.dex_program_id);
+        let bump = if self.canonical_bumps {
+            SignerSeeds::open_orders_address(program_id, dex_program, market, user, self.index).1
+        } else {
+            self.bump
+        };
+        SignerSeeds::open_orders_indexed(dex_program, market, user, self.index, bump)
+    }
+    /// Returns a `SettleFunds` of the given user's funds, signed by their
+    /// open orders PDA, given the coin vault, pc vault, coin wallet, pc
+    /// wallet, vault signer and token program accounts, in order.
+    fn settle_funds_instruction<'info>(
+        &self,
+        ctx: &Context<'_, 'info>,
+        market: &AccountInfo<'info>,
+        open_orders: &AccountInfo<'info>,
+        user: &Pubkey,
+        accounts: &[AccountInfo<'info>],
+    ) -> std::result::Result<(Instruction, Vec<AccountInfo<'info>>, Seeds), ProgramError> {
+        let ix = serum_dex::instruction::settle_funds(
+            ctx.dex_program_id,
+            market.key,
+            accounts[5].key,
+            open_orders.key,
+            open_orders.key,
+            accounts[0].key,
+            accounts[2].key,
+            accounts[1].key,
+            accounts[3].key,
+            None,
+            accounts[4].key,
+        )?;
+        let mut acc_infos = vec![market.clone(), open_orders.clone()];
+        acc_infos.extend_from_slice(accounts);
+        let seeds = self.open_orders_seeds(ctx, market.key, user);
+        Ok((ix, acc_infos, vec![seeds]))
+    }
 }
 
-impl MarketMiddleware for OpenOrdersPda {
-    fn instruction(&mut self, data: &mut &[u8]) -> ProgramResult {
-        // Strip the discriminator.
-        let disc = data[0];
-        *data = &data[1..];
+/// Allowance granted to the open orders PDA on top of an order's size, see
+/// `OpenOrdersPda::approval_buffer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApprovalBuffer {
+    /// A flat amount, in native units of the token paying for the order.
+    Flat(u64),
+    /// A share of the order size, in basis points, rounded up.
+    Bps(u16),
+}
+
+impl ApprovalBuffer {
+    /// Returns the given order size with the buffer added.
+    pub fn apply(self, amount: u64) -> u64 {
+        let buffer = match self {
+            ApprovalBuffer::Flat(buffer) => buffer,
+            ApprovalBuffer::Bps(bps) => {
+                let buffer = (amount as u128 * bps as u128).div_ceil(10_000);
+                buffer.min(u64::MAX as u128) as u64
+            }
+        };
+        amount.saturating_add(buffer)
+    }
+}
+
+/// Post callback sweeping the dust left in the user's wallets once their
+/// open orders are closed, see `OpenOrdersPda::sweep_dust`. Only wallets of
+/// the SPL Token program that are controlled by the signer are swept.
+///
+/// Accounts:
+///
+/// 0. Token program.
+/// 1. The wallet controlling the open orders (signer).
+/// 2. The user's coin wallet.
+/// 3. The user's pc wallet.
+/// 4. The destination's coin account, if any.
+/// 5. The destination's pc account, if any.
+///
+/// Args: the market's coin and pc lot sizes.
+fn sweep_dust_callback(
+    _program_id: &Pubkey,
+    accounts: Vec<AccountInfo>,
+    _ix_data: Vec<u8>,
+    args: Vec<u8>,
+    _return_data: Option<Vec<u8>>,
+) -> ProgramResult {
+    let (token_program, owner) = (&accounts[0], &accounts[1]);
+    if token_program.key != &spl_token::ID {
+        return Ok(());
+    }
+    for (idx, lot_size) in args.chunks(8).enumerate() {
+        let lot_size = u64::from_le_bytes(lot_size.try_into().unwrap());
+        let wallet = &accounts[2 + idx];
+        let authority = token::accessor::authority(wallet).map_err(Into::<ProgramError>::into)?;
+        if authority != *owner.key {
+            continue;
+        }
+        let mut amount = token::accessor::amount(wallet).map_err(Into::<ProgramError>::into)?;
+        let dust_account = accounts.get(4 + idx).filter(|_| amount < lot_size);
+        if let Some(dust_account) = dust_account.filter(|_| amount > 0) {
+            let ix = spl_token::instruction::transfer(
+                token_program.key,
+                wallet.key,
+                dust_account.key,
+                owner.key,
+                &[],
+                amount,
+            )?;
+            let acc_infos = [wallet.clone(), dust_account.clone(), owner.clone()];
+            solana_program::program::invoke(&ix, &acc_infos)?;
+            amount = 0;
+        }
+        if amount == 0 {
+            let ix = spl_token::instruction::close_account(
+                token_program.key,
+                wallet.key,
+                owner.key,
+                owner.key,
+                &[],
+            )?;
+            solana_program::program::invoke(&ix, &[wallet.clone(), owner.clone()])?;
+        }
+    }
+    Ok(())
+}
+
+/// The wallet controlling a user's open orders, see
+/// `OpenOrdersPda::migrations`.
+struct Controller<'info> {
+    key: Pubkey,
+    // The wallet's account, if it signed.
+    signer: Option<AccountInfo<'info>>,
+}
+
+impl<'info> Controller<'info> {
+    fn of(wallet: AccountInfo<'info>) -> Self {
+        Self {
+            key: *wallet.key,
+            signer: Some(wallet).filter(|wallet| wallet.is_signer),
+        }
+    }
+}
+
+/// Post callback closing the user's `TradingDelegate` once their open orders
+/// are closed.
+///
+/// Accounts:
+///
+/// 0. The delegate PDA.
+/// 1. The delegate's owner: the user, or the authority they migrated to.
+fn close_delegate_callback(
+    program_id: &Pubkey,
+    accounts: Vec<AccountInfo>,
+    _ix_data: Vec<u8>,
+    _args: Vec<u8>,
+    _return_data: Option<Vec<u8>>,
+) -> ProgramResult {
+    close_delegate(program_id, &accounts[0], accounts[1].key, &accounts[1])
+}
+
+/// Post callback listing the user's new open orders account in their
+/// `OpenOrdersRegistry`.
+///
+/// Accounts:
+///
+/// 0. The registry PDA.
+/// 1. The user.
+/// 2. System program.
+/// 3. Market.
+/// 4. The open orders account.
+fn register_open_orders_callback(
+    program_id: &Pubkey,
+    accounts: Vec<AccountInfo>,
+    _ix_data: Vec<u8>,
+    _args: Vec<u8>,
+    _return_data: Option<Vec<u8>>,
+) -> ProgramResult {
+    let (market, open_orders) = (accounts[3].key, accounts[4].key);
+    register_open_orders(
+        program_id,
+        &accounts[0],
+        &accounts[1],
+        &accounts[2],
+        market,
+        open_orders,
+    )
+}
+
+/// Header data of `OpenOrdersPda`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpenOrdersPdaData {
+    /// Index of the user's open orders account (see
+    /// `SignerSeeds::open_orders_indexed`).
+    pub index: u8,
+}
 
-        // Discriminator == 0 implies it's the init instruction.
-        if disc == 0 {
-            self.bump = data[0];
-            self.bump_init = data[1];
-            *data = &data[2..];
+impl MiddlewareData for OpenOrdersPdaData {
+    /// 0. The index, 0 if the data is empty.
+    fn decode(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        Ok(Self {
+            index: data.first().copied().unwrap_or(0),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        // The first account's index goes without data.
+        match self.index {
+            0 => Vec::new(),
+            index => vec![index],
         }
+    }
+}
+
+impl MarketMiddleware for OpenOrdersPda {
+    fn error_namespace(&self) -> Option<ErrorNamespace> {
+        Some(PROXY_ERROR_NAMESPACE)
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Sign
+    }
+
+    /// Header data: `OpenOrdersPdaData`.
+    fn header(&mut self, header: &ProxyHeader, data: &[u8]) -> ProgramResult {
+        self.bump = header.bumps.open_orders;
+        self.bump_init = header.bumps.open_orders_init;
+        self.index = OpenOrdersPdaData::decode(data)?.index;
         Ok(())
     }
 
@@ -181,47 +877,68 @@ impl MarketMiddleware for OpenOrdersPda {
     /// 1. System program.
     /// .. serum_dex::MarketInstruction::InitOpenOrders.
     ///
-    /// Data:
+    /// Header:
     ///
-    /// 0.   Discriminant.
-    /// 1..2 Borsh(struct { bump: u8, bump_init: u8 }).
-    /// ..
+    /// bumps.open_orders      Bump of the open orders PDA.
+    /// bumps.open_orders_init Bump of the open orders init authority PDA.
+    ///
+    /// Trailing accounts, with canonical bumps:
+    ///
+    /// 0. The market's `MarketBumps` cache.
+    ///
+    /// With `FLAG_PERMISSIONLESS_INIT`, the market authority and the cache
+    /// are omitted.
+    ///
+    /// Followed by, with a registry:
+    ///
+    /// 0. The user's `OpenOrdersRegistry` PDA on the market (writable).
+    /// 1. System program.
     fn init_open_orders<'a, 'info>(&self, ctx: &mut Context<'a, 'info>) -> ProgramResult {
-        let market = &ctx.accounts[4];
-        let user = &ctx.accounts[3];
+        // Strip off the first 2 accounts (dex_program and system_program).
+        ctx.accounts.strip(2)?;
 
-        // Skip first 2 accounts (dex_program and system_program) for validation
-        let remaining_accounts = &ctx.accounts[2..];
-        
         // Validate account structure
-        Self::validate_init_accounts(remaining_accounts)?;
-
-        // Add PDA seeds to context
-        ctx.seeds.push(open_orders_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            authority = user.key,
-            bump = self.bump
-        });
-        
-        ctx.seeds.push(open_orders_init_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            bump = self.bump_init
-        });
-
-        // Update accounts (skip first 2)
-        ctx.accounts = ctx.accounts[2..].to_vec();
-
-        // Set PDAs - make sure we have enough accounts
-        if ctx.accounts.len() > 1 {
-            ctx.accounts[1] = Self::prepare_pda(&ctx.accounts[0]);
+        let permissionless = ctx.header.is_permissionless_init();
+        Self::validate_init_accounts(&ctx.accounts, permissionless)?;
+
+        let market = ctx.account(Role::Market)?.key;
+        let user_info = ctx.user()?.clone();
+        let user = user_info.key;
+
+        // Set PDAs.
+        self.sign_with_open_orders(ctx, market, user)?;
+        if !permissionless {
+            let bump_init = if self.canonical_bumps {
+                let bumps = MarketBumps::load(ctx.program_id, &ctx.take_trailing(1)?[0])?;
+                if bumps.dex_program_id != *ctx.dex_program_id || bumps.market != *market {
+                    return Err(anchor_lang::error!(ErrorCode::InvalidBumpCache).into());
+                }
+                bumps.open_orders_init
+            } else {
+                self.bump_init
+            };
+            let seeds = open_orders_init_authority! {
+                program = ctx.program_id,
+                dex_program = ctx.dex_program_id,
+                market = market,
+                bump = bump_init
+            };
+            let market_authority = ctx.account_index(Role::MarketAuthority)?;
+            Self::sign_pda(ctx, market_authority, seeds)?;
         }
-        
-        if ctx.accounts.len() > 4 {
-            ctx.accounts[4].is_signer = true;
+
+        // Post: List the open orders in the user's registry.
+        if self.registry {
+            let trailing = ctx.take_trailing(2)?;
+            let acc_infos = vec![
+                trailing[0].clone(),
+                user_info,
+                trailing[1].clone(),
+                ctx.account(Role::Market)?.clone(),
+                ctx.open_orders()?.clone(),
+            ];
+            ctx.post_callbacks
+                .push((register_open_orders_callback, acc_infos, Vec::new()));
         }
 
         Ok(())
@@ -231,47 +948,107 @@ impl MarketMiddleware for OpenOrdersPda {
     ///
     /// ..
     ///
-    /// Data:
+    /// Trailing accounts, after any delegate accounts:
     ///
-    /// 0.   Discriminant.
-    /// ..
+    /// 0. The payer's mint, when the payer is a Token-2022 account.
+    ///
+    /// Followed by, when auto settling an IOC order (see `FLAG_AUTO_SETTLE`):
+    ///
+    /// 0. The user's coin wallet.
+    /// 1. The user's pc wallet.
+    /// 2. Vault signer.
+    ///
+    /// Header:
+    ///
+    /// bumps.open_orders Bump of the open orders PDA.
     fn new_order_v3(&self, ctx: &mut Context, ix: &mut NewOrderInstructionV3) -> ProgramResult {
-        // The user must authorize the tx.
-        let user = &ctx.accounts[7];
-        if !user.is_signer {
-            return Err(ProgramError::Custom(ErrorCode::UnauthorizedUser as u32).into());
+        // The user, or their delegate, must authorize the tx. The trader
+        // owns the token account paying for the order.
+        let trader = self.trader(ctx)?;
+        let user = ctx.user()?.clone();
+
+        let market = ctx.account(Role::Market)?.clone();
+        let open_orders = ctx.open_orders()?.clone();
+        let token_account_payer = ctx.account(Role::OrderPayer)?.clone();
+        let token_program = *token_account_payer.owner;
+
+        // The payer must hold the currency the order pays in, which the DEX
+        // would only check once the payer is approved.
+        let order_mint = match ix.side {
+            Side::Bid => ctx.market_state()?.pc_mint,
+            Side::Ask => ctx.market_state()?.coin_mint,
+        };
+        let payer_mint =
+            token::accessor::mint(&token_account_payer).map_err(Into::<ProgramError>::into)?;
+        if payer_mint != order_mint {
+            msg!(
+                "order payer {} must hold {}",
+                token_account_payer.key,
+                order_mint
+            );
+            return Err(anchor_lang::error!(ErrorCode::InvalidPayerMint).into());
         }
 
-        let market = &ctx.accounts[0];
-        let open_orders = &ctx.accounts[1];
-        let token_account_payer = &ctx.accounts[6];
+        // The most the order can debit from the payer, and then some.
+        let amount = match ix.side {
+            Side::Bid => ix.max_native_pc_qty_including_fees.get(),
+            Side::Ask => {
+                let coin_lot_size = ctx.market_state()?.coin_lot_size;
+                ix.max_coin_qty.get().checked_mul(coin_lot_size).unwrap()
+            }
+        };
+        let amount = self
+            .approval_buffer
+            .map_or(amount, |buffer| buffer.apply(amount));
+
+        // Pre: Wrap the SOL the order pays, if any, topping up the payer.
+        let native_sol = self.native_sol
+            && token_program == spl_token::ID
+            && payer_mint == spl_token::native_mint::ID;
+        if native_sol {
+            let balance = token::accessor::amount(&token_account_payer)
+                .map_err(Into::<ProgramError>::into)?;
+            let lamports = amount.saturating_sub(balance);
+            if lamports > 0 {
+                let ix = solana_program::system_instruction::transfer(
+                    trader.key,
+                    token_account_payer.key,
+                    lamports,
+                );
+                let accounts = vec![trader.clone(), token_account_payer.clone()];
+                ctx.pre_instructions.push((ix, accounts, Vec::new()));
+            }
+            let ix = spl_token::instruction::sync_native(&token_program, token_account_payer.key)?;
+            ctx.pre_instructions
+                .push((ix, vec![token_account_payer.clone()], Vec::new()));
+        }
 
         // Pre: Give the PDA delegate access.
         let pre_instruction = {
-            let amount = match ix.side {
-                Side::Bid => ix.max_native_pc_qty_including_fees.get(),
-                Side::Ask => {
-                    // +5 for padding.
-                    let coin_lot_idx = 5 + 43 * 8;
-                    let data = market.try_borrow_data()?;
-                    let mut coin_lot_array = [0u8; 8];
-                    coin_lot_array.copy_from_slice(&data[coin_lot_idx..coin_lot_idx + 8]);
-                    let coin_lot_size = u64::from_le_bytes(coin_lot_array);
-                    ix.max_coin_qty.get().checked_mul(coin_lot_size).unwrap()
-                }
+            let ix = if token_program == spl_token_2022::ID {
+                let amount = Self::amount_with_transfer_fee(ctx, &token_account_payer, amount)?;
+                spl_token_2022::instruction::approve(
+                    &token_program,
+                    token_account_payer.key,
+                    open_orders.key,
+                    trader.key,
+                    &[],
+                    amount,
+                )?
+            } else {
+                spl_token::instruction::approve(
+                    &token_program,
+                    token_account_payer.key,
+                    open_orders.key,
+                    trader.key,
+                    &[],
+                    amount,
+                )?
             };
-            let ix = spl_token::instruction::approve(
-                &spl_token::ID,
-                token_account_payer.key,
-                open_orders.key,
-                user.key,
-                &[],
-                amount,
-            )?;
             let accounts = vec![
                 token_account_payer.clone(),
                 open_orders.clone(),
-                user.clone(),
+                trader.clone(),
             ];
             (ix, accounts, Vec::new())
         };
@@ -279,26 +1056,61 @@ impl MarketMiddleware for OpenOrdersPda {
 
         // Post: Revoke the PDA's delegate access.
         let post_instruction = {
-            let ix = spl_token::instruction::revoke(
-                &spl_token::ID,
-                token_account_payer.key,
-                user.key,
-                &[],
-            )?;
-            let accounts = vec![token_account_payer.clone(), user.clone()];
+            let ix = if token_program == spl_token_2022::ID {
+                spl_token_2022::instruction::revoke(
+                    &token_program,
+                    token_account_payer.key,
+                    trader.key,
+                    &[],
+                )?
+            } else {
+                spl_token::instruction::revoke(
+                    &token_program,
+                    token_account_payer.key,
+                    trader.key,
+                    &[],
+                )?
+            };
+            let accounts = vec![token_account_payer.clone(), trader.clone()];
             (ix, accounts, Vec::new())
         };
         ctx.post_instructions.push(post_instruction);
 
+        // Post: Settle the funds of IOC orders, which rest on the book at no
+        // point, so that the order's proceeds needn't wait for a settle.
+        if ctx.header.is_auto_settle() && ix.order_type == OrderType::ImmediateOrCancel {
+            let (coin_wallet, pc_wallet, vault_signer) = match ctx.take_trailing(3)? {
+                [coin_wallet, pc_wallet, vault_signer] => (coin_wallet, pc_wallet, vault_signer),
+                _ => unreachable!(),
+            };
+            let accounts = [
+                ctx.account(Role::CoinVault)?.clone(),
+                ctx.account(Role::PcVault)?.clone(),
+                coin_wallet.clone(),
+                pc_wallet.clone(),
+                vault_signer.clone(),
+                ctx.account(Role::TokenProgram)?.clone(),
+            ];
+            let settle =
+                self.settle_funds_instruction(ctx, &market, &open_orders, user.key, &accounts)?;
+            ctx.post_instructions.push(settle);
+        }
+
+        // Post: Unwrap the SOL left in the payer.
+        if native_sol {
+            let ix = spl_token::instruction::close_account(
+                &token_program,
+                token_account_payer.key,
+                trader.key,
+                trader.key,
+                &[],
+            )?;
+            let accounts = vec![token_account_payer.clone(), trader.clone()];
+            ctx.post_instructions.push((ix, accounts, Vec::new()));
+        }
+
         // Proxy: PDA must sign the new order.
-        ctx.seeds.push(open_orders_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            authority = user.key,
-            bump = self.bump
-        });
-        ctx.accounts[7] = Self::prepare_pda(open_orders);
+        self.sign_with_open_orders(ctx, market.key, user.key)?;
 
         Ok(())
     }
@@ -307,210 +1119,774 @@ impl MarketMiddleware for OpenOrdersPda {
     ///
     /// ..
     ///
-    /// Data:
+    /// Header:
     ///
-    /// 0.   Discriminant.
-    /// ..
+    /// bumps.open_orders Bump of the open orders PDA.
     fn cancel_order_v2(
         &self,
         ctx: &mut Context,
         _ix: &mut CancelOrderInstructionV2,
     ) -> ProgramResult {
-        let market = &ctx.accounts[0];
-        let user = &ctx.accounts[4];
-        if !user.is_signer {
-            return Err(ProgramError::Custom(ErrorCode::UnauthorizedUser as u32).into());
-        }
-
-        ctx.seeds.push(open_orders_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            authority = user.key,
-            bump = self.bump
-        });
-
-        ctx.accounts[4] = Self::prepare_pda(&ctx.accounts[3]);
-
-        Ok(())
+        self.sign_as_trader(ctx)
     }
 
     /// Accounts:
     ///
     /// ..
     ///
-    /// Data:
+    /// Header:
     ///
-    /// 0.   Discriminant.
-    /// ..
+    /// bumps.open_orders Bump of the open orders PDA.
     fn cancel_order_by_client_id_v2(
         &self,
         ctx: &mut Context,
         _client_id: &mut u64,
     ) -> ProgramResult {
-        let market = &ctx.accounts[0];
-        let user = &ctx.accounts[4];
-        if !user.is_signer {
-            return Err(ProgramError::Custom(ErrorCode::UnauthorizedUser as u32).into());
-        }
-
-        ctx.seeds.push(open_orders_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            authority = user.key,
-            bump = self.bump
-        });
-
-        ctx.accounts[4] = Self::prepare_pda(&ctx.accounts[3]);
-
-        Ok(())
+        self.sign_as_trader(ctx)
     }
 
     /// Accounts:
     ///
     /// ..
     ///
-    /// Data:
+    /// Header:
     ///
-    /// 0.   Discriminant.
-    /// ..
+    /// bumps.open_orders Bump of the open orders PDA.
+    ///
+    /// The user needn't sign with `permissionless_settle`, in which case the
+    /// coin and pc wallets must be owned by the wallet controlling their
+    /// open orders.
     fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
-        let market = &ctx.accounts[0];
-        let user = &ctx.accounts[2];
-        if !user.is_signer {
-            return Err(ProgramError::Custom(ErrorCode::UnauthorizedUser as u32).into());
-        }
-
-        ctx.seeds.push(open_orders_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            authority = user.key,
-            bump = self.bump
-        });
-
-        ctx.accounts[2] = Self::prepare_pda(&ctx.accounts[1]);
-
-        Ok(())
+        if !self.permissionless_settle {
+            return self.sign_as_user(ctx);
+        }
+        let controller = self.controller(ctx)?;
+        if controller.signer.is_none() {
+            for role in [Role::CoinWallet, Role::PcWallet].iter() {
+                let wallet = ctx.account(*role)?;
+                let authority =
+                    token::accessor::authority(wallet).map_err(Into::<ProgramError>::into)?;
+                if authority != controller.key {
+                    msg!(
+                        "{:?} account {} isn't owned by {}",
+                        role,
+                        wallet.key,
+                        controller.key
+                    );
+                    return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+                }
+            }
+        }
+        let market = ctx.account(Role::Market)?.key;
+        let user = ctx.user()?.key;
+        self.sign_with_open_orders(ctx, market, user)
     }
 
     /// Accounts:
     ///
     /// ..
     ///
-    /// Data:
+    /// Header:
     ///
-    /// 0.   Discriminant.
-    /// ..
+    /// bumps.open_orders Bump of the open orders PDA.
+    ///
+    /// Trailing accounts, when sweeping on close:
+    ///
+    /// 0. Coin vault.
+    /// 1. Pc vault.
+    /// 2. The user's coin wallet.
+    /// 3. The user's pc wallet.
+    /// 4. Vault signer.
+    /// 5. Token program.
+    /// 6. The `TradingDelegate` PDA on the market of the user (or of the
+    ///    authority they migrated to), closed if it exists.
+    ///
+    /// Followed by, when sweeping dust into a destination:
+    ///
+    /// 0. The destination's coin account.
+    /// 1. The destination's pc account.
+    ///
+    /// The rent of the open orders account is always returned to the user,
+    /// whatever the destination given, unless they migrated to another
+    /// authority, which must then be the destination.
     fn close_open_orders(&self, ctx: &mut Context) -> ProgramResult {
-        let market = &ctx.accounts[3];
-        let user = &ctx.accounts[1];
-        if !user.is_signer {
-            return Err(ProgramError::Custom(ErrorCode::UnauthorizedUser as u32).into());
-        }
-
-        ctx.seeds.push(open_orders_authority! {
-            program = ctx.program_id,
-            dex_program = ctx.dex_program_id,
-            market = market.key,
-            authority = user.key,
-            bump = self.bump
-        });
+        let controller = self.signed_controller(ctx)?;
+        let user = ctx.user()?.clone();
+        let market = ctx.account(Role::Market)?.clone();
+
+        // An authority the user migrated to isn't among the DEX accounts, so
+        // it can only be given as the destination.
+        let destination = ctx.account_index(Role::Destination)?;
+        if controller.key == user.key {
+            let user = ctx.account_index(Role::Authority)?;
+            ctx.accounts.replace(destination, user);
+        } else if ctx.accounts[destination].key != controller.key {
+            return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+        }
 
-        ctx.accounts[1] = Self::prepare_pda(&ctx.accounts[0]);
+        if self.sweep_on_close {
+            let open_orders = ctx.open_orders()?.clone();
+            let accounts = ctx.take_trailing(7)?;
 
-        Ok(())
+            // Pre: Settle the residual funds into the user's wallets.
+            let settle_accounts = &accounts[..6];
+            let settle = self.settle_funds_instruction(
+                ctx,
+                &market,
+                &open_orders,
+                user.key,
+                settle_accounts,
+            )?;
+            ctx.pre_instructions.push(settle);
+
+            // Post: Close the delegate, if one was registered.
+            let delegate_info = &accounts[6];
+            if delegate_info.owner == ctx.program_id {
+                let acc_infos = vec![delegate_info.clone(), controller.clone()];
+                ctx.post_callbacks
+                    .push((close_delegate_callback, acc_infos, Vec::new()));
+            }
+
+            // Post: Sweep the dust left in the wallets, once settled.
+            if self.sweep_dust {
+                let dust_accounts = match self.dust_destination {
+                    Some(destination) => {
+                        let dust_accounts = ctx.take_trailing(2)?;
+                        for acc in dust_accounts {
+                            let owner = token::accessor::authority(acc)
+                                .map_err(Into::<ProgramError>::into)?;
+                            if owner != destination {
+                                return Err(
+                                    anchor_lang::error!(ErrorCode::InvalidAccountOwner).into()
+                                );
+                            }
+                        }
+                        dust_accounts
+                    }
+                    None => &[][..],
+                };
+                let market_state = ctx.market_state()?;
+                let mut args = market_state.coin_lot_size.to_le_bytes().to_vec();
+                args.extend_from_slice(&market_state.pc_lot_size.to_le_bytes());
+                let mut acc_infos = vec![
+                    accounts[5].clone(),
+                    controller,
+                    accounts[2].clone(),
+                    accounts[3].clone(),
+                ];
+                acc_infos.extend_from_slice(dust_accounts);
+                ctx.post_callbacks
+                    .push((sweep_dust_callback, acc_infos, args));
+            }
+        }
+
+        self.sign_with_open_orders(ctx, market.key, user.key)
     }
 
     /// Accounts:
     ///
     /// ..
     ///
-    /// Data:
+    /// Header:
     ///
-    /// 0.   Discriminant.
-    /// ..
+    /// bumps.open_orders Bump of the open orders PDA.
     fn prune(&self, ctx: &mut Context, _limit: &mut u16) -> ProgramResult {
         // Set owner of open orders to be itself.
-        ctx.accounts[5] = ctx.accounts[4].clone();
+        let open_orders = ctx.account_index(Role::OpenOrders)?;
+        let owner = ctx.account_index(Role::Authority)?;
+        ctx.accounts.replace(owner, open_orders);
         Ok(())
     }
 }
 
-/// Logs each request.
-pub struct Logger;
+/// Logs each request, as text or, with `events`, as `RequestLogged` events.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Logger {
+    events: bool,
+    level: LogLevel,
+    // Bit set of the `InstructionKind`s not logged.
+    muted: u16,
+    redacted: bool,
+}
 
-impl MarketMiddleware for Logger {
-    fn init_open_orders(&self, _ctx: &mut Context) -> ProgramResult {
-        msg!("proxying open orders");
-        Ok(())
+/// How much `Logger` logs of each request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    /// The instruction, without its arguments, e.g., for production
+    /// deployments, sparing the compute units formatting them takes.
+    Info,
+    /// The instruction and its arguments.
+    #[default]
+    Debug,
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn new_order_v3(&self, _ctx: &mut Context, ix: &mut NewOrderInstructionV3) -> ProgramResult {
-        msg!("proxying new order v3 {:?}", ix);
-        Ok(())
+    /// Builder method for logging requests as typed `RequestLogged` events
+    /// instead of text, so that indexers parse the logs.
+    pub fn events(mut self) -> Self {
+        self.events = true;
+        self
     }
 
-    fn cancel_order_v2(
-        &self,
-        _ctx: &mut Context,
-        ix: &mut CancelOrderInstructionV2,
-    ) -> ProgramResult {
-        msg!("proxying cancel order v2 {:?}", ix);
-        Ok(())
+    /// Builder method for setting the verbosity, `LogLevel::Debug` by
+    /// default.
+    pub fn level(mut self, level: LogLevel) -> Self {
+        self.level = level;
+        self
     }
 
-    fn cancel_order_by_client_id_v2(
-        &self,
-        _ctx: &mut Context,
-        client_id: &mut u64,
-    ) -> ProgramResult {
-        msg!("proxying cancel order by client id v2 {:?}", client_id);
-        Ok(())
+    /// Builder method for not logging requests of the given kind, e.g.,
+    /// frequent crank instructions.
+    pub fn mute(mut self, kind: InstructionKind) -> Self {
+        self.muted |= 1 << kind as u16;
+        self
     }
 
-    fn settle_funds(&self, _ctx: &mut Context) -> ProgramResult {
-        msg!("proxying settle funds");
-        Ok(())
+    /// Builder method for logging pseudonyms of users and client order ids,
+    /// i.e., their hashes salted with the market, in place of the actual
+    /// values, so that venues keep operational logs without publishing who
+    /// trades what. Pseudonyms are stable within a market, so whoever knows a
+    /// user's key can still recognize the user's requests.
+    pub fn redacted(mut self) -> Self {
+        self.redacted = true;
+        self
     }
 
-    fn close_open_orders(&self, _ctx: &mut Context) -> ProgramResult {
-        msg!("proxying close open orders");
+    // Returns the given client order id, or its pseudonym when redacted.
+    fn client_order_id(&self, ctx: &Context, client_order_id: u64) -> u64 {
+        if !self.redacted {
+            return client_order_id;
+        }
+        let hash = pseudonym(ctx, &client_order_id.to_le_bytes());
+        u64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+
+    // Logs the request, named `name` in text, along with its arguments at the
+    // debug level.
+    fn log(
+        &self,
+        ctx: &Context,
+        kind: Option<InstructionKind>,
+        name: &str,
+        args: Option<&dyn std::fmt::Debug>,
+        event_args: impl FnOnce(&mut RequestLogged),
+    ) {
+        let muted = match kind {
+            Some(kind) => self.muted & (1 << kind as u16) != 0,
+            None => false,
+        };
+        if muted || self.level == LogLevel::Off {
+            return;
+        }
+        let debug = self.level == LogLevel::Debug;
+        if self.events {
+            let mut event = RequestLogged::of(ctx);
+            if self.redacted {
+                let user = event.user.map(|user| pseudonym(ctx, user.as_ref()));
+                event.user = user.map(Pubkey::new_from_array);
+            }
+            if debug {
+                event_args(&mut event);
+            }
+            emit!(event);
+            return;
+        }
+        match args.filter(|_| debug) {
+            Some(args) => msg!("proxying {} {:?}", name, args),
+            None => msg!("proxying {}", name),
+        }
+    }
+}
+
+impl MarketMiddleware for Logger {
+    fn stage(&self) -> Stage {
+        Stage::Observe
+    }
+
+    fn init_open_orders(&self, ctx: &mut Context) -> ProgramResult {
+        let kind = Some(InstructionKind::InitOpenOrders);
+        self.log(ctx, kind, "open orders", None, |_| {});
+        Ok(())
+    }
+
+    fn new_order_v3(&self, ctx: &mut Context, ix: &mut NewOrderInstructionV3) -> ProgramResult {
+        let kind = Some(InstructionKind::NewOrderV3);
+        let client_order_id = self.client_order_id(ctx, ix.client_order_id);
+        let redacted;
+        let args: &dyn std::fmt::Debug = if self.redacted {
+            redacted = NewOrderInstructionV3 {
+                client_order_id,
+                ..ix.clone()
+            };
+            &redacted
+        } else {
+            ix
+        };
+        self.log(ctx, kind, "new order v3", Some(args), |event| {
+            event.side = Some(ix.side as u8);
+            event.limit_price = Some(ix.limit_price.get());
+            event.max_coin_qty = Some(ix.max_coin_qty.get());
+            event.client_order_id = Some(client_order_id);
+        });
+        Ok(())
+    }
+
+    fn cancel_order_v2(
+        &self,
+        ctx: &mut Context,
+        ix: &mut CancelOrderInstructionV2,
+    ) -> ProgramResult {
+        let kind = Some(InstructionKind::CancelOrderV2);
+        self.log(ctx, kind, "cancel order v2", Some(ix), |event| {
+            event.side = Some(ix.side as u8);
+            event.order_id = Some(ix.order_id);
+        });
+        Ok(())
+    }
+
+    fn cancel_order_by_client_id_v2(
+        &self,
+        ctx: &mut Context,
+        client_id: &mut u64,
+    ) -> ProgramResult {
+        let kind = Some(InstructionKind::CancelOrderByClientIdV2);
+        let client_id = self.client_order_id(ctx, *client_id);
+        self.log(
+            ctx,
+            kind,
+            "cancel order by client id v2",
+            Some(&client_id),
+            |event| event.client_order_id = Some(client_id),
+        );
+        Ok(())
+    }
+
+    fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
+        let kind = Some(InstructionKind::SettleFunds);
+        self.log(ctx, kind, "settle funds", None, |_| {});
+        Ok(())
+    }
+
+    fn close_open_orders(&self, ctx: &mut Context) -> ProgramResult {
+        let kind = Some(InstructionKind::CloseOpenOrders);
+        self.log(ctx, kind, "close open orders", None, |_| {});
+        Ok(())
+    }
+
+    fn consume_events(&self, ctx: &mut Context, limit: &mut u16) -> ProgramResult {
+        let kind = Some(InstructionKind::ConsumeEvents);
+        self.log(ctx, kind, "consume events", Some(limit), |event| {
+            event.limit = Some(*limit)
+        });
+        Ok(())
+    }
+
+    fn consume_events_permissioned(&self, ctx: &mut Context, limit: &mut u16) -> ProgramResult {
+        let kind = Some(InstructionKind::ConsumeEventsPermissioned);
+        self.log(
+            ctx,
+            kind,
+            "consume events permissioned",
+            Some(limit),
+            |event| event.limit = Some(*limit),
+        );
+        Ok(())
+    }
+
+    fn prune(&self, ctx: &mut Context, limit: &mut u16) -> ProgramResult {
+        let kind = Some(InstructionKind::Prune);
+        self.log(ctx, kind, "prune", Some(limit), |event| {
+            event.limit = Some(*limit)
+        });
+        Ok(())
+    }
+
+    fn expire_orders(&self, ctx: &mut Context, limit: &mut u16) -> ProgramResult {
+        let kind = Some(InstructionKind::ExpireOrders);
+        self.log(ctx, kind, "expire orders", Some(limit), |event| {
+            event.limit = Some(*limit)
+        });
+        Ok(())
+    }
+
+    fn fallback(&self, ctx: &mut Context) -> ProgramResult {
+        self.log(ctx, None, "unknown instruction", None, |_| {});
+        Ok(())
+    }
+}
+
+// Returns the hash of the given value salted with the request's market.
+fn pseudonym(ctx: &Context, value: &[u8]) -> [u8; 32] {
+    let market = ctx
+        .account(Role::Market)
+        .map(|market| market.key.to_bytes());
+    solana_program::hash::hashv(&[&market.unwrap_or_default(), value]).to_bytes()
+}
+
+/// Profiles the compute units consumed by the proxy: logs, at each of its
+/// hooks, the units consumed since the previous profiler hook of the request,
+/// so that a profiler added first in each stage of the pipeline, e.g.,
+/// `CuProfiler::new(Stage::Sign)`, breaks the request down by stage, and its
+/// `after_cpi` hook reports the units the DEX consumed.
+pub struct CuProfiler {
+    stage: Stage,
+}
+
+// Compute units remaining at the last `CuProfiler` hook of the request.
+struct CuSample {
+    label: &'static str,
+    remaining: u64,
+}
+
+impl CuProfiler {
+    pub fn new(stage: Stage) -> Self {
+        Self { stage }
+    }
+
+    // Samples the remaining compute units at the profiler's stage.
+    fn sample_stage(&self, ctx: &mut Context) -> ProgramResult {
+        let label = match self.stage {
+            Stage::Auth => "auth stage",
+            Stage::Default => "default stage",
+            Stage::Sign => "sign stage",
+            Stage::Observe => "observe stage",
+        };
+        Self::sample(ctx, label);
+        Ok(())
+    }
+
+    // Samples the remaining compute units, logging the units consumed since
+    // the previous sample. Consecutive samples of the same label, e.g., by the
+    // `before_cpi` hooks of several profilers, are taken once.
+    fn sample(ctx: &mut Context, label: &'static str) {
+        if ctx.get::<CuSample>().map(|sample| sample.label) == Some(label) {
+            return;
+        }
+        let remaining = solana_program::compute_units::sol_remaining_compute_units();
+        match ctx.insert(CuSample { label, remaining }) {
+            Some(previous) => msg!(
+                "{}: {} compute units since {}",
+                label,
+                previous.remaining.saturating_sub(remaining),
+                previous.label
+            ),
+            None => msg!("{}: {} compute units remaining", label, remaining),
+        }
+    }
+}
+
+impl MarketMiddleware for CuProfiler {
+    fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    fn init_open_orders(&self, ctx: &mut Context) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn new_order_v3(&self, ctx: &mut Context, _ix: &mut NewOrderInstructionV3) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn cancel_order_v2(
+        &self,
+        ctx: &mut Context,
+        _ix: &mut CancelOrderInstructionV2,
+    ) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn cancel_order_by_client_id_v2(
+        &self,
+        ctx: &mut Context,
+        _client_id: &mut u64,
+    ) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn close_open_orders(&self, ctx: &mut Context) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn consume_events(&self, ctx: &mut Context, _limit: &mut u16) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn consume_events_permissioned(&self, ctx: &mut Context, _limit: &mut u16) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn prune(&self, ctx: &mut Context, _limit: &mut u16) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn expire_orders(&self, ctx: &mut Context, _limit: &mut u16) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn fallback(&self, ctx: &mut Context) -> ProgramResult {
+        self.sample_stage(ctx)
+    }
+
+    fn before_cpi(&self, ctx: &mut Context) -> ProgramResult {
+        Self::sample(ctx, "before cpi");
         Ok(())
     }
 
-    fn prune(&self, _ctx: &mut Context, limit: &mut u16) -> ProgramResult {
-        msg!("proxying prune {:?}", limit);
+    fn after_cpi(
+        &self,
+        ctx: &mut Context,
+        _result: &ProgramResult,
+        _return_data: Option<&[u8]>,
+    ) -> ProgramResult {
+        Self::sample(ctx, "dex cpi");
         Ok(())
     }
 }
 
 /// Enforces referral fees being sent to the configured address.
 pub struct ReferralFees {
-    referral: Pubkey,
+    allowed: AllowedReferrals,
+    treasury: bool,
+    proxy_fee: bool,
+    // The referral code of the request, with `codes`.
+    code: Option<[u8; 8]>,
+}
+
+// The referrals `ReferralFees` lets settle.
+enum AllowedReferrals {
+    Key(Pubkey),
+    Set,
+    Codes,
 }
 
 impl ReferralFees {
     pub fn new(referral: Pubkey) -> Self {
-        Self { referral }
+        Self::allowing(AllowedReferrals::Key(referral))
+    }
+
+    /// Enforces referral fees being sent to any referral of the program's
+    /// `ReferralSet`, managed by the config admin, so that several frontends
+    /// can settle through the same proxy. The set PDA is appended as a
+    /// trailing account to `SettleFunds` requests.
+    pub fn allowed_set() -> Self {
+        Self::allowing(AllowedReferrals::Set)
+    }
+
+    /// Enforces referral fees being sent to the referral registered by the
+    /// frontend under the `ReferralCode` given in the header, so that
+    /// frontends register with the proxy rather than with its deployment.
+    /// The code PDA is appended as a trailing account to `SettleFunds`
+    /// requests.
+    pub fn codes() -> Self {
+        Self::allowing(AllowedReferrals::Codes)
+    }
+
+    fn allowing(allowed: AllowedReferrals) -> Self {
+        Self {
+            allowed,
+            treasury: false,
+            proxy_fee: false,
+            code: None,
+        }
+    }
+
+    /// Builder method for sending referral fees into the referral treasury,
+    /// i.e., token accounts of the program's treasury PDA, and accruing them
+    /// to the allowed referrer's `ReferralBalance`, appended as a trailing
+    /// account, so that referrers claim their fees with
+    /// `claim_referral_fees` rather than receive them in a live wallet. With
+    /// `codes`, the referrer is the frontend holding the code.
+    pub fn treasury(mut self) -> Self {
+        self.treasury = true;
+        self
+    }
+
+    /// Builder method for taking the proxy's fee, `fee_bps` of the
+    /// `ProxyConfig`, out of the referral fees accrued with the `treasury`,
+    /// accruing it to the config's `fee_receiver`, who claims it like any
+    /// referrer.
+    pub fn proxy_fee(mut self) -> Self {
+        self.treasury = true;
+        self.proxy_fee = true;
+        self
+    }
+
+    /// Loads the `ReferralCode` of the request from the trailing accounts.
+    fn referral_code(&self, ctx: &mut Context) -> std::result::Result<ReferralCode, ProgramError> {
+        let record = ReferralCode::load(ctx.program_id, &ctx.take_trailing(1)?[0])?;
+        if self.code != Some(record.code) {
+            return Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+        }
+        Ok(record)
+    }
+}
+
+/// Header data of `ReferralFees` with `codes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReferralFeesData {
+    /// The frontend's referral code.
+    pub code: Option<[u8; 8]>,
+}
+
+impl MiddlewareData for ReferralFeesData {
+    /// 0..8. The code, none if the data is shorter.
+    fn decode(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        Ok(Self {
+            code: data.get(..8).map(|code| code.try_into().unwrap()),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.code.map(|code| code.to_vec()).unwrap_or_default()
     }
 }
 
 impl MarketMiddleware for ReferralFees {
+    fn error_namespace(&self) -> Option<ErrorNamespace> {
+        Some(PROXY_ERROR_NAMESPACE)
+    }
+
+    /// Header data, with `codes`: `ReferralFeesData`.
+    fn header(&mut self, _header: &ProxyHeader, data: &[u8]) -> ProgramResult {
+        self.code = ReferralFeesData::decode(data)?.code;
+        Ok(())
+    }
+
     /// Accounts:
     ///
     /// .. serum_dex::MarketInstruction::SettleFunds.
+    ///
+    /// Trailing accounts, with the `ReferralSet` or `codes`:
+    ///
+    /// 0. Set PDA, or the `ReferralCode` PDA of the header's code.
+    ///
+    /// Followed by, with the treasury:
+    ///
+    /// 0. The referrer's `ReferralBalance` PDA in the referral's mint
+    ///    (writable).
+    ///
+    /// Followed by, with the proxy fee:
+    ///
+    /// 0. The `ProxyConfig` PDA.
+    /// 1. The fee receiver's `ReferralBalance` PDA in the referral's mint
+    ///    (writable).
     fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
-        let referral = token::accessor::authority(&ctx.accounts[9])
-            .map_err(|e| Into::<ProgramError>::into(e))?;
-        if referral != self.referral {
-            return Err(ProgramError::Custom(ErrorCode::InvalidReferral as u32).into());
+        let referral_info = ctx.account(Role::Referral)?.clone();
+        let authority =
+            token::accessor::authority(&referral_info).map_err(Into::<ProgramError>::into)?;
+        let referrers = match self.allowed {
+            AllowedReferrals::Key(referral) => vec![referral],
+            AllowedReferrals::Set => {
+                ReferralSet::load(ctx.program_id, &ctx.take_trailing(1)?[0])?.referrals
+            }
+            AllowedReferrals::Codes => {
+                let record = self.referral_code(ctx)?;
+                if !self.treasury {
+                    // Codes resolve the referral account itself.
+                    if referral_info.key != &record.referral {
+                        return Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+                    }
+                    return Ok(());
+                }
+                vec![record.owner]
+            }
+        };
+        if !self.treasury {
+            if !referrers.contains(&authority) {
+                return Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+            }
+            return Ok(());
+        }
+
+        // Post: Accrue what the treasury receives to the referrer.
+        let balance_info = ctx.take_trailing(1)?[0].clone();
+        let balance = ReferralBalance::load(ctx.program_id, &balance_info)?;
+        let mint = token::accessor::mint(&referral_info).map_err(Into::<ProgramError>::into)?;
+        if authority != referral_treasury_address(ctx.program_id).0
+            || balance.mint != mint
+            || !referrers.contains(&balance.referrer)
+        {
+            return Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+        }
+        let previous =
+            token::accessor::amount(&referral_info).map_err(Into::<ProgramError>::into)?;
+        let mut acc_infos = vec![balance_info, referral_info];
+        let mut args = previous.to_le_bytes().to_vec();
+        if self.proxy_fee {
+            let trailing = ctx.take_trailing(2)?;
+            let config = ProxyConfig::load(ctx.program_id, &trailing[0])?;
+            let fee_balance = ReferralBalance::load(ctx.program_id, &trailing[1])?;
+            if fee_balance.referrer != config.fee_receiver || fee_balance.mint != mint {
+                return Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+            }
+            acc_infos.push(trailing[1].clone());
+            args.extend_from_slice(&config.fee_bps.to_le_bytes());
         }
+        ctx.post_callbacks
+            .push((credit_referral_fees_callback, acc_infos, args));
         Ok(())
     }
 }
 
+/// Post callback accruing the referral fees the treasury received to the
+/// referrer's `ReferralBalance`.
+///
+/// Accounts:
+///
+/// 0. The balance PDA.
+/// 1. The treasury token account.
+/// 2. The fee receiver's balance PDA, with the proxy fee.
+///
+/// Args: the treasury's balance before the relay, followed by the proxy's
+/// fee in basis points, with the proxy fee.
+fn credit_referral_fees_callback(
+    program_id: &Pubkey,
+    accounts: Vec<AccountInfo>,
+    _ix_data: Vec<u8>,
+    args: Vec<u8>,
+    _return_data: Option<Vec<u8>>,
+) -> ProgramResult {
+    let previous = u64::from_le_bytes(args[..8].try_into().unwrap());
+    let proxy_fee = accounts.get(2).map(|fee_balance| {
+        (
+            fee_balance,
+            u16::from_le_bytes(args[8..].try_into().unwrap()),
+        )
+    });
+    credit_referral_fees(program_id, &accounts[0], &accounts[1], previous, proxy_fee)
+}
+
+/// Returns `amount` grossed up by the transfer fee the given Token-2022
+/// mint charges in the given epoch, so that the recipient of a transfer of
+/// the returned amount is credited `amount`.
+fn transfer_fee_inclusive(
+    mint_data: &[u8],
+    amount: u64,
+    epoch: u64,
+) -> std::result::Result<u64, ProgramError> {
+    let mint = StateWithExtensions::<Mint>::unpack(mint_data)?;
+    let fee = match mint.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .get_epoch_fee(epoch)
+            .calculate_inverse_fee(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+        Err(_) => 0,
+    };
+    amount
+        .checked_add(fee)
+        .ok_or(ProgramError::ArithmeticOverflow)
+}
+
 // Macros.
 
 /// Returns the seeds used for a user's open orders account PDA.
@@ -523,13 +1899,7 @@ macro_rules! open_orders_authority {
         authority = $authority:expr,
         bump = $bump:expr
     ) => {
-        vec![
-            b"open-orders".to_vec(),
-            $dex_program.as_ref().to_vec(),
-            $market.as_ref().to_vec(),
-            $authority.as_ref().to_vec(),
-            vec![$bump],
-        ]
+        $crate::SignerSeeds::open_orders(&$dex_program, &$market, &$authority, $bump)
     };
     (
         program = $program:expr,
@@ -537,24 +1907,21 @@ macro_rules! open_orders_authority {
         market = $market:expr,
         authority = $authority:expr
     ) => {
-        vec![
-            b"open-orders".to_vec(),
-            $dex_program.as_ref().to_vec(),
-            $market.as_ref().to_vec(),
-            $authority.as_ref().to_vec(),
-            vec![
-                Pubkey::find_program_address(
-                    &[
-                        b"open-orders".as_ref(),
-                        $dex_program.as_ref(),
-                        $market.as_ref(),
-                        $authority.as_ref(),
-                    ],
-                    $program,
-                )
-                .1,
-            ],
-        ]
+        $crate::SignerSeeds::open_orders(
+            &$dex_program,
+            &$market,
+            &$authority,
+            Pubkey::find_program_address(
+                &[
+                    b"open-orders".as_ref(),
+                    $dex_program.as_ref(),
+                    $market.as_ref(),
+                    $authority.as_ref(),
+                ],
+                $program,
+            )
+            .1,
+        )
     };
 }
 
@@ -569,12 +1936,7 @@ macro_rules! open_orders_init_authority {
         market = $market:expr,
         bump = $bump:expr
     ) => {
-        vec![
-            b"open-orders-init".to_vec(),
-            $dex_program.as_ref().to_vec(),
-            $market.as_ref().to_vec(),
-            vec![$bump],
-        ]
+        $crate::SignerSeeds::open_orders_init(&$dex_program, &$market, $bump)
     };
 }
 
@@ -596,6 +1958,50 @@ pub enum ErrorCode {
     NotEnoughAccounts,
     #[msg("Invalid target program ID")]
     InvalidTargetProgram,
+    #[msg("The account role isn't part of the instruction's account layout")]
+    InvalidAccountRole,
+    #[msg("Could not deserialize the proxy header")]
+    InvalidProxyHeader,
+    #[msg("Unsupported proxy header version")]
+    UnsupportedProxyHeaderVersion,
+    #[msg("An account required to sign the instruction didn't")]
+    AccountNotSigner,
+    #[msg("An account required to be writable wasn't")]
+    AccountNotWritable,
+    #[msg("An account isn't owned by the expected program")]
+    InvalidAccountOwner,
+    #[msg("Invalid proxy config")]
+    InvalidConfig,
+    #[msg("Middleware aren't ordered by stage")]
+    InvalidMiddlewareOrder,
+    #[msg("Invalid market account")]
+    InvalidMarket,
+    #[msg("Middleware queued more instructions than the CPI budget allows")]
+    CpiBudgetExceeded,
+    #[msg("Invalid trading delegate account")]
+    InvalidDelegate,
+    #[msg("Invalid market bumps cache account")]
+    InvalidBumpCache,
+    #[msg("Invalid authority migration account")]
+    InvalidMigration,
+    #[msg("Order payer doesn't hold the currency the order pays in")]
+    InvalidPayerMint,
+    #[msg("Invalid open orders registry account")]
+    InvalidRegistry,
+    #[msg("Invalid referral set account")]
+    InvalidReferralSet,
+    #[msg("Invalid referral balance account")]
+    InvalidReferralBalance,
+    #[msg("Invalid referral code account")]
+    InvalidReferralCode,
+    #[msg("Invalid bids or asks account")]
+    InvalidOrderBook,
+    #[msg("Invalid event queue account")]
+    InvalidEventQueue,
+    #[msg("Invalid market metadata account")]
+    InvalidMarketMetadata,
+    #[msg("The proxy is paused")]
+    ProxyPaused,
 }
 
 // Constants.
@@ -608,13 +2014,18 @@ pub enum ErrorCode {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use solana_program::pubkey::Pubkey;
+    use crate::Bumps;
     use solana_program::account_info::AccountInfo;
     use solana_program::clock::Epoch;
+    use solana_program::pubkey::Pubkey;
     use std::convert::TryInto;
 
     fn dummy_account(is_signer: bool) -> AccountInfo<'static> {
-        let key = Box::leak(Box::new(Pubkey::new_unique()));
+        keyed_account(Pubkey::new_unique(), is_signer)
+    }
+
+    fn keyed_account(key: Pubkey, is_signer: bool) -> AccountInfo<'static> {
+        let key = Box::leak(Box::new(key));
         let owner = Box::leak(Box::new(Pubkey::default()));
         let lamports = Box::leak(Box::new(0u64));
         let data = Box::leak(Vec::new().into_boxed_slice());
@@ -630,52 +2041,768 @@ mod tests {
         )
     }
 
+    fn market_account(
+        key: Pubkey,
+        dex_program_id: Pubkey,
+        [coin_mint, pc_mint]: [Pubkey; 2],
+        [coin_lot_size, pc_lot_size]: [u64; 2],
+    ) -> AccountInfo<'static> {
+        use serum_dex::state::{AccountFlag, MarketState};
+
+        let mut data = serum_dex::state::ACCOUNT_HEAD_PADDING.to_vec();
+        data.resize(5 + std::mem::size_of::<MarketState>(), 0);
+        data.extend_from_slice(serum_dex::state::ACCOUNT_TAIL_PADDING);
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::Market as u64;
+        data[5..13].copy_from_slice(&flags.to_le_bytes());
+        data[13..45].copy_from_slice(key.as_ref());
+        data[5 + 6 * 8..5 + 10 * 8].copy_from_slice(coin_mint.as_ref());
+        data[5 + 10 * 8..5 + 14 * 8].copy_from_slice(pc_mint.as_ref());
+        data[5 + 43 * 8..5 + 44 * 8].copy_from_slice(&coin_lot_size.to_le_bytes());
+        data[5 + 44 * 8..5 + 45 * 8].copy_from_slice(&pc_lot_size.to_le_bytes());
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(data.into_boxed_slice()),
+            Box::leak(Box::new(dex_program_id)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    fn token_account(owner: Pubkey) -> AccountInfo<'static> {
+        let mut data = vec![0u8; 165];
+        data[32..64].copy_from_slice(owner.as_ref());
+        AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(data.into_boxed_slice()),
+            Box::leak(Box::new(spl_token::ID)),
+            false,
+            Epoch::default(),
+        )
+    }
+
     #[test]
-    fn test_instruction_parsing() {
+    fn test_header_parsing() {
         let mut pda = OpenOrdersPda::new();
-        let mut data: &[u8] = &[0, 42, 99, 1, 2, 3];
-        pda.instruction(&mut data).unwrap();
+        let header = ProxyHeader {
+            bumps: Bumps {
+                open_orders: 42,
+                open_orders_init: 99,
+            },
+            ..ProxyHeader::default()
+        };
+        pda.header(&header, &[]).unwrap();
         assert_eq!(pda.bump, 42);
         assert_eq!(pda.bump_init, 99);
-        assert_eq!(data, &[1, 2, 3]);
+        assert_eq!(pda.index, 0);
+        pda.header(&header, &[7]).unwrap();
+        assert_eq!(pda.index, 7);
+
+        // The clients' data decodes as sent.
+        for index in [0, 7] {
+            let header = header.clone().with_data(0, &OpenOrdersPdaData { index });
+            pda.header(&header, header.data_for(0)).unwrap();
+            assert_eq!(pda.index, index);
+        }
+        let data = ReferralFeesData {
+            code: Some(*b"frontend"),
+        };
+        assert_eq!(ReferralFeesData::decode(&data.encode()).unwrap(), data);
+        assert_eq!(ReferralFeesData::decode(&[1]).unwrap().code, None);
+    }
+
+    // Accounts for init_open_orders, prefixed with the dex and system
+    // programs, along with the proxy's PDA bumps.
+    fn init_accounts(
+        program_id: &Pubkey,
+        dex_program_id: &Pubkey,
+    ) -> (Vec<AccountInfo<'static>>, OpenOrdersPda) {
+        let user = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let (open_orders, bump) = Pubkey::find_program_address(
+            &[
+                b"open-orders".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                user.as_ref(),
+            ],
+            program_id,
+        );
+        let (market_authority, bump_init) = Pubkey::find_program_address(
+            &[
+                b"open-orders-init".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+            ],
+            program_id,
+        );
+        let accounts = vec![
+            dummy_account(false),
+            dummy_account(false),
+            keyed_account(open_orders, false),
+            keyed_account(user, true),
+            keyed_account(market, false),
+            dummy_account(false),
+            keyed_account(market_authority, false),
+        ];
+        let pda = OpenOrdersPda {
+            bump,
+            bump_init,
+            ..OpenOrdersPda::new()
+        };
+        (accounts, pda)
     }
 
     #[test]
     fn test_init_open_orders_valid() {
-        let pda = OpenOrdersPda { bump: 1, bump_init: 2 };
         let program_id = Pubkey::new_unique();
         let dex_program_id = Pubkey::new_unique();
-        // Provide 7 accounts: after skipping 2, 5 remain.
-        // The 2nd account after skipping (index 1) must be a signer, so set original index 3 as signer.
-        let accounts: Vec<_> = (0..7)
-            .map(|i| dummy_account(i == 3))
-            .collect();
-        let mut ctx = Context::new(&program_id, &dex_program_id, accounts);
+        let (accounts, pda) = init_accounts(&program_id, &dex_program_id);
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
         assert!(pda.init_open_orders(&mut ctx).is_ok());
         assert_eq!(ctx.seeds.len(), 2);
+        assert!(ctx.user().unwrap().is_signer);
+        assert_eq!(ctx.user().unwrap().key, ctx.open_orders().unwrap().key);
+        assert!(ctx.account(Role::MarketAuthority).unwrap().is_signer);
     }
 
     #[test]
-    fn test_init_open_orders_missing_signer() {
-        let pda = OpenOrdersPda { bump: 1, bump_init: 2 };
+    fn test_init_open_orders_permissionless() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let (mut accounts, pda) = init_accounts(&program_id, &dex_program_id);
+        accounts.pop();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        assert!(pda.init_open_orders(&mut ctx).is_err());
+
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        ctx.header.flags |= crate::FLAG_PERMISSIONLESS_INIT;
+        pda.init_open_orders(&mut ctx).unwrap();
+        assert_eq!(ctx.seeds.len(), 1);
+        assert_eq!(ctx.user().unwrap().key, ctx.open_orders().unwrap().key);
+        assert!(ctx.account(Role::MarketAuthority).is_err());
+    }
+
+    #[test]
+    fn test_init_open_orders_registers() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let (accounts, pda) = init_accounts(&program_id, &dex_program_id);
+        let pda = pda.registry();
+        let user = accounts[3].clone();
+        let trailing = [dummy_account(false), dummy_account(false)];
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        assert!(pda.init_open_orders(&mut ctx).is_err());
+
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        ctx.set_trailing_accounts(&trailing);
+        pda.init_open_orders(&mut ctx).unwrap();
+        assert_eq!(ctx.post_callbacks.len(), 1);
+        let (_, acc_infos, _) = &ctx.post_callbacks[0];
+        assert_eq!(acc_infos[0].key, trailing[0].key);
+        assert_eq!(acc_infos[1].key, user.key);
+        assert_eq!(acc_infos[4].key, ctx.open_orders().unwrap().key);
+    }
+
+    #[test]
+    fn test_init_open_orders_invalid_seeds() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let (mut accounts, pda) = init_accounts(&program_id, &dex_program_id);
+        // Open orders derived for another user.
+        accounts[3] = dummy_account(true);
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        assert_eq!(
+            pda.init_open_orders(&mut ctx),
+            Err(ProgramError::InvalidSeeds)
+        );
+
+        // Market authority with the wrong bump.
+        let (accounts, mut pda) = init_accounts(&program_id, &dex_program_id);
+        pda.bump_init = pda.bump_init.wrapping_sub(1);
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        assert!(pda.init_open_orders(&mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_init_open_orders_canonical_bumps() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let (accounts, _) = init_accounts(&program_id, &dex_program_id);
+        let market = *accounts[4].key;
+        let bumps = MarketBumps::resolve(&program_id, &dex_program_id, &market);
+        let (address, _) = MarketBumps::address(&program_id, &dex_program_id, &market);
+        let cache_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; MarketBumps::LEN].into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        );
+        bumps.store(&cache_info).unwrap();
+        let trailing = [cache_info];
+
+        // The header's bumps are ignored.
+        let mut pda = OpenOrdersPda::new().canonical_bumps();
+        let header = ProxyHeader {
+            bumps: Bumps {
+                open_orders: 1,
+                open_orders_init: 2,
+            },
+            ..ProxyHeader::default()
+        };
+        pda.header(&header, &[]).unwrap();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        ctx.set_trailing_accounts(&trailing);
+        assert!(pda.init_open_orders(&mut ctx).is_ok());
+        assert!(ctx.account(Role::MarketAuthority).unwrap().is_signer);
+
+        // The cache is required.
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
+        assert!(pda.init_open_orders(&mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_account_role_without_kind() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..12).map(|_| dummy_account(false)).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        assert!(ctx.user().is_err());
+        ctx.kind = Some(InstructionKind::NewOrderV3);
+        assert_eq!(ctx.user().unwrap().key, ctx.accounts[7].key);
+        assert!(ctx.account(Role::FeeDiscount).is_err());
+    }
+
+    #[test]
+    fn test_cancel_order_as_delegate() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (open_orders, bump) = Pubkey::find_program_address(
+            &[
+                b"open-orders".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                user.as_ref(),
+            ],
+            &program_id,
+        );
+        let accounts = vec![
+            keyed_account(market, false),
+            dummy_account(false),
+            dummy_account(false),
+            keyed_account(open_orders, false),
+            keyed_account(user, false),
+            dummy_account(false),
+        ];
+        let delegate = dummy_account(true);
+        let (address, delegate_bump) = TradingDelegate::address(&program_id, &market, &user);
+        let delegate_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; TradingDelegate::LEN].into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        );
+        let record = TradingDelegate {
+            owner: user,
+            market,
+            delegate: *delegate.key,
+            bump: delegate_bump,
+        };
+        record.store(&delegate_info).unwrap();
+
+        let pda = OpenOrdersPda {
+            bump,
+            ..OpenOrdersPda::new()
+        };
+        let ix = CancelOrderInstructionV2 {
+            side: Side::Bid,
+            order_id: 1,
+        };
+        let cancel = |trailing: &[AccountInfo<'static>]| {
+            let trailing: &'static [AccountInfo<'static>] = Box::leak(trailing.into());
+            let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+            ctx.kind = Some(InstructionKind::CancelOrderV2);
+            ctx.set_trailing_accounts(trailing);
+            pda.cancel_order_v2(&mut ctx, &mut ix.clone())
+                .map(|_| *ctx.user().unwrap().key)
+        };
+        assert_eq!(
+            cancel(&[delegate_info.clone(), delegate.clone()]),
+            Ok(open_orders)
+        );
+
+        // Neither the user nor their delegate signed.
+        let unauthorized: std::result::Result<Pubkey, ProgramError> =
+            Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+        assert_eq!(cancel(&[]), unauthorized);
+        let other = dummy_account(true);
+        assert_eq!(cancel(&[delegate_info.clone(), other]), unauthorized);
+    }
+
+    #[test]
+    fn test_cancel_order_after_migration() {
         let program_id = Pubkey::new_unique();
         let dex_program_id = Pubkey::new_unique();
-        let accounts: Vec<_> = (0..6)
-            .map(|_| dummy_account(false))
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (open_orders, bump) = Pubkey::find_program_address(
+            &[
+                b"open-orders".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                user.as_ref(),
+            ],
+            &program_id,
+        );
+        let accounts = vec![
+            keyed_account(market, false),
+            dummy_account(false),
+            dummy_account(false),
+            keyed_account(open_orders, false),
+            keyed_account(user, true),
+            dummy_account(false),
+        ];
+        let authority = dummy_account(true);
+        let (address, migration_bump) = AuthorityMigration::address(&program_id, &market, &user);
+        let migration_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; AuthorityMigration::LEN].into_boxed_slice()),
+            Box::leak(Box::new(Pubkey::default())),
+            false,
+            Epoch::default(),
+        );
+
+        let pda = OpenOrdersPda {
+            bump,
+            ..OpenOrdersPda::new()
+        }
+        .migrations();
+        let ix = CancelOrderInstructionV2 {
+            side: Side::Bid,
+            order_id: 1,
+        };
+        let cancel = |trailing: &[AccountInfo<'static>]| {
+            let trailing: &'static [AccountInfo<'static>] = Box::leak(trailing.into());
+            let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+            ctx.kind = Some(InstructionKind::CancelOrderV2);
+            ctx.set_trailing_accounts(trailing);
+            pda.cancel_order_v2(&mut ctx, &mut ix.clone())
+                .map(|_| *ctx.user().unwrap().key)
+        };
+        // Before migrating, the user signs, next to their (empty) migration.
+        assert_eq!(
+            cancel(std::slice::from_ref(&migration_info)),
+            Ok(open_orders)
+        );
+        assert!(cancel(&[dummy_account(false)]).is_err());
+
+        let mut migrated = migration_info.clone();
+        migrated.owner = Box::leak(Box::new(program_id));
+        AuthorityMigration {
+            market,
+            original: user,
+            authority: *authority.key,
+            bump: migration_bump,
+        }
+        .store(&migrated)
+        .unwrap();
+        assert_eq!(
+            cancel(&[migrated.clone(), authority.clone()]),
+            Ok(open_orders)
+        );
+
+        // The user's signature no longer suffices.
+        let unauthorized: std::result::Result<Pubkey, ProgramError> =
+            Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+        assert_eq!(
+            cancel(&[migrated.clone(), keyed_account(user, true)]),
+            unauthorized
+        );
+        assert_eq!(cancel(&[migrated, dummy_account(true)]), unauthorized);
+    }
+
+    #[test]
+    fn test_close_open_orders_to_user() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (open_orders, bump) = Pubkey::find_program_address(
+            &[
+                b"open-orders".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                user.as_ref(),
+            ],
+            &program_id,
+        );
+        let accounts = vec![
+            keyed_account(open_orders, false),
+            keyed_account(user, true),
+            dummy_account(false),
+            keyed_account(market, false),
+        ];
+        let mut trailing: Vec<_> = (0..6).map(|_| dummy_account(false)).collect();
+        trailing.push(AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(Vec::new().into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        ));
+
+        let pda = OpenOrdersPda {
+            bump,
+            ..OpenOrdersPda::new()
+        };
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CloseOpenOrders);
+        pda.close_open_orders(&mut ctx).unwrap();
+        assert_eq!(ctx.account(Role::Destination).unwrap().key, &user);
+        assert_eq!(ctx.user().unwrap().key, &open_orders);
+        assert!(ctx.pre_instructions.is_empty());
+
+        let pda = pda.sweep_on_close();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CloseOpenOrders);
+        ctx.set_trailing_accounts(&trailing);
+        pda.close_open_orders(&mut ctx).unwrap();
+        let (settle, _, seeds) = &ctx.pre_instructions[0];
+        assert_eq!(settle.program_id, dex_program_id);
+        assert_eq!(settle.accounts[2].pubkey, open_orders);
+        assert_eq!(
+            seeds[0].create_program_address(&program_id),
+            Ok(open_orders)
+        );
+        assert_eq!(ctx.post_callbacks.len(), 1);
+
+        // Dust is swept into the destination's accounts, given lot sizes.
+        let destination = Pubkey::new_unique();
+        let mut accounts = accounts.clone();
+        let mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+        accounts[3] = market_account(market, dex_program_id, mints, [100, 10]);
+        let dust_accounts = [token_account(destination), token_account(destination)];
+        let with_dust = [&trailing[..], &dust_accounts[..]].concat();
+        let pda = pda.sweep_dust(Some(destination));
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CloseOpenOrders);
+        ctx.set_trailing_accounts(&with_dust);
+        pda.close_open_orders(&mut ctx).unwrap();
+        let (_, acc_infos, args) = &ctx.post_callbacks[1];
+        assert_eq!(acc_infos.len(), 6);
+        assert_eq!(args[..8], 100u64.to_le_bytes());
+        assert_eq!(args[8..], 10u64.to_le_bytes());
+
+        let others = [
+            token_account(destination),
+            token_account(Pubkey::new_unique()),
+        ];
+        let with_others = [&trailing[..], &others[..]].concat();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CloseOpenOrders);
+        ctx.set_trailing_accounts(&with_others);
+        assert!(pda.close_open_orders(&mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_permissionless_settle() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (open_orders, bump) =
+            SignerSeeds::open_orders_address(&program_id, &dex_program_id, &market, &user, 0);
+        let accounts = |signed: bool, coin_wallet_owner: Pubkey| {
+            vec![
+                keyed_account(market, false),
+                keyed_account(open_orders, false),
+                keyed_account(user, signed),
+                dummy_account(false),
+                dummy_account(false),
+                token_account(coin_wallet_owner),
+                token_account(user),
+                dummy_account(false),
+                dummy_account(false),
+            ]
+        };
+        let settle = |pda: &OpenOrdersPda, accounts: &[AccountInfo<'static>]| {
+            let mut ctx = Context::new(&program_id, &dex_program_id, accounts);
+            ctx.kind = Some(InstructionKind::SettleFunds);
+            pda.settle_funds(&mut ctx).map(|_| *ctx.user().unwrap().key)
+        };
+
+        let pda = OpenOrdersPda {
+            bump,
+            ..OpenOrdersPda::new()
+        };
+        assert!(settle(&pda, &accounts(false, user)).is_err());
+
+        let pda = pda.permissionless_settle();
+        assert_eq!(settle(&pda, &accounts(false, user)), Ok(open_orders));
+        let other = Pubkey::new_unique();
+        assert!(settle(&pda, &accounts(false, other)).is_err());
+        assert_eq!(settle(&pda, &accounts(true, other)), Ok(open_orders));
+    }
+
+    #[test]
+    fn test_new_order_in_native_sol() {
+        use solana_program::program_pack::Pack;
+
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (open_orders, bump) = Pubkey::find_program_address(
+            &[
+                b"open-orders".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                user.as_ref(),
+            ],
+            &program_id,
+        );
+        let mut data = vec![0u8; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint: spl_token::native_mint::ID,
+            owner: user,
+            amount: 40,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        let payer = AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(data.into_boxed_slice()),
+            Box::leak(Box::new(spl_token::ID)),
+            false,
+            Epoch::default(),
+        );
+        let mut accounts: Vec<_> = (0..12).map(|_| dummy_account(false)).collect();
+        let mints = [Pubkey::new_unique(), spl_token::native_mint::ID];
+        accounts[0] = market_account(market, dex_program_id, mints, [1, 1]);
+        accounts[1] = keyed_account(open_orders, false);
+        accounts[6] = payer.clone();
+        accounts[7] = keyed_account(user, true);
+
+        let pda = OpenOrdersPda {
+            bump,
+            ..OpenOrdersPda::new()
+        }
+        .native_sol();
+        let mut ix = NewOrderInstructionV3 {
+            side: Side::Bid,
+            limit_price: 1u64.try_into().unwrap(),
+            max_coin_qty: 1u64.try_into().unwrap(),
+            max_native_pc_qty_including_fees: 100u64.try_into().unwrap(),
+            self_trade_behavior: serum_dex::instruction::SelfTradeBehavior::AbortTransaction,
+            order_type: serum_dex::matching::OrderType::Limit,
+            client_order_id: 0,
+            limit: 1,
+            max_ts: 0,
+        };
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::NewOrderV3);
+        pda.new_order_v3(&mut ctx, &mut ix).unwrap();
+
+        // The payer is topped up to the order amount, synced, and approved.
+        let programs: Vec<_> = ctx
+            .pre_instructions
+            .iter()
+            .map(|(ix, ..)| ix.program_id)
             .collect();
-        let mut ctx = Context::new(&program_id, &dex_program_id, accounts);
+        assert_eq!(
+            programs,
+            vec![
+                anchor_lang::system_program::ID,
+                spl_token::ID,
+                spl_token::ID
+            ]
+        );
+        let transfer = &ctx.pre_instructions[0].0;
+        assert_eq!(transfer.data[4..], 60u64.to_le_bytes());
+        assert_eq!(transfer.accounts[1].pubkey, *payer.key);
+
+        // And closed back to the user after the revoke.
+        let (close, ..) = ctx.post_instructions.last().unwrap();
+        assert_eq!(ctx.post_instructions.len(), 2);
+        assert_eq!(
+            close.data,
+            spl_token::instruction::TokenInstruction::CloseAccount.pack()
+        );
+        assert_eq!(close.accounts[1].pubkey, user);
+
+        // IOC orders are settled into the payer before it's closed.
+        let trailing: Vec<_> = (0..3).map(|_| dummy_account(false)).collect();
+        ix.order_type = serum_dex::matching::OrderType::ImmediateOrCancel;
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::NewOrderV3);
+        ctx.header.flags |= crate::FLAG_AUTO_SETTLE;
+        ctx.set_trailing_accounts(&trailing);
+        pda.new_order_v3(&mut ctx, &mut ix).unwrap();
+        assert_eq!(ctx.post_instructions.len(), 3);
+        let (settle, _, seeds) = &ctx.post_instructions[1];
+        assert_eq!(settle.program_id, dex_program_id);
+        assert_eq!(settle.accounts[5].pubkey, *trailing[0].key);
+        assert_eq!(
+            seeds[0].create_program_address(&program_id),
+            Ok(open_orders)
+        );
+
+        // Asks can't be paid in the pc currency.
+        ix.side = Side::Ask;
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::NewOrderV3);
+        let invalid_mint: ProgramResult =
+            Err(anchor_lang::error!(ErrorCode::InvalidPayerMint).into());
+        assert_eq!(pda.new_order_v3(&mut ctx, &mut ix), invalid_mint);
+    }
+
+    #[test]
+    fn test_init_open_orders_missing_signer() {
+        let pda = OpenOrdersPda {
+            bump: 1,
+            bump_init: 2,
+            ..OpenOrdersPda::new()
+        };
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..6).map(|_| dummy_account(false)).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::InitOpenOrders);
         assert!(pda.init_open_orders(&mut ctx).is_err());
     }
 
+    #[test]
+    fn test_context_extensions() {
+        #[derive(Debug, PartialEq)]
+        struct Price(u64);
+
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &[]);
+        assert!(ctx.get::<Price>().is_none());
+        assert!(ctx.insert(Price(10)).is_none());
+        assert_eq!(ctx.insert(Price(20)), Some(Price(10)));
+        ctx.get_mut::<Price>().unwrap().0 += 1;
+        assert_eq!(ctx.get::<Price>(), Some(&Price(21)));
+        assert_eq!(ctx.remove::<Price>(), Some(Price(21)));
+        assert!(ctx.get::<u64>().is_none());
+    }
+
+    #[test]
+    fn test_lazy_instruction() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let data = MarketInstruction::Prune(3).pack();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &[]);
+        ctx.data = &data;
+        assert!(matches!(ctx.relayed_data(), Cow::Borrowed(_)));
+        assert_eq!(ctx.instruction().unwrap(), &MarketInstruction::Prune(3));
+
+        let mut ctx = Context::new(&program_id, &dex_program_id, &[]);
+        ctx.data = &[0, 16, 0, 0, 0];
+        assert!(ctx.instruction().is_err());
+    }
+
     #[test]
     fn test_logger_hooks() {
-        let logger = Logger;
+        let quiet = Logger::new()
+            .level(LogLevel::Info)
+            .mute(InstructionKind::ConsumeEvents);
+        for logger in [Logger::new(), Logger::new().events(), quiet, quiet.events()] {
+            log_requests(logger);
+            log_requests(logger.redacted());
+        }
+    }
+
+    #[test]
+    fn test_cu_profiler_samples() {
         let program_id = Pubkey::new_unique();
         let dex_program_id = Pubkey::new_unique();
-        let accounts: Vec<_> = (0..6)
-            .map(|i| dummy_account(i == 1))
-            .collect();
-        let mut ctx = Context::new(&program_id, &dex_program_id, accounts);
+        let accounts: Vec<_> = (0..6).map(|_| dummy_account(false)).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        let profilers = [
+            CuProfiler::new(Stage::Auth),
+            CuProfiler::new(Stage::Observe),
+        ];
+        let label = |ctx: &Context| ctx.get::<CuSample>().unwrap().label;
+        profilers[0].settle_funds(&mut ctx).unwrap();
+        assert_eq!(label(&ctx), "auth stage");
+        profilers[1].settle_funds(&mut ctx).unwrap();
+        assert_eq!(label(&ctx), "observe stage");
+        for profiler in &profilers {
+            profiler.before_cpi(&mut ctx).unwrap();
+        }
+        assert_eq!(label(&ctx), "before cpi");
+        for profiler in &profilers {
+            profiler.after_cpi(&mut ctx, &Ok(()), None).unwrap();
+            assert_eq!(label(&ctx), "dex cpi");
+        }
+    }
+
+    #[test]
+    fn test_redacted_logger() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..6).map(|_| dummy_account(false)).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::CancelOrderByClientIdV2);
+        let logger = Logger::new().redacted();
+        assert_eq!(Logger::new().client_order_id(&ctx, 7), 7);
+        assert_ne!(logger.client_order_id(&ctx, 7), 7);
+        assert_eq!(
+            logger.client_order_id(&ctx, 7),
+            logger.client_order_id(&ctx, 7)
+        );
+
+        // Pseudonyms differ across markets.
+        let other_market: Vec<_> = (0..6).map(|_| dummy_account(false)).collect();
+        let mut other_ctx = Context::new(&program_id, &dex_program_id, &other_market);
+        other_ctx.kind = Some(InstructionKind::CancelOrderByClientIdV2);
+        assert_ne!(
+            logger.client_order_id(&ctx, 7),
+            logger.client_order_id(&other_ctx, 7)
+        );
+    }
+
+    fn log_requests(logger: Logger) {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let accounts: Vec<_> = (0..6).map(|i| dummy_account(i == 1)).collect();
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
         assert!(logger.init_open_orders(&mut ctx).is_ok());
         let mut ix = NewOrderInstructionV3 {
             side: Side::Bid,
@@ -689,5 +2816,214 @@ mod tests {
             max_ts: 0,
         };
         assert!(logger.new_order_v3(&mut ctx, &mut ix).is_ok());
+        assert!(logger.consume_events(&mut ctx, &mut 5).is_ok());
+        assert!(logger.fallback(&mut ctx).is_ok());
+    }
+
+    #[test]
+    fn test_approval_buffer() {
+        assert_eq!(ApprovalBuffer::Flat(5).apply(100), 105);
+        assert_eq!(ApprovalBuffer::Bps(50).apply(1_000), 1_005);
+        assert_eq!(ApprovalBuffer::Bps(50).apply(1_001), 1_007);
+        assert_eq!(ApprovalBuffer::Bps(0).apply(1_001), 1_001);
+        assert_eq!(ApprovalBuffer::Flat(1).apply(u64::MAX), u64::MAX);
+        assert_eq!(ApprovalBuffer::Bps(20_000).apply(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_referral_set() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let frontends = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (address, bump) = ReferralSet::address(&program_id);
+        let set_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; ReferralSet::LEN].into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        );
+        ReferralSet {
+            referrals: frontends.to_vec(),
+            bump,
+        }
+        .store(&set_info)
+        .unwrap();
+        let trailing = [set_info];
+        let settle = |referral: Pubkey| {
+            let mut accounts: Vec<_> = (0..9).map(|_| dummy_account(false)).collect();
+            accounts.push(token_account(referral));
+            let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+            ctx.kind = Some(InstructionKind::SettleFunds);
+            ctx.set_trailing_accounts(&trailing);
+            ReferralFees::allowed_set().settle_funds(&mut ctx)
+        };
+        settle(frontends[0]).unwrap();
+        settle(frontends[1]).unwrap();
+        let invalid_referral: ProgramResult =
+            Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+        assert_eq!(settle(Pubkey::new_unique()), invalid_referral);
+    }
+
+    #[test]
+    fn test_referral_codes() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let code = *b"frontend";
+        let referral = token_account(Pubkey::new_unique());
+        let (address, bump) = ReferralCode::address(&program_id, &code);
+        let code_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; ReferralCode::LEN].into_boxed_slice()),
+            Box::leak(Box::new(program_id)),
+            false,
+            Epoch::default(),
+        );
+        ReferralCode {
+            code,
+            owner: Pubkey::new_unique(),
+            referral: *referral.key,
+            bump,
+        }
+        .store(&code_info)
+        .unwrap();
+        let trailing = [code_info];
+        let settle = |code: &[u8], referral: AccountInfo<'static>| {
+            let mut referral_fees = ReferralFees::codes();
+            referral_fees.header(&ProxyHeader::default(), code)?;
+            let mut accounts: Vec<_> = (0..9).map(|_| dummy_account(false)).collect();
+            accounts.push(referral);
+            let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+            ctx.kind = Some(InstructionKind::SettleFunds);
+            ctx.set_trailing_accounts(&trailing);
+            referral_fees.settle_funds(&mut ctx)
+        };
+        settle(&code, referral.clone()).unwrap();
+        let invalid_referral: ProgramResult =
+            Err(anchor_lang::error!(ErrorCode::InvalidReferral).into());
+        let other = token_account(Pubkey::new_unique());
+        assert_eq!(settle(&code, other), invalid_referral);
+        assert_eq!(settle(b"frontenx", referral.clone()), invalid_referral);
+        assert_eq!(settle(&[], referral), invalid_referral);
+    }
+
+    #[test]
+    fn test_referral_proxy_fee() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let referrer = Pubkey::new_unique();
+        let fee_receiver = Pubkey::new_unique();
+        let mint = Pubkey::default();
+        let pda = |address: Pubkey, len: usize| {
+            AccountInfo::new(
+                Box::leak(Box::new(address)),
+                false,
+                true,
+                Box::leak(Box::new(0u64)),
+                Box::leak(vec![0u8; len].into_boxed_slice()),
+                Box::leak(Box::new(program_id)),
+                false,
+                Epoch::default(),
+            )
+        };
+        let balance = |referrer: Pubkey| {
+            let (address, bump) = ReferralBalance::address(&program_id, &referrer, &mint);
+            let balance_info = pda(address, ReferralBalance::LEN);
+            ReferralBalance {
+                referrer,
+                mint,
+                amount: 0,
+                bump,
+            }
+            .store(&balance_info)
+            .unwrap();
+            balance_info
+        };
+        let (address, bump) = ProxyConfig::address(&program_id);
+        let config_info = pda(address, ProxyConfig::LEN);
+        ProxyConfig {
+            admin: Pubkey::new_unique(),
+            paused: false,
+            fee_bps: 2_500,
+            fee_receiver,
+            dex_program_id,
+            bump,
+        }
+        .store(&config_info)
+        .unwrap();
+        let treasury = token_account(referral_treasury_address(&program_id).0);
+        let mut accounts: Vec<_> = (0..9).map(|_| dummy_account(false)).collect();
+        accounts.push(treasury.clone());
+
+        // The fee is credited to the config's fee receiver only.
+        let referral_fees = ReferralFees::new(referrer).proxy_fee();
+        let trailing = [
+            balance(referrer),
+            config_info.clone(),
+            balance(Pubkey::new_unique()),
+        ];
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::SettleFunds);
+        ctx.set_trailing_accounts(&trailing);
+        assert_eq!(
+            referral_fees.settle_funds(&mut ctx),
+            Err(anchor_lang::error!(ErrorCode::InvalidReferral).into())
+        );
+
+        let trailing = [balance(referrer), config_info, balance(fee_receiver)];
+        let mut ctx = Context::new(&program_id, &dex_program_id, &accounts);
+        ctx.kind = Some(InstructionKind::SettleFunds);
+        ctx.set_trailing_accounts(&trailing);
+        referral_fees.settle_funds(&mut ctx).unwrap();
+        assert_eq!(ctx.post_callbacks.len(), 1);
+
+        // The treasury receives 100 tokens, a quarter of which is the fee.
+        treasury.try_borrow_mut_data().unwrap()[64..72].copy_from_slice(&100u64.to_le_bytes());
+        let (callback, acc_infos, args) = ctx.post_callbacks.pop().unwrap();
+        callback(&program_id, acc_infos, Vec::new(), args, None).unwrap();
+        let amount = |balance_info| {
+            ReferralBalance::load(&program_id, balance_info)
+                .unwrap()
+                .amount
+        };
+        assert_eq!(amount(&trailing[0]), 75);
+        assert_eq!(amount(&trailing[2]), 25);
+    }
+
+    #[test]
+    fn test_transfer_fee_inclusive() {
+        use spl_token_2022::extension::{
+            BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+        };
+
+        let len =
+            ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+                .unwrap();
+        let mut data = vec![0u8; len];
+        let mut mint = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        let config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.newer_transfer_fee.epoch = 10u64.into();
+        config.newer_transfer_fee.maximum_fee = 1_000u64.into();
+        config.newer_transfer_fee.transfer_fee_basis_points = 100u16.into();
+        mint.base = Mint {
+            is_initialized: true,
+            ..Mint::default()
+        };
+        mint.pack_base();
+        mint.init_account_type().unwrap();
+
+        // The older fee, unset, applies before the newer one's epoch.
+        assert_eq!(transfer_fee_inclusive(&data, 9_900, 9).unwrap(), 9_900);
+        assert_eq!(transfer_fee_inclusive(&data, 9_900, 10).unwrap(), 10_000);
+        assert_eq!(
+            transfer_fee_inclusive(&data, u64::MAX / 2, 10).unwrap(),
+            u64::MAX / 2 + 1_000
+        );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,237 @@
+use crate::{close_delegate, ErrorCode};
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Seed of the `AuthorityMigration` PDAs.
+pub const AUTHORITY_MIGRATION_SEED: &[u8] = b"authority-migration";
+
+/// A wallet controlling a user's open orders PDAs on a market in place of
+/// the user, e.g., after the user's wallet was compromised, stored in a PDA
+/// of the proxy program derived from the market and the user. The PDAs stay
+/// derived from the user, since the DEX can't move an open orders account,
+/// but once migrated, `OpenOrdersPda::migrations` only accepts the new
+/// authority's signature for them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuthorityMigration {
+    pub market: Pubkey,
+    /// The user the open orders PDAs are derived from.
+    pub original: Pubkey,
+    /// The wallet controlling the open orders PDAs.
+    pub authority: Pubkey,
+    pub bump: u8,
+}
+
+impl AuthorityMigration {
+    /// Size of the migration account.
+    pub const LEN: usize = 8 + 32 + 32 + 32 + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxmigrat";
+
+    /// Returns the address and bump of the migration PDA of the given user on
+    /// the given market.
+    pub fn address(program_id: &Pubkey, market: &Pubkey, original: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[AUTHORITY_MIGRATION_SEED, market.as_ref(), original.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Loads the migration from the given account, checking it's the
+    /// program's migration PDA of the market and user it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let migration = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[
+                AUTHORITY_MIGRATION_SEED,
+                migration.market.as_ref(),
+                migration.original.as_ref(),
+                &[migration.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(migration)
+    }
+
+    fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidMigration).into()),
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidMigration).into()
+        })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Data of the `migrate_authority` instruction.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MigrateAuthority {
+    pub market: Pubkey,
+    pub original: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+impl MigrateAuthority {
+    /// Packs the instruction data, to be sent with `pack_custom_instruction`.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.serialize(&mut data).unwrap();
+        data
+    }
+}
+
+/// Custom instruction handing the control of a user's open orders PDAs on a
+/// market over to a new wallet, to be registered by proxies using
+/// `OpenOrdersPda::migrations` (see `declare_market_proxy!`). Signed by the
+/// user on the first migration, and by the current authority afterwards.
+///
+/// The delegation follows the authority: the user's `TradingDelegate` on the
+/// market, if given, is closed, and delegates must be registered by the new
+/// authority.
+///
+/// Accounts:
+///
+/// 0. Migration PDA (writable).
+/// 1. Current authority (signer, writable), paying for the account.
+/// 2. System program, on the first migration.
+/// 3. The user's `TradingDelegate` PDA on the market (writable), optional.
+pub fn migrate_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let ix = MigrateAuthority::deserialize(&mut &data[..]).map_err(|_| -> ProgramError {
+        anchor_lang::error!(ErrorCode::InvalidInstruction).into()
+    })?;
+    if accounts.len() < 2 {
+        return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+    }
+    let migration_info = &accounts[0];
+    let authority = &accounts[1];
+    let (address, bump) = AuthorityMigration::address(program_id, &ix.market, &ix.original);
+    if &address != migration_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let current = if migration_info.owner == program_id {
+        AuthorityMigration::load(program_id, migration_info)?.authority
+    } else {
+        ix.original
+    };
+    if !authority.is_signer || authority.key != &current {
+        return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+    }
+
+    if migration_info.owner != program_id {
+        let system = accounts
+            .get(2)
+            .filter(|acc| acc.key == &system_program::ID)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: authority.clone(),
+                    to: migration_info.clone(),
+                },
+                &[&[
+                    AUTHORITY_MIGRATION_SEED,
+                    ix.market.as_ref(),
+                    ix.original.as_ref(),
+                    &[bump],
+                ]],
+            ),
+            Rent::get()?.minimum_balance(AuthorityMigration::LEN),
+            AuthorityMigration::LEN as u64,
+            program_id,
+        )?;
+    }
+    if let Some(delegate_info) = accounts.get(3).filter(|acc| acc.owner == program_id) {
+        close_delegate(program_id, delegate_info, &ix.original, authority)?;
+    }
+    AuthorityMigration {
+        market: ix.market,
+        original: ix.original,
+        authority: ix.new_authority,
+        bump,
+    }
+    .store(migration_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    fn account(key: Pubkey, owner: Pubkey, is_signer: bool, len: usize) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            is_signer,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; len].into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_rotate_authority() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let original = Pubkey::new_unique();
+        let (address, bump) = AuthorityMigration::address(&program_id, &market, &original);
+        let migration_info = account(address, program_id, false, AuthorityMigration::LEN);
+        let first = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        AuthorityMigration {
+            market,
+            original,
+            authority: *first.key,
+            bump,
+        }
+        .store(&migration_info)
+        .unwrap();
+
+        let second = Pubkey::new_unique();
+        let ix = MigrateAuthority {
+            market,
+            original,
+            new_authority: second,
+        };
+        let accounts = [migration_info.clone(), first.clone()];
+        migrate_authority(&program_id, &accounts, &ix.pack()).unwrap();
+        let migration = AuthorityMigration::load(&program_id, &migration_info).unwrap();
+        assert_eq!(migration.authority, second);
+
+        // The previous authority, like the user, lost control.
+        assert!(migrate_authority(&program_id, &accounts, &ix.pack()).is_err());
+        let user = account(original, Pubkey::default(), true, 0);
+        let accounts = [migration_info.clone(), user];
+        assert!(migrate_authority(&program_id, &accounts, &ix.pack()).is_err());
+    }
+}
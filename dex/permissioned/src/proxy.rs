@@ -1,14 +1,20 @@
-use crate::{Context, ErrorCode, MarketMiddleware};
+use crate::{
+    process_config_instruction, process_user_instruction, validate_layout, Context, ErrorCode,
+    InstructionKind, MarketMiddleware, ProxyConfig, ProxyHeader, RelayAccounts, RelayEvent,
+    SignerSeeds, CONFIG_INSTRUCTION_TAG, USER_INSTRUCTION_TAG,
+};
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
 use anchor_lang::solana_program::program;
 use anchor_lang::solana_program::pubkey::Pubkey;
 use serum_dex::instruction::*;
 use spl_token::solana_program::entrypoint::ProgramResult;
+use std::ops::{Deref, DerefMut};
 
 // Add the correct Serum DEX program ID (mainnet value shown; replace if needed)
 pub const SERUM_DEX_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
-    57, 197, 30, 22, 184, 218, 211, 222, 151, 184, 186, 13, 222, 222, 222, 222,
-    151, 184, 186, 13, 222, 222, 222, 222, 151, 184, 186, 13, 222, 222, 222, 222
+    57, 197, 30, 22, 184, 218, 211, 222, 151, 184, 186, 13, 222, 222, 222, 222, 151, 184, 186, 13,
+    222, 222, 222, 222, 151, 184, 186, 13, 222, 222, 222, 222,
 ]); // Replace with actual bytes if different
 
 /// MarketProxy provides an abstraction for implementing proxy programs to the
@@ -19,9 +25,53 @@ pub const SERUM_DEX_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
 /// The only requirement for a middleware is that, when all are done processing,
 /// a valid DEX instruction--accounts and instruction data--must be left to
 /// forward to the orderbook program.
-#[derive(Default)]
+///
+/// Instruction data sent to the proxy is a Borsh serialized `ProxyHeader`
+/// followed by the DEX instruction (or a batch of them). Accounts needed only
+/// by the middleware are appended after the DEX accounts, with their number
+/// given by the header, and are taken via `Context::take_trailing`.
+///
+/// Requests are relayed to the DEX unless another target is configured, in
+/// which case the target is expected to be a proxy itself (see
+/// `MarketProxy::target`).
 pub struct MarketProxy<'a> {
-    middlewares: Vec<&'a mut dyn MarketMiddleware>,
+    middlewares: Vec<MiddlewareSlot<'a>>,
+    target: Pubkey,
+    budget: CpiBudget,
+    config: bool,
+}
+
+impl<'a> Default for MarketProxy<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A middleware registered with the proxy, either borrowed from the caller
+/// or owned by the proxy itself.
+enum MiddlewareSlot<'a> {
+    Borrowed(&'a mut dyn MarketMiddleware),
+    Owned(Box<dyn MarketMiddleware + 'a>),
+}
+
+impl<'a> Deref for MiddlewareSlot<'a> {
+    type Target = dyn MarketMiddleware + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            MiddlewareSlot::Borrowed(mw) => &**mw,
+            MiddlewareSlot::Owned(mw) => &**mw,
+        }
+    }
+}
+
+impl<'a> DerefMut for MiddlewareSlot<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            MiddlewareSlot::Borrowed(mw) => &mut **mw,
+            MiddlewareSlot::Owned(mw) => &mut **mw,
+        }
+    }
 }
 
 impl<'a> MarketProxy<'a> {
@@ -29,12 +79,71 @@ impl<'a> MarketProxy<'a> {
     pub fn new() -> Self {
         Self {
             middlewares: Vec::new(),
+            target: SERUM_DEX_PROGRAM_ID,
+            budget: CpiBudget::default(),
+            config: false,
         }
     }
 
-    /// Builder method for adding a middleware to the proxy.
-    pub fn middleware(mut self, mw: &'a mut dyn MarketMiddleware) -> Self {
-        self.middlewares.push(mw);
+    /// Builder method for relaying requests to another proxy program rather
+    /// than to the DEX, so that proxies can be stacked. The request then
+    /// carries, after this proxy's header (and any middleware data), the
+    /// nested proxy's header followed by the DEX instruction. Its accounts
+    /// are laid out as
+    ///
+    /// ```text
+    /// 0.   The target proxy program.
+    /// 1.   The DEX program.
+    /// ..   The DEX accounts.
+    /// ..   The nested proxy's middleware accounts.
+    /// ..   This proxy's middleware accounts.
+    /// ..   This proxy's config, if it enforces one.
+    /// ```
+    pub fn target(mut self, program_id: Pubkey) -> Self {
+        self.target = program_id;
+        self
+    }
+
+    /// Builder method for limiting the instructions middleware may queue
+    /// around the relay. Defaults to `CpiBudget::default()`.
+    pub fn cpi_budget(mut self, budget: CpiBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Builder method for enforcing the proxy's `ProxyConfig`, which every
+    /// relayed request then passes as its last account, after the
+    /// middleware accounts. Requests are rejected while the config is
+    /// paused, or unless they're relayed to the config's DEX program.
+    pub fn config(mut self) -> Self {
+        self.config = true;
+        self
+    }
+
+    /// Builder method for adding a middleware to the proxy. The middleware
+    /// runs after those added before it, unless their `Stage` is later (see
+    /// `MarketMiddleware::stage`). Positions in the pipeline, e.g., of
+    /// middleware data in the header, are those after ordering.
+    pub fn middleware(self, mw: &'a mut dyn MarketMiddleware) -> Self {
+        self.insert(MiddlewareSlot::Borrowed(mw))
+    }
+
+    /// Builder method for adding a middleware owned by the proxy, so that
+    /// pipelines can be constructed inline without borrowing locals.
+    pub fn middleware_boxed(self, mw: Box<dyn MarketMiddleware + 'a>) -> Self {
+        self.insert(MiddlewareSlot::Owned(mw))
+    }
+
+    // Inserts the middleware after all middleware of the same or an earlier
+    // stage.
+    fn insert(mut self, slot: MiddlewareSlot<'a>) -> Self {
+        let stage = slot.stage();
+        let idx = self
+            .middlewares
+            .iter()
+            .position(|mw| mw.stage() > stage)
+            .unwrap_or(self.middlewares.len());
+        self.middlewares.insert(idx, slot);
         self
     }
 
@@ -45,194 +154,782 @@ impl<'a> MarketProxy<'a> {
         accounts: &[AccountInfo],
         data: &[u8],
     ) -> ProgramResult {
-        let mut ix_data = data;
+        run_chain(
+            &mut self.middlewares,
+            &self.target,
+            &self.budget,
+            self.config,
+            program_id,
+            accounts,
+            data,
+        )
+    }
+}
 
-        // First account is the Serum DEX executable--used for CPI.
-        let dex = &accounts[0];
-        if dex.key != &SERUM_DEX_PROGRAM_ID {
-            return Err(anchor_lang::error!(ErrorCode::InvalidTargetProgram).into());
+/// A variant of the `MarketProxy` where the middleware are given as a tuple,
+/// e.g., `StaticProxy::new((OpenOrdersPda::new(), Logger::new()))`, so that the
+/// pipeline is monomorphized rather than dispatched through trait objects.
+/// Since the tuple can't be reordered, requests fail unless the middleware
+/// are given in order of their `Stage`.
+pub struct StaticProxy<C: MiddlewareChain> {
+    middlewares: C,
+    target: Pubkey,
+    budget: CpiBudget,
+    config: bool,
+}
+
+impl<C: MiddlewareChain> StaticProxy<C> {
+    /// Constructs a new `StaticProxy` running the given middleware, in order.
+    pub fn new(middlewares: C) -> Self {
+        Self {
+            middlewares,
+            target: SERUM_DEX_PROGRAM_ID,
+            budget: CpiBudget::default(),
+            config: false,
+        }
+    }
+
+    /// Builder method for relaying requests to another proxy program. See
+    /// `MarketProxy::target`.
+    pub fn target(mut self, program_id: Pubkey) -> Self {
+        self.target = program_id;
+        self
+    }
+
+    /// Builder method for limiting the instructions middleware may queue
+    /// around the relay. See `MarketProxy::cpi_budget`.
+    pub fn cpi_budget(mut self, budget: CpiBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Builder method for enforcing the proxy's `ProxyConfig`. See
+    /// `MarketProxy::config`.
+    pub fn config(mut self) -> Self {
+        self.config = true;
+        self
+    }
+
+    /// Entrypoint to the program.
+    pub fn run(
+        mut self,
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: &[u8],
+    ) -> ProgramResult {
+        if !self.middlewares.is_ordered() {
+            return Err(anchor_lang::error!(ErrorCode::InvalidMiddlewareOrder).into());
         }
-        let acc_infos = (accounts[1..]).to_vec();
+        run_chain(
+            &mut self.middlewares,
+            &self.target,
+            &self.budget,
+            self.config,
+            program_id,
+            accounts,
+            data,
+        )
+    }
+}
+
+/// Limits on the pre and post instructions middleware may queue, checked
+/// before any of them is invoked, so that a misbehaving middleware fails the
+/// request with a clear error rather than pushing the transaction over the
+/// runtime's CPI or size limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpiBudget {
+    /// Maximum number of pre and post instructions, combined.
+    pub max_instructions: usize,
+    /// Maximum number of accounts across all pre and post instructions.
+    pub max_accounts: usize,
+}
 
-        // Process the instruction data.
-        for mw in &mut self.middlewares {
-            mw.instruction(&mut ix_data)?;
+impl CpiBudget {
+    /// A budget without limits.
+    pub const UNLIMITED: Self = Self {
+        max_instructions: usize::MAX,
+        max_accounts: usize::MAX,
+    };
+
+    /// Checks the instructions queued on the context are within budget.
+    pub fn check(&self, ctx: &Context) -> ProgramResult {
+        let queued = ctx.pre_instructions.iter().chain(&ctx.post_instructions);
+        let (instructions, accounts) = queued.fold((0, 0), |(ixs, accs), (ix, _, _)| {
+            (ixs + 1, accs + ix.accounts.len())
+        });
+        if instructions > self.max_instructions || accounts > self.max_accounts {
+            msg!(
+                "middleware queued {} instructions with {} accounts, over the budget of {} with {}",
+                instructions,
+                accounts,
+                self.max_instructions,
+                self.max_accounts,
+            );
+            return Err(anchor_lang::error!(ErrorCode::CpiBudgetExceeded).into());
         }
+        Ok(())
+    }
+}
 
-        // Request context.
-        let mut ctx = Context::new(program_id, dex.key, acc_infos);
+impl Default for CpiBudget {
+    fn default() -> Self {
+        Self {
+            max_instructions: 8,
+            max_accounts: 64,
+        }
+    }
+}
 
-        // Decode instruction.
-        let mut ix = MarketInstruction::unpack(ix_data);
+/// An ordered sequence of middleware run by a proxy. Implemented for the
+/// `MarketProxy` pipeline and for tuples of up to eight middleware.
+pub trait MiddlewareChain {
+    /// Returns true if the middleware are ordered by stage.
+    fn is_ordered(&self) -> bool;
 
-        // Method dispatch.
-        match ix {
-            Some(MarketInstruction::InitOpenOrders) => {
-                if ctx.accounts.len() < 4 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.init_open_orders(&mut ctx)?;
-                }
-            }
-            Some(MarketInstruction::NewOrderV3(ref mut ix)) => {
-                if ctx.accounts.len() < 12 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.new_order_v3(&mut ctx, ix)?;
-                }
-            }
-            Some(MarketInstruction::CancelOrderV2(ref mut ix)) => {
-                if ctx.accounts.len() < 6 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.cancel_order_v2(&mut ctx, ix)?;
-                }
-            }
-            Some(MarketInstruction::CancelOrderByClientIdV2(ref mut ix)) => {
-                if ctx.accounts.len() < 6 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.cancel_order_by_client_id_v2(&mut ctx, ix)?;
-                }
+    /// Hands each middleware the request header along with its own data.
+    fn header(&mut self, header: &ProxyHeader) -> ProgramResult;
+
+    /// Runs each middleware's `instruction` hook.
+    fn instruction(&mut self, data: &mut &[u8]) -> ProgramResult;
+
+    /// Dispatches the context's instruction to each middleware in turn.
+    fn dispatch(&self, ctx: &mut Context) -> ProgramResult;
+
+    /// Runs each middleware's `before_cpi` hook.
+    fn before_cpi(&self, ctx: &mut Context) -> ProgramResult;
+
+    /// Runs each middleware's `after_cpi` hook.
+    fn after_cpi(
+        &self,
+        ctx: &mut Context,
+        result: &ProgramResult,
+        return_data: Option<&[u8]>,
+    ) -> ProgramResult;
+}
+
+impl<'a> MiddlewareChain for Vec<MiddlewareSlot<'a>> {
+    fn is_ordered(&self) -> bool {
+        self.windows(2).all(|mws| mws[0].stage() <= mws[1].stage())
+    }
+
+    fn header(&mut self, header: &ProxyHeader) -> ProgramResult {
+        for (idx, mw) in self.iter_mut().enumerate() {
+            let result = mw.header(header, header.data_for(idx));
+            report(idx, &**mw, "header", result)?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, data: &mut &[u8]) -> ProgramResult {
+        for (idx, mw) in self.iter_mut().enumerate() {
+            let result = mw.instruction(data);
+            report(idx, &**mw, "instruction", result)?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(&self, ctx: &mut Context) -> ProgramResult {
+        for (idx, mw) in self.iter().enumerate() {
+            dispatch_instruction(idx, &**mw, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn before_cpi(&self, ctx: &mut Context) -> ProgramResult {
+        for (idx, mw) in self.iter().enumerate() {
+            report(idx, &**mw, "before_cpi", mw.before_cpi(ctx))?;
+        }
+        Ok(())
+    }
+
+    fn after_cpi(
+        &self,
+        ctx: &mut Context,
+        result: &ProgramResult,
+        return_data: Option<&[u8]>,
+    ) -> ProgramResult {
+        for (idx, mw) in self.iter().enumerate() {
+            let hook_result = mw.after_cpi(ctx, result, return_data);
+            report(idx, &**mw, "after_cpi", hook_result)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! impl_middleware_chain {
+    ($($idx:tt $mw:ident),+) => {
+        impl<$($mw: MarketMiddleware),+> MiddlewareChain for ($($mw,)+) {
+            fn is_ordered(&self) -> bool {
+                let stages = [$(self.$idx.stage()),+];
+                stages.windows(2).all(|stages| stages[0] <= stages[1])
             }
-            Some(MarketInstruction::SettleFunds) => {
-                if ctx.accounts.len() < 10 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.settle_funds(&mut ctx)?;
-                }
+
+            fn header(&mut self, header: &ProxyHeader) -> ProgramResult {
+                $(
+                    let result = self.$idx.header(header, header.data_for($idx));
+                    report($idx, &self.$idx, "header", result)?;
+                )+
+                Ok(())
             }
-            Some(MarketInstruction::CloseOpenOrders) => {
-                if ctx.accounts.len() < 4 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.close_open_orders(&mut ctx)?;
-                }
+
+            fn instruction(&mut self, data: &mut &[u8]) -> ProgramResult {
+                $(
+                    let result = self.$idx.instruction(data);
+                    report($idx, &self.$idx, "instruction", result)?;
+                )+
+                Ok(())
             }
-            Some(MarketInstruction::ConsumeEvents(ref mut limit)) => {
-                if ctx.accounts.len() < 4 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.consume_events(&mut ctx, limit)?;
-                }
+
+            fn dispatch(&self, ctx: &mut Context) -> ProgramResult {
+                $(dispatch_instruction($idx, &self.$idx, ctx)?;)+
+                Ok(())
             }
-            Some(MarketInstruction::ConsumeEventsPermissioned(ref mut limit)) => {
-                if ctx.accounts.len() < 3 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.consume_events_permissioned(&mut ctx, limit)?;
-                }
+
+            fn before_cpi(&self, ctx: &mut Context) -> ProgramResult {
+                $(report($idx, &self.$idx, "before_cpi", self.$idx.before_cpi(ctx))?;)+
+                Ok(())
             }
-            Some(MarketInstruction::Prune(ref mut limit)) => {
-                if ctx.accounts.len() < 7 {
-                    return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
-                }
-                for mw in &self.middlewares {
-                    mw.prune(&mut ctx, limit)?;
-                }
+
+            fn after_cpi(
+                &self,
+                ctx: &mut Context,
+                result: &ProgramResult,
+                return_data: Option<&[u8]>,
+            ) -> ProgramResult {
+                $(
+                    let hook_result = self.$idx.after_cpi(ctx, result, return_data);
+                    report($idx, &self.$idx, "after_cpi", hook_result)?;
+                )+
+                Ok(())
             }
-            _ => {
-                for mw in &self.middlewares {
-                    mw.fallback(&mut ctx)?;
-                }
-                return Ok(());
+        }
+    };
+}
+
+impl_middleware_chain!(0 A);
+impl_middleware_chain!(0 A, 1 B);
+impl_middleware_chain!(0 A, 1 B, 2 C);
+impl_middleware_chain!(0 A, 1 B, 2 C, 3 D);
+impl_middleware_chain!(0 A, 1 B, 2 C, 3 D, 4 E);
+impl_middleware_chain!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+impl_middleware_chain!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+impl_middleware_chain!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+
+/// Processes a request with the given middleware.
+fn run_chain<C: MiddlewareChain>(
+    chain: &mut C,
+    target_id: &Pubkey,
+    budget: &CpiBudget,
+    config: bool,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    // Config and user instructions are handled by the proxy itself.
+    match data {
+        [CONFIG_INSTRUCTION_TAG, ix_data @ ..] => {
+            return process_config_instruction(program_id, accounts, ix_data)
+        }
+        [USER_INSTRUCTION_TAG, ix_data @ ..] => {
+            return process_user_instruction(program_id, accounts, ix_data)
+        }
+        _ => {}
+    }
+
+    let mut ix_data = data;
+    let infos = accounts;
+
+    // First account is the target executable--used for CPI.
+    let program = accounts.first().ok_or_else(|| -> ProgramError {
+        anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+    })?;
+    if program.key != target_id {
+        return Err(anchor_lang::error!(ErrorCode::InvalidTargetProgram).into());
+    }
+
+    // Parse the header, handing each middleware its own data.
+    let header = ProxyHeader::unpack(&mut ix_data)?;
+    chain.header(&header)?;
+
+    // Process the instruction data.
+    chain.instruction(&mut ix_data)?;
+
+    // The config, if enforced, is the last account, before the middleware
+    // accounts are split off.
+    let mut accounts = &accounts[1..];
+    let config = if config {
+        let (config_info, rest) = accounts.split_last().ok_or_else(|| -> ProgramError {
+            anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+        })?;
+        accounts = rest;
+        let config = ProxyConfig::load(program_id, config_info)?;
+        if config.paused {
+            return Err(anchor_lang::error!(ErrorCode::ProxyPaused).into());
+        }
+        Some(config)
+    } else {
+        None
+    };
+
+    // Split off the middleware accounts, so that they're never relayed.
+    let dex_accounts_len = accounts
+        .len()
+        .checked_sub(header.trailing_accounts as usize)
+        .ok_or_else(|| -> ProgramError {
+            anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+        })?;
+    let (mut accounts, trailing) = accounts.split_at(dex_accounts_len);
+
+    // A nested proxy expects its own header and accounts, which are passed
+    // through untouched.
+    let target = if program.key == &SERUM_DEX_PROGRAM_ID {
+        Target {
+            program,
+            dex: program,
+            header: &data[..0],
+            trailing: &accounts[..0],
+            infos,
+        }
+    } else {
+        let nested_data = ix_data;
+        let nested_header = ProxyHeader::unpack(&mut ix_data)?;
+        let (dex, rest) = accounts.split_first().ok_or_else(|| -> ProgramError {
+            anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+        })?;
+        if dex.key != &SERUM_DEX_PROGRAM_ID {
+            return Err(anchor_lang::error!(ErrorCode::InvalidTargetProgram).into());
+        }
+        let dex_accounts_len = rest
+            .len()
+            .checked_sub(nested_header.trailing_accounts as usize)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        let (rest, nested_trailing) = rest.split_at(dex_accounts_len);
+        accounts = rest;
+        Target {
+            program,
+            dex,
+            header: &nested_data[..nested_data.len() - ix_data.len()],
+            trailing: nested_trailing,
+            infos,
+        }
+    };
+    if config.is_some_and(|config| &config.dex_program_id != target.dex.key) {
+        return Err(anchor_lang::error!(ErrorCode::InvalidDexPid).into());
+    }
+
+    // Relay each batched instruction in sequence, with the accounts
+    // split between them in order.
+    if ix_data.first() == Some(&BATCH_TAG) {
+        let mut acc_infos = accounts;
+        for (accounts_len, data) in unpack_batch(ix_data)? {
+            if acc_infos.len() < accounts_len {
+                return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
             }
-        };
+            let (batch_accounts, rest) = acc_infos.split_at(accounts_len);
+            relay(
+                chain,
+                budget,
+                program_id,
+                &target,
+                &header,
+                batch_accounts,
+                trailing,
+                data,
+            )?;
+            acc_infos = rest;
+        }
+        if !acc_infos.is_empty() {
+            return Err(anchor_lang::error!(ErrorCode::InvalidInstruction).into());
+        }
+        return Ok(());
+    }
+
+    relay(
+        chain, budget, program_id, &target, &header, accounts, trailing, ix_data,
+    )
+}
+
+/// The program a proxy relays to: either the DEX itself or a nested proxy in
+/// front of it.
+struct Target<'b, 'info> {
+    program: &'b AccountInfo<'info>,
+    dex: &'b AccountInfo<'info>,
+    // Header of the nested proxy, prepended to the relayed instruction.
+    header: &'b [u8],
+    // Middleware accounts of the nested proxy, appended to the DEX accounts.
+    trailing: &'b [AccountInfo<'info>],
+    // All accounts given to the proxy, to invoke the target with.
+    infos: &'b [AccountInfo<'info>],
+}
+
+impl<'b, 'info> Target<'b, 'info> {
+    fn is_nested(&self) -> bool {
+        self.program.key != self.dex.key
+    }
+
+    /// Returns the instruction relaying the given DEX instruction to the
+    /// target.
+    fn instruction(&self, accounts: &RelayAccounts<'_, 'info>, ix_data: &[u8]) -> Instruction {
+        let dex = Some(self.dex).filter(|_| self.is_nested());
+        let metas = dex
+            .into_iter()
+            .chain(accounts.iter())
+            .chain(self.trailing)
+            .map(|acc| AccountMeta {
+                pubkey: *acc.key,
+                is_signer: acc.is_signer,
+                is_writable: acc.is_writable,
+            })
+            .collect();
+        let mut data = self.header.to_vec();
+        data.extend_from_slice(ix_data);
+        Instruction {
+            data,
+            accounts: metas,
+            program_id: *self.program.key,
+        }
+    }
+}
 
-        let ix_data_vec = MarketInstruction::pack(&ix.unwrap());
-        ix_data = ix_data_vec.as_slice();
+/// Runs the middleware on a single DEX instruction and relays it to the
+/// target.
+#[allow(clippy::too_many_arguments)]
+fn relay<'info, C: MiddlewareChain>(
+    chain: &C,
+    budget: &CpiBudget,
+    program_id: &Pubkey,
+    target: &Target<'_, 'info>,
+    header: &ProxyHeader,
+    accounts: &[AccountInfo<'info>],
+    trailing: &[AccountInfo<'info>],
+    data: &[u8],
+) -> ProgramResult {
+    let mut ix_data = data;
 
-        // Extract the middleware adjusted context.
-        let Context {
-            seeds,
+    // Request context.
+    let mut ctx = Context::new(program_id, target.dex.key, accounts);
+    ctx.header = header.clone();
+    ctx.set_trailing_accounts(trailing);
+
+    // Identify the instruction, leaving it to be unpacked on demand.
+    ctx.data = ix_data;
+    ctx.kind = InstructionKind::from_data(ix_data);
+    if let Some(kind) = ctx.kind {
+        check_accounts_len(kind, &ctx)?;
+    }
+
+    // Method dispatch.
+    chain.dispatch(&mut ctx)?;
+
+    // Either there's nothing to relay or a middleware handled the
+    // request itself.
+    let kind = match ctx.kind {
+        Some(kind) if !ctx.skip_relay => kind,
+        _ => return Ok(()),
+    };
+
+    // Now that the middleware are done with the accounts (e.g. marking PDAs
+    // as signers), check they match what the DEX expects, so that a crafted
+    // account list fails here rather than with an opaque error downstream.
+    // New orders may also pass the market's oracle, which only the market
+    // account tells apart from the fee discount account.
+    let oracle = if kind.accepts_oracle() && ctx.accounts.len() > kind.min_accounts() {
+        ctx.market_state()?.oracle
+    } else {
+        None
+    };
+    validate_layout(kind, &ctx.accounts, ctx.dex_program_id, oracle.as_ref())?;
+
+    let ix_data_vec = ctx.relayed_data();
+    ix_data = &ix_data_vec;
+
+    chain.before_cpi(&mut ctx)?;
+    budget.check(&ctx)?;
+
+    // Dry runs stop once the request passed all checks.
+    if ctx.header.is_dry_run() {
+        msg!("dry run");
+        return Ok(());
+    }
+
+    // Execute pre callbacks.
+    for (function, accounts, args) in std::mem::take(&mut ctx.pre_callbacks) {
+        function(program_id, accounts, ix_data.to_vec(), args, None)?;
+    }
+
+    // Execute pre instructions.
+    for (ix, acc_infos, seeds) in &ctx.pre_instructions {
+        invoke_with_seeds(ix, acc_infos, seeds)?;
+    }
+
+    // Execute the main dex relay.
+    let result = {
+        // CPI to the DEX, or to the nested proxy.
+        let ix = target.instruction(&ctx.accounts, ix_data);
+        invoke_with_seeds(&ix, target.infos, &ctx.seeds)
+    };
+    // A nested proxy forwards the DEX's return data as its own.
+    let return_data = program::get_return_data()
+        .filter(|(pid, _)| pid == target.program.key)
+        .map(|(_, data)| data);
+    chain.after_cpi(&mut ctx, &result, return_data.as_deref())?;
+    result?;
+
+    // Execute post instructions.
+    for (ix, acc_infos, seeds) in &ctx.post_instructions {
+        invoke_with_seeds(ix, acc_infos, seeds)?;
+    }
+
+    // Let indexers track the relayed instruction, which the events of
+    // instructions with arguments need decoded.
+    if kind.has_args() {
+        ctx.instruction()?;
+    }
+    if let Some(event) = RelayEvent::of(&ctx) {
+        event.emit();
+    }
+
+    // Execute post callbacks.
+    let Context { post_callbacks, .. } = ctx;
+    for (function, accounts, args) in post_callbacks {
+        function(
+            program_id,
             accounts,
-            pre_instructions,
-            post_instructions,
-            post_callbacks,
-            ..
-        } = ctx;
-
-        // Execute pre instructions.
-        for (ix, acc_infos, seeds) in pre_instructions {
-            let tmp_signers: Vec<Vec<&[u8]>> = seeds
-                .iter()
-                .map(|seeds| {
-                    let seeds: Vec<&[u8]> = seeds.iter().map(|seed| &seed[..]).collect();
-                    seeds
-                })
-                .collect();
-            let signers: Vec<&[&[u8]]> = tmp_signers.iter().map(|seeds| &seeds[..]).collect();
-            program::invoke_signed(&ix, &acc_infos, &signers)?;
-        }
-
-        // Execute the main dex relay.
-        {
-            let tmp_signers: Vec<Vec<&[u8]>> = seeds
-                .iter()
-                .map(|seeds| {
-                    let seeds: Vec<&[u8]> = seeds.iter().map(|seed| &seed[..]).collect();
-                    seeds
-                })
-                .collect();
-            let signers: Vec<&[&[u8]]> = tmp_signers.iter().map(|seeds| &seeds[..]).collect();
-
-            // CPI to the DEX.
-            let dex_accounts = accounts
-                .iter()
-                .map(|acc| AccountMeta {
-                    pubkey: *acc.key,
-                    is_signer: acc.is_signer,
-                    is_writable: acc.is_writable,
-                })
-                .collect();
-            let ix = anchor_lang::solana_program::instruction::Instruction {
-                data: ix_data.to_vec(),
-                accounts: dex_accounts,
-                program_id: SERUM_DEX_PROGRAM_ID,
-            };
-            program::invoke_signed(&ix, &accounts, &signers)?;
+            ix_data.to_vec(),
+            args,
+            return_data.clone(),
+        )?;
+    }
+
+    // Forward the DEX's return data to the proxy's caller. Set last, since
+    // any CPI made after the relay resets it.
+    if let Some(return_data) = &return_data {
+        program::set_return_data(return_data);
+    }
+
+    Ok(())
+}
+
+/// Tag prefixing instruction data that frames several DEX instructions to be
+/// relayed in sequence within a single proxy call. The rest of the data is
+/// laid out as
+///
+/// ```text
+/// 0.   Number of instructions (u8).
+/// ..   For each instruction: number of accounts (u8), data length (u16 le),
+///      and the packed `MarketInstruction`.
+/// ```
+///
+/// The accounts given to the proxy (after the DEX program) are split between
+/// the instructions in order. Since DEX instructions are versioned with a
+/// leading zero byte, the tag never collides with a single instruction.
+pub const BATCH_TAG: u8 = 0xba;
+
+/// Packs the given (number of accounts, instruction data) pairs into a
+/// batch to be relayed by the proxy.
+pub fn pack_batch(ixs: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut data = vec![BATCH_TAG, ixs.len() as u8];
+    for (accounts_len, ix_data) in ixs {
+        data.push(*accounts_len);
+        data.extend_from_slice(&(ix_data.len() as u16).to_le_bytes());
+        data.extend_from_slice(ix_data);
+    }
+    data
+}
+
+/// Splits batched instruction data into (number of accounts, instruction
+/// data) pairs.
+fn unpack_batch(data: &[u8]) -> std::result::Result<Vec<(usize, &[u8])>, ProgramError> {
+    let invalid = || -> ProgramError { anchor_lang::error!(ErrorCode::InvalidInstruction).into() };
+    let (count, mut data) = match data {
+        [BATCH_TAG, count, rest @ ..] if *count > 0 => (*count, rest),
+        _ => return Err(invalid()),
+    };
+    let mut ixs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (accounts_len, len, rest) = match data {
+            [accounts_len, len_lo, len_hi, rest @ ..] => (
+                *accounts_len as usize,
+                u16::from_le_bytes([*len_lo, *len_hi]) as usize,
+                rest,
+            ),
+            _ => return Err(invalid()),
+        };
+        if rest.len() < len {
+            return Err(invalid());
         }
+        let (ix_data, rest) = rest.split_at(len);
+        ixs.push((accounts_len, ix_data));
+        data = rest;
+    }
+    if !data.is_empty() {
+        return Err(invalid());
+    }
+    Ok(ixs)
+}
+
+/// Packs a batch cancelling the order with the given client id and settling
+/// the funds freed, relayed as a `CancelOrderByClientIdV2` followed by a
+/// `SettleFunds`, so that UIs needn't wait for the cancel to settle. The
+/// accounts (after the DEX program) are the cancel's 6 followed by the
+/// settle's 9, or 10 with the referrer's pc wallet.
+pub fn pack_cancel_and_settle(client_order_id: u64, referral: bool) -> Vec<u8> {
+    pack_batch(&[
+        (
+            6,
+            MarketInstruction::CancelOrderByClientIdV2(client_order_id).pack(),
+        ),
+        (9 + referral as u8, MarketInstruction::SettleFunds.pack()),
+    ])
+}
 
-        // Execute post instructions.
-        for (ix, acc_infos, seeds) in post_instructions {
-            let tmp_signers: Vec<Vec<&[u8]>> = seeds
-                .iter()
-                .map(|seeds| {
-                    let seeds: Vec<&[u8]> = seeds.iter().map(|seed| &seed[..]).collect();
-                    seeds
-                })
-                .collect();
-            let signers: Vec<&[&[u8]]> = tmp_signers.iter().map(|seeds| &seeds[..]).collect();
-            program::invoke_signed(&ix, &acc_infos, &signers)?;
+/// Dispatches the context's instruction to the middleware at the given
+/// position in the pipeline. Keeps the instruction unless the middleware
+/// replaced it, in which case the remaining middleware see the replacement.
+fn dispatch_instruction<M: MarketMiddleware + ?Sized>(
+    idx: usize,
+    mw: &M,
+    ctx: &mut Context,
+) -> ProgramResult {
+    let kind = ctx.kind;
+    let hook = hook_name(kind);
+    // Only the arguments handed to the middleware require unpacking.
+    if kind.is_some_and(InstructionKind::has_args) {
+        ctx.instruction()?;
+    }
+    let mut ix = ctx.instruction.take();
+    let original = ix.clone();
+    let result = dispatch(mw, ctx, kind, &mut ix);
+    report(idx, mw, hook, result)?;
+    if std::mem::take(&mut ctx.replaced) {
+        ctx.kind = ctx.instruction.as_ref().and_then(InstructionKind::of);
+    } else {
+        ctx.dirty |= ix != original;
+        ctx.instruction = ix;
+    }
+    Ok(())
+}
+
+/// Logs the position and name of the middleware, along with the hook, when
+/// the hook failed. The error itself is returned unchanged, so that clients
+/// can still decode it.
+fn report<M: MarketMiddleware + ?Sized>(
+    idx: usize,
+    mw: &M,
+    hook: &str,
+    result: ProgramResult,
+) -> ProgramResult {
+    if let Err(err) = &result {
+        msg!(
+            "middleware {} ({}) failed in {}: {}",
+            idx,
+            mw.name(),
+            hook,
+            err
+        );
+        if let (ProgramError::Custom(code), Some(ns)) = (err, mw.error_namespace()) {
+            if ns.contains(*code) {
+                msg!("{} error {}", ns.name, code - ns.offset);
+            }
         }
+    }
+    result
+}
 
-        // Execute post callbacks.
-        for (function, accounts, args) in post_callbacks {
-            function(program_id, accounts, ix_data.to_vec(), args)?;
+/// Returns the name of the middleware hook handling the given instruction.
+fn hook_name(kind: Option<InstructionKind>) -> &'static str {
+    match kind {
+        Some(InstructionKind::InitOpenOrders) => "init_open_orders",
+        Some(
+            InstructionKind::NewOrderV3
+            | InstructionKind::NewOraclePeggedOrder
+            | InstructionKind::NewGoodTilTimeOrder,
+        ) => "new_order_v3",
+        Some(InstructionKind::CancelOrderV2) => "cancel_order_v2",
+        Some(InstructionKind::CancelOrderByClientIdV2) => "cancel_order_by_client_id_v2",
+        Some(InstructionKind::SettleFunds) => "settle_funds",
+        Some(InstructionKind::CloseOpenOrders) => "close_open_orders",
+        Some(InstructionKind::ConsumeEvents) => "consume_events",
+        Some(InstructionKind::ConsumeEventsPermissioned) => "consume_events_permissioned",
+        Some(InstructionKind::Prune) => "prune",
+        Some(InstructionKind::ExpireOrders) => "expire_orders",
+        None => "fallback",
+    }
+}
+
+/// Invokes the middleware's handler for the given instruction.
+fn dispatch<M: MarketMiddleware + ?Sized>(
+    mw: &M,
+    ctx: &mut Context,
+    kind: Option<InstructionKind>,
+    ix: &mut Option<MarketInstruction>,
+) -> ProgramResult {
+    // Instructions without arguments may not have been unpacked.
+    match (kind, ix) {
+        (Some(InstructionKind::InitOpenOrders), _) => mw.init_open_orders(ctx),
+        (_, Some(MarketInstruction::NewOrderV3(ix))) => mw.new_order_v3(ctx, ix),
+        (_, Some(MarketInstruction::CancelOrderV2(ix))) => mw.cancel_order_v2(ctx, ix),
+        (_, Some(MarketInstruction::CancelOrderByClientIdV2(ix))) => {
+            mw.cancel_order_by_client_id_v2(ctx, ix)
+        }
+        (Some(InstructionKind::SettleFunds), _) => mw.settle_funds(ctx),
+        (Some(InstructionKind::CloseOpenOrders), _) => mw.close_open_orders(ctx),
+        (_, Some(MarketInstruction::ConsumeEvents(limit))) => mw.consume_events(ctx, limit),
+        (_, Some(MarketInstruction::ConsumeEventsPermissioned(limit))) => {
+            mw.consume_events_permissioned(ctx, limit)
+        }
+        (_, Some(MarketInstruction::Prune(limit))) => mw.prune(ctx, limit),
+        // Oracle pegged and good-til-time orders are new orders to the
+        // middleware, so that their checks apply to all orders alike.
+        (_, Some(MarketInstruction::NewOraclePeggedOrder(ix))) => {
+            mw.new_order_v3(ctx, &mut ix.order)
+        }
+        (_, Some(MarketInstruction::NewGoodTilTimeOrder(ix))) => {
+            mw.new_order_v3(ctx, &mut ix.order)
         }
+        (_, Some(MarketInstruction::ExpireOrders(limit))) => mw.expire_orders(ctx, limit),
+        _ => mw.fallback(ctx),
+    }
+}
 
-        Ok(())
+/// Checks the request has at least the minimum number of accounts required
+/// by the DEX instruction, so that middleware can index into them.
+fn check_accounts_len(kind: InstructionKind, ctx: &Context) -> ProgramResult {
+    if ctx.accounts.len() < kind.min_accounts() {
+        return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
     }
+    Ok(())
+}
+
+/// Invokes the given instruction, signing with all of the given seeds.
+fn invoke_with_seeds(
+    ix: &Instruction,
+    accounts: &[AccountInfo],
+    seeds: &[SignerSeeds],
+) -> ProgramResult {
+    let slices: Vec<_> = seeds.iter().map(SignerSeeds::slices).collect();
+    let signers: Vec<&[&[u8]]> = seeds
+        .iter()
+        .zip(&slices)
+        .map(|(seeds, slices)| &slices[..seeds.len()])
+        .collect();
+    program::invoke_signed(ix, accounts, &signers)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use solana_program::pubkey::Pubkey;
+    use crate::{Owner, Stage, FLAG_DRY_RUN};
+    use serum_dex::matching::Side;
     use solana_program::account_info::AccountInfo;
     use solana_program::clock::Epoch;
+    use solana_program::pubkey::Pubkey;
+    use std::borrow::Cow;
     use std::cell::RefCell;
-    use std::rc::Rc;
     use std::convert::TryInto;
-    use serum_dex::matching::Side;
+    use std::rc::Rc;
 
     fn dummy_account(is_signer: bool) -> AccountInfo<'static> {
         let key = Box::leak(Box::new(Pubkey::new_unique()));
@@ -251,12 +948,27 @@ mod tests {
         )
     }
 
+    fn program_account(key: &'static Pubkey) -> AccountInfo<'static> {
+        AccountInfo::new(
+            key,
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(Vec::new().into_boxed_slice()),
+            Box::leak(Box::new(Pubkey::default())),
+            true,
+            Epoch::default(),
+        )
+    }
+
     struct CallTracker {
         pub called: Rc<RefCell<Vec<&'static str>>>,
     }
     impl CallTracker {
         fn new() -> Self {
-            Self { called: Rc::new(RefCell::new(vec![])) }
+            Self {
+                called: Rc::new(RefCell::new(vec![])),
+            }
         }
     }
     impl MarketMiddleware for CallTracker {
@@ -268,10 +980,24 @@ mod tests {
             self.called.borrow_mut().push("init_open_orders");
             Ok(())
         }
-        fn new_order_v3(&self, _ctx: &mut Context, _ix: &mut NewOrderInstructionV3) -> ProgramResult {
+        fn new_order_v3(
+            &self,
+            _ctx: &mut Context,
+            _ix: &mut NewOrderInstructionV3,
+        ) -> ProgramResult {
             self.called.borrow_mut().push("new_order_v3");
             Ok(())
         }
+        fn cancel_order_by_client_id_v2(&self, _ctx: &mut Context, _ix: &mut u64) -> ProgramResult {
+            self.called
+                .borrow_mut()
+                .push("cancel_order_by_client_id_v2");
+            Ok(())
+        }
+        fn settle_funds(&self, _ctx: &mut Context) -> ProgramResult {
+            self.called.borrow_mut().push("settle_funds");
+            Ok(())
+        }
         fn fallback(&self, _ctx: &mut Context) -> ProgramResult {
             self.called.borrow_mut().push("fallback");
             Ok(())
@@ -279,7 +1005,38 @@ mod tests {
     }
 
     fn make_accounts(n: usize, signer_idx: Option<usize>) -> Vec<AccountInfo<'static>> {
-        (0..n).map(|i| dummy_account(signer_idx == Some(i))).collect()
+        (0..n)
+            .map(|i| dummy_account(signer_idx == Some(i)))
+            .collect()
+    }
+
+    fn with_header(ix_data: &[u8]) -> Vec<u8> {
+        let mut data = ProxyHeader::default().pack();
+        data.extend_from_slice(ix_data);
+        data
+    }
+
+    // Accounts for the given instruction, writable and owned by the programs
+    // as the DEX expects.
+    fn market_accounts(
+        kind: InstructionKind,
+        n: usize,
+        signer_idx: Option<usize>,
+    ) -> Vec<AccountInfo<'static>> {
+        let mut accounts = make_accounts(n, signer_idx);
+        for spec in kind.layout() {
+            let idx = match kind.index_of(spec.role, n) {
+                Some(idx) if idx < n => idx,
+                _ => continue,
+            };
+            accounts[idx].is_writable = spec.writable;
+            match spec.role.owner() {
+                Some(Owner::Dex) => accounts[idx].owner = &SERUM_DEX_PROGRAM_ID,
+                Some(Owner::TokenProgram) => accounts[idx].owner = &spl_token::ID,
+                None => {}
+            }
+        }
+        accounts
     }
 
     #[test]
@@ -287,21 +1044,10 @@ mod tests {
         let mut mw = CallTracker::new();
         let proxy = MarketProxy::new().middleware(&mut mw);
         let program_id = Pubkey::new_unique();
-        let mut accounts = vec![
-            // 0: DEX program (must match SERUM_DEX_PROGRAM_ID)
-            AccountInfo::new(
-                &SERUM_DEX_PROGRAM_ID,
-                false,
-                false,
-                Box::leak(Box::new(0u64)),
-                Box::leak(Vec::new().into_boxed_slice()),
-                Box::leak(Box::new(Pubkey::default())),
-                false,
-                Epoch::default(),
-            ),
-        ];
-        accounts.extend(make_accounts(4, Some(1))); // 4 more accounts, 1 is signer
-        let data = MarketInstruction::InitOpenOrders.pack();
+        // 0: DEX program (must match SERUM_DEX_PROGRAM_ID)
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::InitOpenOrders, 4, Some(1)));
+        let data = with_header(&MarketInstruction::InitOpenOrders.pack());
         let result = proxy.run(&program_id, &accounts, &data);
         assert!(result.is_ok());
         let calls = mw.called.borrow();
@@ -309,24 +1055,26 @@ mod tests {
         assert!(calls.contains(&"init_open_orders"));
     }
 
+    #[test]
+    fn test_missing_header() {
+        let mut mw = CallTracker::new();
+        let proxy = MarketProxy::new().middleware(&mut mw);
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(make_accounts(4, Some(1)));
+        let data = MarketInstruction::InitOpenOrders.pack();
+        let result = proxy.run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+        assert!(mw.called.borrow().is_empty());
+    }
+
     #[test]
     fn test_dispatch_new_order_v3() {
         let mut mw = CallTracker::new();
         let proxy = MarketProxy::new().middleware(&mut mw);
         let program_id = Pubkey::new_unique();
-        let mut accounts = vec![
-            AccountInfo::new(
-                &SERUM_DEX_PROGRAM_ID,
-                false,
-                false,
-                Box::leak(Box::new(0u64)),
-                Box::leak(Vec::new().into_boxed_slice()),
-                Box::leak(Box::new(Pubkey::default())),
-                false,
-                Epoch::default(),
-            ),
-        ];
-        accounts.extend(make_accounts(12, Some(1)));
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::NewOrderV3, 12, Some(7)));
         let ix = NewOrderInstructionV3 {
             side: Side::Bid,
             limit_price: 1u64.try_into().unwrap(),
@@ -338,7 +1086,7 @@ mod tests {
             limit: 1,
             max_ts: 0,
         };
-        let data = MarketInstruction::NewOrderV3(ix).pack();
+        let data = with_header(&MarketInstruction::NewOrderV3(ix).pack());
         let result = proxy.run(&program_id, &accounts, &data);
         assert!(result.is_ok());
         let calls = mw.called.borrow();
@@ -351,21 +1099,10 @@ mod tests {
         let mut mw = CallTracker::new();
         let proxy = MarketProxy::new().middleware(&mut mw);
         let program_id = Pubkey::new_unique();
-        let mut accounts = vec![
-            AccountInfo::new(
-                &SERUM_DEX_PROGRAM_ID,
-                false,
-                false,
-                Box::leak(Box::new(0u64)),
-                Box::leak(Vec::new().into_boxed_slice()),
-                Box::leak(Box::new(Pubkey::default())),
-                false,
-                Epoch::default(),
-            ),
-        ];
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
         accounts.extend(make_accounts(2, Some(1)));
         // Use an invalid instruction (empty data)
-        let data = vec![];
+        let data = with_header(&[]);
         let result = proxy.run(&program_id, &accounts, &data);
         assert!(result.is_ok());
         let calls = mw.called.borrow();
@@ -374,24 +1111,563 @@ mod tests {
     }
 
     #[test]
-    fn test_account_count_validation() {
+    fn test_dispatch_boxed_middleware() {
+        let mw = CallTracker::new();
+        let called = mw.called.clone();
+        let proxy = MarketProxy::new().middleware_boxed(Box::new(mw));
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::InitOpenOrders, 4, Some(1)));
+        let data = with_header(&MarketInstruction::InitOpenOrders.pack());
+        let result = proxy.run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        let calls = called.borrow();
+        assert!(calls.contains(&"instruction"));
+        assert!(calls.contains(&"init_open_orders"));
+    }
+
+    struct Handled;
+    impl MarketMiddleware for Handled {
+        fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
+            ctx.skip_relay = true;
+            Ok(())
+        }
+    }
+
+    struct Unreachable;
+    impl MarketMiddleware for Unreachable {
+        fn before_cpi(&self, _ctx: &mut Context) -> ProgramResult {
+            Err(ProgramError::Custom(1))
+        }
+    }
+
+    #[test]
+    fn test_skip_relay() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let data = with_header(&MarketInstruction::SettleFunds.pack());
+        let result = MarketProxy::new()
+            .middleware_boxed(Box::new(Handled))
+            .middleware_boxed(Box::new(Unreachable))
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        let result = MarketProxy::new()
+            .middleware_boxed(Box::new(Unreachable))
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+    }
+
+    struct Rewrite;
+    impl MarketMiddleware for Rewrite {
+        fn fallback(&self, ctx: &mut Context) -> ProgramResult {
+            ctx.set_instruction(MarketInstruction::SettleFunds);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rewrite_instruction() {
         let mut mw = CallTracker::new();
-        let proxy = MarketProxy::new().middleware(&mut mw);
         let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        // Custom instruction data, not understood by the DEX.
+        let data = with_header(&[1, 2, 3]);
+        let result = MarketProxy::new()
+            .middleware_boxed(Box::new(Rewrite))
+            .middleware(&mut mw)
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        let calls = mw.called.borrow();
+        assert!(calls.contains(&"settle_funds"));
+        assert!(!calls.contains(&"fallback"));
+    }
+
+    #[test]
+    fn test_batch_relay() {
+        let mut mw = CallTracker::new();
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::InitOpenOrders, 4, Some(1)));
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let data = with_header(&pack_batch(&[
+            (4, MarketInstruction::InitOpenOrders.pack()),
+            (10, MarketInstruction::SettleFunds.pack()),
+        ]));
+        let result = MarketProxy::new()
+            .middleware(&mut mw)
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        let calls = mw.called.borrow();
+        assert_eq!(
+            *calls,
+            vec!["instruction", "init_open_orders", "settle_funds"]
+        );
+    }
+
+    #[test]
+    fn test_cancel_and_settle_relay() {
+        let mut mw = CallTracker::new();
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(
+            InstructionKind::CancelOrderByClientIdV2,
+            6,
+            Some(4),
+        ));
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let data = pack_cancel_and_settle(7, true);
+        let ixs = unpack_batch(&data).unwrap();
+        let cancel = MarketInstruction::CancelOrderByClientIdV2(7).pack();
+        let settle = MarketInstruction::SettleFunds.pack();
+        assert_eq!(ixs, vec![(6, &cancel[..]), (10, &settle[..])]);
+
+        let result =
+            MarketProxy::new()
+                .middleware(&mut mw)
+                .run(&program_id, &accounts, &with_header(&data));
+        assert!(result.is_ok());
+        let calls = mw.called.borrow();
+        assert_eq!(
+            *calls,
+            vec![
+                "instruction",
+                "cancel_order_by_client_id_v2",
+                "settle_funds"
+            ]
+        );
+
+        // The settle's accounts are counted as given.
+        let data = with_header(&pack_cancel_and_settle(7, false));
+        assert!(MarketProxy::new()
+            .run(&program_id, &accounts, &data)
+            .is_err());
+    }
+
+    #[test]
+    fn test_layout_validation() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        // The open orders owner must sign.
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(1)));
+        let data = with_header(&MarketInstruction::SettleFunds.pack());
+        let result = MarketProxy::new().run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+
+        // The open orders account must be writable.
+        let mut accounts = accounts[..1].to_vec();
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        accounts[2].is_writable = false;
+        let result = MarketProxy::new().run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+
+        // The market must be owned by the DEX.
+        let mut accounts = accounts[..1].to_vec();
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let result = MarketProxy::new().run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        accounts[1].owner = &spl_token::ID;
+        let result = MarketProxy::new().run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_layout_validation_with_oracle() {
+        let kind = InstructionKind::NewOraclePeggedOrder;
+        let mut accounts = market_accounts(kind, 12, Some(7));
+        let oracle = dummy_account(false);
+        accounts.push(oracle.clone());
+
+        // The oracle trailing the order isn't taken for a fee discount account.
+        let relayed = RelayAccounts::new(&accounts);
+        let result = validate_layout(kind, &relayed, &SERUM_DEX_PROGRAM_ID, Some(oracle.key));
+        assert!(result.is_ok());
+        let result = validate_layout(kind, &relayed, &SERUM_DEX_PROGRAM_ID, None);
+        assert!(result.is_err());
+
+        // A fee discount account preceding the oracle is still checked.
+        let mut accounts = market_accounts(kind, 13, Some(7));
+        accounts.push(oracle.clone());
+        let relayed = RelayAccounts::new(&accounts);
+        let result = validate_layout(kind, &relayed, &SERUM_DEX_PROGRAM_ID, Some(oracle.key));
+        assert!(result.is_ok());
+        accounts[12].owner = &SERUM_DEX_PROGRAM_ID;
+        let relayed = RelayAccounts::new(&accounts);
+        let result = validate_layout(kind, &relayed, &SERUM_DEX_PROGRAM_ID, Some(oracle.key));
+        assert!(result.is_err());
+    }
+
+    fn config_account(program_id: &Pubkey, config: ProxyConfig) -> AccountInfo<'static> {
+        let (address, bump) = ProxyConfig::address(program_id);
+        let config_info = AccountInfo::new(
+            Box::leak(Box::new(address)),
+            false,
+            false,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0; ProxyConfig::LEN].into_boxed_slice()),
+            Box::leak(Box::new(*program_id)),
+            false,
+            Epoch::default(),
+        );
+        ProxyConfig { bump, ..config }.store(&config_info).unwrap();
+        config_info
+    }
+
+    #[test]
+    fn test_config_enforced() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let mut data = ProxyHeader {
+            flags: FLAG_DRY_RUN,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        data.extend_from_slice(&MarketInstruction::SettleFunds.pack());
+        let config = ProxyConfig {
+            admin: Pubkey::new_unique(),
+            paused: false,
+            fee_bps: 0,
+            fee_receiver: Pubkey::new_unique(),
+            dex_program_id: SERUM_DEX_PROGRAM_ID,
+            bump: 0,
+        };
+
+        // The config trails the request.
+        assert!(MarketProxy::new()
+            .config()
+            .run(&program_id, &accounts, &data)
+            .is_err());
+        accounts.push(config_account(&program_id, config.clone()));
+        assert!(MarketProxy::new()
+            .config()
+            .run(&program_id, &accounts, &data)
+            .is_ok());
+
+        // Requests are rejected while the proxy is paused.
+        let paused = ProxyConfig {
+            paused: true,
+            ..config.clone()
+        };
+        accounts[11] = config_account(&program_id, paused);
+        let result = MarketProxy::new()
+            .config()
+            .run(&program_id, &accounts, &data);
+        assert_eq!(
+            result,
+            Err(anchor_lang::error!(ErrorCode::ProxyPaused).into())
+        );
+
+        // Requests are only relayed to the config's DEX.
+        let other_dex = ProxyConfig {
+            dex_program_id: Pubkey::new_unique(),
+            ..config
+        };
+        accounts[11] = config_account(&program_id, other_dex);
+        let result = MarketProxy::new()
+            .config()
+            .run(&program_id, &accounts, &data);
+        assert_eq!(
+            result,
+            Err(anchor_lang::error!(ErrorCode::InvalidDexPid).into())
+        );
+    }
+
+    struct IncrementLimit;
+    impl MarketMiddleware for IncrementLimit {
+        fn prune(&self, _ctx: &mut Context, limit: &mut u16) -> ProgramResult {
+            *limit += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_repack_only_when_modified() {
+        let program_id = Pubkey::new_unique();
+        let data = MarketInstruction::Prune(3).pack();
+        let mut ctx = Context::new(&program_id, &SERUM_DEX_PROGRAM_ID, &[]);
+        ctx.data = &data;
+        ctx.kind = InstructionKind::from_data(&data);
+
+        dispatch_instruction(0, &CallTracker::new(), &mut ctx).unwrap();
+        assert!(ctx.instruction.is_some());
+        assert!(matches!(ctx.relayed_data(), Cow::Borrowed(_)));
+
+        dispatch_instruction(1, &IncrementLimit, &mut ctx).unwrap();
+        assert_eq!(
+            &*ctx.relayed_data(),
+            &MarketInstruction::Prune(4).pack()[..]
+        );
+    }
+
+    struct TakeTrailing(Rc<RefCell<Vec<Pubkey>>>);
+    impl MarketMiddleware for TakeTrailing {
+        fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
+            for acc in ctx.take_trailing(2)? {
+                self.0.borrow_mut().push(*acc.key);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trailing_accounts() {
+        let taken = Rc::new(RefCell::new(vec![]));
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let trailing = make_accounts(2, None);
+        accounts.extend(trailing.clone());
+        let mut data = ProxyHeader {
+            trailing_accounts: 2,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        data.extend_from_slice(&MarketInstruction::SettleFunds.pack());
+        let result = MarketProxy::new()
+            .middleware_boxed(Box::new(TakeTrailing(taken.clone())))
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        assert_eq!(*taken.borrow(), vec![*trailing[0].key, *trailing[1].key]);
+
+        // The middleware can't take more accounts than were appended.
+        let data = with_header(&MarketInstruction::SettleFunds.pack());
+        let result = MarketProxy::new()
+            .middleware_boxed(Box::new(TakeTrailing(taken.clone())))
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_middleware_name() {
+        let mw: &dyn MarketMiddleware = &Handled;
+        assert!(mw.name().ends_with("Handled"));
+        assert_eq!(
+            hook_name(Some(InstructionKind::SettleFunds)),
+            "settle_funds"
+        );
+        assert_eq!(hook_name(None), "fallback");
+    }
+
+    struct FailAfterCpi;
+    impl MarketMiddleware for FailAfterCpi {
+        fn after_cpi(
+            &self,
+            _ctx: &mut Context,
+            _result: &ProgramResult,
+            _return_data: Option<&[u8]>,
+        ) -> ProgramResult {
+            Err(ProgramError::Custom(2))
+        }
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut mw = CallTracker::new();
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let mut data = ProxyHeader {
+            flags: FLAG_DRY_RUN,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        data.extend_from_slice(&MarketInstruction::SettleFunds.pack());
+        let result = MarketProxy::new()
+            .middleware(&mut mw)
+            .middleware_boxed(Box::new(FailAfterCpi))
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        assert!(mw.called.borrow().contains(&"settle_funds"));
+
+        // Validation still runs.
+        accounts[3].is_signer = false;
+        let result = MarketProxy::new().run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+    }
+
+    // Queues the given number of pre instructions, each with two accounts.
+    struct Queue(usize);
+    impl MarketMiddleware for Queue {
+        fn settle_funds(&self, ctx: &mut Context) -> ProgramResult {
+            for _ in 0..self.0 {
+                let accounts = vec![
+                    AccountMeta::new(Pubkey::new_unique(), false),
+                    AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                ];
+                let ix = Instruction::new_with_bytes(Pubkey::new_unique(), &[], accounts);
+                ctx.pre_instructions.push((ix, Vec::new(), Vec::new()));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cpi_budget() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let mut data = ProxyHeader {
+            flags: FLAG_DRY_RUN,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        data.extend_from_slice(&MarketInstruction::SettleFunds.pack());
+
+        let run = |mw: Queue, budget: CpiBudget| {
+            MarketProxy::new()
+                .cpi_budget(budget)
+                .middleware_boxed(Box::new(mw))
+                .run(&program_id, &accounts, &data)
+        };
+        let exceeded: ProgramError = anchor_lang::error!(ErrorCode::CpiBudgetExceeded).into();
+        let budget = CpiBudget {
+            max_instructions: 2,
+            max_accounts: 3,
+        };
+        assert!(run(Queue(1), budget).is_ok());
+        assert_eq!(run(Queue(2), budget), Err(exceeded.clone()));
+        assert_eq!(run(Queue(3), CpiBudget::default()), Ok(()));
+        assert_eq!(run(Queue(9), CpiBudget::default()), Err(exceeded));
+        assert!(run(Queue(9), CpiBudget::UNLIMITED).is_ok());
+    }
+
+    #[test]
+    fn test_static_proxy() {
+        let mw = CallTracker::new();
+        let called = mw.called.clone();
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let data = with_header(&MarketInstruction::SettleFunds.pack());
+        let result =
+            StaticProxy::new((mw, Handled, Unreachable)).run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        assert_eq!(*called.borrow(), vec!["instruction", "settle_funds"]);
+    }
+
+    #[test]
+    fn test_nested_proxy() {
+        let mut mw = CallTracker::new();
+        let program_id = Pubkey::new_unique();
+        let nested_id = Box::leak(Box::new(Pubkey::new_unique()));
         let mut accounts = vec![
-            AccountInfo::new(
-                &SERUM_DEX_PROGRAM_ID,
-                false,
-                false,
-                Box::leak(Box::new(0u64)),
-                Box::leak(Vec::new().into_boxed_slice()),
-                Box::leak(Box::new(Pubkey::default())),
-                false,
-                Epoch::default(),
-            ),
+            program_account(nested_id),
+            program_account(&SERUM_DEX_PROGRAM_ID),
         ];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        accounts.push(dummy_account(false));
+        let mut data = ProxyHeader {
+            flags: FLAG_DRY_RUN,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        let nested_header = ProxyHeader {
+            trailing_accounts: 1,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        data.extend_from_slice(&nested_header);
+        data.extend_from_slice(&MarketInstruction::SettleFunds.pack());
+
+        // The nested proxy's account isn't mistaken for the referral.
+        let result = MarketProxy::new()
+            .target(*nested_id)
+            .middleware(&mut mw)
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        assert!(mw.called.borrow().contains(&"settle_funds"));
+
+        // Requests to the DEX are rejected.
+        let result = MarketProxy::new()
+            .target(*nested_id)
+            .run(&program_id, &accounts[1..], &data);
+        assert!(result.is_err());
+
+        // The nested proxy gets its header, the DEX program and its accounts.
+        let target = Target {
+            program: &accounts[0],
+            dex: &accounts[1],
+            header: &nested_header,
+            trailing: &accounts[12..],
+            infos: &accounts,
+        };
+        let ix_data = MarketInstruction::SettleFunds.pack();
+        let ix = target.instruction(&RelayAccounts::new(&accounts[2..12]), &ix_data);
+        assert_eq!(ix.program_id, *nested_id);
+        assert_eq!(ix.data, [&nested_header[..], &ix_data[..]].concat());
+        let keys: Vec<_> = ix.accounts.iter().map(|meta| meta.pubkey).collect();
+        let expected: Vec<_> = accounts[1..].iter().map(|acc| *acc.key).collect();
+        assert_eq!(keys, expected);
+        assert!(ix.accounts[3].is_signer);
+    }
+
+    struct Staged(Stage, &'static str, Rc<RefCell<Vec<&'static str>>>);
+    impl MarketMiddleware for Staged {
+        fn stage(&self) -> Stage {
+            self.0
+        }
+
+        fn settle_funds(&self, _ctx: &mut Context) -> ProgramResult {
+            self.2.borrow_mut().push(self.1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_middleware_stages() {
+        let called = Rc::new(RefCell::new(vec![]));
+        let staged = |stage, name| Box::new(Staged(stage, name, called.clone()));
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
+        accounts.extend(market_accounts(InstructionKind::SettleFunds, 10, Some(2)));
+        let mut data = ProxyHeader {
+            flags: FLAG_DRY_RUN,
+            ..ProxyHeader::default()
+        }
+        .pack();
+        data.extend_from_slice(&MarketInstruction::SettleFunds.pack());
+
+        let result = MarketProxy::new()
+            .middleware_boxed(staged(Stage::Observe, "logger"))
+            .middleware_boxed(staged(Stage::Sign, "pda"))
+            .middleware_boxed(staged(Stage::Default, "first"))
+            .middleware_boxed(staged(Stage::Auth, "identity"))
+            .middleware_boxed(staged(Stage::Default, "second"))
+            .run(&program_id, &accounts, &data);
+        assert!(result.is_ok());
+        assert_eq!(
+            *called.borrow(),
+            vec!["identity", "first", "second", "pda", "logger"]
+        );
+
+        // Static pipelines are checked rather than reordered.
+        let result = StaticProxy::new((
+            Staged(Stage::Sign, "pda", called.clone()),
+            Staged(Stage::Auth, "identity", called.clone()),
+        ))
+        .run(&program_id, &accounts, &data);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_unpack_batch() {
+        let data = pack_batch(&[(3, vec![1, 2]), (0, vec![])]);
+        let ixs = unpack_batch(&data).unwrap();
+        assert_eq!(ixs, vec![(3, &[1u8, 2][..]), (0, &[][..])]);
+        assert!(unpack_batch(&data[..data.len() - 1]).is_err());
+        assert!(unpack_batch(&[BATCH_TAG, 0]).is_err());
+    }
+
+    #[test]
+    fn test_account_count_validation() {
+        let mut mw = CallTracker::new();
+        let proxy = MarketProxy::new().middleware(&mut mw);
+        let program_id = Pubkey::new_unique();
+        let mut accounts = vec![program_account(&SERUM_DEX_PROGRAM_ID)];
         accounts.extend(make_accounts(3, Some(1))); // Not enough for InitOpenOrders (needs 4)
-        let data = MarketInstruction::InitOpenOrders.pack();
+        let data = with_header(&MarketInstruction::InitOpenOrders.pack());
         let result = proxy.run(&program_id, &accounts, &data);
         assert!(result.is_err());
     }
@@ -0,0 +1,683 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::token;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Seed of the `ReferralSet` PDA.
+pub const REFERRAL_SET_SEED: &[u8] = b"referral-set";
+
+/// The referrals `ReferralFees::allowed_set` lets settle through the proxy,
+/// e.g., one per frontend of a venue, stored in a PDA of the proxy program
+/// and managed by the `ProxyConfig` admin.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReferralSet {
+    /// Authorities of the allowed referral token accounts.
+    pub referrals: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl ReferralSet {
+    /// Maximum number of allowed referrals.
+    pub const CAPACITY: usize = 16;
+
+    /// Size of the set account.
+    pub const LEN: usize = 8 + 4 + 32 * Self::CAPACITY + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxreferl";
+
+    /// Returns the address and bump of the set PDA.
+    pub fn address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[REFERRAL_SET_SEED], program_id)
+    }
+
+    /// Loads the set from the given account, checking it's the program's set
+    /// PDA.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let set = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(&[REFERRAL_SET_SEED, &[set.bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(set)
+    }
+
+    /// Parses the given set account data, without checking its address.
+    pub fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidReferralSet).into()),
+        }
+        // Unused capacity trails the data.
+        Self::deserialize(&mut &data[8..]).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidReferralSet).into()
+        })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        // Zeroed, so that a shrunk set doesn't keep the removed keys around.
+        data.fill(0);
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Seed of the `ReferralBalance` PDAs.
+pub const REFERRAL_BALANCE_SEED: &[u8] = b"referral-balance";
+
+/// Seed of the PDA owning the referral treasury, i.e., the token accounts
+/// `ReferralFees::treasury` directs referral fees into.
+pub const REFERRAL_TREASURY_SEED: &[u8] = b"referral-treasury";
+
+/// Returns the address and bump of the PDA owning the referral treasury.
+pub fn referral_treasury_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REFERRAL_TREASURY_SEED], program_id)
+}
+
+/// The referral fees accrued to a referrer in the referral treasury, in a
+/// given mint, not yet claimed with `claim_referral_fees`. Stored in a PDA of
+/// the proxy program derived from the referrer and the mint.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReferralBalance {
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+impl ReferralBalance {
+    /// Size of the balance account.
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxrefbal";
+
+    /// Returns the address and bump of the balance PDA of the given referrer
+    /// in the given mint.
+    pub fn address(program_id: &Pubkey, referrer: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[REFERRAL_BALANCE_SEED, referrer.as_ref(), mint.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Loads the balance from the given account, checking it's the program's
+    /// balance PDA of the referrer and mint it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let balance = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[
+                REFERRAL_BALANCE_SEED,
+                balance.referrer.as_ref(),
+                balance.mint.as_ref(),
+                &[balance.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(balance)
+    }
+
+    fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidReferralBalance).into()),
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidReferralBalance).into()
+        })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Credits the given balance with what the given treasury account received
+/// since it held `previous` tokens, less the proxy's fee, in basis points,
+/// credited to the given fee balance.
+pub(crate) fn credit_referral_fees(
+    program_id: &Pubkey,
+    balance_info: &AccountInfo,
+    treasury: &AccountInfo,
+    previous: u64,
+    proxy_fee: Option<(&AccountInfo, u16)>,
+) -> ProgramResult {
+    let amount = token::accessor::amount(treasury).map_err(Into::<ProgramError>::into)?;
+    let received = amount.saturating_sub(previous);
+    let fee = proxy_fee.map_or(0, |(_, fee_bps)| {
+        (received as u128 * fee_bps as u128 / 10_000) as u64
+    });
+    credit(program_id, balance_info, received - fee)?;
+    match proxy_fee {
+        Some((fee_balance_info, _)) if fee > 0 => credit(program_id, fee_balance_info, fee),
+        _ => Ok(()),
+    }
+}
+
+fn credit(program_id: &Pubkey, balance_info: &AccountInfo, amount: u64) -> ProgramResult {
+    let mut balance = ReferralBalance::load(program_id, balance_info)?;
+    balance.amount = balance
+        .amount
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    balance.store(balance_info)
+}
+
+/// Pays the referral fees accrued to the signing referrer in the given mint
+/// out of the treasury, creating their balance, paid for by the referrer, on
+/// the first claim. Referrers claim once before their first referral, since
+/// fees can't be accrued to a missing balance.
+///
+/// Accounts:
+///
+/// 0. Balance PDA (writable).
+/// 1. Referrer (signer, writable).
+/// 2. System program.
+///
+/// Followed by, when the balance isn't empty:
+///
+/// 3. Treasury token account (writable).
+/// 4. Destination token account (writable).
+/// 5. Treasury PDA.
+/// 6. Token program.
+pub(crate) fn process_claim_referral_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+) -> ProgramResult {
+    let balance_info = &accounts[0];
+    let referrer = &accounts[1];
+    let (address, bump) = ReferralBalance::address(program_id, referrer.key, &mint);
+    if &address != balance_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut balance = if balance_info.owner == program_id {
+        ReferralBalance::load(program_id, balance_info)?
+    } else {
+        let system = accounts
+            .get(2)
+            .filter(|acc| acc.key == &system_program::ID)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: referrer.clone(),
+                    to: balance_info.clone(),
+                },
+                &[&[
+                    REFERRAL_BALANCE_SEED,
+                    referrer.key.as_ref(),
+                    mint.as_ref(),
+                    &[bump],
+                ]],
+            ),
+            Rent::get()?.minimum_balance(ReferralBalance::LEN),
+            ReferralBalance::LEN as u64,
+            program_id,
+        )?;
+        ReferralBalance {
+            referrer: *referrer.key,
+            mint,
+            amount: 0,
+            bump,
+        }
+    };
+
+    if balance.amount > 0 {
+        let (treasury, destination, treasury_authority, token_program) = match accounts.get(3..7) {
+            Some([treasury, destination, authority, program]) => {
+                (treasury, destination, authority, program)
+            }
+            _ => return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()),
+        };
+        if token_program.key != &spl_token::ID {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let treasury_mint = token::accessor::mint(treasury).map_err(Into::<ProgramError>::into)?;
+        if treasury_mint != mint {
+            return Err(anchor_lang::error!(ErrorCode::InvalidReferralBalance).into());
+        }
+        let (address, bump) = referral_treasury_address(program_id);
+        if &address != treasury_authority.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let ix = spl_token::instruction::transfer(
+            token_program.key,
+            treasury.key,
+            destination.key,
+            treasury_authority.key,
+            &[],
+            balance.amount,
+        )?;
+        let acc_infos = [
+            treasury.clone(),
+            destination.clone(),
+            treasury_authority.clone(),
+        ];
+        solana_program::program::invoke_signed(
+            &ix,
+            &acc_infos,
+            &[&[REFERRAL_TREASURY_SEED, &[bump]]],
+        )?;
+        balance.amount = 0;
+    }
+    balance.store(balance_info)
+}
+
+/// Seed of the `ReferralCode` PDAs.
+pub const REFERRAL_CODE_SEED: &[u8] = b"referral-code";
+
+/// The referral token account of a frontend, registered by the frontend
+/// under a code of its choosing, stored in a PDA of the proxy program derived
+/// from the code, so that clients pass the code in the proxy header instead
+/// of proxies hard-coding the account (see `ReferralFees::codes`).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReferralCode {
+    pub code: [u8; 8],
+    /// The frontend, who registered the code and alone can update it.
+    pub owner: Pubkey,
+    /// The token account referral fees are sent to.
+    pub referral: Pubkey,
+    pub bump: u8,
+}
+
+impl ReferralCode {
+    /// Size of the code account.
+    pub const LEN: usize = 8 + 8 + 32 + 32 + 1;
+
+    const DISCRIMINATOR: [u8; 8] = *b"pxrefcod";
+
+    /// Returns the address and bump of the PDA of the given code.
+    pub fn address(program_id: &Pubkey, code: &[u8; 8]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[REFERRAL_CODE_SEED, code], program_id)
+    }
+
+    /// Loads the code from the given account, checking it's the program's PDA
+    /// of the code it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let code = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[REFERRAL_CODE_SEED, &code.code, &[code.bump]],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(code)
+    }
+
+    fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidReferralCode).into()),
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| -> ProgramError {
+            anchor_lang::error!(ErrorCode::InvalidReferralCode).into()
+        })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Registers the given code for the signing frontend, or, if the frontend
+/// already holds it, points it to another referral or (given `None`) removes
+/// it, closing its account and refunding the rent to the frontend. Codes are
+/// first come, first served.
+///
+/// Accounts:
+///
+/// 0. Code PDA (writable).
+/// 1. Frontend (signer, writable), paying for the account.
+/// 2. System program, when registering the code.
+pub(crate) fn process_set_referral_code(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    code: [u8; 8],
+    referral: Option<Pubkey>,
+) -> ProgramResult {
+    let code_info = &accounts[0];
+    let owner = &accounts[1];
+    let (address, bump) = ReferralCode::address(program_id, &code);
+    if &address != code_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    if code_info.owner == program_id {
+        if &ReferralCode::load(program_id, code_info)?.owner != owner.key {
+            return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+        }
+    } else if referral.is_some() {
+        let system = accounts
+            .get(2)
+            .filter(|acc| acc.key == &system_program::ID)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: owner.clone(),
+                    to: code_info.clone(),
+                },
+                &[&[REFERRAL_CODE_SEED, &code, &[bump]]],
+            ),
+            Rent::get()?.minimum_balance(ReferralCode::LEN),
+            ReferralCode::LEN as u64,
+            program_id,
+        )?;
+    }
+
+    let referral = match referral {
+        Some(referral) => referral,
+        None => {
+            let lamports = code_info.lamports();
+            **code_info.try_borrow_mut_lamports()? = 0;
+            let balance = owner.lamports().checked_add(lamports).unwrap();
+            **owner.try_borrow_mut_lamports()? = balance;
+            code_info.try_borrow_mut_data()?.fill(0);
+            return Ok(());
+        }
+    };
+    ReferralCode {
+        code,
+        owner: *owner.key,
+        referral,
+        bump,
+    }
+    .store(code_info)
+}
+
+/// Adds the given referral to, or removes it from, the `ReferralSet`,
+/// creating the set, paid for by the admin, on the first addition. The
+/// caller checks the admin.
+///
+/// Accounts:
+///
+/// 0. Config PDA.
+/// 1. Admin (signer, writable).
+/// 2. Set PDA (writable).
+/// 3. System program, when adding the first referral.
+pub(crate) fn process_set_referral(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    referral: Pubkey,
+    allowed: bool,
+) -> ProgramResult {
+    let admin = &accounts[1];
+    let set_info = accounts.get(2).ok_or_else(|| -> ProgramError {
+        anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+    })?;
+    let (address, bump) = ReferralSet::address(program_id);
+    if &address != set_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let mut set = if set_info.owner == program_id {
+        ReferralSet::load(program_id, set_info)?
+    } else {
+        let system = accounts
+            .get(3)
+            .filter(|acc| acc.key == &system_program::ID)
+            .ok_or_else(|| -> ProgramError {
+                anchor_lang::error!(ErrorCode::NotEnoughAccounts).into()
+            })?;
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: admin.clone(),
+                    to: set_info.clone(),
+                },
+                &[&[REFERRAL_SET_SEED, &[bump]]],
+            ),
+            Rent::get()?.minimum_balance(ReferralSet::LEN),
+            ReferralSet::LEN as u64,
+            program_id,
+        )?;
+        ReferralSet {
+            referrals: Vec::new(),
+            bump,
+        }
+    };
+    if !allowed {
+        set.referrals.retain(|key| key != &referral);
+    } else if !set.referrals.contains(&referral) {
+        if set.referrals.len() == ReferralSet::CAPACITY {
+            return Err(anchor_lang::error!(ErrorCode::InvalidReferralSet).into());
+        }
+        set.referrals.push(referral);
+    }
+    set.store(set_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        process_config_instruction, process_user_instruction, ConfigInstruction, ProxyConfig,
+        UserInstruction,
+    };
+    use solana_program::clock::Epoch;
+
+    fn account(key: Pubkey, owner: Pubkey, is_signer: bool, len: usize) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            is_signer,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; len].into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    fn token_account(mint: Pubkey, authority: Pubkey, amount: u64) -> AccountInfo<'static> {
+        let acc_info = account(Pubkey::new_unique(), spl_token::ID, false, 165);
+        let mut data = acc_info.try_borrow_mut_data().unwrap();
+        data[..32].copy_from_slice(mint.as_ref());
+        data[32..64].copy_from_slice(authority.as_ref());
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        drop(data);
+        acc_info
+    }
+
+    #[test]
+    fn test_accrue_and_claim_referral_fees() {
+        let program_id = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let referrer = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        let (address, bump) = ReferralBalance::address(&program_id, referrer.key, &mint);
+        let balance_info = account(address, program_id, false, ReferralBalance::LEN);
+        ReferralBalance {
+            referrer: *referrer.key,
+            mint,
+            amount: 5,
+            bump,
+        }
+        .store(&balance_info)
+        .unwrap();
+        let (treasury_authority, _) = referral_treasury_address(&program_id);
+        let treasury = token_account(mint, treasury_authority, 100);
+        credit_referral_fees(&program_id, &balance_info, &treasury, 90, None).unwrap();
+        let balance = ReferralBalance::load(&program_id, &balance_info).unwrap();
+        assert_eq!(balance.amount, 15);
+
+        // The proxy's fee is accrued to the fee receiver's balance.
+        let fee_receiver = Pubkey::new_unique();
+        let (address, bump) = ReferralBalance::address(&program_id, &fee_receiver, &mint);
+        let fee_balance_info = account(address, program_id, false, ReferralBalance::LEN);
+        ReferralBalance {
+            referrer: fee_receiver,
+            mint,
+            amount: 0,
+            bump,
+        }
+        .store(&fee_balance_info)
+        .unwrap();
+        let proxy_fee = Some((&fee_balance_info, 2_500));
+        credit_referral_fees(&program_id, &balance_info, &treasury, 80, proxy_fee).unwrap();
+        let balance = ReferralBalance::load(&program_id, &balance_info).unwrap();
+        assert_eq!(balance.amount, 30);
+        let fee_balance = ReferralBalance::load(&program_id, &fee_balance_info).unwrap();
+        assert_eq!(fee_balance.amount, 5);
+
+        let accounts = |treasury: AccountInfo<'static>| {
+            [
+                balance_info.clone(),
+                referrer.clone(),
+                account(system_program::ID, Pubkey::default(), false, 0),
+                treasury,
+                token_account(mint, *referrer.key, 0),
+                account(treasury_authority, Pubkey::default(), false, 0),
+                account(spl_token::ID, Pubkey::default(), false, 0),
+            ]
+        };
+        let ix = UserInstruction::ClaimReferralFees { mint };
+        let other_mint = token_account(Pubkey::new_unique(), treasury_authority, 100);
+        let accounts_of_other_mint = accounts(other_mint);
+        assert!(
+            process_user_instruction(&program_id, &accounts_of_other_mint, &ix.pack()[1..])
+                .is_err()
+        );
+        process_user_instruction(&program_id, &accounts(treasury), &ix.pack()[1..]).unwrap();
+        let balance = ReferralBalance::load(&program_id, &balance_info).unwrap();
+        assert_eq!(balance.amount, 0);
+
+        // Balances are claimed by their referrer only.
+        let mut spoofed = accounts_of_other_mint;
+        spoofed[1] = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        assert!(process_user_instruction(&program_id, &spoofed, &ix.pack()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_set_referral_code() {
+        let program_id = Pubkey::new_unique();
+        let code = *b"frontend";
+        let owner = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        let (address, bump) = ReferralCode::address(&program_id, &code);
+        let code_info = account(address, program_id, false, ReferralCode::LEN);
+        ReferralCode {
+            code,
+            owner: *owner.key,
+            referral: Pubkey::new_unique(),
+            bump,
+        }
+        .store(&code_info)
+        .unwrap();
+
+        let referral = Pubkey::new_unique();
+        let accounts = [code_info.clone(), owner.clone()];
+        process_set_referral_code(&program_id, &accounts, code, Some(referral)).unwrap();
+        assert_eq!(
+            ReferralCode::load(&program_id, &code_info)
+                .unwrap()
+                .referral,
+            referral
+        );
+
+        // The code can't be taken over by another frontend.
+        let other = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        let spoofed = [code_info.clone(), other];
+        assert!(process_set_referral_code(&program_id, &spoofed, code, Some(referral)).is_err());
+        assert!(process_set_referral_code(&program_id, &spoofed, code, None).is_err());
+
+        process_set_referral_code(&program_id, &accounts, code, None).unwrap();
+        assert!(ReferralCode::load(&program_id, &code_info).is_err());
+    }
+
+    #[test]
+    fn test_add_and_remove_referrals() {
+        let program_id = Pubkey::new_unique();
+        let admin = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        let (address, bump) = ProxyConfig::address(&program_id);
+        let config_info = account(address, program_id, false, ProxyConfig::LEN);
+        ProxyConfig {
+            admin: *admin.key,
+            paused: false,
+            fee_bps: 0,
+            fee_receiver: Pubkey::new_unique(),
+            dex_program_id: Pubkey::new_unique(),
+            bump,
+        }
+        .store(&config_info)
+        .unwrap();
+        let (address, bump) = ReferralSet::address(&program_id);
+        let set_info = account(address, program_id, false, ReferralSet::LEN);
+        ReferralSet {
+            referrals: Vec::new(),
+            bump,
+        }
+        .store(&set_info)
+        .unwrap();
+        let accounts = [config_info, admin, set_info.clone()];
+
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        for referral in [first, second, first] {
+            let ix = ConfigInstruction::AddReferral { referral };
+            process_config_instruction(&program_id, &accounts, &ix.pack()[1..]).unwrap();
+        }
+        let set = ReferralSet::load(&program_id, &set_info).unwrap();
+        assert_eq!(set.referrals, vec![first, second]);
+
+        let ix = ConfigInstruction::RemoveReferral { referral: first };
+        process_config_instruction(&program_id, &accounts, &ix.pack()[1..]).unwrap();
+        let set = ReferralSet::load(&program_id, &set_info).unwrap();
+        assert_eq!(set.referrals, vec![second]);
+
+        // Only the admin manages the set.
+        let mut spoofed = accounts.clone();
+        spoofed[1] = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        assert!(process_config_instruction(&program_id, &spoofed, &ix.pack()[1..]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Seed of the `OpenOrdersRegistry` PDAs.
+pub const OPEN_ORDERS_REGISTRY_SEED: &[u8] = b"open-orders-registry";
+
+/// The open orders PDAs a user initialized on a market through
+/// `OpenOrdersPda::registry`, stored in a PDA of the proxy program derived
+/// from the market and the user, so that indexers and other programs can
+/// find a user's open orders accounts (e.g. of every index) by reading a
+/// single account. Closed open orders accounts stay listed.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OpenOrdersRegistry {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    /// In the order they were initialized.
+    pub open_orders: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl OpenOrdersRegistry {
+    /// Maximum number of open orders accounts listed per user and market.
+    pub const CAPACITY: usize = 8;
+
+    /// Size of the registry account.
+    pub const LEN: usize = 8 + 32 + 32 + 4 + 32 * Self::CAPACITY + 1;
+
+    /// Prefix of the registry account data, e.g. for filtering the program's
+    /// accounts by type.
+    pub const DISCRIMINATOR: [u8; 8] = *b"pxregist";
+
+    /// Returns the address and bump of the registry PDA of the given user on
+    /// the given market.
+    pub fn address(program_id: &Pubkey, market: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[OPEN_ORDERS_REGISTRY_SEED, market.as_ref(), owner.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Loads the registry from the given account, checking it's the
+    /// program's registry PDA of the market and user it records.
+    pub fn load(
+        program_id: &Pubkey,
+        acc_info: &AccountInfo,
+    ) -> std::result::Result<Self, ProgramError> {
+        if acc_info.owner != program_id {
+            return Err(anchor_lang::error!(ErrorCode::InvalidAccountOwner).into());
+        }
+        let registry = Self::unpack(&acc_info.try_borrow_data()?)?;
+        let address = Pubkey::create_program_address(
+            &[
+                OPEN_ORDERS_REGISTRY_SEED,
+                registry.market.as_ref(),
+                registry.owner.as_ref(),
+                &[registry.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if &address != acc_info.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(registry)
+    }
+
+    /// Parses the given registry account data, without checking its address.
+    pub fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        match data.get(..8) {
+            Some(disc) if disc == Self::DISCRIMINATOR => {}
+            _ => return Err(anchor_lang::error!(ErrorCode::InvalidRegistry).into()),
+        }
+        // Unused capacity trails the data.
+        Self::deserialize(&mut &data[8..])
+            .map_err(|_| -> ProgramError { anchor_lang::error!(ErrorCode::InvalidRegistry).into() })
+    }
+
+    pub(crate) fn store(&self, acc_info: &AccountInfo) -> ProgramResult {
+        let mut buf = Self::DISCRIMINATOR.to_vec();
+        self.serialize(&mut buf).unwrap();
+        let mut data = acc_info.try_borrow_mut_data()?;
+        if data.len() < buf.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..buf.len()].copy_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Lists the given open orders account in the registry of the given user on
+/// the given market, creating the registry, paid for by the user, if needed.
+pub(crate) fn register_open_orders<'info>(
+    program_id: &Pubkey,
+    registry_info: &AccountInfo<'info>,
+    owner: &AccountInfo<'info>,
+    system: &AccountInfo<'info>,
+    market: &Pubkey,
+    open_orders: &Pubkey,
+) -> ProgramResult {
+    let (address, bump) = OpenOrdersRegistry::address(program_id, market, owner.key);
+    if &address != registry_info.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    let mut registry = if registry_info.owner == program_id {
+        OpenOrdersRegistry::load(program_id, registry_info)?
+    } else {
+        if system.key != &system_program::ID {
+            return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+        }
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system.clone(),
+                system_program::CreateAccount {
+                    from: owner.clone(),
+                    to: registry_info.clone(),
+                },
+                &[&[
+                    OPEN_ORDERS_REGISTRY_SEED,
+                    market.as_ref(),
+                    owner.key.as_ref(),
+                    &[bump],
+                ]],
+            ),
+            Rent::get()?.minimum_balance(OpenOrdersRegistry::LEN),
+            OpenOrdersRegistry::LEN as u64,
+            program_id,
+        )?;
+        OpenOrdersRegistry {
+            market: *market,
+            owner: *owner.key,
+            open_orders: Vec::new(),
+            bump,
+        }
+    };
+    if !registry.open_orders.contains(open_orders) {
+        if registry.open_orders.len() == OpenOrdersRegistry::CAPACITY {
+            return Err(anchor_lang::error!(ErrorCode::InvalidRegistry).into());
+        }
+        registry.open_orders.push(*open_orders);
+    }
+    registry.store(registry_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    fn account(key: Pubkey, owner: Pubkey, len: usize) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            false,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; len].into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_register_open_orders() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let owner = account(Pubkey::new_unique(), Pubkey::default(), 0);
+        let system = account(system_program::ID, Pubkey::default(), 0);
+        let (address, bump) = OpenOrdersRegistry::address(&program_id, &market, owner.key);
+        let registry_info = account(address, program_id, OpenOrdersRegistry::LEN);
+        OpenOrdersRegistry {
+            market,
+            owner: *owner.key,
+            open_orders: Vec::new(),
+            bump,
+        }
+        .store(&registry_info)
+        .unwrap();
+
+        let open_orders: Vec<_> = (0..=OpenOrdersRegistry::CAPACITY)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        let register = |open_orders: &Pubkey| {
+            register_open_orders(
+                &program_id,
+                &registry_info,
+                &owner,
+                &system,
+                &market,
+                open_orders,
+            )
+        };
+        register(&open_orders[0]).unwrap();
+        register(&open_orders[0]).unwrap();
+        let registry = OpenOrdersRegistry::load(&program_id, &registry_info).unwrap();
+        assert_eq!(registry.open_orders, vec![open_orders[0]]);
+
+        for key in &open_orders[1..OpenOrdersRegistry::CAPACITY] {
+            register(key).unwrap();
+        }
+        let registry = OpenOrdersRegistry::load(&program_id, &registry_info).unwrap();
+        assert_eq!(
+            registry.open_orders,
+            open_orders[..OpenOrdersRegistry::CAPACITY]
+        );
+        assert!(register(&open_orders[OpenOrdersRegistry::CAPACITY]).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use serum_dex::instruction::MarketInstruction;
+
+/// DEX instructions relayed by the proxy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionKind {
+    InitOpenOrders,
+    NewOrderV3,
+    CancelOrderV2,
+    CancelOrderByClientIdV2,
+    SettleFunds,
+    CloseOpenOrders,
+    ConsumeEvents,
+    ConsumeEventsPermissioned,
+    Prune,
+    NewOraclePeggedOrder,
+    NewGoodTilTimeOrder,
+    ExpireOrders,
+}
+
+/// The role an account plays within a DEX instruction. Resolved to an index
+/// into the request's accounts via `InstructionKind::index_of`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Market,
+    OpenOrders,
+    /// Owner of the open orders account, i.e., the user. Replaced by the
+    /// open orders PDA when using `OpenOrdersPda`.
+    Authority,
+    RequestQueue,
+    EventQueue,
+    Bids,
+    Asks,
+    /// The (coin or price currency) token account paying for an order.
+    OrderPayer,
+    CoinVault,
+    PcVault,
+    CoinWallet,
+    PcWallet,
+    VaultSigner,
+    TokenProgram,
+    Rent,
+    /// The (M)SRM account used for fee discounts.
+    FeeDiscount,
+    /// The referrer's pc wallet.
+    Referral,
+    /// Recipient of the rent exemption SOL when closing open orders.
+    Destination,
+    /// The market's open orders (init) authority.
+    MarketAuthority,
+    PruneAuthority,
+    CrankAuthority,
+}
+
+impl InstructionKind {
+    /// Returns the kind of the given instruction, if it's relayed by the
+    /// proxy.
+    pub fn of(ix: &MarketInstruction) -> Option<Self> {
+        match ix {
+            MarketInstruction::InitOpenOrders => Some(Self::InitOpenOrders),
+            MarketInstruction::NewOrderV3(_) => Some(Self::NewOrderV3),
+            MarketInstruction::CancelOrderV2(_) => Some(Self::CancelOrderV2),
+            MarketInstruction::CancelOrderByClientIdV2(_) => Some(Self::CancelOrderByClientIdV2),
+            MarketInstruction::SettleFunds => Some(Self::SettleFunds),
+            MarketInstruction::CloseOpenOrders => Some(Self::CloseOpenOrders),
+            MarketInstruction::ConsumeEvents(_) => Some(Self::ConsumeEvents),
+            MarketInstruction::ConsumeEventsPermissioned(_) => {
+                Some(Self::ConsumeEventsPermissioned)
+            }
+            MarketInstruction::Prune(_) => Some(Self::Prune),
+            MarketInstruction::NewOraclePeggedOrder(_) => Some(Self::NewOraclePeggedOrder),
+            MarketInstruction::NewGoodTilTimeOrder(_) => Some(Self::NewGoodTilTimeOrder),
+            MarketInstruction::ExpireOrders(_) => Some(Self::ExpireOrders),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of the instruction packed in the given data, if it's
+    /// relayed by the proxy, from its tag and length alone, i.e., without
+    /// unpacking it.
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let (tag, args) = match data {
+            [0, a, b, c, d, args @ ..] => (u32::from_le_bytes([*a, *b, *c, *d]), args),
+            _ => return None,
+        };
+        match (tag, args.len()) {
+            (15, 0) => Some(Self::InitOpenOrders),
+            (10, 46) | (10, 54) => Some(Self::NewOrderV3),
+            (11, 20) => Some(Self::CancelOrderV2),
+            (12, 8) => Some(Self::CancelOrderByClientIdV2),
+            (5, 0) => Some(Self::SettleFunds),
+            (14, 0) => Some(Self::CloseOpenOrders),
+            (3, 2) => Some(Self::ConsumeEvents),
+            (17, 2) => Some(Self::ConsumeEventsPermissioned),
+            (16, 2) => Some(Self::Prune),
+            (21, 56) => Some(Self::NewOraclePeggedOrder),
+            (22, 62) => Some(Self::NewGoodTilTimeOrder),
+            (23, 2) => Some(Self::ExpireOrders),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the instruction has arguments, which are handed to the
+    /// middleware, requiring the instruction to be unpacked.
+    pub fn has_args(self) -> bool {
+        !matches!(
+            self,
+            Self::InitOpenOrders | Self::SettleFunds | Self::CloseOpenOrders
+        )
+    }
+
+    /// Returns true if the market's oracle may trail the accounts of the
+    /// instruction, i.e., for new orders.
+    pub fn accepts_oracle(self) -> bool {
+        matches!(
+            self,
+            Self::NewOrderV3 | Self::NewOraclePeggedOrder | Self::NewGoodTilTimeOrder
+        )
+    }
+
+    /// Returns the index of the account with the given role in the DEX
+    /// account layout of this instruction, given the total number of
+    /// accounts (needed for the variable length consume events layouts).
+    pub fn index_of(self, role: Role, accounts_len: usize) -> Option<usize> {
+        match self {
+            Self::InitOpenOrders => match role {
+                Role::OpenOrders => Some(0),
+                Role::Authority => Some(1),
+                Role::Market => Some(2),
+                Role::Rent => Some(3),
+                Role::MarketAuthority => Some(4),
+                _ => None,
+            },
+            Self::NewOrderV3 | Self::NewOraclePeggedOrder | Self::NewGoodTilTimeOrder => match role
+            {
+                Role::Market => Some(0),
+                Role::OpenOrders => Some(1),
+                Role::RequestQueue => Some(2),
+                Role::EventQueue => Some(3),
+                Role::Bids => Some(4),
+                Role::Asks => Some(5),
+                Role::OrderPayer => Some(6),
+                Role::Authority => Some(7),
+                Role::CoinVault => Some(8),
+                Role::PcVault => Some(9),
+                Role::TokenProgram => Some(10),
+                Role::Rent => Some(11),
+                Role::FeeDiscount => Some(12),
+                _ => None,
+            },
+            Self::CancelOrderV2 | Self::CancelOrderByClientIdV2 => match role {
+                Role::Market => Some(0),
+                Role::Bids => Some(1),
+                Role::Asks => Some(2),
+                Role::OpenOrders => Some(3),
+                Role::Authority => Some(4),
+                Role::EventQueue => Some(5),
+                _ => None,
+            },
+            Self::SettleFunds => match role {
+                Role::Market => Some(0),
+                Role::OpenOrders => Some(1),
+                Role::Authority => Some(2),
+                Role::CoinVault => Some(3),
+                Role::PcVault => Some(4),
+                Role::CoinWallet => Some(5),
+                Role::PcWallet => Some(6),
+                Role::VaultSigner => Some(7),
+                Role::TokenProgram => Some(8),
+                Role::Referral => Some(9),
+                _ => None,
+            },
+            Self::CloseOpenOrders => match role {
+                Role::OpenOrders => Some(0),
+                Role::Authority => Some(1),
+                Role::Destination => Some(2),
+                Role::Market => Some(3),
+                _ => None,
+            },
+            Self::ConsumeEvents => match role {
+                Role::Market => accounts_len.checked_sub(4),
+                Role::EventQueue => accounts_len.checked_sub(3),
+                Role::CoinWallet => accounts_len.checked_sub(2),
+                Role::PcWallet => accounts_len.checked_sub(1),
+                _ => None,
+            },
+            Self::ConsumeEventsPermissioned => match role {
+                Role::Market => accounts_len.checked_sub(3),
+                Role::EventQueue => accounts_len.checked_sub(2),
+                Role::CrankAuthority => accounts_len.checked_sub(1),
+                _ => None,
+            },
+            Self::Prune => match role {
+                Role::Market => Some(0),
+                Role::Bids => Some(1),
+                Role::Asks => Some(2),
+                Role::PruneAuthority => Some(3),
+                Role::OpenOrders => Some(4),
+                Role::Authority => Some(5),
+                Role::EventQueue => Some(6),
+                _ => None,
+            },
+            Self::ExpireOrders => match role {
+                Role::Market => Some(0),
+                Role::Bids => Some(1),
+                Role::Asks => Some(2),
+                Role::EventQueue => Some(3),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_layout_index() {
+        assert_eq!(
+            InstructionKind::NewOrderV3.index_of(Role::Authority, 12),
+            Some(7)
+        );
+        assert_eq!(
+            InstructionKind::SettleFunds.index_of(Role::Referral, 10),
+            Some(9)
+        );
+        assert_eq!(
+            InstructionKind::CloseOpenOrders.index_of(Role::Bids, 4),
+            None
+        );
+        assert_eq!(
+            InstructionKind::NewGoodTilTimeOrder.index_of(Role::FeeDiscount, 13),
+            Some(12)
+        );
+        assert_eq!(
+            InstructionKind::ExpireOrders.index_of(Role::EventQueue, 4),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_variable_layout_index() {
+        let kind = InstructionKind::ConsumeEvents;
+        assert_eq!(kind.index_of(Role::Market, 6), Some(2));
+        assert_eq!(kind.index_of(Role::PcWallet, 6), Some(5));
+        assert_eq!(kind.index_of(Role::Market, 3), None);
+    }
+
+    #[test]
+    fn test_kind_from_data() {
+        let ixs = [
+            MarketInstruction::InitOpenOrders,
+            MarketInstruction::SettleFunds,
+            MarketInstruction::CancelOrderByClientIdV2(7),
+            MarketInstruction::ConsumeEventsPermissioned(5),
+            MarketInstruction::Prune(1),
+            MarketInstruction::ExpireOrders(4),
+            MarketInstruction::DisableMarket,
+        ];
+        for ix in ixs.iter() {
+            assert_eq!(
+                InstructionKind::from_data(&ix.pack()),
+                InstructionKind::of(ix)
+            );
+        }
+        assert_eq!(InstructionKind::from_data(&[1, 5, 0, 0, 0]), None);
+        assert_eq!(InstructionKind::from_data(&[0, 5, 0, 0, 0, 0]), None);
+    }
+}
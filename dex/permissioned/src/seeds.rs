@@ -0,0 +1,284 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::pubkey::{PubkeyError, MAX_SEED_LEN};
+
+/// Seeds of a PDA signing for a CPI, stored inline rather than as nested
+/// vectors, so that signing doesn't allocate per seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignerSeeds {
+    // The seeds, concatenated.
+    buf: [u8; SignerSeeds::CAPACITY],
+    // End offset of each seed in `buf`.
+    ends: [u8; SignerSeeds::MAX_SEEDS],
+    len: u8,
+}
+
+impl SignerSeeds {
+    /// Total number of seed bytes that can be stored.
+    pub const CAPACITY: usize = 128;
+
+    /// Maximum number of seeds.
+    pub const MAX_SEEDS: usize = 8;
+
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; Self::CAPACITY],
+            ends: [0; Self::MAX_SEEDS],
+            len: 0,
+        }
+    }
+
+    /// Constructs the seeds from the given slices.
+    pub fn from_seeds(seeds: &[&[u8]]) -> std::result::Result<Self, ProgramError> {
+        let mut signer_seeds = Self::new();
+        for seed in seeds {
+            signer_seeds.push(seed)?;
+        }
+        Ok(signer_seeds)
+    }
+
+    /// Seeds of a user's open orders account PDA.
+    pub fn open_orders(
+        dex_program: &Pubkey,
+        market: &Pubkey,
+        authority: &Pubkey,
+        bump: u8,
+    ) -> Self {
+        Self::open_orders_indexed(dex_program, market, authority, 0, bump)
+    }
+
+    /// Seeds of a user's open orders account PDA with the given index, so
+    /// that a user can operate several open orders accounts on a market,
+    /// e.g., one per strategy. Index 0 is the user's original open orders
+    /// PDA, derived without an index.
+    pub fn open_orders_indexed(
+        dex_program: &Pubkey,
+        market: &Pubkey,
+        authority: &Pubkey,
+        index: u8,
+        bump: u8,
+    ) -> Self {
+        // At most 109 bytes in 6 seeds, within capacity.
+        let mut seeds = Self::from_seeds(&[
+            b"open-orders",
+            dex_program.as_ref(),
+            market.as_ref(),
+            authority.as_ref(),
+        ])
+        .unwrap();
+        if index != 0 {
+            seeds.push(&[index]).unwrap();
+        }
+        seeds.push(&[bump]).unwrap();
+        seeds
+    }
+
+    /// Returns the address and canonical bump of the open orders PDA with the
+    /// given index (see `open_orders_indexed`).
+    pub fn open_orders_address(
+        program_id: &Pubkey,
+        dex_program: &Pubkey,
+        market: &Pubkey,
+        authority: &Pubkey,
+        index: u8,
+    ) -> (Pubkey, u8) {
+        let index = [index];
+        let mut seeds = vec![
+            b"open-orders".as_ref(),
+            dex_program.as_ref(),
+            market.as_ref(),
+            authority.as_ref(),
+        ];
+        if index[0] != 0 {
+            seeds.push(&index);
+        }
+        Pubkey::find_program_address(&seeds, program_id)
+    }
+
+    /// Seeds of a market's open orders init authority PDA.
+    pub fn open_orders_init(dex_program: &Pubkey, market: &Pubkey, bump: u8) -> Self {
+        // 81 bytes in 4 seeds, within capacity.
+        Self::from_seeds(&[
+            b"open-orders-init",
+            dex_program.as_ref(),
+            market.as_ref(),
+            &[bump],
+        ])
+        .unwrap()
+    }
+
+    /// Appends a seed.
+    pub fn push(&mut self, seed: &[u8]) -> std::result::Result<(), ProgramError> {
+        let start = self.end();
+        if seed.len() > MAX_SEED_LEN
+            || self.len() == Self::MAX_SEEDS
+            || start + seed.len() > Self::CAPACITY
+        {
+            return Err(ProgramError::MaxSeedLengthExceeded);
+        }
+        self.buf[start..start + seed.len()].copy_from_slice(seed);
+        self.ends[self.len()] = (start + seed.len()) as u8;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns the number of seeds.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the seed at the given index.
+    pub fn get(&self, idx: usize) -> Option<&[u8]> {
+        if idx >= self.len() {
+            return None;
+        }
+        let start = match idx {
+            0 => 0,
+            _ => self.ends[idx - 1] as usize,
+        };
+        Some(&self.buf[start..self.ends[idx] as usize])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (0..self.len()).filter_map(move |idx| self.get(idx))
+    }
+
+    /// Returns the seeds as slices, padded with empty slices. The first
+    /// `len()` slices are what `invoke_signed` expects for the signer.
+    pub fn slices(&self) -> [&[u8]; Self::MAX_SEEDS] {
+        let mut slices = [&[][..]; Self::MAX_SEEDS];
+        for (slice, seed) in slices.iter_mut().zip(self.iter()) {
+            *slice = seed;
+        }
+        slices
+    }
+
+    /// Returns the address of the PDA of the given program for the seeds.
+    pub fn create_program_address(
+        &self,
+        program_id: &Pubkey,
+    ) -> std::result::Result<Pubkey, PubkeyError> {
+        let slices = self.slices();
+        Pubkey::create_program_address(&slices[..self.len()], program_id)
+    }
+
+    fn end(&self) -> usize {
+        match self.len() {
+            0 => 0,
+            len => self.ends[len - 1] as usize,
+        }
+    }
+}
+
+impl Default for SignerSeeds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the address and canonical bump of a user's open orders PDA, the
+/// signer of `open_orders_authority!`.
+pub fn open_orders_address(
+    program_id: &Pubkey,
+    dex_program: &Pubkey,
+    market: &Pubkey,
+    authority: &Pubkey,
+) -> (Pubkey, u8) {
+    SignerSeeds::open_orders_address(program_id, dex_program, market, authority, 0)
+}
+
+/// Returns the address and canonical bump of a market's open orders init
+/// authority PDA, the signer of `open_orders_init_authority!`.
+pub fn open_orders_init_authority_address(
+    program_id: &Pubkey,
+    dex_program: &Pubkey,
+    market: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"open-orders-init", dex_program.as_ref(), market.as_ref()],
+        program_id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signer_seeds() {
+        let seeds = SignerSeeds::from_seeds(&[b"a", b"", b"bcd"]).unwrap();
+        assert_eq!(seeds.len(), 3);
+        let collected: Vec<&[u8]> = seeds.iter().collect();
+        assert_eq!(collected, vec![&b"a"[..], &b""[..], &b"bcd"[..]]);
+        assert_eq!(seeds.get(3), None);
+
+        let mut seeds = SignerSeeds::new();
+        assert!(seeds.push(&[0; MAX_SEED_LEN + 1]).is_err());
+        for _ in 0..4 {
+            seeds.push(&[1; MAX_SEED_LEN]).unwrap();
+        }
+        assert!(seeds.push(&[1]).is_err());
+        assert_eq!(seeds.len(), 4);
+    }
+
+    #[test]
+    fn test_open_orders_seeds() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (address, bump) = Pubkey::find_program_address(
+            &[
+                b"open-orders".as_ref(),
+                dex_program_id.as_ref(),
+                market.as_ref(),
+                user.as_ref(),
+            ],
+            &program_id,
+        );
+        let seeds = SignerSeeds::open_orders(&dex_program_id, &market, &user, bump);
+        assert_eq!(seeds.create_program_address(&program_id), Ok(address));
+        assert_eq!(
+            SignerSeeds::open_orders_address(&program_id, &dex_program_id, &market, &user, 0),
+            (address, bump)
+        );
+
+        let (indexed, bump) =
+            SignerSeeds::open_orders_address(&program_id, &dex_program_id, &market, &user, 3);
+        assert_ne!(indexed, address);
+        let seeds = SignerSeeds::open_orders_indexed(&dex_program_id, &market, &user, 3, bump);
+        assert_eq!(seeds.len(), 6);
+        assert_eq!(seeds.create_program_address(&program_id), Ok(indexed));
+    }
+
+    #[test]
+    fn test_pda_addresses() {
+        let program_id = Pubkey::new_unique();
+        let dex_program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+
+        let (address, bump) = open_orders_address(&program_id, &dex_program_id, &market, &user);
+        let seeds = crate::open_orders_authority! {
+            program = &program_id,
+            dex_program = dex_program_id,
+            market = market,
+            authority = user
+        };
+        assert_eq!(seeds.create_program_address(&program_id), Ok(address));
+        assert_eq!(seeds.get(4), Some(&[bump][..]));
+
+        let (address, bump) =
+            open_orders_init_authority_address(&program_id, &dex_program_id, &market);
+        let seeds = crate::open_orders_init_authority! {
+            program = &program_id,
+            dex_program = dex_program_id,
+            market = market,
+            bump = bump
+        };
+        assert_eq!(seeds.create_program_address(&program_id), Ok(address));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use serum_dex::state::{
+    AccountFlag, MarketState, MarketStateV2, ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING,
+};
+use std::mem::size_of;
+
+/// Fields of a DEX market account, parsed once per request by
+/// `Context::market_state` and shared by all middleware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketHeader {
+    pub account_flags: u64,
+    pub own_address: Pubkey,
+    pub vault_signer_nonce: u64,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub req_q: Pubkey,
+    pub event_q: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub fee_rate_bps: u64,
+    /// Set for permissioned markets.
+    pub open_orders_authority: Option<Pubkey>,
+    /// Set for permissioned markets.
+    pub prune_authority: Option<Pubkey>,
+    /// Set for permissioned markets.
+    pub consume_events_authority: Option<Pubkey>,
+    /// Set for markets with an oracle, pricing their oracle pegged orders.
+    pub oracle: Option<Pubkey>,
+}
+
+impl MarketHeader {
+    /// Parses the data of a market account. See `market_state_view`.
+    pub fn unpack(data: &[u8]) -> std::result::Result<Self, ProgramError> {
+        let state = market_state_view(data)?;
+        let account_flags = state.account_flags;
+        let authorities = if account_flags & AccountFlag::Permissioned as u64 != 0 {
+            let state = market_state_v2_view(data)?;
+            Some((
+                state.open_orders_authority,
+                state.prune_authority,
+                state.consume_events_authority,
+            ))
+        } else {
+            None
+        };
+        let oracle = market_state_v2_view(data)
+            .ok()
+            .and_then(|state| state.oracle().copied());
+        let key = |words: [u64; 4]| Pubkey::new_from_array(bytemuck::cast(words));
+
+        Ok(Self {
+            account_flags,
+            own_address: key(state.own_address),
+            vault_signer_nonce: state.vault_signer_nonce,
+            coin_mint: key(state.coin_mint),
+            pc_mint: key(state.pc_mint),
+            coin_vault: key(state.coin_vault),
+            pc_vault: key(state.pc_vault),
+            req_q: key(state.req_q),
+            event_q: key(state.event_q),
+            bids: key(state.bids),
+            asks: key(state.asks),
+            coin_lot_size: state.coin_lot_size,
+            pc_lot_size: state.pc_lot_size,
+            fee_rate_bps: state.fee_rate_bps,
+            open_orders_authority: authorities.map(|keys| keys.0),
+            prune_authority: authorities.map(|keys| keys.1),
+            consume_events_authority: authorities.map(|keys| keys.2),
+            oracle,
+        })
+    }
+}
+
+/// Returns a zero-copy view of the `MarketState` in the given market account
+/// data. The view has the layout of the DEX crate the proxy is built
+/// against, and the data must be exactly the size of a (V1 or V2) market in
+/// that layout, within its padding, so that a layout change between DEX
+/// versions fails loudly instead of reading the wrong bytes.
+pub fn market_state_view(data: &[u8]) -> std::result::Result<&MarketState, ProgramError> {
+    let state = match unpadded(data)? {
+        state if state.len() == size_of::<MarketState>() => state,
+        state if state.len() == size_of::<MarketStateV2>() => &state[..size_of::<MarketState>()],
+        _ => return Err(invalid_market()),
+    };
+    let state: &MarketState = bytemuck::from_bytes(state);
+    let required = AccountFlag::Initialized as u64 | AccountFlag::Market as u64;
+    if state.account_flags & required != required {
+        return Err(invalid_market());
+    }
+    Ok(state)
+}
+
+/// Returns a zero-copy view of the `MarketStateV2` of a permissioned market
+/// in the given account data. See `market_state_view`.
+pub fn market_state_v2_view(data: &[u8]) -> std::result::Result<&MarketStateV2, ProgramError> {
+    market_state_view(data)?;
+    match unpadded(data)? {
+        state if state.len() == size_of::<MarketStateV2>() => Ok(bytemuck::from_bytes(state)),
+        _ => Err(invalid_market()),
+    }
+}
+
+// Strips the padding framing every DEX account.
+fn unpadded(data: &[u8]) -> std::result::Result<&[u8], ProgramError> {
+    let padding = ACCOUNT_HEAD_PADDING.len() + ACCOUNT_TAIL_PADDING.len();
+    if data.len() < padding
+        || !data.starts_with(ACCOUNT_HEAD_PADDING)
+        || !data.ends_with(ACCOUNT_TAIL_PADDING)
+    {
+        return Err(invalid_market());
+    }
+    Ok(&data[ACCOUNT_HEAD_PADDING.len()..data.len() - ACCOUNT_TAIL_PADDING.len()])
+}
+
+fn invalid_market() -> ProgramError {
+    anchor_lang::error!(ErrorCode::InvalidMarket).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market_data(flags: u64, words: &[(usize, u64)]) -> Vec<u8> {
+        let len = match flags & AccountFlag::Permissioned as u64 {
+            0 => size_of::<MarketState>(),
+            _ => size_of::<MarketStateV2>(),
+        };
+        let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+        data.resize(5 + len, 0);
+        data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+        let mut set = |idx: usize, val: u64| {
+            data[5 + idx * 8..5 + idx * 8 + 8].copy_from_slice(&val.to_le_bytes());
+        };
+        set(0, flags);
+        for (idx, val) in words {
+            set(*idx, *val);
+        }
+        data
+    }
+
+    #[test]
+    fn test_unpack_market_header() {
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::Market as u64;
+        let data = market_data(flags, &[(1, 7), (43, 100), (44, 10)]);
+        let header = MarketHeader::unpack(&data).unwrap();
+        assert_eq!(header.own_address.to_bytes()[0], 7);
+        assert_eq!(header.coin_lot_size, 100);
+        assert_eq!(header.pc_lot_size, 10);
+        assert_eq!(header.open_orders_authority, None);
+        assert_eq!(header.oracle, None);
+
+        let data = market_data(
+            flags | AccountFlag::Permissioned as u64,
+            &[(47, 9), (59, 3)],
+        );
+        let header = MarketHeader::unpack(&data).unwrap();
+        assert_eq!(header.open_orders_authority.unwrap().to_bytes()[0], 9);
+        assert_eq!(header.oracle.unwrap().to_bytes()[0], 3);
+
+        assert!(MarketHeader::unpack(&market_data(AccountFlag::Initialized as u64, &[])).is_err());
+        assert!(MarketHeader::unpack(&data[1..]).is_err());
+    }
+
+    #[test]
+    fn test_market_state_view_checks_size() {
+        let flags = AccountFlag::Initialized as u64 | AccountFlag::Market as u64;
+        let mut data = market_data(flags, &[(43, 100)]);
+        assert_eq!({ market_state_view(&data).unwrap().coin_lot_size }, 100);
+        assert!(market_state_v2_view(&data).is_err());
+
+        // A market of another layout is rejected rather than misread.
+        data.splice(5..5, [0; 8]);
+        assert!(market_state_view(&data).is_err());
+    }
+}
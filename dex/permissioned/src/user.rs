@@ -0,0 +1,176 @@
+use crate::{
+    process_cache_market_bumps, process_claim_referral_fees, process_set_delegate,
+    process_set_referral_code, ErrorCode,
+};
+use anchor_lang::prelude::*;
+use spl_token::solana_program::entrypoint::ProgramResult;
+
+/// Tag prefixing instruction data addressed to the proxy's user
+/// instructions. The rest of the data is a Borsh serialized
+/// `UserInstruction`. Handled by the proxy itself, like config instructions,
+/// before any middleware runs.
+pub const USER_INSTRUCTION_TAG: u8 = 0xc1;
+
+/// Instructions handled by the proxy itself on behalf of its users, managing
+/// the per user `TradingDelegate`s, the per market `MarketBumps`, and the
+/// frontends' referrals. Each is signed by the user it concerns, unlike
+/// `ConfigInstruction`s, which are signed by the config's admin.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum UserInstruction {
+    /// Registers, replaces, or (given `None`) removes the signer's
+    /// `TradingDelegate` on the given market.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Delegate PDA (writable).
+    /// 1. User (signer, writable), paying for the account.
+    /// 2. System program, when registering the first delegate.
+    SetDelegate {
+        market: Pubkey,
+        delegate: Option<Pubkey>,
+    },
+    /// Creates the `MarketBumps` cache of the given market. Permissionless.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Cache PDA (writable).
+    /// 1. Payer (signer, writable).
+    /// 2. System program.
+    CacheMarketBumps {
+        dex_program_id: Pubkey,
+        market: Pubkey,
+    },
+    /// Pays the signing referrer's `ReferralBalance` in the given mint out of
+    /// the referral treasury, creating the balance on the first claim.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Balance PDA (writable).
+    /// 1. Referrer (signer, writable), paying for the balance account.
+    /// 2. System program.
+    /// 3. Treasury token account (writable), unless the balance is empty.
+    /// 4. Destination token account (writable), unless the balance is empty.
+    /// 5. Treasury PDA, unless the balance is empty.
+    /// 6. Token program, unless the balance is empty.
+    ClaimReferralFees { mint: Pubkey },
+    /// Registers, updates, or (given `None`) removes the signer's
+    /// `ReferralCode`.
+    ///
+    /// Accounts:
+    ///
+    /// 0. Code PDA (writable).
+    /// 1. Frontend (signer, writable), paying for the account.
+    /// 2. System program, when registering the code.
+    SetReferralCode {
+        code: [u8; 8],
+        referral: Option<Pubkey>,
+    },
+}
+
+impl UserInstruction {
+    /// Packs the instruction data to send to the proxy.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![USER_INSTRUCTION_TAG];
+        self.serialize(&mut data).unwrap();
+        data
+    }
+}
+
+/// Processes a `UserInstruction`, given the data following the tag.
+pub fn process_user_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let ix = UserInstruction::deserialize(&mut &data[..]).map_err(|_| -> ProgramError {
+        anchor_lang::error!(ErrorCode::InvalidInstruction).into()
+    })?;
+    if accounts.len() < 2 {
+        return Err(anchor_lang::error!(ErrorCode::NotEnoughAccounts).into());
+    }
+    if !accounts[1].is_signer {
+        return Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into());
+    }
+
+    match ix {
+        UserInstruction::SetDelegate { market, delegate } => {
+            process_set_delegate(program_id, accounts, market, delegate)
+        }
+        UserInstruction::CacheMarketBumps {
+            dex_program_id,
+            market,
+        } => process_cache_market_bumps(program_id, accounts, dex_program_id, market),
+        UserInstruction::ClaimReferralFees { mint } => {
+            process_claim_referral_fees(program_id, accounts, mint)
+        }
+        UserInstruction::SetReferralCode { code, referral } => {
+            process_set_referral_code(program_id, accounts, code, referral)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MarketProxy, TradingDelegate, CONFIG_INSTRUCTION_TAG};
+    use solana_program::clock::Epoch;
+
+    fn account(key: Pubkey, owner: Pubkey, is_signer: bool, len: usize) -> AccountInfo<'static> {
+        AccountInfo::new(
+            Box::leak(Box::new(key)),
+            is_signer,
+            true,
+            Box::leak(Box::new(0u64)),
+            Box::leak(vec![0u8; len].into_boxed_slice()),
+            Box::leak(Box::new(owner)),
+            false,
+            Epoch::default(),
+        )
+    }
+
+    #[test]
+    fn test_user_instructions_handled_by_proxy() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let owner = account(Pubkey::new_unique(), Pubkey::default(), true, 0);
+        let (address, bump) = TradingDelegate::address(&program_id, &market, owner.key);
+        let delegate_info = account(address, program_id, false, TradingDelegate::LEN);
+        TradingDelegate {
+            owner: *owner.key,
+            market,
+            delegate: Pubkey::new_unique(),
+            bump,
+        }
+        .store(&delegate_info)
+        .unwrap();
+        let accounts = [delegate_info.clone(), owner.clone()];
+
+        // Signed by the user, without any config.
+        let delegate = Pubkey::new_unique();
+        let ix = UserInstruction::SetDelegate {
+            market,
+            delegate: Some(delegate),
+        };
+        MarketProxy::new()
+            .run(&program_id, &accounts, &ix.pack())
+            .unwrap();
+        let record = TradingDelegate::load(&program_id, &delegate_info).unwrap();
+        assert_eq!(record.delegate, delegate);
+
+        let unsigned = [
+            delegate_info.clone(),
+            account(*owner.key, Pubkey::default(), false, 0),
+        ];
+        assert_eq!(
+            MarketProxy::new().run(&program_id, &unsigned, &ix.pack()),
+            Err(anchor_lang::error!(ErrorCode::UnauthorizedUser).into())
+        );
+
+        // User instructions aren't config instructions.
+        let mut data = ix.pack();
+        data[0] = CONFIG_INSTRUCTION_TAG;
+        assert!(MarketProxy::new()
+            .run(&program_id, &accounts, &data)
+            .is_err());
+    }
+}
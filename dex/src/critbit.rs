@@ -10,11 +10,14 @@ use static_assertions::const_assert_eq;
 use std::{
     convert::{identity, TryFrom},
     mem::{align_of, size_of},
-    num::NonZeroU64,
+    num::{NonZeroU32, NonZeroU64},
 };
 
 pub type NodeHandle = u32;
 
+/// The largest client order id of a good-til-time order.
+pub const MAX_GOOD_TIL_TIME_CLIENT_ORDER_ID: u64 = (1 << 48) - 1;
+
 #[derive(IntoPrimitive, TryFromPrimitive)]
 #[repr(u32)]
 enum NodeTag {
@@ -46,13 +49,18 @@ impl InnerNode {
     }
 }
 
+// The expiry of a good-til-time order is a unix timestamp of 32 bits, with its
+// high half kept in `expiry_hi` and its low half in the top 16 bits of
+// `client_order_id`, leaving 48 bits to the client order id. As any expiry in
+// the future has a nonzero high half, `expiry_hi` is zero for the orders
+// without an expiry only, whose client order id takes all 64 bits.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(packed)]
 pub struct LeafNode {
     tag: u32,
     owner_slot: u8,
     fee_tier: u8,
-    padding: [u8; 2],
+    expiry_hi: u16,
     key: u128,
     owner: [u64; 4],
     quantity: u64,
@@ -70,12 +78,23 @@ impl LeafNode {
         quantity: u64,
         fee_tier: FeeTier,
         client_order_id: u64,
+        expiry_ts: Option<NonZeroU32>,
     ) -> Self {
+        let (expiry_hi, client_order_id) = match expiry_ts {
+            None => (0, client_order_id),
+            Some(expiry_ts) => {
+                assert!(client_order_id <= MAX_GOOD_TIL_TIME_CLIENT_ORDER_ID);
+                let expiry_ts = expiry_ts.get();
+                assert!(expiry_ts >> 16 != 0);
+                let expiry_lo = (expiry_ts & 0xffff) as u64;
+                ((expiry_ts >> 16) as u16, expiry_lo << 48 | client_order_id)
+            }
+        };
         LeafNode {
             tag: NodeTag::LeafNode.into(),
             owner_slot,
             fee_tier: fee_tier.into(),
-            padding: [0; 2],
+            expiry_hi,
             key,
             owner,
             quantity,
@@ -90,7 +109,12 @@ impl LeafNode {
 
     #[inline]
     pub fn price(&self) -> NonZeroU64 {
-        NonZeroU64::new((self.key >> 64) as u64).unwrap()
+        NonZeroU64::new(crate::matching::extract_price_from_order_id(self.key)).unwrap()
+    }
+
+    #[inline]
+    pub fn peg_offset(&self) -> Option<i16> {
+        crate::matching::extract_peg_offset_from_order_id(self.key)
     }
 
     #[inline]
@@ -120,7 +144,29 @@ impl LeafNode {
 
     #[inline]
     pub fn client_order_id(&self) -> u64 {
-        self.client_order_id
+        match self.expiry_hi {
+            0 => self.client_order_id,
+            _ => self.client_order_id & MAX_GOOD_TIL_TIME_CLIENT_ORDER_ID,
+        }
+    }
+
+    /// The unix timestamp after which the order is expired, if it's a
+    /// good-til-time order.
+    #[inline]
+    pub fn expiry_ts(&self) -> Option<NonZeroU32> {
+        match self.expiry_hi {
+            0 => None,
+            expiry_hi => {
+                let expiry_lo = (self.client_order_id >> 48) as u32;
+                NonZeroU32::new((expiry_hi as u32) << 16 | expiry_lo)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_expired(&self, unix_timestamp: i64) -> bool {
+        self.expiry_ts()
+            .is_some_and(|expiry_ts| unix_timestamp > expiry_ts.get() as i64)
     }
 }
 
@@ -510,8 +556,30 @@ impl Slab {
         Some(self.header().root_node)
     }
 
+    // Returns the root of the subtree of the oracle pegged orders, or of
+    // those at a fixed price, which differ in the top bit of their keys.
+    fn partition_root(&self, oracle_pegged: bool) -> Option<NodeHandle> {
+        let root = self.root()?;
+        let root_contents = self.get(root).unwrap();
+        match root_contents.case().unwrap() {
+            NodeRef::Inner(&InnerNode {
+                prefix_len: 0,
+                children,
+                ..
+            }) => Some(children[oracle_pegged as usize]),
+            _ => {
+                let root_key = root_contents.key().unwrap();
+                let root_is_pegged = root_key & crate::matching::ORACLE_PEGGED_FLAG != 0;
+                Some(root).filter(|_| root_is_pegged == oracle_pegged)
+            }
+        }
+    }
+
     fn find_min_max(&self, find_max: bool) -> Option<NodeHandle> {
-        let mut root: NodeHandle = self.root()?;
+        self.find_min_max_from(self.root()?, find_max)
+    }
+
+    fn find_min_max_from(&self, mut root: NodeHandle, find_max: bool) -> Option<NodeHandle> {
         loop {
             let root_contents = self.get(root).unwrap();
             match root_contents.case().unwrap() {
@@ -534,6 +602,20 @@ impl Slab {
         self.find_min_max(true)
     }
 
+    /// Like `find_min`, among either the oracle pegged orders or those at a
+    /// fixed price.
+    #[inline]
+    pub fn find_min_of(&self, oracle_pegged: bool) -> Option<NodeHandle> {
+        self.find_min_max_from(self.partition_root(oracle_pegged)?, false)
+    }
+
+    /// Like `find_max`, among either the oracle pegged orders or those at a
+    /// fixed price.
+    #[inline]
+    pub fn find_max_of(&self, oracle_pegged: bool) -> Option<NodeHandle> {
+        self.find_min_max_from(self.partition_root(oracle_pegged)?, true)
+    }
+
     #[inline]
     pub fn insert_leaf(
         &mut self,
@@ -891,7 +973,7 @@ mod tests {
                 let key = rng.gen();
                 let owner = rng.gen();
                 let qty = rng.gen();
-                let leaf = LeafNode::new(offset, key, owner, qty, FeeTier::Base, 0);
+                let leaf = LeafNode::new(offset, key, owner, qty, FeeTier::Base, 0, None);
 
                 println!("{:x}", key);
                 println!("{}", i);
@@ -923,6 +1005,23 @@ mod tests {
                 let slab_max = slab.get(slab.find_max().unwrap()).unwrap();
                 let model_max = model.iter().next_back().unwrap().1;
                 assert_eq!(bytes_of(slab_max), bytes_of(model_max));
+
+                // test find_min_of and find_max_of
+                for &oracle_pegged in &[false, true] {
+                    let model_partition: Vec<_> = model
+                        .values()
+                        .filter(|leaf| (leaf.key >> 127 == 1) == oracle_pegged)
+                        .map(bytes_of)
+                        .collect();
+                    let slab_min = slab
+                        .find_min_of(oracle_pegged)
+                        .map(|h| slab.get(h).unwrap());
+                    assert_eq!(slab_min.map(bytes_of), model_partition.first().copied());
+                    let slab_max = slab
+                        .find_max_of(oracle_pegged)
+                        .map(|h| slab.get(h).unwrap());
+                    assert_eq!(slab_max.map(bytes_of), model_partition.last().copied());
+                }
             }
         }
     }
@@ -986,7 +1085,7 @@ mod tests {
                         };
                         let owner = rng.gen();
                         let qty = rng.gen();
-                        let leaf = LeafNode::new(offset, key, owner, qty, FeeTier::Base, 5);
+                        let leaf = LeafNode::new(offset, key, owner, qty, FeeTier::Base, 5, None);
 
                         println!("Insert {:x}", key);
 
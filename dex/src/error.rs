@@ -112,6 +112,12 @@ pub enum DexErrorCode {
     WouldSelfTrade,
     InvalidOpenOrdersAuthority,
     OrderMaxTimestampExceeded,
+    WrongOracleAccount,
+    StaleOraclePrice,
+    PegLimitExceeded,
+    InvalidLimitPrice,
+    InvalidExpiry,
+    ClientOrderIdTooLarge,
 
     Unknown = 1000,
 
@@ -129,6 +129,28 @@ pub struct NewOrderInstructionV3 {
     pub max_ts: i64,
 }
 
+/// A new order whose price follows the market's oracle, at the given offset
+/// from the oracle price, in pc lots per coin lot. Its limit price is its peg
+/// limit, the worst price it trades at, which its funds are locked at.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub struct NewOraclePeggedOrderInstruction {
+    pub order: NewOrderInstructionV3,
+    pub peg_offset: i16,
+}
+
+/// A new order which, once resting, expires after the given unix timestamp,
+/// to be removed by the matching engine or the `ExpireOrders` crank. Its client
+/// order id must fit in 48 bits.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub struct NewGoodTilTimeOrderInstruction {
+    pub order: NewOrderInstructionV3,
+    pub expiry_ts: i64,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(Arbitrary))]
 pub struct NewOrderInstructionV2 {
@@ -283,6 +305,7 @@ impl NewOrderInstructionV1 {
             0 => OrderType::Limit,
             1 => OrderType::ImmediateOrCancel,
             2 => OrderType::PostOnly,
+            3 => OrderType::PostOnlySlide,
             _ => return None,
         };
         Some(NewOrderInstructionV1 {
@@ -338,6 +361,7 @@ pub enum MarketInstruction {
     /// 10. `[]` open orders market authority (optional)
     /// 11. `[]` prune authority (optional, requires open orders market authority)
     /// 12. `[]` crank authority (optional, requires prune authority)
+    /// 13. `[]` oracle (optional, requires crank authority)
     InitializeMarket(InitializeMarketInstruction),
     /// 0. `[writable]` the market
     /// 1. `[writable]` the OpenOrders account to use
@@ -417,6 +441,7 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` the rent sysvar
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[]` the oracle, if the market has one
     NewOrderV3(NewOrderInstructionV3),
     /// 0. `[writable]` market
     /// 1. `[writable]` bids
@@ -444,6 +469,7 @@ pub enum MarketInstruction {
     /// 9. `[writable]` pc vault
     /// 10. `[]` spl token program
     /// 11. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 12. `[]` the oracle, if the market has one
     SendTake(SendTakeInstruction),
     /// 0. `[writable]` OpenOrders
     /// 1. `[signer]` the OpenOrders owner
@@ -491,6 +517,7 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` the rent sysvar
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[]` the oracle, if the market has one
     ReplaceOrderByClientId(NewOrderInstructionV3),
     /// 0. `[writable]` the market
     /// 1. `[writable]` the OpenOrders account to use
@@ -505,6 +532,7 @@ pub enum MarketInstruction {
     /// 10. `[]` spl token program
     /// 11. `[]` the rent sysvar
     /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[]` the oracle, if the market has one
     #[cfg_attr(
         test,
         proptest(
@@ -512,6 +540,44 @@ pub enum MarketInstruction {
         )
     )]
     ReplaceOrdersByClientIds(Vec<NewOrderInstructionV3>),
+    /// 0. `[writable]` the market
+    /// 1. `[writable]` the OpenOrders account to use
+    /// 2. `[writable]` the request queue
+    /// 3. `[writable]` the event queue
+    /// 4. `[writable]` bids
+    /// 5. `[writable]` asks
+    /// 6. `[writable]` the (coin or price currency) account paying for the order
+    /// 7. `[signer]` owner of the OpenOrders account
+    /// 8. `[writable]` coin vault
+    /// 9. `[writable]` pc vault
+    /// 10. `[]` spl token program
+    /// 11. `[]` the rent sysvar
+    /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[]` the oracle of the market
+    NewOraclePeggedOrder(NewOraclePeggedOrderInstruction),
+    /// 0. `[writable]` the market
+    /// 1. `[writable]` the OpenOrders account to use
+    /// 2. `[writable]` the request queue
+    /// 3. `[writable]` the event queue
+    /// 4. `[writable]` bids
+    /// 5. `[writable]` asks
+    /// 6. `[writable]` the (coin or price currency) account paying for the order
+    /// 7. `[signer]` owner of the OpenOrders account
+    /// 8. `[writable]` coin vault
+    /// 9. `[writable]` pc vault
+    /// 10. `[]` spl token program
+    /// 11. `[]` the rent sysvar
+    /// 12. `[]` (optional) the (M)SRM account used for fee discounts
+    /// 13. `[]` the oracle, if the market has one
+    NewGoodTilTimeOrder(NewGoodTilTimeOrderInstruction),
+    /// Removes the expired good-til-time orders from the orderbook. Anyone
+    /// can call it.
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[writable]` bids
+    /// 2. `[writable]` asks
+    /// 3. `[writable]` event queue
+    ExpireOrders(u16),
 }
 
 impl MarketInstruction {
@@ -647,6 +713,26 @@ impl MarketInstruction {
                     .collect::<Option<Vec<_>>>()?;
                 MarketInstruction::ReplaceOrdersByClientIds(new_orders)
             }
+            (21, 56) => MarketInstruction::NewOraclePeggedOrder({
+                let data_arr = array_ref![data, 0, 56];
+                let (order_arr, peg_offset_arr) = array_refs![data_arr, 54, 2];
+                NewOraclePeggedOrderInstruction {
+                    order: NewOrderInstructionV3::unpack(order_arr)?,
+                    peg_offset: i16::from_le_bytes(*peg_offset_arr),
+                }
+            }),
+            (22, 62) => MarketInstruction::NewGoodTilTimeOrder({
+                let data_arr = array_ref![data, 0, 62];
+                let (order_arr, expiry_ts_arr) = array_refs![data_arr, 54, 8];
+                NewGoodTilTimeOrderInstruction {
+                    order: NewOrderInstructionV3::unpack(order_arr)?,
+                    expiry_ts: i64::from_le_bytes(*expiry_ts_arr),
+                }
+            }),
+            (23, 2) => {
+                let limit = array_ref![data, 0, 2];
+                MarketInstruction::ExpireOrders(u16::from_le_bytes(*limit))
+            }
             _ => return None,
         })
     }
@@ -681,6 +767,51 @@ pub fn initialize_market(
     pc_lot_size: u64,
     vault_signer_nonce: u64,
     pc_dust_threshold: u64,
+) -> Result<solana_program::instruction::Instruction, DexError> {
+    initialize_market_with_oracle(
+        market,
+        program_id,
+        coin_mint_pk,
+        pc_mint_pk,
+        coin_vault_pk,
+        pc_vault_pk,
+        authority_pk,
+        prune_authority_pk,
+        consume_events_authority_pk,
+        None,
+        bids_pk,
+        asks_pk,
+        req_q_pk,
+        event_q_pk,
+        coin_lot_size,
+        pc_lot_size,
+        vault_signer_nonce,
+        pc_dust_threshold,
+    )
+}
+
+/// Like `initialize_market`, also setting the market's oracle, which pegged
+/// orders follow. The oracle is only passed along with all three
+/// authorities.
+pub fn initialize_market_with_oracle(
+    market: &Pubkey,
+    program_id: &Pubkey,
+    coin_mint_pk: &Pubkey,
+    pc_mint_pk: &Pubkey,
+    coin_vault_pk: &Pubkey,
+    pc_vault_pk: &Pubkey,
+    authority_pk: Option<&Pubkey>,
+    prune_authority_pk: Option<&Pubkey>,
+    consume_events_authority_pk: Option<&Pubkey>,
+    oracle_pk: Option<&Pubkey>,
+    bids_pk: &Pubkey,
+    asks_pk: &Pubkey,
+    req_q_pk: &Pubkey,
+    event_q_pk: &Pubkey,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    vault_signer_nonce: u64,
+    pc_dust_threshold: u64,
 ) -> Result<solana_program::instruction::Instruction, DexError> {
     let data = MarketInstruction::InitializeMarket(InitializeMarketInstruction {
         coin_lot_size,
@@ -729,6 +860,9 @@ pub fn initialize_market(
             if let Some(consume_events_auth) = consume_events_authority_pk {
                 let authority = AccountMeta::new_readonly(*consume_events_auth, false);
                 accounts.push(authority);
+                if let Some(oracle) = oracle_pk {
+                    accounts.push(AccountMeta::new_readonly(*oracle, false));
+                }
             }
         }
     }
@@ -1110,6 +1244,28 @@ pub fn prune(
     })
 }
 
+pub fn expire_orders(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    event_q: &Pubkey,
+    limit: u16,
+) -> Result<Instruction, DexError> {
+    let data = MarketInstruction::ExpireOrders(limit).pack();
+    let accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new(*event_q, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        data,
+        accounts,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,4 +1,5 @@
-use std::num::NonZeroU64;
+use std::convert::TryFrom;
+use std::num::{NonZeroU32, NonZeroU64};
 
 use crate::instruction::SelfTradeBehavior;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
@@ -43,10 +44,42 @@ pub enum OrderType {
     Limit = 0,
     ImmediateOrCancel = 1,
     PostOnly = 2,
+    /// A post only order which, instead of being cancelled when it would
+    /// cross the book, posts one tick behind the opposing best.
+    PostOnlySlide = 3,
 }
 
-fn extract_price_from_order_id(order_id: u128) -> u64 {
-    (order_id >> 64) as u64
+// The order id of an oracle pegged order has its top bit set, which keeps it
+// apart from those of the orders at a fixed price, followed by its biased peg
+// offset, the low 47 bits of its sequence number and its peg limit, so that
+// pegged orders sort by offset, then by time.
+pub const ORACLE_PEGGED_FLAG: u128 = 1 << 127;
+
+const PEGGED_SEQ_NUM_MASK: u64 = (1 << 47) - 1;
+
+/// Returns the price of the given order, or the peg limit of an oracle pegged
+/// order, i.e. the price its funds are locked at.
+pub fn extract_price_from_order_id(order_id: u128) -> u64 {
+    if order_id & ORACLE_PEGGED_FLAG != 0 {
+        order_id as u64
+    } else {
+        (order_id >> 64) as u64
+    }
+}
+
+/// Returns the offset from the oracle price of an oracle pegged order, or
+/// `None` if the order is at a fixed price.
+pub fn extract_peg_offset_from_order_id(order_id: u128) -> Option<i16> {
+    if order_id & ORACLE_PEGGED_FLAG == 0 {
+        return None;
+    }
+    Some(((order_id >> 111) as u16 ^ 0x8000) as i16)
+}
+
+pub(crate) fn oracle_pegged_order_id(peg_offset: i16, peg_limit: u64, seq_num: u64) -> u128 {
+    let biased_offset = (peg_offset as u16 ^ 0x8000) as u128;
+    let seq_num = (seq_num & PEGGED_SEQ_NUM_MASK) as u128;
+    ORACLE_PEGGED_FLAG | biased_offset << 111 | seq_num << 64 | peg_limit as u128
 }
 
 pub struct OrderBookState<'a> {
@@ -54,9 +87,20 @@ pub struct OrderBookState<'a> {
     pub bids: &'a mut Slab,
     pub asks: &'a mut Slab,
     pub market_state: &'a mut MarketState,
+    // The price of the market's oracle, in pc lots per coin lot, if it has one.
+    pub oracle_price: Option<NonZeroU64>,
+    // The current unix timestamp, after which good-til-time orders expire.
+    pub unix_timestamp: i64,
 }
 
 impl<'ob> OrderBookState<'ob> {
+    fn orders(&self, side: Side) -> &Slab {
+        match side {
+            Side::Bid => &*self.bids,
+            Side::Ask => &*self.asks,
+        }
+    }
+
     fn orders_mut(&mut self, side: Side) -> &mut Slab {
         match side {
             Side::Bid => self.bids,
@@ -64,13 +108,154 @@ impl<'ob> OrderBookState<'ob> {
         }
     }
 
-    fn find_bbo(&self, side: Side) -> Option<NodeHandle> {
+    fn find_bbo(&self, side: Side, oracle_pegged: bool) -> Option<NodeHandle> {
         match side {
-            Side::Bid => self.bids.find_max(),
-            Side::Ask => self.asks.find_min(),
+            Side::Bid => self.bids.find_max_of(oracle_pegged),
+            Side::Ask => self.asks.find_min_of(oracle_pegged),
+        }
+    }
+
+    // Returns the order id of the least aggressive order of the given side,
+    // comparing the worst order at a fixed price with the worst oracle pegged
+    // one at the price it trades at. Pegged orders beyond their peg limit are
+    // the least aggressive of all. Without the oracle price, pegged orders
+    // are left alone unless the side holds nothing else.
+    fn find_worst_order(&self, side: Side) -> Option<u128> {
+        let find_worst_of = |oracle_pegged| {
+            let orders = self.orders(side);
+            let h = match side {
+                Side::Bid => orders.find_min_of(oracle_pegged),
+                Side::Ask => orders.find_max_of(oracle_pegged),
+            }?;
+            let order_id = orders.get(h).unwrap().as_leaf().unwrap().order_id();
+            Some((order_id, self.order_price(side, order_id)))
+        };
+        match (find_worst_of(false), find_worst_of(true)) {
+            (Some((fixed, _)), Some(_)) if self.oracle_price.is_none() => Some(fixed),
+            (Some((fixed, Some(fixed_price))), Some((pegged, pegged_price))) => {
+                let pegged_is_worse = match (side, pegged_price) {
+                    (_, None) => true,
+                    (Side::Bid, Some(pegged_price)) => pegged_price < fixed_price,
+                    (Side::Ask, Some(pegged_price)) => pegged_price > fixed_price,
+                };
+                Some(if pegged_is_worse { pegged } else { fixed })
+            }
+            (fixed, pegged) => fixed.or(pegged).map(|(order_id, _)| order_id),
+        }
+    }
+
+    // Returns the price the given order trades at, which for an oracle pegged
+    // order is the oracle price plus its offset, or `None` if that is beyond
+    // its peg limit, or if the oracle price isn't known.
+    fn order_price(&self, side: Side, order_id: u128) -> Option<NonZeroU64> {
+        let price = extract_price_from_order_id(order_id);
+        let peg_offset = match extract_peg_offset_from_order_id(order_id) {
+            None => return NonZeroU64::new(price),
+            Some(peg_offset) => peg_offset,
+        };
+        let pegged_price = self.oracle_price?.get() as i128 + peg_offset as i128;
+        let pegged_price = NonZeroU64::new(u64::try_from(pegged_price).ok()?)?;
+        let within_limit = match side {
+            Side::Bid => pegged_price.get() <= price,
+            Side::Ask => pegged_price.get() >= price,
+        };
+        Some(pegged_price).filter(|_| within_limit)
+    }
+
+    // Returns the best order of the given side and the price it trades at,
+    // comparing the best order at a fixed price with the best oracle pegged
+    // one.
+    fn find_best_order(
+        &mut self,
+        side: Side,
+        event_q: &mut EventQueue,
+        limit: &mut u16,
+    ) -> DexResult<BestOrder> {
+        let fixed = self.find_live_order(side, false, event_q, limit)?;
+        let pegged = self.find_live_order(side, true, event_q, limit)?;
+        Ok(match (fixed, pegged) {
+            (BestOrder::Live(_, fixed_price), BestOrder::Live(_, pegged_price)) => {
+                let pegged_is_better = match side {
+                    Side::Bid => pegged_price > fixed_price,
+                    Side::Ask => pegged_price < fixed_price,
+                };
+                if pegged_is_better {
+                    pegged
+                } else {
+                    fixed
+                }
+            }
+            (BestOrder::Unknown, _) | (_, BestOrder::Unknown) => BestOrder::Unknown,
+            (BestOrder::Empty, best) | (best, BestOrder::Empty) => best,
+        })
+    }
+
+    // Returns the best order of the given side among the oracle pegged orders
+    // or those at a fixed price, and the price it trades at. Expired orders and
+    // pegged orders beyond their peg limit are removed on the way, each
+    // removal counting against the limit, so that a book full of them can't
+    // use up the compute of the orders hitting it. Pegged orders can't be
+    // priced without the oracle price, so they're skipped until it's known.
+    fn find_live_order(
+        &mut self,
+        side: Side,
+        oracle_pegged: bool,
+        event_q: &mut EventQueue,
+        limit: &mut u16,
+    ) -> DexResult<BestOrder> {
+        loop {
+            if oracle_pegged && self.oracle_price.is_none() {
+                return Ok(BestOrder::Empty);
+            }
+            let h = match self.find_bbo(side, oracle_pegged) {
+                None => return Ok(BestOrder::Empty),
+                Some(h) => h,
+            };
+            let leaf = *self.orders(side).get(h).unwrap().as_leaf().unwrap();
+            let expired = leaf.is_expired(self.unix_timestamp);
+            if let (false, Some(price)) = (expired, self.order_price(side, leaf.order_id())) {
+                return Ok(BestOrder::Live(h, price));
+            }
+            if *limit == 0 {
+                return Ok(BestOrder::Unknown);
+            }
+            *limit -= 1;
+            if expired {
+                msg!("order expired! removing...");
+            } else {
+                msg!("pegged order beyond its peg limit! removing...");
+            }
+            self.remove_resting_order(side, leaf.order_id(), event_q)?;
         }
     }
 
+    // Removes the given order from the book, releasing all of its funds.
+    fn remove_resting_order(
+        &mut self,
+        side: Side,
+        order_id: u128,
+        event_q: &mut EventQueue,
+    ) -> DexResult {
+        let leaf = self.orders_mut(side).remove_by_key(order_id).unwrap();
+        let native_qty_unlocked = match side {
+            Side::Bid => leaf.quantity() * leaf.price().get() * self.market_state.pc_lot_size,
+            Side::Ask => leaf.quantity() * self.market_state.coin_lot_size,
+        };
+        event_q
+            .push_back(Event::new(EventView::Out {
+                side,
+                release_funds: true,
+                native_qty_unlocked,
+                native_qty_still_locked: 0,
+                order_id: leaf.order_id(),
+                owner: leaf.owner(),
+                owner_slot: leaf.owner_slot(),
+                client_order_id: NonZeroU64::new(leaf.client_order_id()),
+            }))
+            .map_err(|_| DexErrorCode::EventQueueFull)?;
+        Ok(())
+    }
+
     pub(crate) fn process_orderbook_request(
         &mut self,
         request: &RequestView,
@@ -90,6 +275,7 @@ impl<'ob> OrderBookState<'ob> {
                 native_pc_qty_locked,
                 client_order_id,
                 self_trade_behavior,
+                expiry_ts,
             } => self
                 .new_order(
                     NewOrderParams {
@@ -103,6 +289,7 @@ impl<'ob> OrderBookState<'ob> {
                         native_pc_qty_locked,
                         client_order_id: client_order_id.map_or(0, NonZeroU64::get),
                         self_trade_behavior,
+                        expiry_ts,
                     },
                     event_q,
                     proceeds,
@@ -119,6 +306,7 @@ impl<'ob> OrderBookState<'ob> {
                     native_pc_qty_locked: remaining.native_pc_qty_remaining,
                     client_order_id,
                     self_trade_behavior,
+                    expiry_ts,
                 }),
             RequestView::CancelOrder {
                 side,
@@ -142,6 +330,59 @@ impl<'ob> OrderBookState<'ob> {
         })
     }
 
+    // Removes the expired orders of both sides, searching at most `limit` nodes
+    // of each. Returns the number of orders removed.
+    pub(crate) fn expire_orders(
+        &mut self,
+        limit: u16,
+        event_q: &mut EventQueue,
+    ) -> DexResult<usize> {
+        let unix_timestamp = self.unix_timestamp;
+        let mut removed = 0;
+        for side in [Side::Bid, Side::Ask] {
+            let mut side_limit = limit;
+            let expired_order_ids = self
+                .orders(side)
+                .find_by(&mut side_limit, |order| order.is_expired(unix_timestamp));
+            for order_id in expired_order_ids {
+                self.remove_resting_order(side, order_id, event_q)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    // Returns the price a post only order of the given side posts at when it
+    // slides, one tick behind the opposing best if it would cross it. A bid
+    // can't slide below the first tick, and is cancelled as it crosses then,
+    // as is an order whose opposing best isn't known within the limit. An ask
+    // can't slide to 2^63 or above, beyond the prices of fixed order ids.
+    pub(crate) fn post_only_slide_price(
+        &mut self,
+        side: Side,
+        limit_price: NonZeroU64,
+        event_q: &mut EventQueue,
+        limit: &mut u16,
+    ) -> DexResult<NonZeroU64> {
+        let best_order = match side {
+            Side::Bid => self.find_best_order(Side::Ask, event_q, limit)?,
+            Side::Ask => self.find_best_order(Side::Bid, event_q, limit)?,
+        };
+        let best_price = match best_order {
+            BestOrder::Live(_, price) => price.get(),
+            BestOrder::Empty | BestOrder::Unknown => return Ok(limit_price),
+        };
+        let slid_price = match side {
+            Side::Bid if limit_price.get() >= best_price => best_price - 1,
+            Side::Ask if limit_price.get() <= best_price => best_price
+                .checked_add(1)
+                .filter(|price| *price < 1 << 63)
+                .ok_or(DexErrorCode::InvalidLimitPrice)?,
+            _ => limit_price.get(),
+        };
+        Ok(NonZeroU64::new(slid_price).unwrap_or(limit_price))
+    }
+
     // Removes all orders belonging to the given open orders account.
     pub fn remove_all(
         &mut self,
@@ -216,6 +457,7 @@ pub(crate) struct NewOrderParams {
     native_pc_qty_locked: Option<NonZeroU64>,
     client_order_id: u64,
     self_trade_behavior: SelfTradeBehavior,
+    expiry_ts: Option<NonZeroU32>,
 }
 
 struct OrderRemaining {
@@ -223,6 +465,16 @@ struct OrderRemaining {
     native_pc_qty_remaining: Option<NonZeroU64>,
 }
 
+// The best order of a side of the book.
+#[derive(Copy, Clone)]
+enum BestOrder {
+    // The best live order, and the price it trades at.
+    Live(NodeHandle, NonZeroU64),
+    Empty,
+    // The limit ran out removing lapsed orders before a live one was found.
+    Unknown,
+}
+
 impl<'ob> OrderBookState<'ob> {
     fn new_order(
         &mut self,
@@ -242,13 +494,17 @@ impl<'ob> OrderBookState<'ob> {
             mut native_pc_qty_locked,
             client_order_id,
             self_trade_behavior,
+            expiry_ts,
         } = params;
         let (mut post_only, mut post_allowed) = match order_type {
             OrderType::Limit => (false, true),
             OrderType::ImmediateOrCancel => (false, false),
-            OrderType::PostOnly => (true, true),
+            OrderType::PostOnly | OrderType::PostOnlySlide => (true, true),
         };
-        let limit_price = extract_price_from_order_id(order_id);
+        let limit_price = self
+            .order_price(side, order_id)
+            .ok_or(DexErrorCode::PegLimitExceeded)?
+            .get();
         loop {
             if *limit == 0 {
                 // Stop matching and release funds if we're out of cycles
@@ -270,9 +526,11 @@ impl<'ob> OrderBookState<'ob> {
                         post_allowed,
                         client_order_id,
                         self_trade_behavior,
+                        expiry_ts,
                     },
                     event_q,
                     proceeds,
+                    limit,
                 ),
                 Side::Ask => {
                     native_pc_qty_locked.ok_or(()).unwrap_err();
@@ -288,9 +546,11 @@ impl<'ob> OrderBookState<'ob> {
                             post_allowed,
                             client_order_id,
                             self_trade_behavior,
+                            expiry_ts,
                         },
                         event_q,
                         proceeds,
+                        limit,
                     )
                 }
             }?;
@@ -320,6 +580,7 @@ struct NewAskParams {
     post_allowed: bool,
     client_order_id: u64,
     self_trade_behavior: SelfTradeBehavior,
+    expiry_ts: Option<NonZeroU32>,
 }
 
 impl<'ob> OrderBookState<'ob> {
@@ -328,6 +589,7 @@ impl<'ob> OrderBookState<'ob> {
         params: NewAskParams,
         event_q: &mut EventQueue,
         to_release: &mut RequestProceeds,
+        limit: &mut u16,
     ) -> DexResult<Option<OrderRemaining>> {
         let NewAskParams {
             max_qty,
@@ -340,6 +602,7 @@ impl<'ob> OrderBookState<'ob> {
             post_allowed,
             client_order_id,
             self_trade_behavior,
+            expiry_ts,
         } = params;
         let mut unfilled_qty = max_qty.get();
         let mut accum_fill_price = 0;
@@ -350,12 +613,18 @@ impl<'ob> OrderBookState<'ob> {
         let mut accum_maker_rebates = 0;
         let crossed;
         let done = loop {
-            let best_bid_h = match self.find_bbo(Side::Bid) {
-                None => {
+            let best_bid = self.find_best_order(Side::Bid, event_q, limit)?;
+            let (best_bid_h, trade_price) = match best_bid {
+                BestOrder::Live(h, price) => (h, price),
+                BestOrder::Empty => {
                     crossed = false;
                     break true;
                 }
-                Some(h) => h,
+                // Out of cycles, release the funds of an order that may cross.
+                BestOrder::Unknown => {
+                    crossed = true;
+                    break true;
+                }
             };
 
             let best_bid_ref = self
@@ -365,7 +634,6 @@ impl<'ob> OrderBookState<'ob> {
                 .as_leaf_mut()
                 .unwrap();
 
-            let trade_price = best_bid_ref.price();
             crossed = limit_price <= trade_price;
 
             if !crossed || post_only {
@@ -399,14 +667,14 @@ impl<'ob> OrderBookState<'ob> {
                     }
                 };
 
+                // The funds of an oracle pegged bid are locked at its peg limit.
+                let lock_price = best_bid_ref.price().get();
                 let remaining_provide_size = bid_size - cancelled_provide_qty;
                 let provide_out = Event::new(EventView::Out {
                     side: Side::Bid,
                     release_funds: true,
-                    native_qty_unlocked: cancelled_provide_qty * trade_price.get() * pc_lot_size,
-                    native_qty_still_locked: remaining_provide_size
-                        * trade_price.get()
-                        * pc_lot_size,
+                    native_qty_unlocked: cancelled_provide_qty * lock_price * pc_lot_size,
+                    native_qty_still_locked: remaining_provide_size * lock_price * pc_lot_size,
                     order_id: best_bid_id,
                     owner: best_bid_ref.owner(),
                     owner_slot: best_bid_ref.owner_slot(),
@@ -472,13 +740,34 @@ impl<'ob> OrderBookState<'ob> {
             unfilled_qty -= trade_qty;
             accum_fill_price += trade_qty * trade_price.get();
 
+            // An oracle pegged bid filled below its peg limit releases the
+            // rest of the funds locked for the quantity filled.
+            let lock_price = best_bid_ref.price().get();
+            let native_surplus_unlocked =
+                trade_qty * (lock_price - trade_price.get()) * pc_lot_size;
+            if best_bid_ref.quantity() != 0 && native_surplus_unlocked != 0 {
+                let native_qty_still_locked = best_bid_ref.quantity() * lock_price * pc_lot_size;
+                event_q
+                    .push_back(Event::new(EventView::Out {
+                        side: Side::Bid,
+                        release_funds: true,
+                        native_qty_unlocked: native_surplus_unlocked,
+                        native_qty_still_locked,
+                        order_id: best_bid_ref.order_id(),
+                        owner: best_bid_ref.owner(),
+                        owner_slot: best_bid_ref.owner_slot(),
+                        client_order_id: NonZeroU64::new(best_bid_ref.client_order_id()),
+                    }))
+                    .map_err(|_| DexErrorCode::EventQueueFull)?;
+            }
+
             if best_bid_ref.quantity() == 0 {
                 let best_bid_id = best_bid_ref.order_id();
                 event_q
                     .push_back(Event::new(EventView::Out {
                         side: Side::Bid,
                         release_funds: true,
-                        native_qty_unlocked: 0,
+                        native_qty_unlocked: native_surplus_unlocked,
                         native_qty_still_locked: 0,
                         order_id: best_bid_id,
                         owner: best_bid_ref.owner(),
@@ -541,7 +830,6 @@ impl<'ob> OrderBookState<'ob> {
         }
 
         if post_allowed && !crossed && unfilled_qty > 0 {
-            let offers = self.orders_mut(Side::Ask);
             let new_order = LeafNode::new(
                 owner_slot,
                 order_id,
@@ -549,12 +837,15 @@ impl<'ob> OrderBookState<'ob> {
                 unfilled_qty,
                 fee_tier,
                 client_order_id,
+                expiry_ts,
             );
-            let insert_result = offers.insert_leaf(&new_order);
+            let insert_result = self.orders_mut(Side::Ask).insert_leaf(&new_order);
             if let Err(SlabTreeError::OutOfSpace) = insert_result {
                 // boot out the least aggressive offer
                 msg!("offers full! booting...");
-                let order = offers.remove_max().unwrap();
+                let worst_order_id = self.find_worst_order(Side::Ask).unwrap();
+                let offers = self.orders_mut(Side::Ask);
+                let order = offers.remove_by_key(worst_order_id).unwrap();
                 let out = Event::new(EventView::Out {
                     side: Side::Ask,
                     release_funds: true,
@@ -605,6 +896,7 @@ struct NewBidParams {
     post_allowed: bool,
     client_order_id: u64,
     self_trade_behavior: SelfTradeBehavior,
+    expiry_ts: Option<NonZeroU32>,
 }
 
 impl<'ob> OrderBookState<'ob> {
@@ -613,6 +905,7 @@ impl<'ob> OrderBookState<'ob> {
         params: NewBidParams,
         event_q: &mut EventQueue,
         to_release: &mut RequestProceeds,
+        limit: &mut u16,
     ) -> DexResult<Option<OrderRemaining>> {
         let NewBidParams {
             max_coin_qty,
@@ -626,6 +919,7 @@ impl<'ob> OrderBookState<'ob> {
             post_allowed,
             client_order_id,
             self_trade_behavior,
+            expiry_ts,
         } = params;
         if post_allowed {
             check_assert!(limit_price.is_some())?;
@@ -642,12 +936,18 @@ impl<'ob> OrderBookState<'ob> {
 
         let crossed;
         let done = loop {
-            let best_offer_h = match self.find_bbo(Side::Ask) {
-                None => {
+            let best_offer = self.find_best_order(Side::Ask, event_q, limit)?;
+            let (best_offer_h, trade_price) = match best_offer {
+                BestOrder::Live(h, price) => (h, price),
+                BestOrder::Empty => {
                     crossed = false;
                     break true;
                 }
-                Some(h) => h,
+                // Out of cycles, release the funds of an order that may cross.
+                BestOrder::Unknown => {
+                    crossed = true;
+                    break true;
+                }
             };
 
             let best_offer_ref = self
@@ -657,7 +957,6 @@ impl<'ob> OrderBookState<'ob> {
                 .as_leaf_mut()
                 .unwrap();
 
-            let trade_price = best_offer_ref.price();
             crossed = limit_price
                 .map(|limit_price| limit_price >= trade_price)
                 .unwrap_or(true);
@@ -668,7 +967,7 @@ impl<'ob> OrderBookState<'ob> {
             let offer_size = best_offer_ref.quantity();
             let trade_qty = offer_size
                 .min(coin_qty_remaining)
-                .min(pc_qty_remaining / best_offer_ref.price().get());
+                .min(pc_qty_remaining / trade_price.get());
 
             if trade_qty == 0 {
                 break true;
@@ -860,11 +1159,13 @@ impl<'ob> OrderBookState<'ob> {
             }
         }
 
+        // The funds of an oracle pegged bid are locked at its peg limit.
+        let lock_price = extract_price_from_order_id(order_id);
         let (coin_qty_to_post, pc_qty_to_keep_locked) = match limit_price {
-            Some(price) if post_allowed && !crossed => {
+            Some(_) if post_allowed && !crossed => {
                 let coin_qty_to_post =
-                    coin_qty_remaining.min(native_pc_qty_remaining / pc_lot_size / price.get());
-                (coin_qty_to_post, coin_qty_to_post * price.get())
+                    coin_qty_remaining.min(native_pc_qty_remaining / pc_lot_size / lock_price);
+                (coin_qty_to_post, coin_qty_to_post * lock_price)
             }
             _ => (0, 0),
         };
@@ -891,7 +1192,6 @@ impl<'ob> OrderBookState<'ob> {
             .map_err(|_| DexErrorCode::EventQueueFull)?;
 
         if pc_qty_to_keep_locked > 0 {
-            let bids = self.orders_mut(Side::Bid);
             let new_leaf = LeafNode::new(
                 owner_slot,
                 order_id,
@@ -899,12 +1199,15 @@ impl<'ob> OrderBookState<'ob> {
                 coin_qty_to_post,
                 fee_tier,
                 client_order_id,
+                expiry_ts,
             );
-            let insert_result = bids.insert_leaf(&new_leaf);
+            let insert_result = self.orders_mut(Side::Bid).insert_leaf(&new_leaf);
             if let Err(SlabTreeError::OutOfSpace) = insert_result {
                 // boot out the least aggressive bid
                 msg!("bids full! booting...");
-                let order = bids.remove_min().unwrap();
+                let worst_order_id = self.find_worst_order(Side::Bid).unwrap();
+                let bids = self.orders_mut(Side::Bid);
+                let order = bids.remove_by_key(worst_order_id).unwrap();
                 let out = Event::new(EventView::Out {
                     side: Side::Bid,
                     release_funds: true,
@@ -3,9 +3,10 @@ use num_enum::TryFromPrimitive;
 use std::{
     cell::{Ref, RefMut},
     convert::identity,
+    convert::TryFrom,
     convert::TryInto,
     mem::size_of,
-    num::NonZeroU64,
+    num::{NonZeroU32, NonZeroU64},
     ops::Deref,
     ops::DerefMut,
 };
@@ -27,7 +28,7 @@ use solana_program::{
 use spl_token::error::TokenError;
 
 use crate::{
-    critbit::Slab,
+    critbit::{Slab, MAX_GOOD_TIL_TIME_CLIENT_ORDER_ID},
     error::{DexError, DexErrorCode, DexResult, SourceFileId},
     fees::{self, FeeTier},
     instruction::{
@@ -35,10 +36,13 @@ use crate::{
         InitializeMarketInstruction, MarketInstruction, NewOrderInstructionV3, SelfTradeBehavior,
         SendTakeInstruction,
     },
-    matching::{OrderBookState, OrderType, RequestProceeds, Side},
+    matching::{oracle_pegged_order_id, OrderBookState, OrderType, RequestProceeds, Side},
 };
 
-use anchor_lang::{Discriminator, prelude::{borsh, emit, event, AnchorDeserialize, AnchorSerialize}};
+use anchor_lang::{
+    prelude::{borsh, emit, event, AnchorDeserialize, AnchorSerialize},
+    Discriminator,
+};
 
 declare_check_assert_macros!(SourceFileId::State);
 
@@ -198,6 +202,40 @@ impl<'a> Market<'a> {
         }
     }
 
+    pub fn oracle(&self) -> Option<&Pubkey> {
+        match &self {
+            Market::V1(_) | Market::V1Ref(_) => None,
+            Market::V2(state) => state.oracle(),
+            Market::V2Ref(state) => state.oracle(),
+        }
+    }
+
+    /// Returns the price published by the market's oracle, in pc lots per
+    /// coin lot, failing if it's older than `MAX_ORACLE_STALENESS_SLOTS`.
+    ///
+    /// The oracle account holds the price and the slot it was published at,
+    /// both little endian `u64`s.
+    pub fn load_oracle_price(&self, oracle: &AccountInfo) -> DexResult<NonZeroU64> {
+        if Some(oracle.key) != self.oracle() {
+            return Err(DexErrorCode::WrongOracleAccount.into());
+        }
+        let data = oracle.try_borrow_data()?;
+        check_assert!(data.len() >= 16)?;
+        let (price, publish_slot) = array_refs![array_ref![data, 0, 16], 8, 8];
+        let price = u64::from_le_bytes(*price);
+        let publish_slot = u64::from_le_bytes(*publish_slot);
+
+        // Dynamic sysvars don't work in unit tests.
+        #[cfg(any(test, feature = "fuzz"))]
+        let cur_slot: u64 = 1_000;
+        #[cfg(not(any(test, feature = "fuzz")))]
+        let cur_slot = solana_program::clock::Clock::get()?.slot;
+        if cur_slot.saturating_sub(publish_slot) > MAX_ORACLE_STALENESS_SLOTS {
+            return Err(DexErrorCode::StaleOraclePrice.into());
+        }
+        NonZeroU64::new(price).ok_or_else(|| DexErrorCode::StaleOraclePrice.into())
+    }
+
     pub fn load_orders_mut(
         &self,
         orders_account: &'a AccountInfo,
@@ -251,8 +289,11 @@ pub struct MarketStateV2 {
     pub open_orders_authority: Pubkey,
     pub prune_authority: Pubkey,
     pub consume_events_authority: Pubkey,
+    // The account oracle pegged orders are priced from, the default pubkey if
+    // the market has no oracle.
+    pub oracle: Pubkey,
     // Unused bytes for future upgrades.
-    padding: [u8; 992],
+    padding: [u8; 960],
 }
 
 impl Deref for MarketStateV2 {
@@ -270,6 +311,10 @@ impl DerefMut for MarketStateV2 {
 }
 
 impl MarketStateV2 {
+    pub fn oracle(&self) -> Option<&Pubkey> {
+        Some(&self.oracle).filter(|oracle| **oracle != Pubkey::default())
+    }
+
     #[inline]
     pub fn load<'a>(
         market_account: &'a AccountInfo,
@@ -419,6 +464,9 @@ unsafe impl TriviallyTransmutable for MarketState {}
 pub const ACCOUNT_HEAD_PADDING: &[u8; 5] = b"serum";
 pub const ACCOUNT_TAIL_PADDING: &[u8; 7] = b"padding";
 
+/// The number of slots after which the price of an oracle is stale.
+pub const MAX_ORACLE_STALENESS_SLOTS: u64 = 25;
+
 fn init_account_padding(data: &mut [u8]) -> DexResult<&mut [[u8; 8]]> {
     check_assert!(data.len() >= 12)?;
     let (head, data, tail) = mut_array_refs![data, 5; ..; 7];
@@ -1106,6 +1154,15 @@ impl RequestQueue<'_> {
         upper | (lower as u128)
     }
 
+    fn gen_oracle_pegged_order_id(&mut self, peg_offset: i16, peg_limit: u64, side: Side) -> u128 {
+        let seq_num = self.gen_seq_num();
+        let seq_num = match side {
+            Side::Bid => !seq_num,
+            Side::Ask => seq_num,
+        };
+        oracle_pegged_order_id(peg_offset, peg_limit, seq_num)
+    }
+
     fn gen_seq_num(&mut self) -> u64 {
         let seq_num = self.header.next_seq_num;
         self.header.next_seq_num += 1;
@@ -1131,7 +1188,7 @@ pub struct Request {
     owner_slot: u8,
     fee_tier: u8,
     self_trade_behavior: u8,
-    padding: [u8; 4],
+    expiry_ts: u32,
     max_coin_qty_or_cancel_id: u64,
     native_pc_qty_locked: u64,
     order_id: u128,
@@ -1154,6 +1211,7 @@ pub enum RequestView {
         owner: [u64; 4],
         client_order_id: Option<NonZeroU64>,
         self_trade_behavior: SelfTradeBehavior,
+        expiry_ts: Option<NonZeroU32>,
     },
     CancelOrder {
         side: Side,
@@ -1180,13 +1238,16 @@ impl Request {
                 native_pc_qty_locked,
                 client_order_id,
                 self_trade_behavior,
+                expiry_ts,
             } => {
                 let mut flags = BitFlags::from_flag(RequestFlag::NewOrder);
                 if side == Side::Bid {
                     flags.insert(RequestFlag::Bid);
                 }
                 match order_type {
-                    OrderType::PostOnly => flags |= RequestFlag::PostOnly,
+                    OrderType::PostOnly | OrderType::PostOnlySlide => {
+                        flags |= RequestFlag::PostOnly
+                    }
                     OrderType::ImmediateOrCancel => flags |= RequestFlag::ImmediateOrCancel,
                     OrderType::Limit => (),
                 };
@@ -1196,7 +1257,7 @@ impl Request {
                     owner_slot,
                     fee_tier: fee_tier.into(),
                     self_trade_behavior: self_trade_behavior.into(),
-                    padding: Zeroable::zeroed(),
+                    expiry_ts: expiry_ts.map_or(0, NonZeroU32::get),
                     order_id,
                     owner,
                     max_coin_qty_or_cancel_id: max_coin_qty.get(),
@@ -1225,7 +1286,7 @@ impl Request {
                     self_trade_behavior: 0,
                     owner: expected_owner,
                     native_pc_qty_locked: 0,
-                    padding: Zeroable::zeroed(),
+                    expiry_ts: 0,
                     client_order_id: client_order_id.map_or(0, NonZeroU64::get),
                 }
             }
@@ -1269,6 +1330,7 @@ impl Request {
                 max_coin_qty: NonZeroU64::new(self.max_coin_qty_or_cancel_id).unwrap(),
                 native_pc_qty_locked: NonZeroU64::new(self.native_pc_qty_locked),
                 client_order_id: NonZeroU64::new(self.client_order_id),
+                expiry_ts: NonZeroU32::new(self.expiry_ts),
             })
         } else {
             check_assert!(flags.contains(RequestFlag::CancelOrder))?;
@@ -1638,6 +1700,23 @@ fn invoke_spl_token(
     Ok(())
 }
 
+#[cfg(not(any(test, feature = "fuzz")))]
+fn unix_timestamp() -> DexResult<i64> {
+    Ok(solana_program::clock::Clock::get()?.unix_timestamp)
+}
+
+// Dynamic sysvars don't work in unit tests, which set the clock here instead.
+#[cfg(any(test, feature = "fuzz"))]
+thread_local! {
+    pub(crate) static TEST_UNIX_TIMESTAMP: std::cell::Cell<i64> =
+        std::cell::Cell::new(1_650_000_000);
+}
+
+#[cfg(any(test, feature = "fuzz"))]
+fn unix_timestamp() -> DexResult<i64> {
+    Ok(TEST_UNIX_TIMESTAMP.with(|unix_timestamp| unix_timestamp.get()))
+}
+
 #[cfg(feature = "program")]
 fn send_from_vault<'a, 'b: 'a>(
     native_amount: u64,
@@ -1839,6 +1918,7 @@ pub(crate) mod account_parser {
         pub market_authority: Option<&'a AccountInfo<'b>>,
         pub prune_authority: Option<&'a AccountInfo<'b>>,
         pub consume_events_authority: Option<&'a AccountInfo<'b>>,
+        pub oracle: Option<&'a AccountInfo<'b>>,
     }
 
     impl<'a, 'b: 'a> InitializeMarketArgs<'a, 'b> {
@@ -1847,7 +1927,7 @@ pub(crate) mod account_parser {
             instruction: &'a InitializeMarketInstruction,
             accounts: &'a [AccountInfo<'b>],
         ) -> DexResult<Self> {
-            check_assert!(accounts.len() >= 10 && accounts.len() <= 13)?;
+            check_assert!(accounts.len() >= 10 && accounts.len() <= 14)?;
             let (
                 unchecked_serum_dex_accounts,
                 unchecked_vaults,
@@ -1860,6 +1940,7 @@ pub(crate) mod account_parser {
             let market_authority = rem_iter.next();
             let prune_authority = rem_iter.next();
             let consume_events_authority = rem_iter.next();
+            let oracle = rem_iter.next();
 
             {
                 // Dynamic sysvars don't work in unit tests.
@@ -1927,6 +2008,7 @@ pub(crate) mod account_parser {
                 market_authority,
                 prune_authority,
                 consume_events_authority,
+                oracle,
             })
         }
 
@@ -1972,8 +2054,12 @@ pub(crate) mod account_parser {
             f: impl FnOnce(SendTakeArgs) -> DexResult<T>,
         ) -> DexResult<T> {
             const MIN_ACCOUNTS: usize = 11;
-            check_assert!(accounts.len() == MIN_ACCOUNTS || accounts.len() == MIN_ACCOUNTS + 1)?;
-            let (fixed_accounts, fee_discount_account): (
+            check_assert!(
+                accounts.len() == MIN_ACCOUNTS
+                    || accounts.len() == MIN_ACCOUNTS + 1
+                    || accounts.len() == MIN_ACCOUNTS + 2
+            )?;
+            let (fixed_accounts, trailing_accounts): (
                 &'a [AccountInfo<'b>; MIN_ACCOUNTS],
                 &'a [AccountInfo<'b>],
             ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
@@ -1990,13 +2076,22 @@ pub(crate) mod account_parser {
                 ref pc_vault_acc,
                 ref spl_token_program_acc,
             ]: &'a [AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
+
+            let mut market = Market::load(market_acc, program_id, false)?;
+
+            // As for new orders, the oracle may follow the optional fee
+            // discount account, and is only needed to take pegged orders.
+            let (fee_discount_account, oracle_acc) = match (trailing_accounts, market.oracle()) {
+                ([rest @ .., last], Some(oracle)) if last.key == oracle => (rest, Some(last)),
+                ([_, _], _) => return Err(DexErrorCode::WrongOracleAccount.into()),
+                (_, _) => (trailing_accounts, None),
+            };
             let srm_or_msrm_account = match fee_discount_account {
                 &[] => None,
                 &[ref account] => Some(TokenAccount::new(account)?),
                 _ => check_unreachable!()?,
             };
-
-            let mut market = Market::load(market_acc, program_id, false)?;
+            let oracle_price = oracle_acc.and_then(|oracle| market.load_oracle_price(oracle).ok());
 
             let signer = SignerAccount::new(signer_acc)?;
             let fee_tier = market
@@ -2020,6 +2115,8 @@ pub(crate) mod account_parser {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
                 market_state: market.deref_mut(),
+                oracle_price,
+                unix_timestamp: unix_timestamp()?,
             };
 
             let args = SendTakeArgs {
@@ -2041,6 +2138,8 @@ pub(crate) mod account_parser {
 
     pub struct NewOrderV3Args<'a, 'b: 'a> {
         pub instruction: &'a NewOrderInstructionV3,
+        pub peg_offset: Option<i16>,
+        pub expiry_ts: Option<i64>,
         pub open_orders: RefMut<'a, OpenOrders>,
         pub open_orders_address: [u64; 4],
         pub owner: SignerAccount<'a, 'b>,
@@ -2057,6 +2156,8 @@ pub(crate) mod account_parser {
         pub fn with_parsed_args<T>(
             program_id: &'a Pubkey,
             instruction: &'a NewOrderInstructionV3,
+            peg_offset: Option<i16>,
+            expiry_ts: Option<i64>,
             accounts: &'a [AccountInfo<'b>],
             f: impl FnOnce(NewOrderV3Args) -> DexResult<T>,
         ) -> DexResult<T> {
@@ -2066,7 +2167,7 @@ pub(crate) mod account_parser {
                     || accounts.len() == MIN_ACCOUNTS + 1
                     || accounts.len() == MIN_ACCOUNTS + 2
             )?;
-            let (fixed_accounts, fee_discount_account): (
+            let (fixed_accounts, trailing_accounts): (
                 &'a [AccountInfo<'b>; MIN_ACCOUNTS],
                 &'a [AccountInfo<'b>],
             ) = array_refs![accounts, MIN_ACCOUNTS; .. ;];
@@ -2084,13 +2185,27 @@ pub(crate) mod account_parser {
                 ref spl_token_program_acc,
                 ref rent_sysvar_acc,
             ]: &'a [AccountInfo<'b>; MIN_ACCOUNTS] = fixed_accounts;
+
+            let mut market = Market::load(market_acc, program_id, false)?;
+
+            // The oracle of the market, if it has one, may follow the optional
+            // fee discount account. Only oracle pegged orders and the orders
+            // meeting them need its price, so that a stale oracle doesn't halt
+            // the rest of the market.
+            let (fee_discount_account, oracle_acc) = match (trailing_accounts, market.oracle()) {
+                ([rest @ .., last], Some(oracle)) if last.key == oracle => (rest, Some(last)),
+                ([_, _], _) => return Err(DexErrorCode::WrongOracleAccount.into()),
+                (_, _) => (trailing_accounts, None),
+            };
             let srm_or_msrm_account = match fee_discount_account {
                 &[] => None,
                 &[ref account] => Some(TokenAccount::new(account)?),
                 _ => check_unreachable!()?,
             };
-
-            let mut market = Market::load(market_acc, program_id, false)?;
+            let oracle_price = match oracle_acc.map(|oracle| market.load_oracle_price(oracle)) {
+                Some(Err(err)) if peg_offset.is_some() => return Err(err),
+                oracle_price => oracle_price.and_then(Result::ok),
+            };
 
             // Dynamic sysvars don't work in unit tests.
             #[cfg(any(test, feature = "fuzz"))]
@@ -2130,10 +2245,14 @@ pub(crate) mod account_parser {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
                 market_state: market.deref_mut(),
+                oracle_price,
+                unix_timestamp: unix_timestamp()?,
             };
 
             let args = NewOrderV3Args {
                 instruction,
+                peg_offset,
+                expiry_ts,
                 order_book_state,
                 open_orders,
                 open_orders_address,
@@ -2306,6 +2425,8 @@ pub(crate) mod account_parser {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
                 market_state: market.deref_mut(),
+                oracle_price: None,
+                unix_timestamp: unix_timestamp()?,
             };
 
             let args = CancelOrderV2Args {
@@ -2369,6 +2490,8 @@ pub(crate) mod account_parser {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
                 market_state: market.deref_mut(),
+                oracle_price: None,
+                unix_timestamp: unix_timestamp()?,
             };
 
             let args = CancelOrderByClientIdV2Args {
@@ -2436,6 +2559,8 @@ pub(crate) mod account_parser {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
                 market_state: market.deref_mut(),
+                oracle_price: None,
+                unix_timestamp: unix_timestamp()?,
             };
 
             let args = CancelOrdersByClientIdsArgs {
@@ -2744,6 +2869,8 @@ pub(crate) mod account_parser {
                 bids: bids.deref_mut(),
                 asks: asks.deref_mut(),
                 market_state: market.deref_mut(),
+                oracle_price: None,
+                unix_timestamp: unix_timestamp()?,
             };
 
             let args = PruneArgs {
@@ -2756,6 +2883,50 @@ pub(crate) mod account_parser {
             f(args)
         }
     }
+
+    pub struct ExpireOrdersArgs<'a> {
+        pub order_book_state: OrderBookState<'a>,
+        pub event_q: EventQueue<'a>,
+        pub limit: u16,
+    }
+
+    impl<'a> ExpireOrdersArgs<'a> {
+        pub fn with_parsed_args<T>(
+            program_id: &Pubkey,
+            accounts: &[AccountInfo],
+            limit: u16,
+            f: impl FnOnce(ExpireOrdersArgs) -> DexResult<T>,
+        ) -> DexResult<T> {
+            // Parse accounts.
+            check_assert!(accounts.len() == 4)?;
+            #[rustfmt::skip]
+            let &[
+                ref market_acc,
+                ref bids_acc,
+                ref asks_acc,
+                ref event_q_acc,
+            ] = array_ref![accounts, 0, 4];
+
+            let mut market = Market::load(market_acc, program_id, true)?;
+            let mut bids = market.load_bids_mut(bids_acc).or(check_unreachable!())?;
+            let mut asks = market.load_asks_mut(asks_acc).or(check_unreachable!())?;
+            let event_q = market.load_event_queue_mut(event_q_acc)?;
+            let order_book_state = OrderBookState {
+                bids: bids.deref_mut(),
+                asks: asks.deref_mut(),
+                market_state: market.deref_mut(),
+                oracle_price: None,
+                unix_timestamp: unix_timestamp()?,
+            };
+
+            let args = ExpireOrdersArgs {
+                order_book_state,
+                event_q,
+                limit,
+            };
+            f(args)
+        }
+    }
 }
 
 #[inline]
@@ -2791,6 +2962,28 @@ impl State {
                 account_parser::NewOrderV3Args::with_parsed_args(
                     program_id,
                     inner,
+                    None,
+                    None,
+                    accounts,
+                    Self::process_new_order_v3,
+                )?
+            }
+            MarketInstruction::NewOraclePeggedOrder(ref inner) => {
+                account_parser::NewOrderV3Args::with_parsed_args(
+                    program_id,
+                    &inner.order,
+                    Some(inner.peg_offset),
+                    None,
+                    accounts,
+                    Self::process_new_order_v3,
+                )?
+            }
+            MarketInstruction::NewGoodTilTimeOrder(ref inner) => {
+                account_parser::NewOrderV3Args::with_parsed_args(
+                    program_id,
+                    &inner.order,
+                    None,
+                    Some(inner.expiry_ts),
                     accounts,
                     Self::process_new_order_v3,
                 )?
@@ -2903,6 +3096,14 @@ impl State {
                 limit,
                 Self::process_prune,
             )?,
+            MarketInstruction::ExpireOrders(limit) => {
+                account_parser::ExpireOrdersArgs::with_parsed_args(
+                    program_id,
+                    accounts,
+                    limit,
+                    Self::process_expire_orders,
+                )?
+            }
         };
         Ok(())
     }
@@ -2957,6 +3158,17 @@ impl State {
         Ok(())
     }
 
+    fn process_expire_orders(args: account_parser::ExpireOrdersArgs) -> DexResult {
+        let account_parser::ExpireOrdersArgs {
+            mut order_book_state,
+            mut event_q,
+            limit,
+        } = args;
+        let removed = order_book_state.expire_orders(limit, &mut event_q)?;
+        solana_program::msg!("Expired {:?} orders", removed);
+        Ok(())
+    }
+
     fn process_init_open_orders(_args: account_parser::InitOpenOrdersArgs) -> DexResult {
         Ok(())
     }
@@ -3243,9 +3455,11 @@ impl State {
                         );
                     }
 
-                    let open_orders_pk = Pubkey::try_from_slice(cast_slice(&identity(owner) as &[_])).unwrap();
+                    let open_orders_pk =
+                        Pubkey::try_from_slice(cast_slice(&identity(owner) as &[_])).unwrap();
                     let open_orders_owner_pk =
-                        Pubkey::try_from_slice(cast_slice(&identity(open_orders.owner) as &[_])).unwrap();
+                        Pubkey::try_from_slice(cast_slice(&identity(open_orders.owner) as &[_]))
+                            .unwrap();
                     emit!(FillEventLog {
                         market: market.pubkey(),
                         bid: match side {
@@ -3329,6 +3543,8 @@ impl State {
     fn process_new_order_v3(args: account_parser::NewOrderV3Args) -> DexResult {
         let account_parser::NewOrderV3Args {
             instruction,
+            peg_offset,
+            expiry_ts,
             mut order_book_state,
             mut open_orders,
             open_orders_address,
@@ -3342,16 +3558,52 @@ impl State {
             fee_tier,
         } = args;
 
-        if instruction.max_ts < i64::MAX {
-            // Dynamic sysvars don't work in unit tests.
-            #[cfg(any(test, feature = "fuzz"))]
-            let cur_ts = 1_650_000_000;
-            #[cfg(not(any(test, feature = "fuzz")))]
-            let cur_ts = Clock::get()?.unix_timestamp;
-            if cur_ts > instruction.max_ts {
-                return Err(DexErrorCode::OrderMaxTimestampExceeded.into());
+        if order_book_state.unix_timestamp > instruction.max_ts {
+            return Err(DexErrorCode::OrderMaxTimestampExceeded.into());
+        }
+
+        // A post-only-slide order crossing the book posts one tick behind the
+        // opposing best instead, as a post only order. Oracle pegged orders
+        // don't slide, their peg limit isn't their price.
+        let mut limit = instruction.limit;
+        let (order_type, limit_price) = match instruction.order_type {
+            OrderType::PostOnlySlide if peg_offset.is_none() => {
+                let limit_price = order_book_state.post_only_slide_price(
+                    instruction.side,
+                    instruction.limit_price,
+                    &mut event_q,
+                    &mut limit,
+                )?;
+                (OrderType::PostOnly, limit_price)
             }
+            OrderType::PostOnlySlide => (OrderType::PostOnly, instruction.limit_price),
+            order_type => (order_type, instruction.limit_price),
+        };
+
+        // The top bit of the order id is set for oracle pegged orders only,
+        // whose limit price is their peg limit.
+        if peg_offset.is_none() && limit_price.get() >= 1 << 63 {
+            return Err(DexErrorCode::InvalidLimitPrice.into());
         }
+        if peg_offset.is_some() && order_book_state.oracle_price.is_none() {
+            return Err(DexErrorCode::WrongOracleAccount.into());
+        }
+
+        // The expiry of a good-til-time order is kept in 32 bits, along with
+        // its client order id in 48 bits.
+        let expiry_ts = match expiry_ts {
+            None => None,
+            Some(expiry_ts) => {
+                if expiry_ts < order_book_state.unix_timestamp {
+                    return Err(DexErrorCode::InvalidExpiry.into());
+                }
+                if instruction.client_order_id > MAX_GOOD_TIL_TIME_CLIENT_ORDER_ID {
+                    return Err(DexErrorCode::ClientOrderIdTooLarge.into());
+                }
+                let expiry_ts = u32::try_from(expiry_ts).ok().and_then(NonZeroU32::new);
+                Some(expiry_ts.ok_or(DexErrorCode::InvalidExpiry)?)
+            }
+        };
 
         let open_orders_mut = open_orders.deref_mut();
 
@@ -3402,7 +3654,14 @@ impl State {
             }
         };
 
-        let order_id = req_q.gen_order_id(instruction.limit_price.get(), instruction.side);
+        let order_id = match peg_offset {
+            None => req_q.gen_order_id(limit_price.get(), instruction.side),
+            Some(peg_offset) => req_q.gen_oracle_pegged_order_id(
+                peg_offset,
+                instruction.limit_price.get(),
+                instruction.side,
+            ),
+        };
         let owner_slot = open_orders_mut.add_order(order_id, instruction.side)?;
         open_orders_mut.client_order_ids[owner_slot as usize] = instruction.client_order_id;
 
@@ -3410,7 +3669,7 @@ impl State {
 
         let request = RequestView::NewOrder {
             side: instruction.side,
-            order_type: instruction.order_type,
+            order_type,
             order_id,
             fee_tier,
             self_trade_behavior: instruction.self_trade_behavior,
@@ -3419,8 +3678,8 @@ impl State {
             max_coin_qty: instruction.max_coin_qty,
             native_pc_qty_locked,
             client_order_id: NonZeroU64::new(instruction.client_order_id),
+            expiry_ts,
         };
-        let mut limit = instruction.limit;
         let unfilled_portion = order_book_state.process_orderbook_request(
             &request,
             &mut event_q,
@@ -3474,9 +3733,6 @@ impl State {
         // `invoke_spl_token` will try to borrow the account info refcell,
         // which would cause an error (as there would be two borrows while
         // one of them is mutable).
-
-        use solana_program::clock::Clock;
-
         drop(open_orders);
 
         if deposit_amount != 0 {
@@ -3537,6 +3793,8 @@ impl State {
             account_parser::NewOrderV3Args::with_parsed_args(
                 program_id,
                 instruction,
+                None,
+                None,
                 accounts,
                 Self::process_new_order_v3,
             )?;
@@ -3601,6 +3859,7 @@ impl State {
         let market_authority = args.market_authority;
         let prune_authority = args.prune_authority;
         let consume_events_authority = args.consume_events_authority;
+        let oracle = args.oracle;
 
         // initialize request queue
         let mut rq_data = req_q.try_borrow_mut_data()?;
@@ -3704,6 +3963,9 @@ impl State {
                 if let Some(consume_events_authority) = consume_events_authority {
                     market_hdr.consume_events_authority = *consume_events_authority.key;
                 }
+                if let Some(oracle) = oracle {
+                    market_hdr.oracle = *oracle.key;
+                }
             }
         }
         Ok(())
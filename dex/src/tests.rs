@@ -1,10 +1,10 @@
 use std::convert::identity;
 use std::mem::size_of;
-use std::num::NonZeroU64;
+use std::num::{NonZeroU32, NonZeroU64};
 
 use bumpalo::{collections::Vec as BumpVec, vec as bump_vec, Bump};
 use rand::prelude::*;
-use safe_transmute::to_bytes::{transmute_to_bytes, transmute_to_bytes_mut};
+use safe_transmute::to_bytes::transmute_to_bytes_mut;
 use solana_program::account_info::AccountInfo;
 use solana_program::bpf_loader;
 use solana_program::clock::Epoch;
@@ -16,10 +16,14 @@ use solana_program::sysvar;
 use solana_program::sysvar::Sysvar;
 use spl_token::state::{Account, AccountState, Mint};
 
-use instruction::{initialize_market, MarketInstruction, NewOrderInstructionV3, SelfTradeBehavior};
-use matching::{OrderType, Side};
+use critbit::SlabView;
+use instruction::{
+    initialize_market_with_oracle, MarketInstruction, NewGoodTilTimeOrderInstruction,
+    NewOraclePeggedOrderInstruction, NewOrderInstructionV3, SelfTradeBehavior,
+};
+use matching::{OrderType, Side, ORACLE_PEGGED_FLAG};
 use state::gen_vault_signer_key;
-use state::{Market, MarketState, OpenOrders, State, ToAlignedBytes};
+use state::{EventView, Market, MarketState, MarketStateV2, OpenOrders, State, ToAlignedBytes};
 
 use crate::error::DexErrorCode;
 use crate::state::account_parser::CancelOrderByClientIdV2Args;
@@ -27,7 +31,7 @@ use crate::state::account_parser::CancelOrderByClientIdV2Args;
 use super::*;
 
 fn random_pubkey<'bump, G: rand::Rng>(_rng: &mut G, bump: &'bump Bump) -> &'bump Pubkey {
-    bump.alloc(Pubkey::new(transmute_to_bytes(&rand::random::<[u64; 4]>())))
+    bump.alloc(Pubkey::new_from_array(rand::random::<[u8; 32]>()))
 }
 
 struct MarketAccounts<'bump> {
@@ -153,6 +157,27 @@ fn new_token_account<'bump, Gen: Rng>(
     )
 }
 
+// Returns an oracle account, holding a price and the slot it was published
+// at.
+fn new_oracle_account<'bump, Gen: Rng>(rng: &mut Gen, bump: &'bump Bump) -> AccountInfo<'bump> {
+    AccountInfo::new(
+        random_pubkey(rng, bump),
+        false,
+        false,
+        bump.alloc(10_000_000),
+        bump_vec![in bump; 0u8; 16].into_bump_slice_mut(),
+        random_pubkey(rng, bump),
+        false,
+        Epoch::default(),
+    )
+}
+
+fn set_oracle_price(oracle: &AccountInfo, price: u64, publish_slot: u64) {
+    let mut data = oracle.try_borrow_mut_data().unwrap();
+    data[..8].copy_from_slice(&price.to_le_bytes());
+    data[8..].copy_from_slice(&publish_slot.to_le_bytes());
+}
+
 fn new_spl_token_program<'bump>(bump: &'bump Bump) -> AccountInfo<'bump> {
     AccountInfo::new(
         &spl_token::ID,
@@ -167,10 +192,34 @@ fn new_spl_token_program<'bump>(bump: &'bump Bump) -> AccountInfo<'bump> {
 }
 
 fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts<'bump> {
+    setup_market_with_oracle(rng, bump, None)
+}
+
+// Sets up a permissioned market if given its authority, the open orders,
+// prune and crank authority alike, and its oracle.
+fn setup_market_with_oracle<'bump, R: Rng>(
+    rng: &mut R,
+    bump: &'bump Bump,
+    authority_and_oracle: Option<(&AccountInfo<'bump>, &AccountInfo<'bump>)>,
+) -> MarketAccounts<'bump> {
+    setup_market_with_slab_len(rng, bump, authority_and_oracle, 1 << 23)
+}
+
+// Like `setup_market_with_oracle`, with bids and asks of the given length.
+fn setup_market_with_slab_len<'bump, R: Rng>(
+    rng: &mut R,
+    bump: &'bump Bump,
+    authority_and_oracle: Option<(&AccountInfo<'bump>, &AccountInfo<'bump>)>,
+    slab_len: usize,
+) -> MarketAccounts<'bump> {
     let program_id = random_pubkey(rng, bump);
-    let market = new_dex_owned_account(rng, size_of::<MarketState>(), program_id, bump);
-    let bids = new_dex_owned_account(rng, 1 << 23, program_id, bump);
-    let asks = new_dex_owned_account(rng, 1 << 23, program_id, bump);
+    let market_len = match authority_and_oracle {
+        None => size_of::<MarketState>(),
+        Some(_) => size_of::<MarketStateV2>(),
+    };
+    let market = new_dex_owned_account(rng, market_len, program_id, bump);
+    let bids = new_dex_owned_account(rng, slab_len, program_id, bump);
+    let asks = new_dex_owned_account(rng, slab_len, program_id, bump);
     let req_q = new_dex_owned_account(rng, 640, program_id, bump);
     let event_q = new_dex_owned_account(rng, 65536, program_id, bump);
 
@@ -196,16 +245,19 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
 
     let pc_dust_threshold = 5;
 
-    let init_instruction = initialize_market(
+    let authority = authority_and_oracle.map(|(authority, _)| authority.key);
+    let oracle = authority_and_oracle.map(|(_, oracle)| oracle.key);
+    let init_instruction = initialize_market_with_oracle(
         &market.key,
         &program_id,
         &coin_mint.key,
         &pc_mint.key,
         &coin_vault.key,
         &pc_vault.key,
-        None,
-        None,
-        None,
+        authority,
+        authority,
+        authority,
+        oracle,
         &bids.key,
         &asks.key,
         &req_q.key,
@@ -218,7 +270,7 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
     .unwrap();
 
     {
-        let accounts: &'bump [AccountInfo<'bump>] = bump_vec![in bump;
+        let mut accounts: BumpVec<AccountInfo<'bump>> = bump_vec![in bump;
             market.clone(),
             req_q.clone(),
             event_q.clone(),
@@ -229,9 +281,16 @@ fn setup_market<'bump, R: Rng>(rng: &mut R, bump: &'bump Bump) -> MarketAccounts
             coin_mint.clone(),
             pc_mint.clone(),
             rent_sysvar.clone(),
-        ]
-        .into_bump_slice_mut();
-        State::process(&program_id, accounts, &init_instruction.data).unwrap();
+        ];
+        if let Some((authority, oracle)) = authority_and_oracle {
+            accounts.extend(
+                [authority, authority, authority, oracle]
+                    .iter()
+                    .cloned()
+                    .cloned(),
+            );
+        }
+        State::process(&program_id, &accounts, &init_instruction.data).unwrap();
     }
 
     MarketAccounts {
@@ -771,3 +830,671 @@ fn test_replace_orders() {
         assert_eq!(identity(open_orders.native_pc_total), 260_000);
     }
 }
+
+#[test]
+fn test_oracle_pegged_orders() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let bump = Bump::new();
+
+    let authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let oracle = new_oracle_account(&mut rng, &bump);
+    set_oracle_price(&oracle, 10_000, 1_000);
+    let accounts = setup_market_with_oracle(&mut rng, &bump, Some((&authority, &oracle)));
+
+    let dex_program_id = accounts.market.owner;
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let mut new_open_orders = || {
+        let open_orders =
+            new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+        open_orders
+    };
+    let seller = new_open_orders();
+    let buyer = new_open_orders();
+    let maker = new_open_orders();
+
+    let order =
+        |side, order_type, limit_price, max_coin_qty, max_native_pc_qty| NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(max_native_pc_qty).unwrap(),
+            order_type,
+            client_order_id: 0,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        };
+    let pegged = |order, peg_offset| {
+        MarketInstruction::NewOraclePeggedOrder(NewOraclePeggedOrderInstruction {
+            order,
+            peg_offset,
+        })
+    };
+    let place = |open_orders, side, instruction: MarketInstruction| {
+        let payer = match side {
+            Side::Bid => pc_account.clone(),
+            Side::Ask => coin_account.clone(),
+        };
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders,
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer,
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+            oracle.clone(),
+        ]
+        .into_bump_slice();
+        State::process(dex_program_id, instruction_accounts, &instruction.pack())
+    };
+
+    // An ask 50 above the oracle, never below 9_000, trades above the oracle
+    // once it moved.
+    let ask = order(Side::Ask, OrderType::PostOnly, 9_000, 2, u64::MAX);
+    place(seller.clone(), Side::Ask, pegged(ask, 50)).unwrap();
+    set_oracle_price(&oracle, 10_100, 1_000);
+    let bid = order(Side::Bid, OrderType::ImmediateOrCancel, 10_200, 1, 20_000);
+    place(buyer.clone(), Side::Bid, MarketInstruction::NewOrderV3(bid)).unwrap();
+
+    // A bid 100 below the oracle, never above 10_050, locks its funds at its
+    // peg limit, and trades below the oracle once it moved.
+    let bid = order(Side::Bid, OrderType::PostOnly, 10_050, 2, 30_000);
+    place(maker.clone(), Side::Bid, pegged(bid, -100)).unwrap();
+    set_oracle_price(&oracle, 9_500, 1_000);
+    let ask = order(Side::Ask, OrderType::ImmediateOrCancel, 9_000, 1, u64::MAX);
+    place(
+        seller.clone(),
+        Side::Ask,
+        MarketInstruction::NewOrderV3(ask),
+    )
+    .unwrap();
+
+    // Filled 650 below its peg limit, it releases the surplus locked for the
+    // quantity filled, keeping the rest locked at its peg limit.
+    {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let event_q = market.load_event_queue_mut(&accounts.event_q).unwrap();
+        let surplus_out = event_q
+            .iter()
+            .find_map(|event| match event.as_view().unwrap() {
+                EventView::Out {
+                    side: Side::Bid,
+                    release_funds: true,
+                    native_qty_unlocked,
+                    native_qty_still_locked,
+                    order_id,
+                    ..
+                } if order_id & ORACLE_PEGGED_FLAG != 0 => {
+                    Some((native_qty_unlocked, native_qty_still_locked))
+                }
+                _ => None,
+            });
+        assert_eq!(surplus_out, Some((650, 10_050)));
+    }
+
+    // Once the oracle moves its price above its peg limit, the bid is
+    // removed instead of trading.
+    set_oracle_price(&oracle, 10_200, 1_000);
+    let ask = order(Side::Ask, OrderType::ImmediateOrCancel, 9_000, 1, u64::MAX);
+    place(
+        seller.clone(),
+        Side::Ask,
+        MarketInstruction::NewOrderV3(ask),
+    )
+    .unwrap();
+    {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let bids = market.load_bids_mut(&accounts.bids).unwrap();
+        assert_eq!(bids.find_max(), None);
+    }
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            seller.clone(),
+            buyer.clone(),
+            maker.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            authority.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..3].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEventsPermissioned(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    let balances = |open_orders| {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let open_orders = market
+            .load_orders_mut(open_orders, None, &dex_program_id, None, None)
+            .unwrap();
+        (
+            identity(open_orders.native_coin_free),
+            identity(open_orders.native_coin_total),
+            identity(open_orders.native_pc_free),
+            identity(open_orders.native_pc_total),
+        )
+    };
+    assert_eq!(balances(&seller), (1_000, 2_000, 19_548, 19_548));
+    assert_eq!(balances(&buyer), (1_000, 1_000, 9_845, 9_845));
+    assert_eq!(balances(&maker), (1_000, 1_000, 20_601, 20_601));
+
+    // A pegged order is priced beyond its peg limit as soon as it's placed.
+    let bid = order(Side::Bid, OrderType::Limit, 10_050, 1, 20_000);
+    let result = place(maker.clone(), Side::Bid, pegged(bid, 600));
+    assert_eq!(result, Err(DexErrorCode::PegLimitExceeded.into()));
+
+    // While the oracle price is stale, orders skip the pegged ones, priced
+    // at 10_250 when it was last published, and trade with those at a fixed
+    // price. Nor can orders be pegged to it.
+    let ask = order(Side::Ask, OrderType::PostOnly, 10_400, 1, u64::MAX);
+    place(
+        seller.clone(),
+        Side::Ask,
+        MarketInstruction::NewOrderV3(ask),
+    )
+    .unwrap();
+    set_oracle_price(&oracle, 10_200, 900);
+    let bid = order(Side::Bid, OrderType::ImmediateOrCancel, 10_400, 1, 20_000);
+    place(buyer.clone(), Side::Bid, MarketInstruction::NewOrderV3(bid)).unwrap();
+    {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let asks = market.load_asks_mut(&accounts.asks).unwrap();
+        let best_ask = asks
+            .get(asks.find_min().unwrap())
+            .unwrap()
+            .as_leaf()
+            .unwrap();
+        assert_ne!(best_ask.order_id() & ORACLE_PEGGED_FLAG, 0);
+        assert_eq!(asks.find_max(), asks.find_min());
+    }
+    let ask = order(Side::Ask, OrderType::PostOnly, 9_000, 1, u64::MAX);
+    let result = place(seller.clone(), Side::Ask, pegged(ask, 50));
+    assert_eq!(result, Err(DexErrorCode::StaleOraclePrice.into()));
+
+    // Orders not meeting pegged ones don't need the oracle price.
+    let ask = order(Side::Ask, OrderType::PostOnly, 11_000, 1, u64::MAX);
+    place(
+        seller.clone(),
+        Side::Ask,
+        MarketInstruction::NewOrderV3(ask),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_full_book_evicts_least_aggressive_order() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let bump = Bump::new();
+
+    let authority = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let oracle = new_oracle_account(&mut rng, &bump);
+    set_oracle_price(&oracle, 10_000, 1_000);
+    // Bids and asks of the minimum capacity of 201 nodes, i.e. 101 orders.
+    let slab_len = 8 + 32 + 201 * 72;
+    let authority_and_oracle = Some((&authority, &oracle));
+    let accounts = setup_market_with_slab_len(&mut rng, &bump, authority_and_oracle, slab_len);
+
+    let dex_program_id = accounts.market.owner;
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 200_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 2_000_000, &bump);
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let mut new_open_orders = || {
+        let open_orders =
+            new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            open_orders.clone(),
+            owner.clone(),
+            accounts.market.clone(),
+            accounts.rent_sysvar.clone(),
+            authority.clone(),
+        ]
+        .into_bump_slice();
+        let instruction_data = MarketInstruction::InitOpenOrders.pack();
+        State::process(dex_program_id, instruction_accounts, &instruction_data).unwrap();
+        open_orders
+    };
+    let seller = new_open_orders();
+    let buyer = new_open_orders();
+
+    let place = |side, limit_price, client_order_id, peg_offset| {
+        let (open_orders, payer) = match side {
+            Side::Bid => (buyer.clone(), pc_account.clone()),
+            Side::Ask => (seller.clone(), coin_account.clone()),
+        };
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders,
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer,
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+            oracle.clone(),
+        ]
+        .into_bump_slice();
+        let order = NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+            order_type: OrderType::PostOnly,
+            client_order_id,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        };
+        let instruction = match peg_offset {
+            None => MarketInstruction::NewOrderV3(order),
+            Some(peg_offset) => {
+                MarketInstruction::NewOraclePeggedOrder(NewOraclePeggedOrderInstruction {
+                    order,
+                    peg_offset,
+                })
+            }
+        };
+        State::process(dex_program_id, instruction_accounts, &instruction.pack()).unwrap();
+    };
+    let is_resting = |side, client_order_id| {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let slab = match side {
+            Side::Bid => market.load_bids_mut(&accounts.bids).unwrap(),
+            Side::Ask => market.load_asks_mut(&accounts.asks).unwrap(),
+        };
+        let mut limit = u16::MAX;
+        !slab
+            .find_by(&mut limit, |leaf| leaf.client_order_id() == client_order_id)
+            .is_empty()
+    };
+
+    place(Side::Ask, 10_500, 1, None);
+    for _ in 0..98 {
+        place(Side::Ask, 10_200, 2, None);
+    }
+    place(Side::Ask, 9_000, 3, Some(100));
+    place(Side::Ask, 9_000, 4, Some(50));
+
+    // The asks are full, and the least aggressive one is at a fixed price
+    // while the pegged ones sort last.
+    place(Side::Ask, 10_300, 5, None);
+    assert!(!is_resting(Side::Ask, 1));
+    assert!(is_resting(Side::Ask, 3));
+
+    // Once the oracle moved, the least aggressive ask is a pegged one.
+    set_oracle_price(&oracle, 10_450, 1_000);
+    place(Side::Ask, 10_250, 6, None);
+    assert!(!is_resting(Side::Ask, 3));
+    assert!(is_resting(Side::Ask, 5));
+
+    place(Side::Bid, 9_000, 7, None);
+    for _ in 0..98 {
+        place(Side::Bid, 9_500, 8, None);
+    }
+    place(Side::Bid, 20_000, 9, Some(-2_000));
+    place(Side::Bid, 20_000, 10, Some(-500));
+
+    // The least aggressive bid is a pegged one, while the fixed ones sort
+    // first.
+    place(Side::Bid, 9_200, 11, None);
+    assert!(!is_resting(Side::Bid, 9));
+    assert!(is_resting(Side::Bid, 7));
+
+    // While the oracle price is stale, the pegged bids are left alone, and
+    // the least aggressive bid at a fixed price is evicted instead.
+    set_oracle_price(&oracle, 10_450, 900);
+    place(Side::Bid, 9_300, 12, None);
+    assert!(!is_resting(Side::Bid, 7));
+    assert!(is_resting(Side::Bid, 10));
+    assert!(is_resting(Side::Bid, 12));
+}
+
+#[test]
+fn test_good_til_time_orders() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let maker = new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker = new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let order = |side, order_type, limit_price, client_order_id, expiry_ts| {
+        let order = NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(10_000).unwrap(),
+            order_type,
+            client_order_id,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        };
+        match expiry_ts {
+            None => MarketInstruction::NewOrderV3(order),
+            Some(expiry_ts) => {
+                MarketInstruction::NewGoodTilTimeOrder(NewGoodTilTimeOrderInstruction {
+                    order,
+                    expiry_ts,
+                })
+            }
+        }
+    };
+    let place = |open_orders, side, instruction: MarketInstruction| {
+        let payer = match side {
+            Side::Bid => pc_account.clone(),
+            Side::Ask => coin_account.clone(),
+        };
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders,
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer,
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        State::process(dex_program_id, instruction_accounts, &instruction.pack())
+    };
+    let best = |side| {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let slab = match side {
+            Side::Bid => market.load_bids_mut(&accounts.bids).unwrap(),
+            Side::Ask => market.load_asks_mut(&accounts.asks).unwrap(),
+        };
+        let leaf = match side {
+            Side::Bid => slab.find_max(),
+            Side::Ask => slab.find_min(),
+        };
+        leaf.map(|h| *slab.get(h).unwrap().as_leaf().unwrap())
+    };
+
+    // Resting orders keep their expiry along with their client order id.
+    let ask = order(
+        Side::Ask,
+        OrderType::PostOnly,
+        10_000,
+        7,
+        Some(1_650_000_100),
+    );
+    place(maker.clone(), Side::Ask, ask).unwrap();
+    let bid = order(
+        Side::Bid,
+        OrderType::PostOnly,
+        9_000,
+        8,
+        Some(1_650_000_200),
+    );
+    place(maker.clone(), Side::Bid, bid).unwrap();
+    let ask = best(Side::Ask).unwrap();
+    assert_eq!(ask.client_order_id(), 7);
+    assert_eq!(ask.expiry_ts().map(NonZeroU32::get), Some(1_650_000_100));
+    assert!(!ask.is_expired(1_650_000_100));
+    assert!(ask.is_expired(1_650_000_101));
+
+    let bid = order(Side::Bid, OrderType::Limit, 9_000, 9, Some(1_649_999_999));
+    let result = place(maker.clone(), Side::Bid, bid);
+    assert_eq!(result, Err(DexErrorCode::InvalidExpiry.into()));
+    let bid = order(
+        Side::Bid,
+        OrderType::Limit,
+        9_000,
+        1 << 48,
+        Some(1_650_000_100),
+    );
+    let result = place(maker.clone(), Side::Bid, bid);
+    assert_eq!(result, Err(DexErrorCode::ClientOrderIdTooLarge.into()));
+
+    // An expired order is removed instead of trading.
+    state::TEST_UNIX_TIMESTAMP.with(|unix_timestamp| unix_timestamp.set(1_650_000_150));
+    let bid = order(Side::Bid, OrderType::ImmediateOrCancel, 10_000, 0, None);
+    place(taker.clone(), Side::Bid, bid).unwrap();
+    assert_eq!(best(Side::Ask), None);
+    assert!(best(Side::Bid).is_some());
+
+    // Anyone may remove the orders once they're expired.
+    let expire_accounts: &[AccountInfo] = bump_vec![in &bump;
+        accounts.market.clone(),
+        accounts.bids.clone(),
+        accounts.asks.clone(),
+        accounts.event_q.clone(),
+    ]
+    .into_bump_slice();
+    let expire = MarketInstruction::ExpireOrders(10).pack();
+    State::process(dex_program_id, expire_accounts, &expire).unwrap();
+    assert!(best(Side::Bid).is_some());
+    state::TEST_UNIX_TIMESTAMP.with(|unix_timestamp| unix_timestamp.set(1_650_000_201));
+    State::process(dex_program_id, expire_accounts, &expire).unwrap();
+    assert_eq!(best(Side::Bid), None);
+
+    {
+        let crank_accounts = bump_vec![in &bump;
+            maker.clone(),
+            taker.clone(),
+            accounts.market.clone(),
+            accounts.event_q.clone(),
+            coin_account.clone(),
+            pc_account.clone(),
+        ]
+        .into_bump_slice_mut();
+        crank_accounts[0..2].sort_by_key(|account_info| account_info.key.to_aligned_bytes());
+        let instruction_data = MarketInstruction::ConsumeEvents(200).pack();
+        State::process(dex_program_id, crank_accounts, &instruction_data).unwrap();
+    }
+
+    let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+    let open_orders = market
+        .load_orders_mut(&maker, None, &dex_program_id, None, None)
+        .unwrap();
+    assert_eq!(identity(open_orders.free_slot_bits), !0);
+    assert_eq!(identity(open_orders.native_coin_free), 1_000);
+    assert_eq!(identity(open_orders.native_coin_total), 1_000);
+    assert_eq!(identity(open_orders.native_pc_free), 10_000);
+    assert_eq!(identity(open_orders.native_pc_total), 10_000);
+}
+
+#[test]
+fn test_post_only_slide_orders() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let maker = new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 10_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let place = |side, order_type, limit_price, client_order_id| {
+        let payer = match side {
+            Side::Bid => pc_account.clone(),
+            Side::Ask => coin_account.clone(),
+        };
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            maker.clone(),
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer,
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        let instruction = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+            side,
+            limit_price: NonZeroU64::new(limit_price).unwrap(),
+            max_coin_qty: NonZeroU64::new(1).unwrap(),
+            max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+            order_type,
+            client_order_id,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            limit: 5,
+            max_ts: i64::MAX,
+        });
+        State::process(dex_program_id, instruction_accounts, &instruction.pack())
+    };
+    let price = |side, client_order_id| {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let slab = match side {
+            Side::Bid => market.load_bids_mut(&accounts.bids).unwrap(),
+            Side::Ask => market.load_asks_mut(&accounts.asks).unwrap(),
+        };
+        let mut limit = u16::MAX;
+        let order_ids = slab.find_by(&mut limit, |leaf| leaf.client_order_id() == client_order_id);
+        order_ids.first().map(|&order_id| (order_id >> 64) as u64)
+    };
+
+    place(Side::Ask, OrderType::PostOnly, 10_000, 1).unwrap();
+    place(Side::Bid, OrderType::PostOnly, 9_000, 2).unwrap();
+
+    // A post only order crossing the book is cancelled, while a
+    // post-only-slide one posts one tick behind the opposing best.
+    place(Side::Ask, OrderType::PostOnly, 8_000, 3).unwrap();
+    assert_eq!(price(Side::Ask, 3), None);
+    place(Side::Ask, OrderType::PostOnlySlide, 8_000, 4).unwrap();
+    assert_eq!(price(Side::Ask, 4), Some(9_001));
+    place(Side::Bid, OrderType::PostOnlySlide, 12_000, 5).unwrap();
+    assert_eq!(price(Side::Bid, 5), Some(9_000));
+
+    // Orders not crossing the book post at their own price.
+    place(Side::Bid, OrderType::PostOnlySlide, 8_500, 6).unwrap();
+    assert_eq!(price(Side::Bid, 6), Some(8_500));
+}
+
+#[test]
+fn test_lapsed_orders_count_against_limit() {
+    let mut rng = StdRng::seed_from_u64(6);
+    let bump = Bump::new();
+
+    let accounts = setup_market(&mut rng, &bump);
+
+    let dex_program_id = accounts.market.owner;
+
+    let owner = new_sol_account(&mut rng, 1_000_000_000, &bump);
+    let maker = new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let taker = new_dex_owned_account(&mut rng, size_of::<OpenOrders>(), dex_program_id, &bump);
+    let coin_account =
+        new_token_account(&mut rng, accounts.coin_mint.key, owner.key, 20_000, &bump);
+    let pc_account = new_token_account(&mut rng, accounts.pc_mint.key, owner.key, 1_000_000, &bump);
+    let spl_token_program = new_spl_token_program(&bump);
+
+    let place = |open_orders, side, instruction: MarketInstruction| {
+        let payer = match side {
+            Side::Bid => pc_account.clone(),
+            Side::Ask => coin_account.clone(),
+        };
+        let instruction_accounts: &[AccountInfo] = bump_vec![in &bump;
+            accounts.market.clone(),
+            open_orders,
+            accounts.req_q.clone(),
+            accounts.event_q.clone(),
+            accounts.bids.clone(),
+            accounts.asks.clone(),
+            payer,
+            owner.clone(),
+            accounts.coin_vault.clone(),
+            accounts.pc_vault.clone(),
+            spl_token_program.clone(),
+            accounts.rent_sysvar.clone(),
+        ]
+        .into_bump_slice();
+        State::process(dex_program_id, instruction_accounts, &instruction.pack()).unwrap();
+    };
+    let order = |side, order_type, limit_price, limit| NewOrderInstructionV3 {
+        side,
+        limit_price: NonZeroU64::new(limit_price).unwrap(),
+        max_coin_qty: NonZeroU64::new(1).unwrap(),
+        max_native_pc_qty_including_fees: NonZeroU64::new(20_000).unwrap(),
+        order_type,
+        client_order_id: 1,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        limit,
+        max_ts: i64::MAX,
+    };
+    let resting_asks = || {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let asks = market.load_asks_mut(&accounts.asks).unwrap();
+        let mut limit = u16::MAX;
+        asks.find_by(&mut limit, |_| true).len()
+    };
+
+    for _ in 0..10 {
+        let ask = order(Side::Ask, OrderType::PostOnly, 10_000, 5);
+        let ask = MarketInstruction::NewGoodTilTimeOrder(NewGoodTilTimeOrderInstruction {
+            order: ask,
+            expiry_ts: 1_650_000_100,
+        });
+        place(maker.clone(), Side::Ask, ask);
+    }
+    let ask = order(Side::Ask, OrderType::PostOnly, 10_100, 5);
+    place(maker.clone(), Side::Ask, MarketInstruction::NewOrderV3(ask));
+    state::TEST_UNIX_TIMESTAMP.with(|unix_timestamp| unix_timestamp.set(1_650_000_101));
+
+    // A taker removes as many expired orders as its limit allows, and its
+    // funds are released once it ran out.
+    let bid = order(Side::Bid, OrderType::Limit, 10_100, 5);
+    place(taker.clone(), Side::Bid, MarketInstruction::NewOrderV3(bid));
+    assert_eq!(resting_asks(), 6);
+    {
+        let market = Market::load(&accounts.market, &dex_program_id, false).unwrap();
+        let bids = market.load_bids_mut(&accounts.bids).unwrap();
+        assert_eq!(bids.find_max(), None);
+    }
+
+    let bid = order(Side::Bid, OrderType::Limit, 10_100, 20);
+    place(taker.clone(), Side::Bid, MarketInstruction::NewOrderV3(bid));
+    assert_eq!(resting_asks(), 0);
+}
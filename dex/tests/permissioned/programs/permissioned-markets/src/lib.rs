@@ -5,7 +5,7 @@ use serum_dex_permissioned::serum_dex::instruction::{
     CancelOrderInstructionV2, NewOrderInstructionV3,
 };
 use serum_dex_permissioned::{
-    Context, Logger, MarketMiddleware, MarketProxy, OpenOrdersPda, ReferralFees,
+    Context, Logger, MarketMiddleware, MarketProxy, OpenOrdersPda, ReferralFees, Stage,
 };
 use solana_program::account_info::AccountInfo;
 use solana_program::entrypoint::ProgramResult;
@@ -36,7 +36,9 @@ declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 /// to intercept, modify, and perform any access control on DEX requests before
 /// they get forwarded to the orderbook. These middleware can be mixed and
 /// matched. Note, however, that the order of middleware matters since they can
-/// mutate the request.
+/// mutate the request. Middleware declare a `Stage`, by which the proxy orders
+/// them, so that, e.g., access control always runs before accounts are
+/// rewritten.
 ///
 /// One useful pattern is to treat the request like layers of an onion, where
 /// each middleware unwraps the request by stripping accounts and instruction
@@ -60,10 +62,10 @@ pub mod permissioned_markets {
     use super::*;
     pub fn entry(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
         MarketProxy::new()
-            .middleware(&mut Logger)
             .middleware(&mut Identity)
             .middleware(&mut ReferralFees::new(referral::ID))
             .middleware(&mut OpenOrdersPda::new())
+            .middleware(&mut Logger::new())
             .run(program_id, accounts, data)
     }
 }
@@ -72,15 +74,11 @@ pub mod permissioned_markets {
 /// The identity token must be given as the fist account.
 struct Identity;
 
-impl Identity {
-    fn prepare_pda<'info>(acc_info: &AccountInfo<'info>) -> AccountInfo<'info> {
-        let mut acc_info = acc_info.clone();
-        acc_info.is_signer = true;
-        acc_info
+impl MarketMiddleware for Identity {
+    fn stage(&self) -> Stage {
+        Stage::Auth
     }
-}
 
-impl MarketMiddleware for Identity {
     /// Accounts:
     ///
     /// 0. Authorization token.
@@ -152,7 +150,7 @@ impl MarketMiddleware for Identity {
             market = market.key
         });
 
-        ctx.accounts[3] = Self::prepare_pda(&ctx.accounts[3]);
+        ctx.accounts.sign(3);
         Ok(())
     }
 
@@ -174,7 +172,7 @@ impl MarketMiddleware for Identity {
             market = market.key
         });
 
-        ctx.accounts[auth_idx] = Self::prepare_pda(&ctx.accounts[auth_idx]);
+        ctx.accounts.sign(auth_idx);
         Ok(())
     }
 
@@ -195,9 +193,7 @@ fn verify_and_strip_auth(ctx: &mut Context) -> ProgramResult {
     require!(auth.key == &rent::ID, InvalidAuth);
 
     // Strip off the account before possing on the message.
-    ctx.accounts = (&ctx.accounts[1..]).to_vec();
-
-    Ok(())
+    ctx.accounts.strip(1)
 }
 
 fn verify_revoked_and_strip_auth(ctx: &mut Context) -> ProgramResult {
@@ -206,9 +202,7 @@ fn verify_revoked_and_strip_auth(ctx: &mut Context) -> ProgramResult {
     require!(auth.key != &rent::ID, TokenNotRevoked);
 
     // Strip off the account before possing on the message.
-    ctx.accounts = (&ctx.accounts[1..]).to_vec();
-
-    Ok(())
+    ctx.accounts.strip(1)
 }
 
 // Macros.
@@ -222,30 +216,29 @@ macro_rules! prune_authority {
         market = $market:expr,
         bump = $bump:expr
     ) => {
-        vec![
-            b"prune".to_vec(),
-            $dex_program.as_ref().to_vec(),
-            $market.as_ref().to_vec(),
-            vec![$bump],
-        ]
+        serum_dex_permissioned::SignerSeeds::from_seeds(&[
+            b"prune",
+            $dex_program.as_ref(),
+            $market.as_ref(),
+            &[$bump],
+        ])
+        .unwrap()
     };
     (
         program = $program:expr,
         dex_program = $dex_program:expr,
         market = $market:expr
     ) => {
-        vec![
-            b"prune".to_vec(),
-            $dex_program.as_ref().to_vec(),
-            $market.as_ref().to_vec(),
-            vec![
-                Pubkey::find_program_address(
-                    &[b"prune".as_ref(), $dex_program.as_ref(), $market.as_ref()],
-                    $program,
-                )
-                .1,
-            ],
-        ]
+        prune_authority!(
+            program = $program,
+            dex_program = $dex_program,
+            market = $market,
+            bump = Pubkey::find_program_address(
+                &[b"prune".as_ref(), $dex_program.as_ref(), $market.as_ref()],
+                $program,
+            )
+            .1
+        )
     };
 }
 